@@ -0,0 +1,220 @@
+use std::{collections::HashMap, io};
+
+use crate::{index, Error};
+
+/// Magic bytes starting serialized alias matcher
+const MAGIC: &[u8; 4] = b"LMAL";
+
+/// Version of serialized format, matchers of other versions are rejected
+const VERSION: u8 = 1;
+
+/// Aliases of modules (as in modules.alias) matched against device modaliases without module tree
+///
+/// Matcher is built from module index with `ModuleIndex::alias_matcher` and can be serialized
+/// into compact binary form with `to_bytes`. Serialized matcher is loaded with `from_bytes`
+/// (e.g. in early initramfs, where only a few modules are available), so modules for devices
+/// can be found without modules.alias and module files.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// liblmod::set_filesystem(
+///     liblmod::MemoryFs::new()
+///         .file("/lib/modules/6.1.0/modules.dep", "kernel/e1000e.ko:\n")
+///         .file("/lib/modules/6.1.0/modules.alias", "alias pci:v00008086d000015B8sv*sd*bc*sc*i* e1000e\n"),
+/// );
+///
+/// let index = liblmod::ModuleIndex::open(liblmod::Selection::Other("6.1.0".to_string())).unwrap();
+/// let blob = index.alias_matcher().unwrap().to_bytes();
+/// liblmod::clear_filesystem();
+///
+/// let matcher = liblmod::AliasMatcher::from_bytes(&blob).unwrap();
+/// assert_eq!(matcher.kernel(), "6.1.0");
+/// assert_eq!(matcher.matches("pci:v00008086d000015B8sv00001028sd000006DEbc02sc00i00"), ["e1000e"]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AliasMatcher {
+	kernel: String,
+	modules: Vec<String>,
+	patterns: Vec<Pattern>,
+}
+
+/// Alias pattern with position of its module
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Pattern {
+	pattern: String,
+
+	/// Length of pattern before first wildcard, compared before matching whole pattern
+	literal: usize,
+
+	module: usize,
+}
+
+impl AliasMatcher {
+	/// Builds matcher from patterns with names of modules, in order of modules.alias
+	pub(crate) fn new(kernel: &str, aliases: Vec<(String, String)>) -> AliasMatcher {
+		let mut matcher = AliasMatcher {
+			kernel: kernel.to_string(),
+			..AliasMatcher::default()
+		};
+		let mut positions = HashMap::new();
+		for (pattern, module) in aliases {
+			let module = *positions.entry(module).or_insert_with_key(|module| {
+				matcher.modules.push(module.clone());
+				matcher.modules.len() - 1
+			});
+			matcher.push(pattern, module);
+		}
+
+		matcher
+	}
+
+	/// Returns release of kernel matcher has been built for
+	pub fn kernel(&self) -> &str {
+		&self.kernel
+	}
+
+	/// Returns names of modules whose aliases match modalias (or other alias, e.g. `fs-ext4`)
+	///
+	/// Modules are returned in order of modules.alias, each only once. Like with
+	/// `ModuleIndex::resolve_alias`, blacklist and other configuration is not applied.
+	pub fn matches(&self, modalias: &str) -> Vec<&str> {
+		let modalias = index::normalize_alias(modalias);
+		let mut modules: Vec<&str> = Vec::new();
+		for pattern in &self.patterns {
+			let module = self.modules[pattern.module].as_str();
+			if !modules.contains(&module)
+				&& modalias.starts_with(&pattern.pattern[..pattern.literal])
+				&& index::fnmatch(pattern.pattern.as_bytes(), modalias.as_bytes())
+			{
+				modules.push(module);
+			}
+		}
+
+		modules
+	}
+
+	/// Serializes matcher into compact binary form, loaded back with `from_bytes`
+	///
+	/// Module names are stored once, so aliases refer to them by their position.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = MAGIC.to_vec();
+		bytes.push(VERSION);
+		push_string(&mut bytes, &self.kernel);
+
+		push_number(&mut bytes, self.modules.len());
+		for module in &self.modules {
+			push_string(&mut bytes, module);
+		}
+		push_number(&mut bytes, self.patterns.len());
+		for pattern in &self.patterns {
+			push_string(&mut bytes, &pattern.pattern);
+			push_number(&mut bytes, pattern.module);
+		}
+
+		bytes
+	}
+
+	/// Loads matcher serialized with `to_bytes`
+	///
+	/// Data which isn't valid matcher of this version of format is rejected with
+	/// `Error::InvalidIndex`.
+	pub fn from_bytes(bytes: &[u8]) -> io::Result<AliasMatcher> {
+		let mut reader = Reader(bytes);
+		if reader.take(MAGIC.len())? != MAGIC {
+			return Err(invalid("not an alias matcher"));
+		}
+		if reader.take(1)? != [VERSION] {
+			return Err(invalid("unsupported version of alias matcher"));
+		}
+
+		let mut matcher = AliasMatcher {
+			kernel: reader.string()?,
+			..AliasMatcher::default()
+		};
+		for _ in 0..reader.number()? {
+			matcher.modules.push(reader.string()?);
+		}
+		for _ in 0..reader.number()? {
+			let pattern = reader.string()?;
+			let module = reader.number()?;
+			if module >= matcher.modules.len() {
+				return Err(invalid("alias refers to unknown module"));
+			}
+			matcher.push(pattern, module);
+		}
+		if !reader.0.is_empty() {
+			return Err(invalid("trailing data after alias matcher"));
+		}
+
+		Ok(matcher)
+	}
+
+	fn push(&mut self, pattern: String, module: usize) {
+		let literal = pattern.find(['*', '?', '[']).unwrap_or(pattern.len());
+		self.patterns.push(Pattern {
+			pattern,
+			literal,
+			module,
+		});
+	}
+}
+
+/// Appends number encoded in 7-bit groups, lowest first, with high bit marking next group
+fn push_number(bytes: &mut Vec<u8>, mut number: usize) {
+	while number >= 0x80 {
+		bytes.push(number as u8 | 0x80);
+		number >>= 7;
+	}
+	bytes.push(number as u8);
+}
+
+/// Appends string terminated by NUL byte
+fn push_string(bytes: &mut Vec<u8>, string: &str) {
+	bytes.extend_from_slice(string.as_bytes());
+	bytes.push(0);
+}
+
+/// Remaining data of serialized matcher
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+	fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+		if self.0.len() < len {
+			return Err(invalid("alias matcher is truncated"));
+		}
+		let (taken, rest) = self.0.split_at(len);
+		self.0 = rest;
+		Ok(taken)
+	}
+
+	fn number(&mut self) -> io::Result<usize> {
+		let mut number = 0;
+		for shift in (0..usize::BITS).step_by(7) {
+			let byte = self.take(1)?[0];
+			number |= ((byte & 0x7f) as usize) << shift;
+			if byte & 0x80 == 0 {
+				return Ok(number);
+			}
+		}
+
+		Err(invalid("number in alias matcher is too large"))
+	}
+
+	fn string(&mut self) -> io::Result<String> {
+		let len = self
+			.0
+			.iter()
+			.position(|&byte| byte == 0)
+			.ok_or_else(|| invalid("alias matcher is truncated"))?;
+		let string = String::from_utf8(self.take(len)?.to_vec())
+			.map_err(|_| invalid("alias matcher contains invalid UTF-8"))?;
+		self.take(1)?;
+		Ok(string)
+	}
+}
+
+fn invalid(reason: &str) -> io::Error {
+	Error::InvalidIndex(reason.to_string()).into()
+}
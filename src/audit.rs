@@ -0,0 +1,37 @@
+use std::{ffi::CString, io};
+
+/// Records loading of module in system log
+pub(crate) fn load(module: &str, params: &str, result: &io::Result<()>) {
+	record("load", module, params, result);
+}
+
+/// Records unloading of module in system log
+pub(crate) fn unload(name: &str, result: &io::Result<()>) {
+	record("unload", name, "", result);
+}
+
+/// Writes record to authpriv facility of syslog
+fn record(operation: &str, module: &str, params: &str, result: &io::Result<()>) {
+	let result = match result {
+		Ok(()) => "success".to_string(),
+		Err(e) => format!("failure ({})", e),
+	};
+	let message = format!(
+		"liblmod: {} module={} params={:?} uid={} result={}",
+		operation,
+		module,
+		params,
+		unsafe { libc::getuid() },
+		result
+	);
+
+	// Message is passed as argument, so it's not interpreted as format string
+	let message = CString::new(message.replace('\0', "\\0")).unwrap();
+	unsafe {
+		libc::syslog(
+			libc::LOG_AUTHPRIV | libc::LOG_NOTICE,
+			c"%s".as_ptr(),
+			message.as_ptr(),
+		)
+	};
+}
@@ -0,0 +1,411 @@
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap},
+	io,
+	path::{Path, PathBuf},
+	sync::{Condvar, Mutex},
+	thread,
+};
+
+use crate::{index, modprobe, priority, vfs, Config, ModuleResolver, Options, Outcome};
+
+/// Directory with devices known to kernel
+const SYSFS_DEVICES: &str = "/sys/devices";
+
+/// Reason why module matching device hasn't been loaded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Skipped {
+	/// Module is blacklisted in modprobe.d or on kernel command line
+	Blacklisted,
+
+	/// Module is refused by denylist set with `Options::denylist`
+	Denied,
+}
+
+/// Modules loaded and skipped for devices, returned by `coldplug` and `autoload`
+#[derive(Debug, Default)]
+pub struct AutoloadReport {
+	/// Results of loading modules matching devices, by module name
+	pub results: BTreeMap<String, io::Result<Outcome>>,
+
+	/// Modules matching devices which haven't been loaded, by module name
+	pub skipped: BTreeMap<String, Skipped>,
+}
+
+impl AutoloadReport {
+	/// Returns names of modules which have been loaded or were already loaded
+	pub fn loaded(&self) -> impl Iterator<Item = &str> {
+		self.results
+			.iter()
+			.filter(|(_, result)| result.is_ok())
+			.map(|(name, _)| name.as_str())
+	}
+
+	/// Returns modules which failed to load with their errors
+	pub fn failed(&self) -> impl Iterator<Item = (&str, &io::Error)> {
+		self.results
+			.iter()
+			.filter_map(|(name, result)| Some((name.as_str(), result.as_ref().err()?)))
+	}
+}
+
+/// Module matching alias, see `resolve_alias`
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AliasCandidate {
+	/// Name of module
+	pub name: String,
+
+	/// Reason why module wouldn't be loaded for alias, `None` if it would be
+	pub skipped: Option<Skipped>,
+
+	/// File with blacklist directive (`/proc/cmdline` for kernel command line)
+	pub blacklisted_by: Option<PathBuf>,
+}
+
+/// All modules matching alias with decisions about them, returned by `resolve_alias`
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AliasResolution {
+	/// Resolved alias (e.g. modalias of device)
+	pub alias: String,
+
+	/// Alias after applying aliases from modprobe.d
+	pub target: String,
+
+	/// Modules matching alias, in order of modules.alias
+	pub candidates: Vec<AliasCandidate>,
+}
+
+impl AliasResolution {
+	/// Returns names of modules which would be loaded for alias
+	pub fn selected(&self) -> impl Iterator<Item = &str> {
+		self.candidates
+			.iter()
+			.filter(|candidate| candidate.skipped.is_none())
+			.map(|candidate| candidate.name.as_str())
+	}
+
+	/// Returns modules matching alias which wouldn't be loaded, with reasons
+	pub fn skipped(&self) -> impl Iterator<Item = (&str, Skipped)> {
+		self.candidates
+			.iter()
+			.filter_map(|candidate| Some((candidate.name.as_str(), candidate.skipped?)))
+	}
+}
+
+/// Returns all modules matching alias, including ones filtered by blacklist or denylist
+///
+/// Unlike `autoload`, nothing is loaded. Modules are matched like by `autoload`, so device
+/// managers can tell users that driver exists, but is blacklisted.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let device = liblmod::PciDevice::new(0x10de, 0x1c82).class(0x030000);
+/// match liblmod::resolve_alias(device.modalias(), &liblmod::Options::new()) {
+///     Ok(resolution) => {
+///         for candidate in &resolution.candidates {
+///             match &candidate.blacklisted_by {
+///                 Some(file) => println!("Driver {} is blacklisted in {}", candidate.name, file.display()),
+///                 None => println!("Driver {}: {:?}", candidate.name, candidate.skipped),
+///             }
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to resolve alias: {e}"),
+/// }
+/// ```
+pub fn resolve_alias(alias: impl AsRef<str>, options: &Options) -> io::Result<AliasResolution> {
+	let index = options.index()?;
+	let config = options.load_config()?;
+	resolution(&*index, &config, alias.as_ref(), options)
+}
+
+/// Loads modules for devices present in system, like `udevadm trigger` at boot
+///
+/// Modaliases are read from `/sys/devices` and matched against aliases from modprobe.d and
+/// modules.alias. Modules blacklisted in modprobe.d or refused by `Options::denylist` are
+/// skipped. Every module is loaded once, in order of modules.order. Failure of one module
+/// doesn't stop loading of the others.
+///
+/// With `Options::jobs`, modules are loaded by several threads. Module is then started only
+/// after matched modules it depends on have finished loading.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// match liblmod::coldplug(&liblmod::Options::new().jobs(0)) {
+///     Ok(report) => {
+///         for (name, reason) in &report.skipped {
+///             println!("Module {name} not loaded: {reason:?}");
+///         }
+///         for (name, e) in report.failed() {
+///             eprintln!("Failed to load module {name}: {e}");
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to scan devices: {e}"),
+/// }
+/// ```
+pub fn coldplug(options: &Options) -> io::Result<AutoloadReport> {
+	let mut modaliases = BTreeSet::new();
+	find_modaliases(Path::new(SYSFS_DEVICES), &mut modaliases);
+
+	let index = options.index()?;
+	let config = options.load_config()?;
+	load_matching(&*index, &config, modaliases.iter(), options)
+}
+
+/// Loads modules matching modalias of device (e.g. from uevent), skipping blacklisted ones
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let device = liblmod::PciDevice::new(0x10de, 0x1c82).class(0x030000);
+/// match liblmod::autoload(device.modalias(), &liblmod::Options::new()) {
+///     Ok(report) => {
+///         println!("Loaded {:?}, skipped {:?}", report.loaded().collect::<Vec<_>>(), report.skipped);
+///     }
+///     Err(e) => eprintln!("Failed to load modules for device: {e}"),
+/// }
+/// ```
+pub fn autoload(modalias: impl AsRef<str>, options: &Options) -> io::Result<AutoloadReport> {
+	let index = options.index()?;
+	let config = options.load_config()?;
+	load_matching(&*index, &config, [modalias.as_ref()].iter(), options)
+}
+
+/// Loads modules matching modaliases, sorted by modules.order
+pub(crate) fn load_matching<S: AsRef<str>>(
+	index: &dyn ModuleResolver,
+	config: &Config,
+	modaliases: impl Iterator<Item = S>,
+	options: &Options,
+) -> io::Result<AutoloadReport> {
+	priority::scope(options, || load_all(index, config, modaliases, options))
+}
+
+/// Loads modules matching modaliases with priorities already set
+fn load_all<S: AsRef<str>>(
+	index: &dyn ModuleResolver,
+	config: &Config,
+	modaliases: impl Iterator<Item = S>,
+	options: &Options,
+) -> io::Result<AutoloadReport> {
+	let mut report = AutoloadReport::default();
+	let mut names = Vec::new();
+	for modalias in modaliases {
+		for candidate in resolution(index, config, modalias.as_ref(), options)?.candidates {
+			match candidate.skipped {
+				Some(skipped) => {
+					report.skipped.insert(candidate.name, skipped);
+				}
+				None if !names.contains(&candidate.name) => names.push(candidate.name),
+				None => (),
+			}
+		}
+	}
+
+	// Unlisted modules get no position, so they are sorted last
+	names.sort_by_key(|name| {
+		index
+			.resolve(name)
+			.and_then(|module| index.order(module))
+			.unwrap_or(usize::MAX)
+	});
+	if options.jobs > 1 && names.len() > 1 && !cfg!(feature = "testing") {
+		report.results = load_parallel(index, config, &names, options);
+		options.check_cancelled()?;
+		return Ok(report);
+	}
+
+	for name in names {
+		options.check_cancelled()?;
+		let result =
+			modprobe::probe(index, config, &name, "", options).map(|report| report.outcome);
+		report.results.insert(name, result);
+	}
+
+	Ok(report)
+}
+
+/// Modules loaded by worker threads of `load_parallel`
+#[derive(Default)]
+struct Progress {
+	/// Whether loading of module has started, by position in loaded modules
+	started: Vec<bool>,
+
+	/// Whether loading of module has finished, by position in loaded modules
+	finished: Vec<bool>,
+
+	/// Number of modules being loaded
+	running: usize,
+
+	/// Results of finished modules
+	results: BTreeMap<String, io::Result<Outcome>>,
+}
+
+impl Progress {
+	/// Takes next module whose dependencies among loaded modules are finished
+	fn next(&mut self, waits: &[Vec<usize>]) -> Option<usize> {
+		let pending = (0..self.started.len()).filter(|&i| !self.started[i]);
+		let mut ready = pending
+			.clone()
+			.filter(|&i| waits[i].iter().all(|&dep| self.finished[dep]));
+
+		// Dependency cycle would block workers forever, so it's broken when nothing else runs
+		let next = match self.running {
+			0 => ready.next().or_else(|| pending.clone().next()),
+			_ => ready.next(),
+		}?;
+		self.started[next] = true;
+		self.running += 1;
+		Some(next)
+	}
+
+	/// Checks if all modules have been started
+	fn is_drained(&self) -> bool {
+		self.started.iter().all(|&started| started)
+	}
+}
+
+/// Loads modules on `Options::jobs` threads, each module after its dependencies among them
+fn load_parallel(
+	index: &dyn ModuleResolver,
+	config: &Config,
+	names: &[String],
+	options: &Options,
+) -> BTreeMap<String, io::Result<Outcome>> {
+	let positions: HashMap<&str, usize> = names
+		.iter()
+		.enumerate()
+		.map(|(i, name)| (name.as_str(), i))
+		.collect();
+	let waits: Vec<Vec<usize>> = names
+		.iter()
+		.map(|name| match index.resolve(name) {
+			Some(module) => index
+				.dependencies(module)
+				.iter()
+				.filter_map(|dep| positions.get(index::module_name(dep).as_str()).copied())
+				.collect(),
+			None => Vec::new(),
+		})
+		.collect();
+
+	let progress = Mutex::new(Progress {
+		started: vec![false; names.len()],
+		finished: vec![false; names.len()],
+		..Progress::default()
+	});
+	let changed = Condvar::new();
+	let fs = vfs::current();
+	thread::scope(|scope| {
+		for _ in 0..options.jobs.min(names.len()) {
+			scope.spawn(|| {
+				vfs::inherit(fs.clone());
+				priority::scope(options, || loop {
+					let mut state = progress.lock().unwrap();
+					let next = loop {
+						if options.check_cancelled().is_err() || state.is_drained() {
+							break None;
+						}
+						match state.next(&waits) {
+							Some(next) => break Some(next),
+							None => state = changed.wait(state).unwrap(),
+						}
+					};
+					let Some(next) = next else {
+						return;
+					};
+					drop(state);
+
+					let name = &names[next];
+					let result = modprobe::probe(index, config, name, "", options)
+						.map(|report| report.outcome);
+
+					let mut state = progress.lock().unwrap();
+					state.finished[next] = true;
+					state.running -= 1;
+					state.results.insert(name.clone(), result);
+					changed.notify_all();
+				})
+			});
+		}
+	});
+
+	progress.into_inner().unwrap().results
+}
+
+/// Returns modules matching modalias with decisions about them
+fn resolution(
+	index: &dyn ModuleResolver,
+	config: &Config,
+	modalias: &str,
+	options: &Options,
+) -> io::Result<AliasResolution> {
+	let target = config.resolve_alias(modalias)?;
+	let candidates = candidates(index, &target)
+		.into_iter()
+		.map(|name| {
+			let blacklisted_by = config
+				.blacklisted_by(&name)
+				.map(|directive| directive.source.clone());
+			let skipped = if config.is_blacklisted(&name) {
+				Some(Skipped::Blacklisted)
+			} else if options.is_denied(&name) {
+				Some(Skipped::Denied)
+			} else {
+				None
+			};
+			AliasCandidate {
+				name,
+				skipped,
+				blacklisted_by,
+			}
+		})
+		.collect();
+
+	Ok(AliasResolution {
+		alias: modalias.to_string(),
+		target,
+		candidates,
+	})
+}
+
+/// Returns names of modules matching alias resolved by modprobe.d
+fn candidates(index: &dyn ModuleResolver, alias: &str) -> Vec<String> {
+	let names = index.resolve_alias(alias);
+	if !names.is_empty() {
+		return names;
+	}
+
+	// Alias from modprobe.d can name module directly
+	index
+		.resolve(alias)
+		.map(|module| vec![index::module_name(module)])
+		.unwrap_or_default()
+}
+
+/// Collects modaliases of devices in directory and its subdirectories
+fn find_modaliases(dir: &Path, modaliases: &mut BTreeSet<String>) {
+	// Devices are linked to each other, only real directories are followed
+	for path in vfs::read_dir(dir).unwrap_or_default() {
+		if vfs::is_symlink(&path) {
+			continue;
+		}
+
+		if vfs::is_dir(&path) {
+			find_modaliases(&path, modaliases);
+		} else if path.file_name().is_some_and(|name| name == "modalias") {
+			if let Ok(modalias) = vfs::read_to_string(&path) {
+				let modalias = modalias.trim();
+				if !modalias.is_empty() {
+					modaliases.insert(modalias.to_string());
+				}
+			}
+		}
+	}
+}
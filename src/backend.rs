@@ -0,0 +1,118 @@
+use std::{
+	ffi::{CStr, CString},
+	io::{self, ErrorKind},
+	os::{fd::BorrowedFd, raw::*},
+	sync::atomic::{AtomicBool, Ordering},
+};
+
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+
+// Syscall numbers are taken from libc, which provides them for every Linux architecture
+#[cfg(target_os = "linux")]
+use libc::{
+	syscall, SYS_delete_module as DELETE_MODULE, SYS_finit_module as FINIT_MODULE,
+	SYS_init_module as INIT_MODULE,
+};
+
+use crate::Error;
+
+/// Flag of finit_module asking kernel to decompress module itself
+pub const MODULE_INIT_COMPRESSED_FILE: c_uint = 4;
+
+/// Whether init_module and delete_module are called through functions exported by libc
+static LIBC_WRAPPERS: AtomicBool = AtomicBool::new(false);
+
+/// Selects whether libc wrappers are called instead of syscall()
+pub fn use_libc_wrappers(enabled: bool) {
+	LIBC_WRAPPERS.store(enabled, Ordering::Relaxed);
+}
+
+/// Checks if libc wrappers are called instead of syscall()
+pub fn libc_wrappers() -> bool {
+	LIBC_WRAPPERS.load(Ordering::Relaxed)
+}
+
+// Wrappers exported by glibc, musl and bionic, but not declared by libc crate. They are symbols
+// which LD_PRELOAD libraries (e.g. fakeroot-like environments) can replace, unlike syscall().
+#[cfg(target_os = "linux")]
+mod wrappers {
+	use std::os::raw::*;
+
+	extern "C" {
+		pub fn init_module(image: *const c_void, len: c_ulong, params: *const c_char) -> c_int;
+		pub fn delete_module(name: *const c_char, flags: c_uint) -> c_int;
+	}
+}
+
+/// Converts string passed to kernel, rejecting interior NUL bytes instead of truncating it
+///
+/// Returned string is owned by caller and has to outlive the syscall it's passed to.
+pub fn cstring(value: &str) -> io::Result<CString> {
+	CString::new(value).map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))
+}
+
+/// Returns error of operations which need Linux kernel
+pub fn unsupported() -> io::Error {
+	Error::Unsupported(std::env::consts::OS.to_string()).into()
+}
+
+/// Turns return value of syscall into result, capturing errno before anything else can change it
+#[cfg(target_os = "linux")]
+fn check(ret: c_long) -> io::Result<c_long> {
+	match ret {
+		-1 => Err(io::Error::last_os_error()),
+		ret => Ok(ret),
+	}
+}
+
+#[cfg(target_os = "linux")]
+pub fn init_module(image: &[u8], params: &CStr) -> io::Result<()> {
+	// Length is passed as unsigned long, which is as wide as pointers on every Linux architecture
+	let len = c_ulong::try_from(image.len()).map_err(|_| {
+		io::Error::new(
+			ErrorKind::InvalidInput,
+			format!("Module image of {} bytes is too large", image.len()),
+		)
+	})?;
+
+	if libc_wrappers() {
+		let image = image.as_ptr().cast();
+		return check(unsafe { wrappers::init_module(image, len, params.as_ptr()) }.into())
+			.map(|_| ());
+	}
+
+	check(unsafe { syscall(INIT_MODULE, image.as_ptr(), len, params.as_ptr()) }).map(|_| ())
+}
+
+#[cfg(target_os = "linux")]
+pub fn finit_module(fd: BorrowedFd, params: &CStr, flags: c_uint) -> io::Result<()> {
+	// libc has no finit_module wrapper, syscall() is used with both backends
+	check(unsafe { syscall(FINIT_MODULE, fd.as_raw_fd(), params.as_ptr(), flags) }).map(|_| ())
+}
+
+#[cfg(target_os = "linux")]
+pub fn delete_module(name: &CStr, flags: c_uint) -> io::Result<()> {
+	if libc_wrappers() {
+		return check(unsafe { wrappers::delete_module(name.as_ptr(), flags) }.into()).map(|_| ());
+	}
+
+	check(unsafe { syscall(DELETE_MODULE, name.as_ptr(), flags) }).map(|_| ())
+}
+
+// Module syscalls exist only in Linux, other systems get errors at runtime
+
+#[cfg(not(target_os = "linux"))]
+pub fn init_module(_image: &[u8], _params: &CStr) -> io::Result<()> {
+	Err(unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn finit_module(_fd: BorrowedFd, _params: &CStr, _flags: c_uint) -> io::Result<()> {
+	Err(unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn delete_module(_name: &CStr, _flags: c_uint) -> io::Result<()> {
+	Err(unsupported())
+}
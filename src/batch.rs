@@ -0,0 +1,203 @@
+use std::{io, path::Path, str::FromStr};
+
+use crate::{modprobe, plan, vfs, Error, KernelVersion, Options, Outcome};
+
+/// Prefix of condition requiring minimal version of kernel in manifest line
+const MIN_KERNEL: &str = "kernel>=";
+
+/// List of modules to load with their parameters, e.g. carried by embedded image
+///
+/// Every line of manifest names module, optionally followed by its parameters and condition
+/// `kernel>=<version>` as the last word. Blank lines and comments (`#`) are skipped.
+///
+/// ```text
+/// # Loaded on every kernel
+/// kvm_intel nested=1 enable_apicv=1
+/// # Loaded only on kernels since 5.6
+/// wireguard kernel>=5.6
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModuleManifest {
+	/// Entries in order of loading
+	pub entries: Vec<ManifestEntry>,
+}
+
+/// Module listed in manifest
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ManifestEntry {
+	/// Name of module
+	pub name: String,
+
+	/// Parameters of module, in addition to ones from modprobe.d
+	pub params: String,
+
+	/// Minimal version of kernel, on older kernels entry is skipped
+	pub min_kernel: Option<KernelVersion>,
+}
+
+impl ManifestEntry {
+	/// Creates entry of module without parameters and conditions
+	pub fn new(name: impl Into<String>) -> ManifestEntry {
+		ManifestEntry {
+			name: name.into(),
+			params: String::new(),
+			min_kernel: None,
+		}
+	}
+}
+
+impl ModuleManifest {
+	/// Reads manifest from file
+	pub fn from_file(path: impl AsRef<Path>) -> io::Result<ModuleManifest> {
+		let path = path.as_ref();
+		parse(&vfs::read_to_string(path)?, &path.display().to_string())
+	}
+}
+
+impl FromStr for ModuleManifest {
+	type Err = io::Error;
+
+	fn from_str(text: &str) -> io::Result<ModuleManifest> {
+		parse(text, "manifest")
+	}
+}
+
+/// Results of applying manifest, returned by `apply_manifest`
+#[derive(Debug, Default)]
+pub struct ManifestReport {
+	/// Results of applied entries by module name, in order of manifest
+	///
+	/// Entries after the failed one aren't applied. Modules reported as loaded have been
+	/// unloaded again if `rolled_back` is set.
+	pub results: Vec<(String, io::Result<Outcome>)>,
+
+	/// Entries skipped, because their condition isn't met by kernel
+	pub skipped: Vec<String>,
+
+	/// Whether modules loaded by manifest have been unloaded after failure of entry
+	pub rolled_back: bool,
+}
+
+impl ManifestReport {
+	/// Returns whether all entries have been applied or skipped without failure
+	pub fn is_applied(&self) -> bool {
+		self.results.iter().all(|(_, result)| result.is_ok())
+	}
+}
+
+/// Loads modules listed in manifest with their parameters, all of them or none
+///
+/// Entries are loaded in order of manifest, entries whose condition isn't met by selected kernel
+/// are skipped. When entry fails, the remaining ones aren't loaded and modules loaded by
+/// manifest (including dependencies) are unloaded, the last loaded first. Modules which were
+/// loaded before are left loaded.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let manifest: liblmod::ModuleManifest = "kvm\nkvm_intel nested=1 kernel>=4.0".parse().unwrap();
+/// match liblmod::apply_manifest(&manifest, &liblmod::Options::new()) {
+///     Ok(report) if report.is_applied() => println!("Manifest applied, skipped {:?}", report.skipped),
+///     Ok(report) => {
+///         for (name, result) in &report.results {
+///             if let Err(e) = result {
+///                 eprintln!("Failed to load module {name}: {e}");
+///             }
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to read module index: {e}"),
+/// }
+/// ```
+pub fn apply_manifest(manifest: &ModuleManifest, options: &Options) -> io::Result<ManifestReport> {
+	let index = options.index()?;
+	let config = options.load_config()?;
+
+	// Failed entry unloads its own dependencies, earlier entries are unloaded below
+	let options = options.clone().rollback(true);
+	let mut version = None;
+	let mut loaded = Vec::new();
+	let mut report = ManifestReport::default();
+	for entry in &manifest.entries {
+		if let Some(min_kernel) = &entry.min_kernel {
+			if version.is_none() {
+				version = Some(index.kernel().parse::<KernelVersion>()?);
+			}
+			if version.as_ref().is_some_and(|version| version < min_kernel) {
+				report.skipped.push(entry.name.clone());
+				continue;
+			}
+		}
+
+		match modprobe::probe(&*index, &config, &entry.name, &entry.params, &options) {
+			Ok(load) => {
+				report.results.push((entry.name.clone(), Ok(load.outcome)));
+				loaded.push(load);
+			}
+			Err(e) => {
+				report.results.push((entry.name.clone(), Err(e)));
+				for load in loaded.iter().rev() {
+					plan::rollback(load);
+				}
+				report.rolled_back = true;
+				break;
+			}
+		}
+	}
+
+	Ok(report)
+}
+
+/// Parses manifest, naming file in errors
+fn parse(text: &str, file: &str) -> io::Result<ModuleManifest> {
+	let mut manifest = ModuleManifest::default();
+	for (number, line) in text.lines().enumerate() {
+		let trimmed = line.trim();
+		if trimmed.is_empty() || trimmed.starts_with('#') {
+			continue;
+		}
+
+		let column = line.len() - line.trim_start().len() + 1;
+		let (name, mut rest) = match trimmed.split_once(char::is_whitespace) {
+			Some((name, rest)) => (name, rest.trim()),
+			None => (trimmed, ""),
+		};
+
+		// Condition is the last word, parameters may contain quoted spaces
+		let mut min_kernel = None;
+		let last = rest.rsplit(char::is_whitespace).next().unwrap_or_default();
+		if let Some(version) = last.strip_prefix(MIN_KERNEL) {
+			let position = column + trimmed.len() - last.len();
+			min_kernel = Some(version.parse().map_err(|_| {
+				error(
+					file,
+					number + 1,
+					position,
+					&format!("invalid kernel version {}", version),
+				)
+			})?);
+			rest = rest[..rest.len() - last.len()].trim_end();
+		}
+
+		manifest.entries.push(ManifestEntry {
+			name: name.to_string(),
+			params: rest.to_string(),
+			min_kernel,
+		});
+	}
+
+	Ok(manifest)
+}
+
+/// Returns error of malformed manifest line
+fn error(file: &str, line: usize, column: usize, reason: &str) -> io::Error {
+	Error::ManifestParse {
+		file: file.to_string(),
+		line,
+		column,
+		reason: reason.to_string(),
+	}
+	.into()
+}
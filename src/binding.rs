@@ -0,0 +1,172 @@
+use std::{
+	fs,
+	io::{self, ErrorKind},
+	path::PathBuf,
+};
+
+/// Directory with buses in sysfs
+const SYSFS_BUS: &str = "/sys/bus";
+
+/// Binds device to driver (`/sys/bus/<bus>/drivers/<driver>/bind`)
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// if let Err(e) = liblmod::binding::bind("pci", "vfio-pci", "0000:01:00.0") {
+///     eprintln!("Failed to bind device to vfio-pci: {e}");
+/// }
+/// ```
+pub fn bind(
+	bus: impl AsRef<str>,
+	driver: impl AsRef<str>,
+	device: impl AsRef<str>,
+) -> io::Result<()> {
+	fs::write(
+		driver_path(bus.as_ref(), driver.as_ref())?.join("bind"),
+		device.as_ref(),
+	)
+}
+
+/// Unbinds device from its current driver, does nothing if device isn't bound
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// if let Err(e) = liblmod::binding::unbind("pci", "0000:01:00.0") {
+///     eprintln!("Failed to unbind device: {e}");
+/// }
+/// ```
+pub fn unbind(bus: impl AsRef<str>, device: impl AsRef<str>) -> io::Result<()> {
+	let device = device.as_ref();
+	let path = device_path(bus.as_ref(), device)?.join("driver/unbind");
+	if !path.exists() {
+		return Ok(());
+	}
+
+	fs::write(path, device)
+}
+
+/// Returns name of driver device is bound to
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::binding::driver("pci", "0000:00:00.0") {
+///     Ok(driver) => println!("Device is bound to {:?}", driver),
+///     Err(e) => eprintln!("Failed to check driver of device: {e}"),
+/// }
+/// ```
+pub fn driver(bus: impl AsRef<str>, device: impl AsRef<str>) -> io::Result<Option<String>> {
+	match fs::read_link(device_path(bus.as_ref(), device.as_ref())?.join("driver")) {
+		Ok(target) => Ok(target
+			.file_name()
+			.map(|name| name.to_string_lossy().into_owned())),
+		Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(e),
+	}
+}
+
+/// Sets driver which is the only one allowed to bind device, `None` clears the override
+///
+/// Override is used on next probe of device, see `probe`.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// if let Err(e) = liblmod::binding::set_driver_override("pci", "0000:01:00.0", Some("vfio-pci")) {
+///     eprintln!("Failed to set driver override: {e}");
+/// }
+/// ```
+pub fn set_driver_override(
+	bus: impl AsRef<str>,
+	device: impl AsRef<str>,
+	driver: Option<&str>,
+) -> io::Result<()> {
+	let path = device_path(bus.as_ref(), device.as_ref())?.join("driver_override");
+	match driver {
+		Some(driver) => fs::write(path, check_name(driver)?),
+		// Writing newline clears the override
+		None => fs::write(path, "\n"),
+	}
+}
+
+/// Asks kernel to bind device to matching driver (`/sys/bus/<bus>/drivers_probe`)
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// if let Err(e) = liblmod::binding::probe("pci", "0000:01:00.0") {
+///     eprintln!("Failed to probe device: {e}");
+/// }
+/// ```
+pub fn probe(bus: impl AsRef<str>, device: impl AsRef<str>) -> io::Result<()> {
+	fs::write(
+		bus_path(bus.as_ref())?.join("drivers_probe"),
+		check_name(device.as_ref())?,
+	)
+}
+
+/// Adds dynamic device ID to driver (`/sys/bus/<bus>/drivers/<driver>/new_id`)
+///
+/// Format of ID depends on bus, for PCI it's `vendor device [subvendor subdevice class class_mask]`
+/// in hexadecimal (e.g. `8086 10d3`).
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// if let Err(e) = liblmod::binding::new_id("pci", "vfio-pci", "8086 10d3") {
+///     eprintln!("Failed to add device ID to vfio-pci: {e}");
+/// }
+/// ```
+pub fn new_id(
+	bus: impl AsRef<str>,
+	driver: impl AsRef<str>,
+	id: impl AsRef<str>,
+) -> io::Result<()> {
+	fs::write(
+		driver_path(bus.as_ref(), driver.as_ref())?.join("new_id"),
+		id.as_ref(),
+	)
+}
+
+/// Removes dynamic device ID from driver (`/sys/bus/<bus>/drivers/<driver>/remove_id`)
+pub fn remove_id(
+	bus: impl AsRef<str>,
+	driver: impl AsRef<str>,
+	id: impl AsRef<str>,
+) -> io::Result<()> {
+	fs::write(
+		driver_path(bus.as_ref(), driver.as_ref())?.join("remove_id"),
+		id.as_ref(),
+	)
+}
+
+fn bus_path(bus: &str) -> io::Result<PathBuf> {
+	Ok(PathBuf::from(SYSFS_BUS).join(check_name(bus)?))
+}
+
+fn driver_path(bus: &str, driver: &str) -> io::Result<PathBuf> {
+	Ok(bus_path(bus)?.join("drivers").join(check_name(driver)?))
+}
+
+fn device_path(bus: &str, device: &str) -> io::Result<PathBuf> {
+	Ok(bus_path(bus)?.join("devices").join(check_name(device)?))
+}
+
+/// Checks if name can be safely used as sysfs path component
+fn check_name(name: &str) -> io::Result<&str> {
+	if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+		return Err(io::Error::new(
+			ErrorKind::InvalidInput,
+			format!("Invalid sysfs name: {:?}", name),
+		));
+	}
+
+	Ok(name)
+}
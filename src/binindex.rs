@@ -0,0 +1,247 @@
+use std::{
+	ffi::CString,
+	fs,
+	io::{self, ErrorKind},
+	os::fd::AsRawFd,
+	path::Path,
+	ptr, slice,
+};
+
+use crate::{index, Error};
+
+/// Magic number of kmod binary index
+const INDEX_MAGIC: u32 = 0xB007F457;
+
+/// Supported major version of kmod binary index
+const INDEX_VERSION_MAJOR: u32 = 0x0002;
+
+/// Node flags stored in upper bits of node offset
+const INDEX_NODE_PREFIX: u32 = 0x80000000;
+const INDEX_NODE_VALUES: u32 = 0x40000000;
+const INDEX_NODE_CHILDS: u32 = 0x20000000;
+const INDEX_NODE_MASK: u32 = 0x0FFFFFFF;
+
+/// Memory-mapped kmod binary index (e.g. modules.alias.bin)
+///
+/// Index is a trie, lookups are performed directly on the mapping, so the file is never copied
+/// into memory. Pages are loaded by kernel on demand.
+pub(crate) struct BinIndex {
+	map: *const u8,
+	len: usize,
+}
+
+// Mapping is read-only and private, so it can be shared between threads
+unsafe impl Send for BinIndex {}
+unsafe impl Sync for BinIndex {}
+
+/// Node of trie
+struct Node<'a> {
+	prefix: &'a [u8],
+	first: u8,
+	last: u8,
+	children: &'a [u8],
+	values: Vec<(u32, &'a [u8])>,
+}
+
+impl BinIndex {
+	/// Maps index file into memory
+	pub(crate) fn open(path: impl AsRef<Path>) -> io::Result<BinIndex> {
+		let file = fs::File::open(path)?;
+		let len = usize::try_from(file.metadata()?.len())
+			.map_err(|_| invalid("index is too large to be mapped"))?;
+		if len < 12 {
+			return Err(invalid("index is truncated"));
+		}
+
+		let map = unsafe {
+			libc::mmap(
+				ptr::null_mut(),
+				len,
+				libc::PROT_READ,
+				libc::MAP_PRIVATE,
+				file.as_raw_fd(),
+				0,
+			)
+		};
+		if map == libc::MAP_FAILED {
+			return Err(io::Error::last_os_error());
+		}
+		let index = BinIndex {
+			map: map as *const u8,
+			len,
+		};
+
+		// Check header
+		if index.u32(0)? != INDEX_MAGIC {
+			return Err(invalid("bad magic number"));
+		}
+		if index.u32(4)? >> 16 != INDEX_VERSION_MAJOR {
+			return Err(invalid("unsupported version"));
+		}
+
+		Ok(index)
+	}
+
+	/// Returns values of all keys matching key, keys are fnmatch(3) patterns
+	///
+	/// Values are returned in order of their priority.
+	pub(crate) fn lookup_wild(&self, key: &str) -> io::Result<Vec<String>> {
+		let key = CString::new(key).map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+		let key = key.as_bytes();
+		let mut values = Vec::new();
+
+		let mut offset = self.u32(8)?;
+		let mut i = 0;
+		'nodes: while let Some(node) = self.node(offset)? {
+			for (j, ch) in node.prefix.iter().enumerate() {
+				if is_wildcard(*ch) {
+					self.lookup_all(&node, j, &mut Vec::new(), &key[i + j..], &mut values)?;
+					break 'nodes;
+				}
+				if key.get(i + j) != Some(ch) {
+					break 'nodes;
+				}
+			}
+			i += node.prefix.len();
+
+			// Children starting with wildcard can match rest of key
+			for ch in [b'*', b'?', b'['] {
+				if let Some(child) = self.node(self.child(&node, ch)?)? {
+					self.lookup_all(&child, 0, &mut vec![ch], &key[i..], &mut values)?;
+				}
+			}
+
+			if i == key.len() {
+				values.extend(node.values.iter().copied());
+				break;
+			}
+
+			offset = self.child(&node, key[i])?;
+			i += 1;
+		}
+
+		values.sort_by_key(|(priority, _)| *priority);
+		Ok(values.into_iter().map(|(_, value)| string(value)).collect())
+	}
+
+	/// Collects values of subtree, whose keys (pattern) match rest of key
+	fn lookup_all<'a>(
+		&'a self,
+		node: &Node<'a>,
+		start: usize,
+		pattern: &mut Vec<u8>,
+		key: &[u8],
+		values: &mut Vec<(u32, &'a [u8])>,
+	) -> io::Result<()> {
+		let pushed = node.prefix.len() - start;
+		pattern.extend_from_slice(&node.prefix[start..]);
+
+		for ch in node.first..=node.last {
+			if let Some(child) = self.node(self.child(node, ch)?)? {
+				pattern.push(ch);
+				self.lookup_all(&child, 0, pattern, key, values)?;
+				pattern.pop();
+			}
+		}
+
+		if !node.values.is_empty() && index::fnmatch(pattern, key) {
+			values.extend(node.values.iter().copied());
+		}
+		pattern.truncate(pattern.len() - pushed);
+
+		Ok(())
+	}
+
+	/// Reads node at offset, `None` is returned for empty offset
+	fn node(&self, offset: u32) -> io::Result<Option<Node<'_>>> {
+		let mut position = (offset & INDEX_NODE_MASK) as usize;
+		if position == 0 {
+			return Ok(None);
+		}
+
+		let mut node = Node {
+			prefix: &[],
+			first: 1,
+			last: 0,
+			children: &[],
+			values: Vec::new(),
+		};
+		if offset & INDEX_NODE_PREFIX != 0 {
+			node.prefix = self.cstr(position)?;
+			position += node.prefix.len() + 1;
+		}
+		if offset & INDEX_NODE_CHILDS != 0 {
+			node.first = *self.bytes(position, 1)?.first().unwrap();
+			node.last = *self.bytes(position + 1, 1)?.first().unwrap();
+			let count = (node.last as usize + 1).saturating_sub(node.first as usize);
+			node.children = self.bytes(position + 2, count * 4)?;
+			position += 2 + count * 4;
+		}
+		if offset & INDEX_NODE_VALUES != 0 {
+			let count = self.u32(position)?;
+			position += 4;
+			for _ in 0..count {
+				let priority = self.u32(position)?;
+				let value = self.cstr(position + 4)?;
+				position += 4 + value.len() + 1;
+				node.values.push((priority, value));
+			}
+		}
+
+		Ok(Some(node))
+	}
+
+	/// Returns offset of child node for character, 0 if there's no such child
+	fn child(&self, node: &Node, ch: u8) -> io::Result<u32> {
+		if node.children.is_empty() || ch < node.first || ch > node.last {
+			return Ok(0);
+		}
+
+		let i = (ch - node.first) as usize * 4;
+		Ok(u32::from_be_bytes(
+			node.children[i..i + 4].try_into().unwrap(),
+		))
+	}
+
+	fn data(&self) -> &[u8] {
+		unsafe { slice::from_raw_parts(self.map, self.len) }
+	}
+
+	fn bytes(&self, offset: usize, len: usize) -> io::Result<&[u8]> {
+		self.data()
+			.get(offset..offset.saturating_add(len))
+			.ok_or_else(|| invalid("index is truncated"))
+	}
+
+	fn u32(&self, offset: usize) -> io::Result<u32> {
+		Ok(u32::from_be_bytes(
+			self.bytes(offset, 4)?.try_into().unwrap(),
+		))
+	}
+
+	fn cstr(&self, offset: usize) -> io::Result<&[u8]> {
+		let data = self.data().get(offset..).unwrap_or(&[]);
+		match data.iter().position(|b| *b == 0) {
+			Some(end) => Ok(&data[..end]),
+			None => Err(invalid("index is truncated")),
+		}
+	}
+}
+
+impl Drop for BinIndex {
+	fn drop(&mut self) {
+		unsafe { libc::munmap(self.map as *mut libc::c_void, self.len) };
+	}
+}
+
+fn is_wildcard(ch: u8) -> bool {
+	ch == b'*' || ch == b'?' || ch == b'['
+}
+
+fn string(value: &[u8]) -> String {
+	String::from_utf8_lossy(value).into_owned()
+}
+
+fn invalid(reason: &str) -> io::Error {
+	Error::InvalidIndex(reason.to_string()).into()
+}
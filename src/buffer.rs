@@ -0,0 +1,48 @@
+use std::cell::RefCell;
+
+thread_local! {
+	/// Buffer of module images kept for next module while scope is active, `None` outside of scope
+	static SCRATCH: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+}
+
+/// Reuses buffer of module images for all modules loaded by function (e.g. dependency chain)
+///
+/// Buffer is released when the outermost scope ends, so memory isn't kept between calls.
+pub(crate) fn scope<R>(f: impl FnOnce() -> R) -> R {
+	let outermost = SCRATCH.with(|scratch| {
+		let mut scratch = scratch.borrow_mut();
+		let outermost = scratch.is_none();
+		if outermost {
+			*scratch = Some(Vec::new());
+		}
+		outermost
+	});
+
+	let result = f();
+	if outermost {
+		SCRATCH.with(|scratch| scratch.borrow_mut().take());
+	}
+
+	result
+}
+
+/// Returns empty buffer able to hold `size` bytes, reusing one of previous module in scope
+pub(crate) fn take(size: usize) -> Vec<u8> {
+	let mut buffer = SCRATCH
+		.with(|scratch| scratch.borrow_mut().as_mut().map(std::mem::take))
+		.unwrap_or_default();
+	buffer.clear();
+	buffer.reserve_exact(size);
+	buffer
+}
+
+/// Gives buffer back for next module, it's dropped outside of scope
+pub(crate) fn give(buffer: Vec<u8>) {
+	SCRATCH.with(|scratch| {
+		if let Some(scratch) = scratch.borrow_mut().as_mut() {
+			if buffer.capacity() > scratch.capacity() {
+				*scratch = buffer;
+			}
+		}
+	});
+}
@@ -0,0 +1,56 @@
+use std::{
+	io,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+};
+
+use crate::Error;
+
+/// Handle for cancelling module loading from another thread (e.g. during shutdown)
+///
+/// Token is checked between steps of modprobe (resolution, reading module files for checks and
+/// insertion of every module). Cancelled operation fails with `Error::Cancelled`, modules
+/// inserted before cancellation stay loaded. Insertion already passed to kernel isn't aborted.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let token = liblmod::CancellationToken::new();
+/// let options = liblmod::Options::new().cancellation(token.clone());
+///
+/// // On shutdown, from any thread
+/// token.cancel();
+/// assert!(liblmod::modprobe_with("kvm", "", &options).is_err());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+	cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+	/// Creates token which isn't cancelled
+	pub fn new() -> CancellationToken {
+		CancellationToken::default()
+	}
+
+	/// Cancels all operations using this token (or its clones)
+	pub fn cancel(&self) {
+		self.cancelled.store(true, Ordering::Relaxed);
+	}
+
+	/// Checks if token has been cancelled
+	pub fn is_cancelled(&self) -> bool {
+		self.cancelled.load(Ordering::Relaxed)
+	}
+
+	/// Fails with `Error::Cancelled` if token has been cancelled
+	pub(crate) fn check(&self) -> io::Result<()> {
+		match self.is_cancelled() {
+			true => Err(Error::Cancelled.into()),
+			false => Ok(()),
+		}
+	}
+}
@@ -0,0 +1,272 @@
+use std::{
+	collections::HashMap,
+	fs, io,
+	path::{Path, PathBuf},
+};
+
+use crate::{compress, index, loader, suggest, vfs, ModuleIndex, ModuleInfo, Selection};
+
+/// Manifests copied to staging directory with field naming module in their lines
+///
+/// Lines of other modules are left out, so only staged modules are found by aliases.
+const STAGED_MANIFESTS: [(&str, Option<usize>); 5] = [
+	("modules.alias", Some(2)),
+	("modules.softdep", Some(1)),
+	("modules.devname", Some(0)),
+	("modules.builtin", None),
+	("modules.builtin.modinfo", None),
+];
+
+/// Compression of module files copied to staging directory by `stage_modules`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Compression {
+	/// Files are copied unchanged, with compression of module tree
+	#[default]
+	Keep,
+
+	/// Files are decompressed (compressed ones need `compression` feature)
+	None,
+
+	/// Files are compressed with xz, using CRC32 check required by kernel (`compression` feature)
+	Xz,
+
+	/// Files are compressed with zstd (`compression` feature)
+	Zstd,
+}
+
+impl Compression {
+	/// Returns compression method and extension of staged files
+	fn method(self) -> Option<(&'static str, &'static str)> {
+		match self {
+			Compression::Keep | Compression::None => None,
+			Compression::Xz => Some(("xz", "xz")),
+			Compression::Zstd => Some(("zstd", "zst")),
+		}
+	}
+}
+
+/// Modules and firmware needed by set of modules (e.g. for initramfs)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Closure {
+	/// Absolute paths of module files in order of loading (dependencies first)
+	pub modules: Vec<PathBuf>,
+
+	/// Firmware files referenced by modules (relative to firmware directory)
+	pub firmware: Vec<String>,
+
+	/// Requested modules which are built into kernel
+	pub builtin: Vec<String>,
+}
+
+/// Returns all modules (with dependencies and weak dependencies) and firmware needed by modules
+///
+/// Firmware is read from `.modinfo` of modules, so firmware of compressed modules is not listed.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::closure_for(&["ext4", "nvme", "virtio_blk"], liblmod::Selection::Current) {
+///     Ok(closure) => {
+///         for module in closure.modules {
+///             println!("{}", module.display());
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to compute module closure: {e}"),
+/// }
+/// ```
+pub fn closure_for<S: AsRef<str>>(names: &[S], kernel: Selection) -> io::Result<Closure> {
+	let index = ModuleIndex::shared(kernel)?;
+	compute(&index, names).map(|(closure, _)| closure)
+}
+
+/// Copies modules needed by set of modules into module tree in directory (e.g. for initramfs)
+///
+/// Modules are copied to `<dir>/lib/modules/<release>` with their paths relative to module
+/// directory, compressed as chosen by `compression`. modules.dep and modules.order are written
+/// for staged modules, and lines of staged modules are copied from modules.alias,
+/// modules.softdep and modules.devname, so staged tree can be used by this crate (with
+/// `ModuleIndex::set_base_dirs`) without running depmod. Binary indices (`.bin`) are not
+/// written. Firmware isn't copied, its names are listed in returned closure,
+/// whose modules are paths of staged files.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// let compression = liblmod::Compression::Keep;
+/// match liblmod::stage_modules(&["ext4", "nvme"], liblmod::Selection::Current, "/tmp/initramfs", compression) {
+///     Ok(closure) => println!("Staged {} modules", closure.modules.len()),
+///     Err(e) => eprintln!("Failed to stage modules: {e}"),
+/// }
+/// ```
+pub fn stage_modules<S: AsRef<str>>(
+	names: &[S],
+	kernel: Selection,
+	dir: impl AsRef<Path>,
+	compression: Compression,
+) -> io::Result<Closure> {
+	let index = ModuleIndex::shared(kernel)?;
+	let (mut closure, modules) = compute(&index, names)?;
+	let target = dir.as_ref().join("lib/modules").join(index.kernel());
+	fs::create_dir_all(&target)?;
+
+	// Modules are staged under their paths in modules.dep, absolute ones under extra
+	let mut staged = HashMap::new();
+	for module in &modules {
+		let source = index.path(module);
+		let relative = match Path::new(module).is_absolute() {
+			true => Path::new("extra").join(source.file_name().unwrap_or_default()),
+			false => PathBuf::from(module),
+		};
+		let relative = stage_file(&source, &target.join(&relative), compression)?
+			.strip_prefix(&target)
+			.map(Path::to_path_buf)
+			.unwrap_or(relative);
+		staged.insert(module.as_str(), relative.to_string_lossy().into_owned());
+	}
+
+	// Dependencies of staged modules are staged too
+	let mut dep = String::new();
+	for module in &modules {
+		dep.push_str(&staged[module.as_str()]);
+		dep.push(':');
+		for path in index
+			.dependencies(module)
+			.iter()
+			.filter_map(|dep| staged.get(dep.as_str()))
+		{
+			dep.push(' ');
+			dep.push_str(path);
+		}
+		dep.push('\n');
+	}
+	fs::write(target.join("modules.dep"), dep)?;
+
+	let mut ordered = modules.iter().collect::<Vec<_>>();
+	ordered.sort_by_key(|module| index.order(module).unwrap_or(usize::MAX));
+	let order = ordered
+		.iter()
+		.map(|module| format!("{}\n", staged[module.as_str()]))
+		.collect::<String>();
+	fs::write(target.join("modules.order"), order)?;
+
+	let names = modules
+		.iter()
+		.map(|module| index::module_name(module))
+		.collect::<Vec<_>>();
+	for (manifest, field) in STAGED_MANIFESTS {
+		let Ok(content) = vfs::read_to_string(index.path(manifest)) else {
+			continue;
+		};
+		let content = match field {
+			Some(field) => content
+				.lines()
+				.filter(|line| {
+					line.split_whitespace()
+						.nth(field)
+						.is_some_and(|name| names.contains(&index::normalize(name)))
+				})
+				.map(|line| format!("{}\n", line))
+				.collect(),
+			None => content,
+		};
+		fs::write(target.join(manifest), content)?;
+	}
+
+	closure.modules = modules
+		.iter()
+		.map(|module| target.join(&staged[module.as_str()]))
+		.collect();
+	Ok(closure)
+}
+
+/// Copies module file to target path, changing its compression, returns path of staged file
+fn stage_file(source: &Path, target: &Path, compression: Compression) -> io::Result<PathBuf> {
+	let data = vfs::read(source)?;
+	let current = loader::file_compression(source);
+	let (data, target) = match (compression, current) {
+		(Compression::Keep, _) => (data, target.to_path_buf()),
+		(compression, current) => {
+			let data = match &current {
+				Some(method) => compress::decompress(&data, method)?,
+				None => data,
+			};
+			let target = match current {
+				Some(_) => target.with_extension(""),
+				None => target.to_path_buf(),
+			};
+			match compression.method() {
+				Some((method, extension)) => (
+					compress::compress(&data, method)?,
+					PathBuf::from(format!("{}.{}", target.display(), extension)),
+				),
+				None => (data, target),
+			}
+		}
+	};
+
+	if let Some(parent) = target.parent() {
+		fs::create_dir_all(parent)?;
+	}
+	fs::write(&target, data)?;
+	Ok(target)
+}
+
+/// Returns closure of modules, with entries of modules.dep for its modules
+fn compute<S: AsRef<str>>(index: &ModuleIndex, names: &[S]) -> io::Result<(Closure, Vec<String>)> {
+	let mut closure = Closure::default();
+	let mut modules = Vec::new();
+	for name in names {
+		let name = name.as_ref();
+		let module = match index.resolve(name) {
+			Some(o) => o,
+			None if index.is_builtin(name) => {
+				closure.builtin.push(index::normalize(name));
+				continue;
+			}
+			None => return Err(suggest::not_found(index, name).into()),
+		};
+
+		// Weak dependencies are optional, so missing ones are skipped
+		for weakdep in index.weak_dependencies(&index::module_name(module)) {
+			if let Some(weakmodule) = index.resolve(weakdep) {
+				add(index, &mut modules, weakmodule);
+			}
+		}
+		add(index, &mut modules, module);
+	}
+
+	closure.modules = modules.iter().map(|module| index.path(module)).collect();
+
+	// Collect firmware of all modules
+	for path in &closure.modules {
+		if let Ok(info) = ModuleInfo::from_file(path) {
+			for firmware in info.firmware {
+				if !closure.firmware.contains(&firmware) {
+					closure.firmware.push(firmware);
+				}
+			}
+		}
+	}
+
+	Ok((closure, modules))
+}
+
+/// Adds module with its dependencies to entries of closure
+fn add(index: &ModuleIndex, modules: &mut Vec<String>, module: &str) {
+	// modules.dep lists the deepest dependency last
+	for entry in index
+		.dependencies(module)
+		.iter()
+		.rev()
+		.map(String::as_str)
+		.chain([module])
+	{
+		if !modules.iter().any(|added| added == entry) {
+			modules.push(entry.to_string());
+		}
+	}
+}
@@ -0,0 +1,3 @@
+//! Compatibility layers mirroring APIs of other module management libraries
+
+pub mod kmod;
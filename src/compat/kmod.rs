@@ -0,0 +1,351 @@
+//! Object model of libkmod, for projects ported from libkmod bindings
+//!
+//! `Context` and `Module` correspond to `struct kmod_ctx` and `struct kmod_module`, their
+//! methods are named after `kmod_*` functions without prefix. Modules are resolved with
+//! `ModuleIndex` and configuration from modprobe.d, like in the rest of this crate.
+//!
+//! Example:
+//! ```rust,no_run
+//! extern crate liblmod;
+//!
+//! use liblmod::compat::kmod::{Context, Module};
+//!
+//! fn main() -> std::io::Result<()> {
+//!     let ctx = Context::new()?;
+//!     let module = Module::new_from_name(&ctx, "kvm");
+//!     for (key, value) in module.info()? {
+//!         println!("{key}: {value}");
+//!     }
+//!
+//!     module.insert_module(0, "")?;
+//!     module.remove_module(0)
+//! }
+//! ```
+
+use std::{
+	io::{self, ErrorKind},
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+
+use crate::{
+	index, modprobe, rmmod, sys, vfs, Config, Flags, ModuleInfo, ModuleResolver, Options,
+	RemoveOptions,
+};
+
+/// Flag of `insert_module` ignoring kernel version magic of module
+pub const KMOD_INSERT_FORCE_VERMAGIC: u32 = 0x1;
+
+/// Flag of `insert_module` ignoring symbol version hashes of module
+pub const KMOD_INSERT_FORCE_MODVERSION: u32 = 0x2;
+
+/// Flag of `remove_module` forcing removal of module (requires `CONFIG_MODULE_FORCE_UNLOAD`)
+pub const KMOD_REMOVE_FORCE: u32 = sys::O_TRUNC;
+
+/// Flag of `remove_module` kept for compatibility, modules are always removed without waiting
+pub const KMOD_REMOVE_NOWAIT: u32 = sys::O_NONBLOCK;
+
+/// Initialization state of module, returned by `Module::initstate`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InitState {
+	/// Module is built into kernel
+	Builtin,
+
+	/// Module is loaded and initialized
+	Live,
+
+	/// Module is being initialized
+	Coming,
+
+	/// Module is being removed
+	Going,
+}
+
+/// Library context holding module index of kernel, like `struct kmod_ctx`
+pub struct Context {
+	index: Arc<dyn ModuleResolver>,
+	machine: Option<String>,
+}
+
+impl Context {
+	/// Creates context for current running kernel, like `kmod_new(NULL, NULL)`
+	pub fn new() -> io::Result<Context> {
+		Context::with_options(&Options::new())
+	}
+
+	/// Creates context for kernel or resolver selected by options
+	///
+	/// Modules of context are checked against machine of options (`Options::machine`) when
+	/// inserted.
+	pub fn with_options(options: &Options) -> io::Result<Context> {
+		Ok(Context {
+			index: options.index()?,
+			machine: options.machine.clone(),
+		})
+	}
+
+	/// Creates module object for module name, module doesn't have to exist
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// use liblmod::compat::kmod::Context;
+	///
+	/// liblmod::set_filesystem(
+	///     liblmod::MemoryFs::new()
+	///         .file("/lib/modules/6.1.0/modules.order", "kernel/arch/x86/kvm/kvm.ko\n")
+	///         .file("/lib/modules/6.1.0/modules.dep", "kernel/arch/x86/kvm/kvm.ko:\n"),
+	/// );
+	///
+	/// let options = liblmod::Options::new().kernel(liblmod::Selection::Other("6.1.0".to_string()));
+	/// let ctx = Context::with_options(&options).unwrap();
+	/// let module = ctx.module_new_from_name("kvm");
+	/// assert_eq!(module.path().unwrap().to_str(), Some("/lib/modules/6.1.0/kernel/arch/x86/kvm/kvm.ko"));
+	/// assert!(ctx.module_new_from_name("missing").path().is_none());
+	///
+	/// liblmod::clear_filesystem();
+	/// ```
+	pub fn module_new_from_name(&self, name: &str) -> Module {
+		let name = index::normalize(name);
+		Module {
+			path: self
+				.index
+				.resolve(&name)
+				.map(|module| self.index.path(module)),
+			index: self.index.clone(),
+			machine: self.machine.clone(),
+			name,
+		}
+	}
+
+	/// Creates module object for module file, name is taken from file name
+	pub fn module_new_from_path(&self, path: impl AsRef<Path>) -> io::Result<Module> {
+		let path = path.as_ref();
+		if !vfs::is_file(path) {
+			return Err(io::Error::new(
+				ErrorKind::NotFound,
+				format!("Module file {} not found", path.display()),
+			));
+		}
+
+		Ok(Module {
+			index: self.index.clone(),
+			machine: self.machine.clone(),
+			name: index::module_name(&path.to_string_lossy()),
+			path: Some(path.to_path_buf()),
+		})
+	}
+
+	/// Returns modules matching name or alias, including aliases from modprobe.d
+	pub fn module_new_from_lookup(&self, alias: &str) -> io::Result<Vec<Module>> {
+		let alias = Config::load()?.resolve_alias(alias)?;
+		let names = match self.index.resolve(&alias) {
+			Some(module) => vec![index::module_name(module)],
+			None => self.index.resolve_alias(&alias),
+		};
+
+		Ok(names
+			.iter()
+			.map(|name| self.module_new_from_name(name))
+			.collect())
+	}
+
+	/// Returns modules currently loaded into kernel
+	pub fn module_new_from_loaded(&self) -> io::Result<Vec<Module>> {
+		Ok(crate::lsmod()?
+			.iter()
+			.map(|module| self.module_new_from_name(&module.name))
+			.collect())
+	}
+}
+
+/// Module known to context, like `struct kmod_module`
+#[derive(Clone)]
+pub struct Module {
+	index: Arc<dyn ModuleResolver>,
+	machine: Option<String>,
+	name: String,
+	path: Option<PathBuf>,
+}
+
+impl Module {
+	/// Creates module object for module name, see `Context::module_new_from_name`
+	pub fn new_from_name(ctx: &Context, name: &str) -> Module {
+		ctx.module_new_from_name(name)
+	}
+
+	/// Creates module object for module file, see `Context::module_new_from_path`
+	pub fn new_from_path(ctx: &Context, path: impl AsRef<Path>) -> io::Result<Module> {
+		ctx.module_new_from_path(path)
+	}
+
+	/// Returns name of module
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	/// Returns path of module file, `None` if module has no file (e.g. it's built into kernel)
+	pub fn path(&self) -> Option<&Path> {
+		self.path.as_deref()
+	}
+
+	/// Inserts module without its dependencies, `options` are module parameters
+	///
+	/// `flags` are `KMOD_INSERT_*` bits. Forced modules are loaded like other ones (consulting
+	/// load policy and hooks), only kernel is asked to ignore their version information.
+	pub fn insert_module(&self, flags: u32, options: &str) -> io::Result<()> {
+		// libkmod flags differ from flags of finit_module
+		let mut init_flags = 0;
+		if flags & KMOD_INSERT_FORCE_VERMAGIC != 0 {
+			init_flags |= sys::MODULE_INIT_IGNORE_VERMAGIC;
+		}
+		if flags & KMOD_INSERT_FORCE_MODVERSION != 0 {
+			init_flags |= sys::MODULE_INIT_IGNORE_MODVERSIONS;
+		}
+
+		crate::load_path(
+			self.file()?,
+			options,
+			None,
+			true,
+			self.machine.as_deref(),
+			None,
+			init_flags,
+		)
+		.map(|_| ())
+	}
+
+	/// Loads module with its dependencies and options from modprobe.d, like modprobe
+	pub fn probe_insert_module(&self, extra_options: &str) -> io::Result<()> {
+		let config = Config::load()?;
+		let options = match &self.machine {
+			Some(machine) => Options::new().machine(machine.clone()),
+			None => Options::new(),
+		};
+		modprobe::probe(&*self.index, &config, &self.name, extra_options, &options).map(|_| ())
+	}
+
+	/// Removes module from kernel, `flags` are `KMOD_REMOVE_*` bits
+	///
+	/// Like in libkmod, forced removal never waits for module to be released (`Flags::Force`).
+	pub fn remove_module(&self, flags: u32) -> io::Result<()> {
+		let flags = match flags & KMOD_REMOVE_FORCE {
+			0 => Flags::Casual,
+			_ => Flags::Force,
+		};
+
+		rmmod::remove(&self.name, flags, &RemoveOptions::new().ignore_remove(true))
+	}
+
+	/// Returns `key=value` entries of `.modinfo` section of module file
+	pub fn info(&self) -> io::Result<std::vec::IntoIter<(String, String)>> {
+		Ok(ModuleInfo::from_file(self.file()?)?.fields.into_iter())
+	}
+
+	/// Returns dependencies of module, as listed in modules.dep
+	pub fn dependencies(&self) -> Vec<Module> {
+		let module = match self.index.resolve(&self.name) {
+			Some(module) => module,
+			None => return Vec::new(),
+		};
+
+		self.index
+			.dependencies(module)
+			.iter()
+			.map(|dep| Module {
+				index: self.index.clone(),
+				machine: self.machine.clone(),
+				name: index::module_name(dep),
+				path: Some(self.index.path(dep)),
+			})
+			.collect()
+	}
+
+	/// Returns options of module from modprobe.d and kernel command line
+	pub fn options(&self) -> io::Result<String> {
+		Ok(Config::load()?.params(&self.name, ""))
+	}
+
+	/// Returns install command of module from modprobe.d
+	pub fn install_commands(&self) -> io::Result<Option<String>> {
+		Ok(Config::load()?
+			.install_command(&self.name)
+			.map(|command| command.to_string()))
+	}
+
+	/// Returns initialization state of module, fails with `NotFound` if it's not loaded
+	pub fn initstate(&self) -> io::Result<InitState> {
+		let base = format!("/sys/module/{}", self.name);
+		let state = match vfs::read_to_string(format!("{}/initstate", base)) {
+			Ok(state) => state,
+			Err(e) if e.kind() == ErrorKind::NotFound && vfs::is_dir(&base) => {
+				return Ok(InitState::Builtin)
+			}
+			Err(e) => return Err(e),
+		};
+
+		match state.trim() {
+			"live" => Ok(InitState::Live),
+			"coming" => Ok(InitState::Coming),
+			"going" => Ok(InitState::Going),
+			state => Err(io::Error::new(
+				ErrorKind::InvalidData,
+				format!("Unknown state {} of module {}", state, self.name),
+			)),
+		}
+	}
+
+	/// Returns number of references to loaded module
+	pub fn refcnt(&self) -> io::Result<u32> {
+		self.sysfs("refcnt")?
+			.parse()
+			.map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+	}
+
+	/// Returns size of loaded module in bytes
+	pub fn size(&self) -> io::Result<u64> {
+		self.sysfs("coresize")?
+			.parse()
+			.map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+	}
+
+	/// Returns modules holding loaded module
+	pub fn holders(&self) -> Vec<Module> {
+		let holders =
+			vfs::read_dir(format!("/sys/module/{}/holders", self.name)).unwrap_or_default();
+		holders
+			.iter()
+			.filter_map(|holder| Some(holder.file_name()?.to_string_lossy().into_owned()))
+			.map(|name| Module {
+				path: self
+					.index
+					.resolve(&name)
+					.map(|module| self.index.path(module)),
+				index: self.index.clone(),
+				machine: self.machine.clone(),
+				name,
+			})
+			.collect()
+	}
+
+	/// Returns path of module file or error if module has no file
+	fn file(&self) -> io::Result<&Path> {
+		self.path.as_deref().ok_or_else(|| {
+			io::Error::new(
+				ErrorKind::NotFound,
+				format!(
+					"Module {} is not provided by {} kernel",
+					self.name,
+					self.index.kernel()
+				),
+			)
+		})
+	}
+
+	/// Reads attribute of loaded module from sysfs
+	fn sysfs(&self, attribute: &str) -> io::Result<String> {
+		vfs::read_to_string(format!("/sys/module/{}/{}", self.name, attribute))
+			.map(|value| value.trim().to_string())
+	}
+}
@@ -0,0 +1,327 @@
+use std::{
+	fs,
+	io::{self, ErrorKind, Read, Write},
+	os::unix::fs::FileExt,
+	sync::{Arc, RwLock},
+};
+
+/// Size of chunks modules are decompressed in by `decompress_into`
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Extensions of module files with compression methods known to kernel and built into library
+const BUILTIN: [(&str, &str); 3] = [("xz", "xz"), ("zst", "zstd"), ("gz", "gzip")];
+
+/// Decompressors registered by application, the latest first
+static DECOMPRESSORS: RwLock<Vec<Arc<dyn Decompressor>>> = RwLock::new(Vec::new());
+
+/// Decoder of module files compressed with one compression method
+///
+/// Decoders of xz, zstd and gzip are built in with features of the same names (all of them
+/// with `compression` feature). Other methods (e.g. lz4 or vendor formats of some Android
+/// kernels) are added with `register_decompressor`, files with their extension are then
+/// recognized as modules, decompressed when read and before loading.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// use std::io::{self, Read};
+///
+/// // Vendor "format" storing module image as is after 4-byte header
+/// struct Vendor;
+///
+/// impl liblmod::Decompressor for Vendor {
+///     fn method(&self) -> &str {
+///         "vendor"
+///     }
+///
+///     fn extension(&self) -> &str {
+///         "vnd"
+///     }
+///
+///     fn decoder<'a>(&self, mut input: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+///         let mut header = [0; 4];
+///         input.read_exact(&mut header)?;
+///         match &header {
+///             b"VND0" => Ok(input),
+///             _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Missing VND0 header")),
+///         }
+///     }
+/// }
+///
+/// liblmod::register_decompressor(Vendor);
+/// ```
+pub trait Decompressor: Send + Sync {
+	/// Returns name of compression method (e.g. `lz4`), as reported with module files
+	fn method(&self) -> &str;
+
+	/// Returns extension of compressed module files without dot (e.g. `lz4` for `foo.ko.lz4`)
+	fn extension(&self) -> &str;
+
+	/// Returns reader of data decompressed from input (e.g. module file, read as it's decompressed)
+	fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>>;
+}
+
+/// Adds decoder of compression method for whole process
+///
+/// Decompressor replaces built-in or earlier registered one with the same extension or method.
+/// Kernel isn't asked to decompress modules with methods it doesn't support, they are
+/// decompressed by library before loading. Shared module indices are dropped, so module files
+/// with new extension are found by the next lookup.
+pub fn register_decompressor(decompressor: impl Decompressor + 'static) {
+	DECOMPRESSORS
+		.write()
+		.unwrap_or_else(|e| e.into_inner())
+		.insert(0, Arc::new(decompressor));
+	crate::ModuleIndex::invalidate();
+}
+
+/// Returns compression method of files with extension, if it's known
+pub(crate) fn method(extension: &str) -> Option<String> {
+	let decompressors = DECOMPRESSORS.read().unwrap_or_else(|e| e.into_inner());
+	let registered = decompressors
+		.iter()
+		.find(|decompressor| decompressor.extension() == extension)
+		.map(|decompressor| decompressor.method().to_string());
+	registered.or_else(|| {
+		BUILTIN
+			.iter()
+			.find(|(builtin, _)| *builtin == extension)
+			.map(|(_, method)| method.to_string())
+	})
+}
+
+/// Strips extension of compression method from file name
+pub(crate) fn strip_extension(file: &str) -> Option<&str> {
+	let (stem, extension) = file.rsplit_once('.')?;
+	if BUILTIN.iter().any(|(builtin, _)| *builtin == extension) {
+		return Some(stem);
+	}
+
+	let decompressors = DECOMPRESSORS.read().unwrap_or_else(|e| e.into_inner());
+	decompressors
+		.iter()
+		.any(|decompressor| decompressor.extension() == extension)
+		.then_some(stem)
+}
+
+/// Checks if library can decompress files compressed with method
+pub(crate) fn is_supported(method: &str) -> bool {
+	registered(method).is_some()
+		|| (method == "xz" && cfg!(feature = "xz"))
+		|| (method == "zstd" && cfg!(feature = "zstd"))
+		|| (method == "gzip" && cfg!(feature = "gzip"))
+}
+
+/// Returns content of file compressed with method (e.g. `xz`, `zstd` or `gzip`)
+pub(crate) fn decompress(data: &[u8], method: &str) -> io::Result<Vec<u8>> {
+	let mut content = Vec::new();
+	with_decoder(Box::new(data), method, |decoder| {
+		decoder.read_to_end(&mut content)
+	})?;
+	Ok(content)
+}
+
+/// Decompresses input in fixed-size chunks into writer, stopping once more than limit would be written
+///
+/// Only chunk and decoder state are kept in memory. Every chunk is passed to check with its
+/// offset before it's written, so e.g. header in the first chunk is checked before the rest is
+/// decompressed. Returns size of decompressed data, or `None` if it exceeds limit.
+pub(crate) fn decompress_into<'a>(
+	input: impl Read + 'a,
+	method: &str,
+	writer: &mut impl Write,
+	limit: u64,
+	mut check: impl FnMut(u64, &[u8]) -> io::Result<()>,
+) -> io::Result<Option<u64>> {
+	with_decoder(Box::new(input), method, |decoder| {
+		let mut chunk = vec![0; CHUNK_SIZE];
+		let mut size = 0u64;
+		loop {
+			let read = fill(decoder, &mut chunk)?;
+			if read == 0 {
+				return Ok(Some(size));
+			}
+
+			check(size, &chunk[..read])?;
+			size += read as u64;
+			if size > limit {
+				return Ok(None);
+			}
+			writer.write_all(&chunk[..read])?;
+		}
+	})
+}
+
+/// Reads from decoder until chunk is full or data ends, returning number of bytes read
+fn fill(decoder: &mut dyn Read, chunk: &mut [u8]) -> io::Result<usize> {
+	let mut filled = 0;
+	while filled < chunk.len() {
+		match decoder.read(&mut chunk[filled..]) {
+			Ok(0) => break,
+			Ok(read) => filled += read,
+			Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+			Err(e) => return Err(e),
+		}
+	}
+
+	Ok(filled)
+}
+
+/// Returns data compressed with method (`xz` or `zstd`) in format accepted by kernel
+#[cfg_attr(not(all(feature = "xz", feature = "zstd")), allow(unused_variables))]
+pub(crate) fn compress(data: &[u8], method: &str) -> io::Result<Vec<u8>> {
+	match method {
+		#[cfg(feature = "xz")]
+		"xz" => {
+			// Kernel decompressor supports only CRC32 checks, not default CRC64
+			let stream = xz2::stream::Stream::new_easy_encoder(6, xz2::stream::Check::Crc32)
+				.map_err(io::Error::other)?;
+			let mut encoder = xz2::write::XzEncoder::new_stream(Vec::new(), stream);
+			encoder.write_all(data)?;
+			encoder.finish()
+		}
+		#[cfg(feature = "zstd")]
+		"zstd" => zstd::encode_all(data, 0),
+		_ if matches!(method, "xz" | "zstd") => Err(disabled(method)),
+		_ => Err(unknown(method)),
+	}
+}
+
+/// Runs function with decoder of input, registered decoders take precedence over built-in ones
+fn with_decoder<'a, R>(
+	input: Box<dyn Read + 'a>,
+	method: &str,
+	f: impl FnOnce(&mut dyn Read) -> io::Result<R>,
+) -> io::Result<R> {
+	// Registry isn't locked while decoding, so decoders can register other ones
+	let mut decoder = match registered(method) {
+		Some(decompressor) => decompressor.decoder(input)?,
+		None => builtin(method, input)?,
+	};
+	f(&mut decoder)
+}
+
+/// Returns registered decompressor of method
+fn registered(method: &str) -> Option<Arc<dyn Decompressor>> {
+	let decompressors = DECOMPRESSORS.read().unwrap_or_else(|e| e.into_inner());
+	decompressors
+		.iter()
+		.find(|decompressor| decompressor.method() == method)
+		.cloned()
+}
+
+/// Reads size of decompressed content recorded in compressed file, without decompressing it
+///
+/// Size is read from trailer of gzip (modulo 4 GiB) and index of xz files, and from frame header
+/// of zstd files. `None` is returned if it's not recorded (e.g. zstd frame without content size)
+/// or file isn't a single stream or frame.
+pub(crate) fn uncompressed_size(file: &fs::File, method: &str) -> Option<u64> {
+	let len = file.metadata().ok()?.len();
+	let read = |offset: u64, size: usize| {
+		let mut buf = vec![0; size];
+		file.read_exact_at(&mut buf, offset).ok().map(|()| buf)
+	};
+
+	match method {
+		"gzip" => {
+			let trailer = read(len.checked_sub(4)?, 4)?;
+			Some(u32::from_le_bytes(trailer.try_into().ok()?) as u64)
+		}
+		"zstd" => {
+			let header = read(0, 18.min(len as usize))?;
+			if !header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+				return None;
+			}
+
+			// Content size follows window descriptor and dictionary ID, whose sizes are flagged
+			let descriptor = *header.get(4)?;
+			let single_segment = descriptor & 0x20 != 0;
+			let dictionary = [0, 1, 2, 4][(descriptor & 3) as usize];
+			let start = 5 + usize::from(!single_segment) + dictionary;
+			let size = match descriptor >> 6 {
+				0 if single_segment => 1,
+				0 => return None,
+				1 => 2,
+				2 => 4,
+				_ => 8,
+			};
+			let value = header
+				.get(start..start + size)?
+				.iter()
+				.rev()
+				.fold(0u64, |value, byte| value << 8 | *byte as u64);
+			Some(if size == 2 { value + 256 } else { value })
+		}
+		"xz" => {
+			// Stream footer points to index listing sizes of blocks
+			let footer = read(len.checked_sub(12)?, 12)?;
+			if &footer[10..] != b"YZ" {
+				return None;
+			}
+			let backward = (u32::from_le_bytes(footer[4..8].try_into().ok()?) as u64 + 1) * 4;
+			let index = read(len.checked_sub(12 + backward)?, backward as usize)?;
+			if index.first() != Some(&0) {
+				return None;
+			}
+
+			let mut bytes = index[1..].iter();
+			let mut varint = || {
+				let mut value = 0u64;
+				for shift in (0..63).step_by(7) {
+					let byte = *bytes.next()?;
+					value |= ((byte & 0x7f) as u64) << shift;
+					if byte & 0x80 == 0 {
+						return Some(value);
+					}
+				}
+				None
+			};
+
+			// Records are pairs of unpadded and uncompressed size
+			let records = varint()?;
+			let mut size = 0u64;
+			for _ in 0..records {
+				varint()?;
+				size = size.checked_add(varint()?)?;
+			}
+			Some(size)
+		}
+		_ => None,
+	}
+}
+
+/// Returns built-in decoder of method, if its feature is enabled
+#[cfg_attr(
+	not(all(feature = "xz", feature = "zstd", feature = "gzip")),
+	allow(unused_variables)
+)]
+fn builtin<'a>(method: &str, input: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+	match method {
+		#[cfg(feature = "xz")]
+		"xz" => Ok(Box::new(xz2::read::XzDecoder::new(input))),
+		#[cfg(feature = "zstd")]
+		"zstd" => Ok(Box::new(zstd::stream::read::Decoder::new(input)?)),
+		#[cfg(feature = "gzip")]
+		"gzip" => Ok(Box::new(flate2::read::GzDecoder::new(input))),
+		_ if BUILTIN.iter().any(|(_, builtin)| *builtin == method) => Err(disabled(method)),
+		_ => Err(unknown(method)),
+	}
+}
+
+fn unknown(method: &str) -> io::Error {
+	io::Error::new(
+		ErrorKind::Unsupported,
+		format!("Unknown compression method {}", method),
+	)
+}
+
+fn disabled(method: &str) -> io::Error {
+	io::Error::new(
+		ErrorKind::Unsupported,
+		format!(
+			"{} compression requires liblmod to be built with {} feature",
+			method, method
+		),
+	)
+}
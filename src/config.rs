@@ -0,0 +1,788 @@
+use std::{
+	collections::{BTreeMap, HashMap, HashSet},
+	fmt,
+	io::{self, ErrorKind},
+	path::{Path, PathBuf},
+	process::{Command, Stdio},
+};
+
+use crate::{index, vfs, Error, Selection};
+
+/// Directories with modprobe configuration, from the most important one
+#[cfg(feature = "config")]
+pub(crate) const CONFIG_DIRS: [&str; 5] = [
+	"/etc/modprobe.d",
+	"/run/modprobe.d",
+	"/usr/local/lib/modprobe.d",
+	"/usr/lib/modprobe.d",
+	"/lib/modprobe.d",
+];
+
+/// Environment variable with install and remove commands being run, one per line
+const INSTALL_CHAIN: &str = "LIBLMOD_INSTALL_CHAIN";
+
+/// Maximum number of nested install and remove commands
+const MAX_INSTALL_DEPTH: usize = 8;
+
+/// Parsed modprobe configuration (modprobe.d)
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::Config::load() {
+///     Ok(config) => println!("Options for kvm: {:?}", config.options("kvm")),
+///     Err(e) => eprintln!("Failed to read modprobe configuration: {e}"),
+/// }
+/// ```
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Config {
+	options: HashMap<String, String>,
+	cmdline: HashMap<String, String>,
+	aliases: Vec<(String, String)>,
+	install: HashMap<String, String>,
+	remove: HashMap<String, String>,
+	blacklist: HashSet<String>,
+	directives: Vec<Directive>,
+}
+
+/// Directive of modprobe configuration with file it comes from
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Directive {
+	/// Name of directive (e.g. `alias`, `options`, `blacklist`, `softdep` or `install`)
+	pub command: String,
+
+	/// Arguments of directive (e.g. `kvm_intel nested=1`)
+	pub args: String,
+
+	/// File directive has been read from (`/proc/cmdline` for kernel command line options)
+	pub source: PathBuf,
+}
+
+impl fmt::Display for Directive {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{} {}", self.command, self.args)
+	}
+}
+
+/// Directives added and removed by `Config::reload`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConfigChanges {
+	/// Directives which are new, in order of configuration
+	pub added: Vec<Directive>,
+
+	/// Directives which are gone, in order of previous configuration
+	pub removed: Vec<Directive>,
+}
+
+impl ConfigChanges {
+	/// Checks if configuration has not changed
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.removed.is_empty()
+	}
+}
+
+/// Directives of modprobe.d, in addition to ones used by this crate they include `softdep` and
+/// `weakdep`
+#[cfg(feature = "config")]
+const DIRECTIVES: [&str; 7] = [
+	"alias",
+	"options",
+	"install",
+	"remove",
+	"blacklist",
+	"softdep",
+	"weakdep",
+];
+
+impl Config {
+	/// Reads configuration files from modprobe.d directories and module options from kernel command line
+	///
+	/// Files are read in alphabetical order. File in more important directory (e.g. `/etc/modprobe.d`)
+	/// overrides file with the same name in less important directory (e.g. `/lib/modprobe.d`).
+	/// Without `config` feature, only kernel command line is read.
+	pub fn load() -> io::Result<Config> {
+		Config::load_for(&Selection::Current)
+	}
+
+	/// Reads configuration for selected kernel, command line of running kernel applies only to it
+	pub(crate) fn load_for(kernel: &Selection) -> io::Result<Config> {
+		let mut config = Config::default();
+		#[cfg(feature = "config")]
+		for path in config_files(&CONFIG_DIRS)? {
+			config.parse(&vfs::read_to_string(&path)?, &path);
+		}
+
+		// Read `module.param=value` entries from kernel command line
+		if let Selection::Current = kernel {
+			match vfs::read_to_string("/proc/cmdline") {
+				Ok(cmdline) => config.parse_cmdline(&cmdline),
+				Err(e) if e.kind() == ErrorKind::NotFound => (),
+				Err(e) => return Err(e),
+			}
+		}
+
+		Ok(config)
+	}
+
+	/// Reads configuration again, replacing this one, and returns which directives have changed
+	///
+	/// Long-running daemons can pick up new blacklists and options without restart. Directive
+	/// moved to another file is reported as removed and added. Configuration built with
+	/// `Config::new` is replaced by system one.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// # #[cfg(feature = "config")]
+	/// # {
+	/// liblmod::set_filesystem(liblmod::MemoryFs::new().file("/etc/modprobe.d/a.conf", "blacklist pcspkr\n"));
+	/// let mut config = liblmod::Config::load().unwrap();
+	///
+	/// liblmod::set_filesystem(liblmod::MemoryFs::new().file("/etc/modprobe.d/a.conf", "blacklist nouveau\n"));
+	/// let changes = config.reload().unwrap();
+	/// assert_eq!(changes.added[0].args, "nouveau");
+	/// assert_eq!(changes.removed[0].args, "pcspkr");
+	/// assert!(config.is_blacklisted("nouveau"));
+	///
+	/// liblmod::clear_filesystem();
+	/// # }
+	/// ```
+	pub fn reload(&mut self) -> io::Result<ConfigChanges> {
+		let config = Config::load()?;
+		let changes = ConfigChanges {
+			added: difference(&config.directives, &self.directives),
+			removed: difference(&self.directives, &config.directives),
+		};
+
+		*self = config;
+		Ok(changes)
+	}
+
+	/// Creates empty configuration, filled with `file` and `cmdline` instead of reading system
+	/// files
+	///
+	/// Configuration built this way can be passed to `Options::config`, so no modprobe.d files
+	/// are read (e.g. in sandboxed process receiving configuration from another one).
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// # #[cfg(feature = "config")]
+	/// # {
+	/// let config = liblmod::Config::new()
+	///     .file("/etc/modprobe.d/kvm.conf", "options kvm_intel nested=1\nblacklist pcspkr\n")
+	///     .cmdline("quiet kvm_intel.enable_apicv=0");
+	/// assert_eq!(config.options("kvm_intel"), Some("nested=1"));
+	/// assert_eq!(config.cmdline_options("kvm_intel"), Some("enable_apicv=0"));
+	/// assert!(config.is_blacklisted("pcspkr"));
+	/// # }
+	/// ```
+	pub fn new() -> Config {
+		Config::default()
+	}
+
+	/// Adds directives of modprobe.d file with content, as if read from source
+	///
+	/// Requires `config` feature.
+	#[cfg(feature = "config")]
+	pub fn file(mut self, source: impl AsRef<Path>, content: impl AsRef<str>) -> Config {
+		self.parse(content.as_ref(), source.as_ref());
+		self
+	}
+
+	/// Adds module options and blacklist from kernel command line (`module.param=value`)
+	pub fn cmdline(mut self, cmdline: impl AsRef<str>) -> Config {
+		self.parse_cmdline(cmdline.as_ref());
+		self
+	}
+
+	/// Returns configured options for module
+	pub fn options(&self, name: &str) -> Option<&str> {
+		self.options
+			.get(&index::normalize(name))
+			.map(|o| o.as_str())
+	}
+
+	/// Returns module options passed on kernel command line
+	pub fn cmdline_options(&self, name: &str) -> Option<&str> {
+		self.cmdline
+			.get(&index::normalize(name))
+			.map(|o| o.as_str())
+	}
+
+	/// Returns options of module from modprobe.d and kernel command line, merged by parameter
+	///
+	/// Options lines are concatenated in order of files (then kernel command line), like they are
+	/// passed to kernel. Kernel uses the last value of repeated parameter, so every parameter is
+	/// returned once with its last value (empty for parameters without value), in order of first
+	/// appearance. Dashes and underscores in parameter names are equivalent.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// # #[cfg(feature = "config")]
+	/// # {
+	/// let config = liblmod::Config::new()
+	///     .file("/lib/modprobe.d/iwlwifi.conf", "options iwlwifi power_save=1 11n_disable=8\n")
+	///     .file("/etc/modprobe.d/local.conf", "options iwlwifi power_save=0\noptions iwlwifi debug\n");
+	/// assert_eq!(config.options("iwlwifi"), Some("power_save=1 11n_disable=8 power_save=0 debug"));
+	/// assert_eq!(
+	///     config.merged_options("iwlwifi"),
+	///     [
+	///         ("power_save".to_string(), "0".to_string()),
+	///         ("11n_disable".to_string(), "8".to_string()),
+	///         ("debug".to_string(), "".to_string()),
+	///     ]
+	/// );
+	/// # }
+	/// ```
+	pub fn merged_options(&self, name: &str) -> Vec<(String, String)> {
+		let mut merged: Vec<(String, String)> = Vec::new();
+		for word in [self.options(name), self.cmdline_options(name)]
+			.into_iter()
+			.flatten()
+			.flat_map(cmdline_words)
+		{
+			let (key, value) = word.split_once('=').unwrap_or((word, ""));
+			let normalized = index::normalize(key);
+			match merged
+				.iter_mut()
+				.find(|(known, _)| index::normalize(known) == normalized)
+			{
+				Some((_, known)) => *known = value.to_string(),
+				None => merged.push((key.to_string(), value.to_string())),
+			}
+		}
+
+		merged
+	}
+
+	/// Returns module name alias from modprobe.d refers to (`alias my-nic e1000e`)
+	///
+	/// Alias can be a glob pattern. If multiple aliases match, the first defined one is used.
+	pub fn alias(&self, name: &str) -> Option<&str> {
+		let name = index::normalize_alias(name);
+		self.aliases
+			.iter()
+			.find(|(pattern, _)| fnmatch(pattern, &name))
+			.map(|(_, module)| module.as_str())
+	}
+
+	/// Follows aliases from modprobe.d until name which isn't an alias is found
+	///
+	/// Name which isn't an alias is returned unchanged. Aliases referring to each other in a loop
+	/// fail with `Error::AliasLoop`.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// # #[cfg(feature = "config")]
+	/// # {
+	/// liblmod::set_filesystem(liblmod::MemoryFs::new().file(
+	///     "/etc/modprobe.d/nic.conf",
+	///     "alias my-nic eth-driver\nalias eth-driver e1000e\nalias ping pong\nalias pong ping\n",
+	/// ));
+	///
+	/// let config = liblmod::Config::load().unwrap();
+	/// assert_eq!(config.resolve_alias("my-nic").unwrap(), "e1000e");
+	/// assert_eq!(config.resolve_alias("e1000e").unwrap(), "e1000e");
+	/// assert!(config.resolve_alias("ping").is_err());
+	///
+	/// liblmod::clear_filesystem();
+	/// # }
+	/// ```
+	pub fn resolve_alias(&self, name: &str) -> io::Result<String> {
+		let mut chain = vec![name.to_string()];
+		let mut current = name;
+		while let Some(target) = self.alias(current) {
+			let looped = chain
+				.iter()
+				.any(|seen| index::normalize_alias(seen) == index::normalize_alias(target));
+			chain.push(target.to_string());
+			if looped {
+				return Err(Error::AliasLoop(chain).into());
+			}
+			current = target;
+		}
+
+		Ok(current.to_string())
+	}
+
+	/// Returns command run instead of loading module (`install` directive)
+	pub fn install_command(&self, name: &str) -> Option<&str> {
+		self.install
+			.get(&index::normalize(name))
+			.map(|o| o.as_str())
+	}
+
+	/// Checks if module is disabled with install command doing nothing, like `install foo /bin/false`
+	///
+	/// Many distributions disable modules this way instead of `blacklist`, which also prevents
+	/// loading modules requested by name. Commands `true` and `false` are recognized, with or
+	/// without path.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// # #[cfg(feature = "config")]
+	/// # {
+	/// let config = liblmod::Config::new().file(
+	///     "/etc/modprobe.d/disable.conf",
+	///     "install cramfs /bin/false\ninstall usb-storage /bin/true\ninstall foo /sbin/foo-setup\n",
+	/// );
+	/// assert!(config.is_disabled("cramfs"));
+	/// assert!(config.is_disabled("usb_storage"));
+	/// assert!(!config.is_disabled("foo"));
+	/// # }
+	/// ```
+	pub fn is_disabled(&self, name: &str) -> bool {
+		let mut words = match self.install_command(name) {
+			Some(command) => command.split_whitespace(),
+			None => return false,
+		};
+
+		match (words.next(), words.next()) {
+			(Some(program), None) => {
+				let program = Path::new(program);
+				program
+					.file_name()
+					.is_some_and(|file| file == "true" || file == "false")
+			}
+			_ => false,
+		}
+	}
+
+	/// Returns command run instead of unloading module (`remove` directive)
+	pub fn remove_command(&self, name: &str) -> Option<&str> {
+		self.remove.get(&index::normalize(name)).map(|o| o.as_str())
+	}
+
+	/// Checks if module is blacklisted in modprobe.d or with `modprobe.blacklist` on kernel command line
+	///
+	/// Like with modprobe, blacklist only prevents loading modules matched by aliases (e.g. by
+	/// `coldplug` and `autoload`), modules requested by name are loaded anyway.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// # #[cfg(feature = "config")]
+	/// # {
+	/// liblmod::set_filesystem(
+	///     liblmod::MemoryFs::new()
+	///         .file("/etc/modprobe.d/nouveau.conf", "blacklist nouveau\n")
+	///         .file("/proc/cmdline", "quiet modprobe.blacklist=pcspkr,snd-pcsp\n"),
+	/// );
+	///
+	/// let config = liblmod::Config::load().unwrap();
+	/// assert!(config.is_blacklisted("nouveau"));
+	/// assert!(config.is_blacklisted("snd_pcsp"));
+	/// assert!(!config.is_blacklisted("kvm"));
+	///
+	/// liblmod::clear_filesystem();
+	/// # }
+	/// ```
+	pub fn is_blacklisted(&self, name: &str) -> bool {
+		self.blacklist.contains(&index::normalize(name))
+	}
+
+	/// Returns directive blacklisting module, from modprobe.d or kernel command line
+	pub fn blacklisted_by(&self, name: &str) -> Option<&Directive> {
+		let name = index::normalize(name);
+		self.directives.iter().find(|directive| {
+			directive.command == "blacklist"
+				&& directive
+					.args
+					.split_whitespace()
+					.next()
+					.is_some_and(|module| index::normalize(module) == name)
+		})
+	}
+
+	/// Returns all directives in order they have been read, see `show_config`
+	pub fn directives(&self) -> &[Directive] {
+		&self.directives
+	}
+
+	/// Builds final parameters for module
+	///
+	/// Options from modprobe.d come first, then parameters passed by caller and finally options
+	/// from kernel command line. Kernel uses the last value of repeated parameter, so kernel
+	/// command line takes precedence over everything else.
+	pub(crate) fn params(&self, name: &str, params: &str) -> String {
+		[self.options(name), Some(params), self.cmdline_options(name)]
+			.into_iter()
+			.flatten()
+			.filter(|p| !p.is_empty())
+			.collect::<Vec<&str>>()
+			.join(" ")
+	}
+
+	#[cfg(feature = "config")]
+	fn parse(&mut self, content: &str, source: &Path) {
+		for line in logical_lines(content) {
+			let (command, rest) = next_word(&line);
+			if DIRECTIVES.contains(&command) && !rest.is_empty() {
+				self.directives.push(Directive {
+					command: command.to_string(),
+					args: rest.to_string(),
+					source: source.to_path_buf(),
+				});
+			}
+
+			match command {
+				"options" => {
+					let (module, options) = next_word(rest);
+					if module.is_empty() || options.is_empty() {
+						continue;
+					}
+
+					// Multiple options lines for one module are concatenated
+					append(&mut self.options, index::normalize(module), options);
+				}
+				"alias" => {
+					let (alias, module) = next_word(rest);
+					let (module, _) = next_word(module);
+					if !alias.is_empty() && !module.is_empty() {
+						self.aliases
+							.push((index::normalize_alias(alias), module.to_string()));
+					}
+				}
+				"blacklist" => {
+					let (module, _) = next_word(rest);
+					if !module.is_empty() {
+						self.blacklist.insert(index::normalize(module));
+					}
+				}
+				"install" | "remove" => {
+					let (module, shell) = next_word(rest);
+					if module.is_empty() || shell.is_empty() {
+						continue;
+					}
+
+					// The first command for module is used
+					let commands = match command {
+						"install" => &mut self.install,
+						_ => &mut self.remove,
+					};
+					commands
+						.entry(index::normalize(module))
+						.or_insert_with(|| shell.to_string());
+				}
+				_ => (),
+			}
+		}
+	}
+
+	fn parse_cmdline(&mut self, cmdline: &str) {
+		for word in cmdline_words(cmdline) {
+			// Arguments after `--` are passed to init
+			if word == "--" {
+				break;
+			}
+
+			let word = match word.strip_prefix('"') {
+				Some(unquoted) => unquoted.strip_suffix('"').unwrap_or(unquoted),
+				None => word,
+			};
+			let (module, param) = match word.split_once('.') {
+				Some(o) => o,
+				None => continue,
+			};
+			if module.is_empty() || param.is_empty() || module.contains('=') {
+				continue;
+			}
+
+			// Blacklist of modprobe itself, modules are separated by commas
+			if let ("modprobe", Some(modules)) = (module, param.strip_prefix("blacklist=")) {
+				for module in modules.split(',').filter(|m| !m.is_empty()) {
+					self.directives.push(Directive {
+						command: "blacklist".to_string(),
+						args: module.to_string(),
+						source: PathBuf::from("/proc/cmdline"),
+					});
+					self.blacklist.insert(index::normalize(module));
+				}
+				continue;
+			}
+
+			self.directives.push(Directive {
+				command: "options".to_string(),
+				args: format!("{} {}", module, param),
+				source: PathBuf::from("/proc/cmdline"),
+			});
+			append(&mut self.cmdline, index::normalize(module), param);
+		}
+	}
+}
+
+/// Runs `install` or `remove` command of module with shell, like modprobe
+///
+/// `$CMDLINE_OPTS` in command is replaced with parameters and `MODPROBE_MODULE` environment
+/// variable is set to name of module. Commands being run by parent processes are passed down in
+/// `LIBLMOD_INSTALL_CHAIN` variable, so commands loading modules whose commands load them back
+/// fail with `Error::RecursionLimit` instead of running forever.
+pub(crate) fn run_command(
+	directive: &str,
+	command: &str,
+	module: &str,
+	params: &str,
+) -> io::Result<()> {
+	let mut chain: Vec<String> = std::env::var(INSTALL_CHAIN)
+		.map(|chain| chain.lines().map(|entry| entry.to_string()).collect())
+		.unwrap_or_default();
+	let entry = format!("{} {} {}", directive, module, command);
+	let looped = chain.contains(&entry);
+	chain.push(entry);
+	if looped || chain.len() > MAX_INSTALL_DEPTH {
+		return Err(Error::RecursionLimit {
+			module: module.to_string(),
+			chain,
+		}
+		.into());
+	}
+
+	let status = Command::new("/bin/sh")
+		.arg("-c")
+		.arg(command.replace("$CMDLINE_OPTS", params))
+		.env("MODPROBE_MODULE", module)
+		.env(INSTALL_CHAIN, chain.join("\n"))
+		.stdin(Stdio::null())
+		.status()?;
+	if !status.success() {
+		return Err(io::Error::other(format!(
+			"Command '{}' for module {} failed: {}",
+			command, module, status
+		)));
+	}
+
+	Ok(())
+}
+
+/// Appends options to options of module
+fn append(options: &mut HashMap<String, String>, module: String, new: &str) {
+	options
+		.entry(module)
+		.and_modify(|o| {
+			o.push(' ');
+			o.push_str(new);
+		})
+		.or_insert_with(|| new.to_string());
+}
+
+/// Returns directives missing from other list
+fn difference(directives: &[Directive], other: &[Directive]) -> Vec<Directive> {
+	directives
+		.iter()
+		.filter(|directive| !other.contains(directive))
+		.cloned()
+		.collect()
+}
+
+/// Splits kernel command line into words, keeping double-quoted text together
+pub(crate) fn cmdline_words(cmdline: &str) -> Vec<&str> {
+	let mut words = Vec::new();
+	let mut start = None;
+	let mut quoted = false;
+	for (i, c) in cmdline.char_indices() {
+		if c == '"' {
+			quoted = !quoted;
+		}
+
+		if c.is_whitespace() && !quoted {
+			if let Some(s) = start.take() {
+				words.push(&cmdline[s..i]);
+			}
+		} else if start.is_none() {
+			start = Some(i);
+		}
+	}
+	if let Some(s) = start {
+		words.push(&cmdline[s..]);
+	}
+
+	words
+}
+
+/// Directories with depmod configuration, from the most important one
+#[cfg(feature = "config")]
+const DEPMOD_DIRS: [&str; 5] = [
+	"/etc/depmod.d",
+	"/run/depmod.d",
+	"/usr/local/lib/depmod.d",
+	"/usr/lib/depmod.d",
+	"/lib/depmod.d",
+];
+
+/// Search order used when depmod.d doesn't contain any `search` directive
+const DEFAULT_SEARCH: [&str; 4] = ["updates", "extra", "built-in", "weak-updates"];
+
+/// Search configuration of depmod (depmod.d), deciding which of modules with the same name is used
+#[derive(Default)]
+pub(crate) struct Depmod {
+	search: Vec<String>,
+	overrides: Vec<(String, String, String)>,
+}
+
+impl Depmod {
+	/// Reads configuration files from depmod.d directories, default search order is used
+	/// without `config` feature
+	pub(crate) fn load() -> io::Result<Depmod> {
+		let mut depmod = Depmod::default();
+		#[cfg(feature = "config")]
+		for path in config_files(&DEPMOD_DIRS)? {
+			depmod.parse(&vfs::read_to_string(path)?);
+		}
+
+		if depmod.search.is_empty() {
+			depmod.search = DEFAULT_SEARCH.iter().map(|s| s.to_string()).collect();
+		}
+
+		Ok(depmod)
+	}
+
+	/// Returns priority of module path (relative to module directory), lower one is preferred
+	///
+	/// Module matching `override` directive is preferred over all others, then modules are ordered
+	/// by `search` directives, where `built-in` stands for `kernel` directory.
+	pub(crate) fn priority(&self, kernel: &str, path: &str) -> usize {
+		let name = index::module_name(path);
+		let in_dir = |dir: &str| path.starts_with(&format!("{}/", dir.trim_end_matches('/')));
+
+		let overridden = self.overrides.iter().any(|(module, version, dir)| {
+			index::normalize(module) == name
+				&& (version == "*" || version == kernel || fnmatch(version, kernel))
+				&& in_dir(dir)
+		});
+		if overridden {
+			return 0;
+		}
+
+		self.search
+			.iter()
+			.position(|dir| match dir.as_str() {
+				"built-in" => in_dir("kernel"),
+				dir => in_dir(dir),
+			})
+			.unwrap_or(self.search.len())
+			+ 1
+	}
+
+	#[cfg(feature = "config")]
+	fn parse(&mut self, content: &str) {
+		for line in logical_lines(content) {
+			let (command, rest) = next_word(&line);
+			match command {
+				"search" => self
+					.search
+					.extend(rest.split_whitespace().map(|dir| dir.to_string())),
+				"override" => {
+					let words: Vec<&str> = rest.split_whitespace().collect();
+					if let [module, version, dir] = words[..] {
+						self.overrides.push((
+							module.to_string(),
+							version.to_string(),
+							dir.to_string(),
+						));
+					}
+				}
+				_ => (),
+			}
+		}
+	}
+}
+
+fn fnmatch(pattern: &str, text: &str) -> bool {
+	index::fnmatch(pattern.as_bytes(), text.as_bytes())
+}
+
+/// Directories with lists of modules to load at boot, from the most important one
+const MODULES_LOAD_DIRS: [&str; 5] = [
+	"/etc/modules-load.d",
+	"/run/modules-load.d",
+	"/usr/local/lib/modules-load.d",
+	"/usr/lib/modules-load.d",
+	"/lib/modules-load.d",
+];
+
+/// Returns names of modules listed in modules-load.d directories
+pub(crate) fn modules_load_list() -> io::Result<Vec<String>> {
+	let mut names = Vec::new();
+	for path in config_files(&MODULES_LOAD_DIRS)? {
+		for line in vfs::read_to_string(path)?.lines() {
+			let line = line.trim();
+			if !line.is_empty() && !line.starts_with('#') && !line.starts_with(';') {
+				names.push(line.to_string());
+			}
+		}
+	}
+
+	Ok(names)
+}
+
+/// Collects `.conf` files from directories sorted by file name
+///
+/// File in more important directory overrides file with the same name in less important one.
+fn config_files(dirs: &[&str]) -> io::Result<Vec<PathBuf>> {
+	let mut files: BTreeMap<String, PathBuf> = BTreeMap::new();
+	for dir in dirs {
+		let entries = match vfs::read_dir(dir) {
+			Ok(o) => o,
+			Err(e) if e.kind() == ErrorKind::NotFound => continue,
+			Err(e) => return Err(e),
+		};
+
+		for path in entries {
+			let name = match path.file_name() {
+				Some(o) => o.to_string_lossy().into_owned(),
+				None => continue,
+			};
+			if name.ends_with(".conf") {
+				files.entry(name).or_insert(path);
+			}
+		}
+	}
+
+	Ok(files.into_values().collect())
+}
+
+/// Joins lines ending with backslash and skips comments and empty lines
+#[cfg(feature = "config")]
+fn logical_lines(content: &str) -> Vec<String> {
+	let mut lines = Vec::new();
+	let mut current = String::new();
+	for line in content.lines() {
+		match line.strip_suffix('\\') {
+			Some(part) => current.push_str(part),
+			None => {
+				current.push_str(line);
+				let trimmed = current.trim();
+				if !trimmed.is_empty() && !trimmed.starts_with('#') {
+					lines.push(trimmed.to_string());
+				}
+				current.clear();
+			}
+		}
+	}
+
+	lines
+}
+
+/// Splits first whitespace-separated word from the rest of text
+#[cfg(feature = "config")]
+fn next_word(text: &str) -> (&str, &str) {
+	let text = text.trim_start();
+	match text.split_once(char::is_whitespace) {
+		Some((word, rest)) => (word, rest.trim()),
+		None => (text, ""),
+	}
+}
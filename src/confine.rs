@@ -0,0 +1,138 @@
+use std::{
+	fs, io,
+	os::unix::fs::OpenOptionsExt,
+	path::{Path, PathBuf},
+};
+
+#[cfg(target_os = "linux")]
+use std::os::{
+	fd::{AsRawFd, FromRawFd},
+	unix::ffi::OsStrExt,
+};
+
+use crate::{index, Error};
+
+// Path resolution flags of openat2, defined by Linux for every architecture
+const RESOLVE_NO_MAGICLINKS: u64 = 0x02;
+const RESOLVE_NO_SYMLINKS: u64 = 0x04;
+const RESOLVE_BENEATH: u64 = 0x08;
+
+/// Opens file for reading, files in module tree cannot be reached through paths escaping it
+///
+/// Paths under base directory of modules (e.g. `/lib/modules`, see `ModuleIndex::set_base_dirs`)
+/// are opened with openat2 relative to it with `RESOLVE_BENEATH`, so `..` components and symbolic
+/// links leading outside (e.g. from malicious modules.dep) fail with `Error::OutsideModuleTree`.
+/// Absolute symbolic links (e.g. weak-updates) are always rejected by kernel, so they are resolved
+/// first and resolved path is opened without following any link. Other paths are opened normally.
+///
+/// If file itself is a symbolic link and links are not followed, `Error::SymlinkRejected` is
+/// returned.
+pub(crate) fn open(path: &Path, follow: bool) -> io::Result<fs::File> {
+	let flags = match follow {
+		true => libc::O_RDONLY | libc::O_CLOEXEC,
+		false => libc::O_RDONLY | libc::O_CLOEXEC | libc::O_NOFOLLOW,
+	};
+	let rejected = |e: io::Error| match e.raw_os_error() {
+		Some(libc::ELOOP) if !follow => Error::SymlinkRejected(path.display().to_string()).into(),
+		_ => e,
+	};
+
+	let base = index::base_dirs()
+		.into_iter()
+		.find(|base| path.starts_with(base));
+	let (base, relative) = match &base {
+		Some(base) => (base, path.strip_prefix(base).unwrap_or(path)),
+		None => {
+			return fs::OpenOptions::new()
+				.read(true)
+				.custom_flags(flags)
+				.open(path)
+				.map_err(rejected)
+		}
+	};
+
+	// Final link is not followed with `O_NOFOLLOW`, so only links in directories can escape
+	let root = fs::File::open(base)?;
+	match openat2(
+		&root,
+		relative,
+		flags,
+		RESOLVE_BENEATH | RESOLVE_NO_MAGICLINKS,
+	)
+	.map_err(rejected)
+	{
+		Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+			let relative = resolve(base, path)?;
+			openat2(
+				&root,
+				&relative,
+				flags,
+				RESOLVE_BENEATH | RESOLVE_NO_SYMLINKS,
+			)
+			.map_err(|e| match e.raw_os_error() {
+				Some(libc::EXDEV) | Some(libc::ELOOP) => outside(path),
+				_ => e,
+			})
+		}
+
+		// openat2 is available since Linux 5.6, check resolved path instead
+		Err(e) if e.raw_os_error() == Some(libc::ENOSYS) => {
+			if !follow && fs::symlink_metadata(path)?.file_type().is_symlink() {
+				return Err(rejected(io::Error::from_raw_os_error(libc::ELOOP)));
+			}
+			fs::File::open(base.join(resolve(base, path)?))
+		}
+		result => result,
+	}
+}
+
+/// Resolves symbolic links in path, returning it relative to module tree
+fn resolve(base: &Path, path: &Path) -> io::Result<PathBuf> {
+	// Module tree itself can be reached through a link (e.g. `/lib` to `/usr/lib`)
+	let root = fs::canonicalize(base)?;
+	match fs::canonicalize(path)?.strip_prefix(&root) {
+		Ok(relative) => Ok(relative.to_path_buf()),
+		Err(_) => Err(outside(path)),
+	}
+}
+
+/// Opens file relative to directory, resolving path according to `RESOLVE_*` flags
+#[cfg(target_os = "linux")]
+fn openat2(dir: &fs::File, path: &Path, flags: i32, resolve: u64) -> io::Result<fs::File> {
+	// Empty path refers to directory itself
+	let path = match path.as_os_str().is_empty() {
+		true => Path::new("."),
+		false => path,
+	};
+	let path = std::ffi::CString::new(path.as_os_str().as_bytes())
+		.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+	let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+	how.flags = flags as u64;
+	how.resolve = resolve;
+
+	let fd = unsafe {
+		libc::syscall(
+			libc::SYS_openat2,
+			dir.as_raw_fd(),
+			path.as_ptr(),
+			&how as *const libc::open_how,
+			std::mem::size_of::<libc::open_how>(),
+		)
+	};
+	if fd < 0 {
+		return Err(io::Error::last_os_error());
+	}
+
+	Ok(unsafe { fs::File::from_raw_fd(fd as i32) })
+}
+
+/// Reports openat2 as unavailable on other systems than Linux, resolved paths are checked instead
+#[cfg(not(target_os = "linux"))]
+fn openat2(_dir: &fs::File, _path: &Path, _flags: i32, _resolve: u64) -> io::Result<fs::File> {
+	Err(io::Error::from_raw_os_error(libc::ENOSYS))
+}
+
+fn outside(path: &Path) -> io::Error {
+	Error::OutsideModuleTree(path.display().to_string()).into()
+}
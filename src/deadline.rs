@@ -0,0 +1,356 @@
+use std::{
+	io::{self, ErrorKind},
+	path::{Path, PathBuf},
+	str::FromStr,
+	sync::{mpsc, Arc},
+	thread,
+	time::Duration,
+};
+
+use crate::{index, metrics, vfs, Error, ImageReport, LoadTimings, MemoryFs, Metrics, Options};
+
+/// Policy of system read by `LoadDeadlines::system`
+const DEADLINES_PATH: &str = "/etc/liblmod/deadlines.conf";
+
+/// Interval between attempts to load module, unless given by rule
+const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Rule applied to modules without their own rule
+const ANY_MODULE: &str = "*";
+
+/// Timeouts and retries of loading modules, by module name
+///
+/// Every line names module (or `*` for all other modules), followed by timeout and optionally
+/// by number of retries and interval between them. Durations are numbers with `ms`, `s` or `m`
+/// suffix (seconds without suffix), `none` disables timeout. Blank lines and comments (`#`)
+/// are skipped.
+///
+/// ```text
+/// # Module  timeout  retries  interval
+/// i915      30s
+/// pcspkr    1s
+/// iwlwifi   10s      3        500ms
+/// *         none     1
+/// ```
+///
+/// Policy is set with `Options::deadlines`, policy of system (`/etc/liblmod/deadlines.conf`)
+/// is read with `LoadDeadlines::system`.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// use std::time::Duration;
+///
+/// let deadlines: liblmod::LoadDeadlines = "i915 30s\npcspkr 1s 2 100ms".parse().unwrap();
+/// assert_eq!(deadlines.rule("pcspkr").unwrap().timeout, Some(Duration::from_secs(1)));
+/// assert!(deadlines.rule("kvm").is_none());
+///
+/// let options = liblmod::Options::new().deadlines(deadlines);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoadDeadlines {
+	/// Rules in order of policy
+	pub rules: Vec<DeadlineRule>,
+}
+
+/// Timeout and retries of loading module
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeadlineRule {
+	/// Name of module, `*` for modules without their own rule
+	pub module: String,
+
+	/// How long loading of module is waited for, `None` to wait until kernel finishes
+	pub timeout: Option<Duration>,
+
+	/// How many times failed loading is repeated
+	pub retries: u32,
+
+	/// Time between attempts to load module
+	pub retry_interval: Duration,
+}
+
+impl LoadDeadlines {
+	/// Reads policy of system from `/etc/liblmod/deadlines.conf`, so timeouts can be tuned
+	/// without changes of application
+	///
+	/// Policy is empty if file doesn't exist, malformed policy is an error.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// match liblmod::LoadDeadlines::system() {
+	///     Ok(deadlines) => {
+	///         let options = liblmod::Options::new().deadlines(deadlines);
+	///     }
+	///     Err(e) => eprintln!("Invalid deadlines policy: {e}"),
+	/// }
+	/// ```
+	pub fn system() -> io::Result<LoadDeadlines> {
+		match LoadDeadlines::from_file(DEADLINES_PATH) {
+			Err(e) if e.kind() == ErrorKind::NotFound => Ok(LoadDeadlines::default()),
+			result => result,
+		}
+	}
+
+	/// Reads policy from file
+	pub fn from_file(path: impl AsRef<Path>) -> io::Result<LoadDeadlines> {
+		let path = path.as_ref();
+		parse(&vfs::read_to_string(path)?, &path.display().to_string())
+	}
+
+	/// Returns rule of module, or rule for all modules (`*`) if module has none
+	///
+	/// If module has several rules, the last one is used.
+	pub fn rule(&self, module: &str) -> Option<&DeadlineRule> {
+		let module = index::normalize(module);
+		let rule = |name: &str| self.rules.iter().rev().find(|rule| rule.module == name);
+		rule(&module).or_else(|| rule(ANY_MODULE))
+	}
+}
+
+impl FromStr for LoadDeadlines {
+	type Err = io::Error;
+
+	fn from_str(text: &str) -> io::Result<LoadDeadlines> {
+		parse(text, "deadlines")
+	}
+}
+
+/// Loads module file within timeout of its rule, retrying failures
+///
+/// Timed out loading isn't retried, as module may still be loaded by kernel.
+pub(crate) fn load(
+	name: &str,
+	path: &Path,
+	params: &str,
+	options: &Options,
+) -> io::Result<(LoadTimings, ImageReport)> {
+	let (machine, cancellation) = (options.machine.as_deref(), options.cancellation.as_ref());
+	let rule = match options.deadlines.as_ref().and_then(|d| d.rule(name)) {
+		Some(rule) => rule,
+		None => return crate::load_path(path, params, None, false, machine, cancellation, 0),
+	};
+
+	let mut retries = 0;
+	loop {
+		let result = match rule.timeout {
+			Some(timeout) => load_timeout(
+				name,
+				path.to_path_buf(),
+				params.to_string(),
+				options,
+				timeout,
+			),
+			_ => crate::load_path(path, params, None, false, machine, cancellation, 0),
+		};
+
+		match result {
+			Err(e) if retries < rule.retries && is_transient(&e) => {
+				retries += 1;
+				thread::sleep(rule.retry_interval);
+				options.check_cancelled()?;
+			}
+			result => return result,
+		}
+	}
+}
+
+/// Loads module file on helper thread, see `run_timeout`
+fn load_timeout(
+	name: &str,
+	path: PathBuf,
+	params: String,
+	options: &Options,
+	timeout: Duration,
+) -> io::Result<(LoadTimings, ImageReport)> {
+	let machine = options.machine.clone();
+	let cancellation = options.cancellation.clone();
+	run_timeout(name, timeout, move || {
+		crate::load_path(
+			&path,
+			&params,
+			None,
+			false,
+			machine.as_deref(),
+			cancellation.as_ref(),
+			0,
+		)
+	})
+}
+
+/// Per-thread state of library carried between caller and helper thread
+struct ThreadState {
+	fs: Option<Arc<MemoryFs>>,
+	metrics: Metrics,
+	#[cfg(feature = "testing")]
+	kernel: crate::testing::FakeKernel,
+}
+
+impl ThreadState {
+	/// Captures state of current thread, taking metrics recorded on it if requested
+	fn capture(take_metrics: bool) -> ThreadState {
+		ThreadState {
+			fs: vfs::current(),
+			metrics: match take_metrics {
+				true => metrics::take_metrics(),
+				false => Metrics::default(),
+			},
+			#[cfg(feature = "testing")]
+			kernel: crate::testing::state(),
+		}
+	}
+
+	/// Installs captured state on current thread, adding metrics to recorded ones
+	fn install(self) {
+		vfs::inherit(self.fs);
+		metrics::merge(self.metrics);
+		#[cfg(feature = "testing")]
+		crate::testing::set_state(self.kernel);
+	}
+}
+
+/// Runs loading or unloading of module on helper thread, which keeps waiting for kernel after
+/// timeout
+///
+/// Helper thread inherits in-memory filesystem and fake kernel of caller, metrics and calls of
+/// fake kernel are brought back to caller unless operation times out.
+pub(crate) fn run_timeout<T: Send + 'static>(
+	name: &str,
+	timeout: Duration,
+	load: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> io::Result<T> {
+	let state = ThreadState::capture(false);
+	let (tx, rx) = mpsc::channel();
+	thread::spawn(move || {
+		state.install();
+		let result = load();
+		let _ = tx.send((result, ThreadState::capture(true)));
+	});
+
+	match rx.recv_timeout(timeout) {
+		Ok((result, state)) => {
+			state.install();
+			result
+		}
+		Err(_) => Err(Error::Timeout(name.to_string()).into()),
+	}
+}
+
+/// Checks if failed loading may succeed when repeated
+///
+/// Modules rejected by kernel for good (`EPERM`, `ENOEXEC` or invalid parameters) or by checks
+/// of library, loaded modules, timed out and cancelled loading are not retried.
+fn is_transient(error: &io::Error) -> bool {
+	if matches!(
+		Error::errno(error),
+		Some(libc::EPERM | libc::ENOEXEC | libc::EINVAL)
+	) {
+		return false;
+	}
+
+	!matches!(
+		error.kind(),
+		ErrorKind::AlreadyExists
+			| ErrorKind::TimedOut
+			| ErrorKind::PermissionDenied
+			| ErrorKind::InvalidData
+			| ErrorKind::InvalidInput
+			| ErrorKind::Interrupted
+	)
+}
+
+/// Parses policy, naming file in errors
+fn parse(text: &str, file: &str) -> io::Result<LoadDeadlines> {
+	let mut deadlines = LoadDeadlines::default();
+	for (number, line) in text.lines().enumerate() {
+		let line = line.split('#').next().unwrap_or_default();
+		let mut words = Vec::new();
+		let mut rest = line;
+		while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+			let end = rest[start..]
+				.find(char::is_whitespace)
+				.map_or(rest.len(), |end| start + end);
+			words.push((line.len() - rest.len() + start + 1, &rest[start..end]));
+			rest = &rest[end..];
+		}
+		let (module, timeout) = match words[..] {
+			[] => continue,
+			[_] => return Err(error(file, number + 1, line.len() + 1, "missing timeout")),
+			[(_, module), (_, timeout), ..] if words.len() <= 4 => (module, timeout),
+			_ => {
+				let (column, _) = words[4];
+				return Err(error(file, number + 1, column, "unexpected word"));
+			}
+		};
+
+		let invalid = |(column, word): (usize, &str)| {
+			error(
+				file,
+				number + 1,
+				column,
+				&format!("invalid duration {}", word),
+			)
+		};
+		let timeout = match timeout {
+			"none" => None,
+			_ => Some(parse_duration(timeout).ok_or_else(|| invalid(words[1]))?),
+		};
+		let retries = match words.get(2) {
+			Some(&(column, retries)) => retries.parse().map_err(|_| {
+				error(
+					file,
+					number + 1,
+					column,
+					&format!("invalid number of retries {}", retries),
+				)
+			})?,
+			None => 0,
+		};
+		let retry_interval = match words.get(3) {
+			Some(&word) => parse_duration(word.1).ok_or_else(|| invalid(word))?,
+			None => DEFAULT_RETRY_INTERVAL,
+		};
+
+		deadlines.rules.push(DeadlineRule {
+			module: match module {
+				ANY_MODULE => module.to_string(),
+				_ => index::normalize(module),
+			},
+			timeout,
+			retries,
+			retry_interval,
+		});
+	}
+
+	Ok(deadlines)
+}
+
+/// Parses duration like `500ms`, `30s`, `2m` or `5` (seconds)
+fn parse_duration(text: &str) -> Option<Duration> {
+	let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+		Some(position) => text.split_at(position),
+		None => (text, "s"),
+	};
+	let number: u64 = number.parse().ok()?;
+	match unit {
+		"ms" => Some(Duration::from_millis(number)),
+		"s" => Some(Duration::from_secs(number)),
+		"m" => Some(Duration::from_secs(number.checked_mul(60)?)),
+		_ => None,
+	}
+}
+
+/// Returns error of malformed policy line
+fn error(file: &str, line: usize, column: usize, reason: &str) -> io::Error {
+	Error::ManifestParse {
+		file: file.to_string(),
+		line,
+		column,
+		reason: reason.to_string(),
+	}
+	.into()
+}
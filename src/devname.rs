@@ -0,0 +1,117 @@
+use std::{io, path::PathBuf};
+
+use crate::{index, ModuleIndex, Selection};
+
+/// Device node created before module is loaded, so opening it loads module (as in modules.devname)
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StaticNode {
+	/// Name of module
+	pub module: String,
+
+	/// Name of device node relative to `/dev` (e.g. `snd/timer`)
+	pub name: String,
+
+	/// Type of device node
+	pub kind: NodeKind,
+
+	/// Major number of device
+	pub major: u32,
+
+	/// Minor number of device
+	pub minor: u32,
+}
+
+/// Type of device node
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NodeKind {
+	/// Character device (`c`)
+	Character,
+
+	/// Block device (`b`)
+	Block,
+}
+
+impl StaticNode {
+	/// Returns absolute path of device node
+	pub fn path(&self) -> PathBuf {
+		PathBuf::from("/dev").join(&self.name)
+	}
+
+	/// Returns lines of tmpfiles.d creating device node, like `kmod static-nodes --format=tmpfiles`
+	///
+	/// Directory of node (if any) is created first, lines end with newline.
+	pub fn to_tmpfiles(&self) -> String {
+		let mut lines = String::new();
+		if let Some((dir, _)) = self.name.rsplit_once('/') {
+			lines.push_str(&format!("d /dev/{} 0755 - - -\n", dir));
+		}
+		let kind = match self.kind {
+			NodeKind::Character => 'c',
+			NodeKind::Block => 'b',
+		};
+		lines.push_str(&format!(
+			"{}! /dev/{} 0600 - - - {}:{}\n",
+			kind, self.name, self.major, self.minor
+		));
+		lines
+	}
+}
+
+/// Returns device nodes of modules of selected kernel, like `kmod static-nodes`
+///
+/// Init systems create these nodes before udev starts, so modules are loaded when their device
+/// node is opened for the first time. Nodes are returned in order of modules.devname, which
+/// isn't required to exist (there are no nodes then). Malformed lines are skipped like by kmod.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// liblmod::set_filesystem(
+///     liblmod::MemoryFs::new()
+///         .file("/lib/modules/6.1.0/modules.dep", "kernel/fs/fuse/fuse.ko:\n")
+///         .file("/lib/modules/6.1.0/modules.devname", "# Device nodes\nfuse fuse c10:229\nsnd_timer snd/timer c116:33\n"),
+/// );
+///
+/// let nodes = liblmod::static_nodes(liblmod::Selection::Other("6.1.0".to_string())).unwrap();
+/// assert_eq!(nodes[1].path(), std::path::Path::new("/dev/snd/timer"));
+/// assert_eq!((nodes[1].major, nodes[1].minor), (116, 33));
+///
+/// let tmpfiles: String = nodes.iter().map(|node| node.to_tmpfiles()).collect();
+/// assert_eq!(
+///     tmpfiles,
+///     "c! /dev/fuse 0600 - - - 10:229\nd /dev/snd 0755 - - -\nc! /dev/snd/timer 0600 - - - 116:33\n"
+/// );
+///
+/// liblmod::clear_filesystem();
+/// ```
+pub fn static_nodes(kernel: Selection) -> io::Result<Vec<StaticNode>> {
+	ModuleIndex::shared(kernel)?.static_nodes()
+}
+
+/// Parses modules.devname
+pub(crate) fn parse(text: &str) -> Vec<StaticNode> {
+	text.lines()
+		.filter(|line| !line.starts_with('#'))
+		.filter_map(|line| {
+			let mut words = line.split_whitespace();
+			let (module, name, node) = (words.next()?, words.next()?, words.next()?);
+			let kind = match node.as_bytes().first()? {
+				b'c' => NodeKind::Character,
+				b'b' => NodeKind::Block,
+				_ => return None,
+			};
+			let (major, minor) = node[1..].split_once(':')?;
+
+			Some(StaticNode {
+				module: index::normalize(module),
+				name: name.to_string(),
+				kind,
+				major: major.parse().ok()?,
+				minor: minor.parse().ok()?,
+			})
+		})
+		.collect()
+}
@@ -0,0 +1,413 @@
+use std::io;
+
+use crate::Error;
+
+const ELFCLASS32: u8 = 1;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ELFDATA2MSB: u8 = 2;
+const ET_REL: u16 = 1;
+
+const EM_386: u16 = 3;
+const EM_MIPS: u16 = 8;
+const EM_PPC: u16 = 20;
+const EM_PPC64: u16 = 21;
+const EM_S390: u16 = 22;
+const EM_ARM: u16 = 40;
+const EM_SPARCV9: u16 = 43;
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+const EM_RISCV: u16 = 243;
+const EM_LOONGARCH: u16 = 258;
+
+/// Identification of ELF image target
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Target {
+	class: u8,
+	data: Option<u8>,
+	machine: u16,
+}
+
+/// Returns ELF target of kernel running on specified machine (uname -m)
+fn kernel_target(machine: &str) -> Option<Target> {
+	let (class, data, machine) = match machine {
+		"x86_64" => (ELFCLASS64, Some(ELFDATA2LSB), EM_X86_64),
+		"i386" | "i486" | "i586" | "i686" => (ELFCLASS32, Some(ELFDATA2LSB), EM_386),
+		"aarch64" => (ELFCLASS64, Some(ELFDATA2LSB), EM_AARCH64),
+		"aarch64_be" => (ELFCLASS64, Some(ELFDATA2MSB), EM_AARCH64),
+		"riscv64" => (ELFCLASS64, Some(ELFDATA2LSB), EM_RISCV),
+		"riscv32" => (ELFCLASS32, Some(ELFDATA2LSB), EM_RISCV),
+		"ppc64le" => (ELFCLASS64, Some(ELFDATA2LSB), EM_PPC64),
+		"ppc64" => (ELFCLASS64, Some(ELFDATA2MSB), EM_PPC64),
+		"ppc" => (ELFCLASS32, Some(ELFDATA2MSB), EM_PPC),
+		"s390x" => (ELFCLASS64, Some(ELFDATA2MSB), EM_S390),
+		"loongarch64" => (ELFCLASS64, Some(ELFDATA2LSB), EM_LOONGARCH),
+		"sparc64" => (ELFCLASS64, Some(ELFDATA2MSB), EM_SPARCV9),
+		// MIPS kernels report the same machine for both endiannesses
+		"mips" => (ELFCLASS32, None, EM_MIPS),
+		"mips64" => (ELFCLASS64, None, EM_MIPS),
+		m if m.starts_with("armv") && m.ends_with('b') => (ELFCLASS32, Some(ELFDATA2MSB), EM_ARM),
+		m if m.starts_with("arm") => (ELFCLASS32, Some(ELFDATA2LSB), EM_ARM),
+		_ => return None,
+	};
+
+	Some(Target {
+		class,
+		data,
+		machine,
+	})
+}
+
+fn describe(target: Target) -> String {
+	let machine = match target.machine {
+		EM_386 => "x86".to_string(),
+		EM_MIPS => "mips".to_string(),
+		EM_PPC => "powerpc".to_string(),
+		EM_PPC64 => "powerpc64".to_string(),
+		EM_S390 => "s390".to_string(),
+		EM_ARM => "arm".to_string(),
+		EM_SPARCV9 => "sparc64".to_string(),
+		EM_X86_64 => "x86_64".to_string(),
+		EM_AARCH64 => "aarch64".to_string(),
+		EM_RISCV => "riscv".to_string(),
+		EM_LOONGARCH => "loongarch".to_string(),
+		m => format!("machine {}", m),
+	};
+	let class = match target.class {
+		ELFCLASS32 => "32-bit",
+		ELFCLASS64 => "64-bit",
+		_ => "unknown class",
+	};
+	let data = match target.data {
+		Some(ELFDATA2LSB) => ", little endian",
+		Some(ELFDATA2MSB) => ", big endian",
+		Some(_) => ", unknown endianness",
+		None => "",
+	};
+
+	format!("{} ({}{})", machine, class, data)
+}
+
+/// Parsed ELF image
+pub(crate) struct Elf<'a> {
+	image: &'a [u8],
+	class: u8,
+	data: u8,
+	kind: u16,
+	machine: u16,
+	sections: Vec<SectionHeader>,
+	names: Option<usize>,
+}
+
+/// ELF section header
+#[derive(Clone, Copy)]
+pub(crate) struct SectionHeader {
+	name: u32,
+	kind: u32,
+	offset: u64,
+	size: u64,
+	#[cfg(feature = "modinfo")]
+	link: u32,
+	#[cfg(feature = "modinfo")]
+	entsize: u64,
+}
+
+#[cfg(feature = "modinfo")]
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOBITS: u32 = 8;
+
+impl<'a> Elf<'a> {
+	/// Parses ELF header only, which is at start of image
+	fn header(image: &'a [u8]) -> io::Result<Elf<'a>> {
+		// Check ELF identification
+		if image.len() < 52 || !image.starts_with(b"\x7fELF") {
+			return Err(invalid("image is not an ELF file"));
+		}
+
+		let mut elf = Elf {
+			image,
+			class: image[4],
+			data: image[5],
+			kind: 0,
+			machine: 0,
+			sections: Vec::new(),
+			names: None,
+		};
+		elf.kind = elf.u16(16)?;
+		elf.machine = elf.u16(18)?;
+
+		Ok(elf)
+	}
+
+	/// Parses ELF header and section headers
+	pub(crate) fn parse(image: &'a [u8]) -> io::Result<Elf<'a>> {
+		let mut elf = Elf::header(image)?;
+
+		// Read section headers
+		let (shoff, shentsize, shnum, shstrndx) = match elf.class {
+			ELFCLASS64 => (
+				elf.u64(0x28)?,
+				elf.u16(0x3a)?,
+				elf.u16(0x3c)?,
+				elf.u16(0x3e)?,
+			),
+			ELFCLASS32 => (
+				elf.u32(0x20)? as u64,
+				elf.u16(0x2e)?,
+				elf.u16(0x30)?,
+				elf.u16(0x32)?,
+			),
+			_ => return Err(invalid("unknown ELF class")),
+		};
+		for i in 0..shnum as usize {
+			// Offsets of fields are added to base, so it has to lie within image
+			let base = to_usize(shoff)?
+				.checked_add(i * shentsize as usize)
+				.filter(|base| *base <= image.len())
+				.ok_or_else(|| invalid("section header out of bounds"))?;
+			let header = match elf.class {
+				ELFCLASS64 => SectionHeader {
+					name: elf.u32(base)?,
+					kind: elf.u32(base + 4)?,
+					offset: elf.u64(base + 24)?,
+					size: elf.u64(base + 32)?,
+					#[cfg(feature = "modinfo")]
+					link: elf.u32(base + 40)?,
+					#[cfg(feature = "modinfo")]
+					entsize: elf.u64(base + 56)?,
+				},
+				_ => SectionHeader {
+					name: elf.u32(base)?,
+					kind: elf.u32(base + 4)?,
+					offset: elf.u32(base + 16)? as u64,
+					size: elf.u32(base + 20)? as u64,
+					#[cfg(feature = "modinfo")]
+					link: elf.u32(base + 24)?,
+					#[cfg(feature = "modinfo")]
+					entsize: elf.u32(base + 36)? as u64,
+				},
+			};
+			elf.sections.push(header);
+		}
+		if (shstrndx as usize) < elf.sections.len() {
+			elf.names = Some(shstrndx as usize);
+		}
+
+		Ok(elf)
+	}
+
+	/// Returns contents of section with specified name
+	pub(crate) fn section(&self, name: &str) -> io::Result<Option<&'a [u8]>> {
+		for header in &self.sections {
+			if self.section_name(header)? == name {
+				return self.section_data(header).map(Some);
+			}
+		}
+
+		Ok(None)
+	}
+}
+
+/// Sections and symbols read by module analysis
+#[cfg(feature = "modinfo")]
+impl<'a> Elf<'a> {
+	/// Returns names, types and sizes of sections in order of section headers
+	pub(crate) fn sections(&self) -> io::Result<Vec<(&'a str, u32, u64)>> {
+		self.sections
+			.iter()
+			.map(|header| Ok((self.section_name(header)?, header.kind, header.size)))
+			.collect()
+	}
+
+	/// Returns names of symbols with index of section defining them (0 for undefined symbols)
+	pub(crate) fn symbols(&self) -> io::Result<Vec<(&'a str, u16)>> {
+		let Some(table) = self
+			.sections
+			.iter()
+			.find(|header| header.kind == SHT_SYMTAB)
+		else {
+			return Ok(Vec::new());
+		};
+		let names = match self.sections.get(table.link as usize) {
+			Some(header) => self.section_data(header)?,
+			None => return Err(invalid("symbol names out of bounds")),
+		};
+		let entsize = match (to_usize(table.entsize)?, self.class) {
+			(0, ELFCLASS64) => 24,
+			(0, _) => 16,
+			(entsize, _) => entsize,
+		};
+
+		// Symbol table starts with null symbol
+		let data = self.section_data(table)?;
+		let start = to_usize(table.offset)?;
+		let mut symbols = Vec::new();
+		for base in (0..data.len() / entsize)
+			.skip(1)
+			.map(|i| start + i * entsize)
+		{
+			let (name, section) = match self.class {
+				ELFCLASS64 => (self.u32(base)?, self.u16(base + 6)?),
+				_ => (self.u32(base)?, self.u16(base + 14)?),
+			};
+			symbols.push((cstr(names, name as usize), section));
+		}
+
+		Ok(symbols)
+	}
+
+	/// Returns symbol versions (CRCs) of undefined symbols recorded by modpost
+	///
+	/// Kernels since 6.13 may store long names in `__version_ext_*` sections instead.
+	pub(crate) fn modversions(&self) -> io::Result<Vec<(&'a str, u32)>> {
+		let mut versions = Vec::new();
+
+		// Entries of __versions have CRC of size of long and name filling up 64 bytes
+		if let Some(section) = self.section("__versions")? {
+			let crcsize = match self.class {
+				ELFCLASS64 => 8,
+				_ => 4,
+			};
+			for entry in section.chunks_exact(64) {
+				versions.push((cstr(&entry[crcsize..], 0), self.crc(entry)));
+			}
+		}
+
+		if let (Some(crcs), Some(names)) = (
+			self.section("__version_ext_crcs")?,
+			self.section("__version_ext_names")?,
+		) {
+			for (crc, name) in crcs.chunks_exact(4).zip(names.split(|b| *b == 0)) {
+				versions.push((std::str::from_utf8(name).unwrap_or(""), self.crc(crc)));
+			}
+		}
+
+		Ok(versions)
+	}
+
+	/// Returns CRC stored at start of bytes in byte order of image
+	fn crc(&self, bytes: &[u8]) -> u32 {
+		// CRC of 64-bit images is stored in long, whose low half comes first in little endian
+		let bytes = match (self.class, self.data) {
+			(ELFCLASS64, ELFDATA2MSB) if bytes.len() >= 8 => &bytes[4..8],
+			_ => &bytes[..4],
+		};
+		let mut array = [0; 4];
+		array.copy_from_slice(bytes);
+		match self.data {
+			ELFDATA2MSB => u32::from_be_bytes(array),
+			_ => u32::from_le_bytes(array),
+		}
+	}
+
+	/// Returns name of section with specified index
+	pub(crate) fn section_name_at(&self, index: u16) -> io::Result<&'a str> {
+		match self.sections.get(index as usize) {
+			Some(header) => self.section_name(header),
+			None => Ok(""),
+		}
+	}
+}
+
+impl<'a> Elf<'a> {
+	fn section_name(&self, header: &SectionHeader) -> io::Result<&'a str> {
+		let names = match self.names {
+			Some(names) => self.section_data(&self.sections[names])?,
+			None => return Ok(""),
+		};
+
+		Ok(cstr(names, header.name as usize))
+	}
+
+	fn section_data(&self, header: &SectionHeader) -> io::Result<&'a [u8]> {
+		if header.kind == SHT_NOBITS {
+			return Ok(&[]);
+		}
+
+		let start = to_usize(header.offset)?;
+		let end = start
+			.checked_add(to_usize(header.size)?)
+			.ok_or_else(|| invalid("section out of bounds"))?;
+		self.image
+			.get(start..end)
+			.ok_or_else(|| invalid("section out of bounds"))
+	}
+
+	fn bytes<const N: usize>(&self, offset: usize) -> io::Result<[u8; N]> {
+		let bytes = self
+			.image
+			.get(offset..offset.saturating_add(N))
+			.filter(|bytes| bytes.len() == N)
+			.ok_or_else(|| invalid("image is truncated"))?;
+		let mut array = [0; N];
+		array.copy_from_slice(bytes);
+		if self.data == ELFDATA2MSB {
+			array.reverse();
+		}
+
+		Ok(array)
+	}
+
+	fn u16(&self, offset: usize) -> io::Result<u16> {
+		self.bytes(offset).map(u16::from_le_bytes)
+	}
+
+	fn u32(&self, offset: usize) -> io::Result<u32> {
+		self.bytes(offset).map(u32::from_le_bytes)
+	}
+
+	fn u64(&self, offset: usize) -> io::Result<u64> {
+		self.bytes(offset).map(u64::from_le_bytes)
+	}
+}
+
+/// Returns NUL-terminated string starting at offset
+fn cstr(data: &[u8], offset: usize) -> &str {
+	let data = data.get(offset..).unwrap_or(&[]);
+	let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+	std::str::from_utf8(&data[..end]).unwrap_or("")
+}
+
+fn to_usize(value: u64) -> io::Result<usize> {
+	usize::try_from(value).map_err(|_| invalid("offset out of range"))
+}
+
+fn invalid(reason: &str) -> io::Error {
+	Error::InvalidModuleFormat(reason.to_string()).into()
+}
+
+/// Checks if image is a relocatable ELF object built for kernel running on specified machine
+pub(crate) fn check(image: &[u8], machine: &str) -> io::Result<()> {
+	check_target(&Elf::parse(image)?, machine)
+}
+
+/// Checks ELF header at start of image (e.g. its first decompressed chunk), see `check`
+pub(crate) fn check_header(start: &[u8], machine: &str) -> io::Result<()> {
+	check_target(&Elf::header(start)?, machine)
+}
+
+fn check_target(elf: &Elf, machine: &str) -> io::Result<()> {
+	if elf.kind != ET_REL {
+		return Err(invalid("image is not a relocatable ELF object"));
+	}
+
+	// Compare target of image with running kernel
+	let expected = match kernel_target(machine) {
+		Some(o) => o,
+		None => return Ok(()),
+	};
+	let found = Target {
+		class: elf.class,
+		data: expected.data.map(|_| elf.data),
+		machine: elf.machine,
+	};
+	if found != expected {
+		return Err(Error::WrongArchitecture {
+			expected: describe(expected),
+			found: describe(found),
+		}
+		.into());
+	}
+
+	Ok(())
+}
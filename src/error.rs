@@ -0,0 +1,759 @@
+use std::{fmt, io};
+
+/// Module management errors
+///
+/// These errors are returned wrapped in `io::Error`, so they can be retrieved with `Error::from_io`.
+/// Errors of kernel rejecting module (e.g. `EEXIST` or `EKEYREJECTED`) are turned into them too,
+/// keeping error number and name of failed syscall available with `Error::errno` and
+/// `Error::syscall`.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// if let Err(e) = liblmod::modprobe("kvm", "", liblmod::Selection::Current) {
+///     match liblmod::Error::from_io(&e) {
+///         Some(liblmod::Error::ModulesDisabled) => eprintln!("Module loading is disabled"),
+///         Some(liblmod::Error::SignatureRejected(_)) => eprintln!("Module kvm is not trusted"),
+///         Some(liblmod::Error::VersionMismatch { .. }) => eprintln!("Module kvm is outdated"),
+///         _ => eprintln!("Failed to load module kvm: {e}"),
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Error {
+	/// Process doesn't have CAP_SYS_MODULE capability
+	MissingCapability,
+
+	/// Loading and unloading modules is disabled by `kernel.modules_disabled` sysctl
+	ModulesDisabled,
+
+	/// Module image is not a valid kernel module
+	InvalidModuleFormat(String),
+
+	/// Binary module index (e.g. modules.alias.bin) is corrupted or unsupported
+	InvalidIndex(String),
+
+	/// Module is built for different architecture than running kernel
+	WrongArchitecture {
+		/// Architecture of running kernel
+		expected: String,
+
+		/// Architecture of module image
+		found: String,
+	},
+
+	/// SHA-256 digest of module image doesn't match expected one
+	IntegrityMismatch {
+		/// Expected digest (hexadecimal)
+		expected: String,
+
+		/// Digest of module image (hexadecimal)
+		found: String,
+	},
+
+	/// Module license is not compatible with GPL and proprietary modules are rejected
+	ProprietaryModule {
+		/// Name of module
+		module: String,
+
+		/// Declared license of module
+		license: Option<String>,
+	},
+
+	/// Loading module has been denied by load policy
+	PolicyDenied {
+		/// Name of module
+		module: String,
+
+		/// Reason given by policy
+		reason: String,
+	},
+
+	/// Module is a livepatch and unloading livepatches hasn't been allowed
+	Livepatch(String),
+
+	/// Operation on module hasn't finished in time
+	Timeout(String),
+
+	/// Module cannot be unloaded, because it's in use
+	ModuleBusy {
+		/// Name of module
+		module: String,
+
+		/// Number of references to module, `None` if it cannot be read
+		refcount: Option<u32>,
+
+		/// Names of modules holding module
+		holders: Vec<String>,
+	},
+
+	/// Operation has been cancelled with `CancellationToken`
+	Cancelled,
+
+	/// Kernel release string cannot be parsed as version
+	InvalidKernelVersion(String),
+
+	/// Name is too long or contains characters not allowed in module names
+	InvalidModuleName(String),
+
+	/// Parameter is not declared by module
+	UnknownParameter {
+		/// Name of module
+		module: String,
+
+		/// Name of parameter
+		parameter: String,
+	},
+
+	/// Value of parameter doesn't match type declared by module
+	InvalidParameterValue {
+		/// Name of module
+		module: String,
+
+		/// Name of parameter
+		parameter: String,
+
+		/// Rejected value
+		value: String,
+
+		/// Declared type of parameter
+		expected: String,
+	},
+
+	/// Module couldn't be loaded, kernel has logged reason
+	KernelLog {
+		/// Name of module
+		module: String,
+
+		/// Kernel log messages mentioning module, logged during loading
+		messages: Vec<String>,
+
+		/// Error returned by kernel
+		#[cfg_attr(feature = "serde", serde(with = "serde_io"))]
+		source: io::Error,
+	},
+
+	/// Dependency of requested module couldn't be loaded
+	DependencyFailed {
+		/// Names of modules from requested module to failed dependency
+		chain: Vec<String>,
+
+		/// Error of failed dependency
+		#[cfg_attr(feature = "serde", serde(with = "serde_io"))]
+		source: io::Error,
+	},
+
+	/// Aliases from modprobe.d refer to each other in a loop
+	AliasLoop(Vec<String>),
+
+	/// Install or remove commands load or unload modules in a loop, or are nested too deep
+	RecursionLimit {
+		/// Module whose command hasn't been run
+		module: String,
+
+		/// Directives of commands from the outermost one (e.g. `install foo /sbin/modprobe bar`)
+		chain: Vec<String>,
+	},
+
+	/// Path in module directory leads outside of it (e.g. through `..` or symbolic link)
+	OutsideModuleTree(String),
+
+	/// Module file is a symbolic link, which are not followed (see `loader::follow_symlinks`)
+	SymlinkRejected(String),
+
+	/// Module is disabled with install command doing nothing (e.g. `install foo /bin/false`)
+	DisabledByConfig(String),
+
+	/// Module is built for different kernel version or with different symbol versions
+	VersionMismatch {
+		/// Name of module
+		module: String,
+
+		/// Version magic of module, `None` if it cannot be read (e.g. compressed module)
+		vermagic: Option<String>,
+
+		/// Release of running kernel
+		kernel: String,
+	},
+
+	/// Module uses symbols whose versions (CRCs) differ from kernel (see `check_modversions`)
+	SymbolVersionMismatch {
+		/// Name of module
+		module: String,
+
+		/// Symbols with different versions
+		symbols: Vec<CrcMismatch>,
+	},
+
+	/// Kernel couldn't allocate memory for module (`ENOMEM`), e.g. because vmalloc space is exhausted
+	OutOfMemory {
+		/// Name of module
+		module: String,
+
+		/// Size of module image in bytes, 0 if unknown (e.g. module loaded from file descriptor)
+		image_size: u64,
+
+		/// Memory used by loaded modules in bytes, as listed in `/proc/modules`
+		modules_size: u64,
+
+		/// Size of vmalloc address space in bytes (`VmallocTotal` of `/proc/meminfo`)
+		vmalloc_total: Option<u64>,
+
+		/// Used vmalloc memory in bytes (`VmallocUsed`, 0 on kernels 4.4 to 5.2)
+		vmalloc_used: Option<u64>,
+
+		/// Memory available for new allocations in bytes (`MemAvailable`)
+		available: Option<u64>,
+
+		/// Error returned by kernel
+		#[cfg_attr(feature = "serde", serde(with = "serde_io"))]
+		source: io::Error,
+	},
+
+	/// Several module files with the same name are equally preferred (see `Options::reject_ambiguous`)
+	AmbiguousModule {
+		/// Name of module
+		module: String,
+
+		/// Paths of module files (relative to module directory), the used one first
+		paths: Vec<String>,
+	},
+
+	/// Decompressed module exceeds limit set with `loader::decompression_limit`
+	DecompressionLimit {
+		/// Path of compressed module file
+		module: String,
+
+		/// Limit of decompressed size in bytes
+		limit: u64,
+	},
+
+	/// Module is not provided by kernel, with names of similar modules
+	ModuleNotFound {
+		/// Requested name of module
+		module: String,
+
+		/// Release of kernel module has been looked for in
+		kernel: String,
+
+		/// Names of provided modules similar to requested one (e.g. misspelled), the closest first
+		suggestions: Vec<String>,
+	},
+
+	/// Module is already loaded (`EEXIST`)
+	AlreadyLoaded(String),
+
+	/// Module isn't signed by a key known to kernel (`ENOKEY`), or isn't signed at all while kernel
+	/// enforces signatures (checked before loading)
+	SignatureRequired {
+		/// Name of module
+		module: String,
+
+		/// Signer of module image, `None` if image isn't signed or signer can't be read
+		signer: Option<String>,
+	},
+
+	/// Signature of module has been rejected by kernel (`EKEYREJECTED`)
+	SignatureRejected(String),
+
+	/// Modules manifest (e.g. modules.dep) is malformed or truncated
+	ManifestParse {
+		/// Path of manifest
+		file: String,
+
+		/// Line number, starting from 1
+		line: usize,
+
+		/// Column of malformed entry, starting from 1
+		column: usize,
+
+		/// Description of problem
+		reason: String,
+	},
+
+	/// New image of module couldn't be loaded by `reload`, after the old module has been unloaded
+	ReloadFailed {
+		/// Name of module
+		module: String,
+
+		/// Whether the old module has been loaded back
+		restored: bool,
+
+		/// Error of loading new image
+		#[cfg_attr(feature = "serde", serde(with = "serde_io"))]
+		source: io::Error,
+	},
+
+	/// Modules cannot be managed on this operating system (name of system), only on Linux
+	Unsupported(String),
+}
+
+impl Error {
+	/// Returns module management error carried by `io::Error`
+	pub fn from_io(error: &io::Error) -> Option<&Error> {
+		let inner = error.get_ref()?;
+		match inner.downcast_ref::<SyscallError>() {
+			Some(failure) => failure.error.as_ref(),
+			None => inner.downcast_ref(),
+		}
+	}
+
+	/// Returns error number kernel has failed with, also if error has been explained by `Error`
+	///
+	/// Errors of dependencies and replaced modules (`DependencyFailed` and `ReloadFailed`) return
+	/// error number of the failure they wrap. Errors not coming from kernel return `None`.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// if let Err(e) = liblmod::modprobe("kvm", "", liblmod::Selection::Current) {
+	///     match liblmod::Error::errno(&e) {
+	///         Some(errno) => eprintln!("{} failed with errno {errno}: {e}",
+	///             liblmod::Error::syscall(&e).unwrap_or("Kernel")),
+	///         None => eprintln!("Failed to load module kvm: {e}"),
+	///     }
+	/// }
+	/// ```
+	pub fn errno(error: &io::Error) -> Option<i32> {
+		if let Some(errno) = error.raw_os_error() {
+			return Some(errno);
+		}
+		let inner = error.get_ref()?;
+		if let Some(failure) = inner.downcast_ref::<SyscallError>() {
+			return Some(failure.errno);
+		}
+		Error::errno(inner.downcast_ref::<Error>()?.os_source()?)
+	}
+
+	/// Returns name of syscall which has failed (e.g. `init_module` or `delete_module`)
+	///
+	/// Only errors of module loading and unloading know their syscall, errors of thin wrappers of
+	/// `sys` module and other OS errors return `None`.
+	pub fn syscall(error: &io::Error) -> Option<&'static str> {
+		let inner = error.get_ref()?;
+		if let Some(failure) = inner.downcast_ref::<SyscallError>() {
+			return Some(failure.syscall);
+		}
+		Error::syscall(inner.downcast_ref::<Error>()?.os_source()?)
+	}
+
+	/// Attaches error number and syscall name to error of kernel, after it's explained by `explain`
+	///
+	/// Errors which don't come from kernel (e.g. of hooks) are only explained.
+	pub(crate) fn syscall_failed(
+		syscall: &'static str,
+		error: io::Error,
+		explain: impl FnOnce(io::Error) -> io::Error,
+	) -> io::Error {
+		let errno = match Error::errno(&error) {
+			Some(errno) => errno,
+			None => return explain(error),
+		};
+
+		let error = explain(error);
+		let kind = error.kind();
+		let explained = match error.raw_os_error() {
+			Some(_) => None,
+			None => match error.into_inner().map(|inner| inner.downcast::<Error>()) {
+				Some(Ok(explained)) => Some(*explained),
+				Some(Err(inner)) => return io::Error::new(kind, inner),
+				None => return io::Error::from(kind),
+			},
+		};
+
+		io::Error::new(
+			kind,
+			SyscallError {
+				syscall,
+				errno,
+				error: explained,
+				message: None,
+			},
+		)
+	}
+
+	/// Rebuilds failure of syscall from its error number, kind and message, e.g. for threads
+	/// sharing outcome of one loading
+	pub(crate) fn syscall_copy(
+		syscall: &'static str,
+		errno: i32,
+		kind: io::ErrorKind,
+		message: String,
+	) -> io::Error {
+		io::Error::new(
+			kind,
+			SyscallError {
+				syscall,
+				errno,
+				error: None,
+				message: Some(message),
+			},
+		)
+	}
+
+	/// Returns failure error of kernel is kept in
+	fn os_source(&self) -> Option<&io::Error> {
+		match self {
+			Error::KernelLog { source, .. }
+			| Error::DependencyFailed { source, .. }
+			| Error::OutOfMemory { source, .. }
+			| Error::ReloadFailed { source, .. } => Some(source),
+			_ => None,
+		}
+	}
+
+	fn kind(&self) -> io::ErrorKind {
+		match self {
+			Error::MissingCapability | Error::ModulesDisabled => io::ErrorKind::PermissionDenied,
+			Error::InvalidModuleFormat(_)
+			| Error::InvalidIndex(_)
+			| Error::WrongArchitecture { .. }
+			| Error::IntegrityMismatch { .. }
+			| Error::AliasLoop(_)
+			| Error::RecursionLimit { .. }
+			| Error::VersionMismatch { .. }
+			| Error::SymbolVersionMismatch { .. }
+			| Error::AmbiguousModule { .. }
+			| Error::ManifestParse { .. } => io::ErrorKind::InvalidData,
+			Error::Livepatch(_)
+			| Error::ProprietaryModule { .. }
+			| Error::PolicyDenied { .. }
+			| Error::OutsideModuleTree(_)
+			| Error::SymlinkRejected(_)
+			| Error::DisabledByConfig(_)
+			| Error::SignatureRequired { .. }
+			| Error::SignatureRejected(_) => io::ErrorKind::PermissionDenied,
+			Error::AlreadyLoaded(_) => io::ErrorKind::AlreadyExists,
+			Error::ModuleNotFound { .. } => io::ErrorKind::NotFound,
+			Error::OutOfMemory { .. } | Error::DecompressionLimit { .. } => {
+				io::ErrorKind::OutOfMemory
+			}
+			Error::Timeout(_) => io::ErrorKind::TimedOut,
+			Error::Unsupported(_) => io::ErrorKind::Unsupported,
+			Error::ModuleBusy { .. } => io::ErrorKind::ResourceBusy,
+			Error::Cancelled => io::ErrorKind::Interrupted,
+			Error::InvalidKernelVersion(_)
+			| Error::InvalidModuleName(_)
+			| Error::UnknownParameter { .. }
+			| Error::InvalidParameterValue { .. } => io::ErrorKind::InvalidInput,
+			Error::KernelLog { source, .. }
+			| Error::DependencyFailed { source, .. }
+			| Error::ReloadFailed { source, .. } => source.kind(),
+		}
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::MissingCapability => write!(f, "Missing CAP_SYS_MODULE capability"),
+			Error::ModulesDisabled => {
+				write!(f, "Module loading is disabled by kernel.modules_disabled")
+			}
+			Error::InvalidModuleFormat(reason) => write!(f, "Invalid module format: {}", reason),
+			Error::InvalidIndex(reason) => write!(f, "Invalid module index: {}", reason),
+			Error::WrongArchitecture { expected, found } => write!(
+				f,
+				"Module is built for {}, but running kernel is {}",
+				found, expected
+			),
+			Error::IntegrityMismatch { expected, found } => write!(
+				f,
+				"Module image digest {} doesn't match expected {}",
+				found, expected
+			),
+			Error::ProprietaryModule { module, license } => write!(
+				f,
+				"Module {} has license {} not compatible with GPL",
+				module,
+				license.as_deref().unwrap_or("(none)")
+			),
+			Error::PolicyDenied { module, reason } => {
+				write!(f, "Loading module {} denied by policy: {}", module, reason)
+			}
+			Error::Livepatch(name) => write!(
+				f,
+				"Module {} is a livepatch, unloading it can destabilize the system",
+				name
+			),
+			Error::Timeout(name) => write!(f, "Operation on module {} timed out", name),
+			Error::ModuleBusy {
+				module,
+				refcount,
+				holders,
+			} => {
+				write!(f, "Module {} is in use", module)?;
+				if let Some(refcount) = refcount {
+					write!(f, " (refcount {})", refcount)?;
+				}
+				if !holders.is_empty() {
+					write!(f, ", held by {}", holders.join(", "))?;
+				}
+				Ok(())
+			}
+			Error::Cancelled => write!(f, "Operation has been cancelled"),
+			Error::InvalidKernelVersion(release) => {
+				write!(f, "Invalid kernel version: {}", release)
+			}
+			Error::InvalidModuleName(name) => write!(f, "Invalid module name: {:?}", name),
+			Error::UnknownParameter { module, parameter } => {
+				write!(f, "Module {} doesn't have parameter {}", module, parameter)
+			}
+			Error::InvalidParameterValue {
+				module,
+				parameter,
+				value,
+				expected,
+			} => write!(
+				f,
+				"Invalid value {:?} of parameter {} of module {}, expected {}",
+				value, parameter, module, expected
+			),
+			Error::KernelLog {
+				module,
+				messages,
+				source,
+			} => write!(
+				f,
+				"Failed to load module {}: {} (kernel log: {})",
+				module,
+				source,
+				messages.join("; ")
+			),
+			Error::DependencyFailed { chain, source } => {
+				write!(f, "{}: {}", chain.join(" -> "), source)
+			}
+			Error::AliasLoop(chain) => write!(f, "Alias loop: {}", chain.join(" -> ")),
+			Error::RecursionLimit { module, chain } => write!(
+				f,
+				"Commands for module {} recurse too deep: {}",
+				module,
+				chain.join(" -> ")
+			),
+			Error::OutsideModuleTree(path) => {
+				write!(f, "Path {} leads outside of module directory", path)
+			}
+			Error::SymlinkRejected(path) => {
+				write!(f, "Module file {} is a symbolic link", path)
+			}
+			Error::DisabledByConfig(name) => {
+				write!(
+					f,
+					"Module {} is disabled by install command in modprobe.d",
+					name
+				)
+			}
+			Error::VersionMismatch {
+				module,
+				vermagic,
+				kernel,
+			} => match vermagic {
+				Some(vermagic) => write!(
+					f,
+					"Module {} is built for {}, but running kernel is {}",
+					module, vermagic, kernel
+				),
+				None => write!(
+					f,
+					"Module {} doesn't match version of running kernel {}",
+					module, kernel
+				),
+			},
+			Error::SymbolVersionMismatch { module, symbols } => {
+				let symbols: Vec<String> = symbols
+					.iter()
+					.map(|mismatch| {
+						format!(
+							"{} ({:#010x}, kernel has {:#010x})",
+							mismatch.symbol, mismatch.module, mismatch.kernel
+						)
+					})
+					.collect();
+				write!(
+					f,
+					"Module {} disagrees about versions of symbols: {}",
+					module,
+					symbols.join(", ")
+				)
+			}
+			Error::OutOfMemory {
+				module,
+				image_size,
+				modules_size,
+				vmalloc_total,
+				vmalloc_used,
+				available,
+				source,
+			} => {
+				let kib = |size: &Option<u64>| match size {
+					Some(size) => format!("{} KiB", size / 1024),
+					None => "unknown".to_string(),
+				};
+				write!(
+					f,
+					"Failed to allocate memory for module {} ({} KiB): {} (loaded modules use {} KiB, vmalloc used {} of {}, available memory {})",
+					module,
+					image_size / 1024,
+					source,
+					modules_size / 1024,
+					kib(vmalloc_used),
+					kib(vmalloc_total),
+					kib(available)
+				)
+			}
+			Error::AmbiguousModule { module, paths } => write!(
+				f,
+				"Module {} is provided by equally preferred files: {}",
+				module,
+				paths.join(", ")
+			),
+			Error::DecompressionLimit { module, limit } => write!(
+				f,
+				"Module {} exceeds decompression limit of {} bytes",
+				module, limit
+			),
+			Error::ModuleNotFound {
+				module,
+				kernel,
+				suggestions,
+			} => match suggestions.is_empty() {
+				true => write!(f, "Module {} is not provided by {} kernel", module, kernel),
+				false => write!(
+					f,
+					"Module {} is not provided by {} kernel, did you mean {}?",
+					module,
+					kernel,
+					suggestions.join(", ")
+				),
+			},
+			Error::AlreadyLoaded(name) => write!(f, "Module {} is already loaded", name),
+			Error::SignatureRequired {
+				module,
+				signer: Some(signer),
+			} => write!(
+				f,
+				"Module {} is signed by {}, whose key isn't trusted by kernel",
+				module, signer
+			),
+			Error::SignatureRequired {
+				module,
+				signer: None,
+			} => write!(f, "Module {} isn't signed by a trusted key", module),
+			Error::SignatureRejected(name) => {
+				write!(f, "Signature of module {} has been rejected", name)
+			}
+			Error::ManifestParse {
+				file,
+				line,
+				column,
+				reason,
+			} => write!(f, "{}:{}:{}: {}", file, line, column, reason),
+			Error::ReloadFailed {
+				module,
+				restored,
+				source,
+			} => match restored {
+				true => write!(
+					f,
+					"Failed to reload module {}, old module restored: {}",
+					module, source
+				),
+				false => write!(f, "Failed to reload module {}: {}", module, source),
+			},
+			Error::Unsupported(system) => {
+				write!(f, "Kernel modules are not supported on {}", system)
+			}
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::KernelLog { source, .. }
+			| Error::DependencyFailed { source, .. }
+			| Error::OutOfMemory { source, .. }
+			| Error::ReloadFailed { source, .. } => Some(source),
+			_ => None,
+		}
+	}
+}
+
+/// Symbol whose version in module differs from version in kernel
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CrcMismatch {
+	/// Name of symbol
+	pub symbol: String,
+
+	/// CRC module has been built with
+	pub module: u32,
+
+	/// CRC of symbol exported by kernel or its modules
+	pub kernel: u32,
+}
+
+/// Failure of module syscall, explained by `Error` unless error number isn't known by library
+#[derive(Debug)]
+struct SyscallError {
+	syscall: &'static str,
+	errno: i32,
+	error: Option<Error>,
+
+	/// Message of explanation copied from failure of another thread, which can't be cloned
+	message: Option<String>,
+}
+
+impl fmt::Display for SyscallError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match (&self.error, &self.message) {
+			(Some(error), _) => error.fmt(f),
+			(None, Some(message)) => f.write_str(message),
+			(None, None) => io::Error::from_raw_os_error(self.errno).fmt(f),
+		}
+	}
+}
+
+impl std::error::Error for SyscallError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		self.error.as_ref()?.source()
+	}
+}
+
+impl From<Error> for io::Error {
+	fn from(error: Error) -> io::Error {
+		io::Error::new(error.kind(), error)
+	}
+}
+
+/// Serializes `io::Error` as its OS error code and message
+#[cfg(feature = "serde")]
+mod serde_io {
+	use serde::{Deserialize, Deserializer, Serialize, Serializer};
+	use std::io;
+
+	#[derive(Serialize, Deserialize)]
+	struct IoError {
+		errno: Option<i32>,
+		message: String,
+	}
+
+	pub fn serialize<S: Serializer>(error: &io::Error, serializer: S) -> Result<S::Ok, S::Error> {
+		IoError {
+			errno: error.raw_os_error(),
+			message: error.to_string(),
+		}
+		.serialize(serializer)
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<io::Error, D::Error> {
+		let error = IoError::deserialize(deserializer)?;
+		Ok(match error.errno {
+			Some(errno) => io::Error::from_raw_os_error(errno),
+			None => io::Error::other(error.message),
+		})
+	}
+}
@@ -0,0 +1,111 @@
+use std::{
+	ffi::OsStr,
+	io,
+	path::{Path, PathBuf},
+};
+
+use crate::{index, modinfo, vfs, Selection};
+
+/// Custom firmware directory set by `firmware_class.path` kernel parameter
+const FIRMWARE_CLASS_PATH: &str = "/sys/module/firmware_class/parameters/path";
+
+/// Extensions of compressed firmware files loaded by kernel
+const COMPRESSED: [&str; 2] = [".xz", ".zst"];
+
+/// Firmware file referenced by module
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Firmware {
+	/// Name of firmware, as declared by module (e.g. `iwlwifi-cc-a0-77.ucode`)
+	pub name: String,
+
+	/// Path of firmware file, `None` if firmware is missing
+	pub path: Option<String>,
+}
+
+/// Looks for firmware file in firmware directories used by selected kernel
+///
+/// Directories are searched in the same order as kernel does: custom path (`firmware_class.path`),
+/// `/lib/firmware/updates/<release>`, `/lib/firmware/updates`, `/lib/firmware/<release>` and
+/// `/lib/firmware`. Compressed variants (`.xz` and `.zst`) are found too.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::find_firmware("regulatory.db", liblmod::Selection::Current) {
+///     Ok(Some(path)) => println!("regulatory.db is {path}"),
+///     Ok(None) => println!("regulatory.db is missing"),
+///     Err(e) => eprintln!("Failed to look for firmware: {e}"),
+/// }
+/// ```
+pub fn find_firmware(name: impl AsRef<str>, kernel: Selection) -> io::Result<Option<String>> {
+	let release = index::release(kernel)?;
+	Ok(search(name.as_ref(), &directories(&release)))
+}
+
+/// Returns firmware files referenced by module with their paths
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::module_firmware("iwlwifi", liblmod::Selection::Current) {
+///     Ok(firmware) => {
+///         for missing in firmware.iter().filter(|f| f.path.is_none()) {
+///             eprintln!("Missing firmware {}", missing.name);
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to check firmware of iwlwifi: {e}"),
+/// }
+/// ```
+pub fn module_firmware(name: impl AsRef<str>, kernel: Selection) -> io::Result<Vec<Firmware>> {
+	let release = index::release(kernel.clone())?;
+	let info = modinfo::modinfo(name, kernel)?;
+	let directories = directories(&release);
+
+	Ok(info
+		.firmware
+		.into_iter()
+		.map(|name| Firmware {
+			path: search(&name, &directories),
+			name,
+		})
+		.collect())
+}
+
+/// Returns firmware directories in order of searching
+fn directories(release: &OsStr) -> Vec<PathBuf> {
+	let mut directories = Vec::new();
+	if let Ok(path) = vfs::read_to_string(FIRMWARE_CLASS_PATH) {
+		let path = path.trim();
+		if !path.is_empty() {
+			directories.push(PathBuf::from(path));
+		}
+	}
+
+	directories.extend([
+		Path::new("/lib/firmware/updates").join(release),
+		PathBuf::from("/lib/firmware/updates"),
+		Path::new("/lib/firmware").join(release),
+		PathBuf::from("/lib/firmware"),
+	]);
+
+	directories
+}
+
+fn search(name: &str, directories: &[PathBuf]) -> Option<String> {
+	for directory in directories {
+		let path = directory.join(name);
+		for extension in [""].iter().chain(COMPRESSED.iter()) {
+			let mut candidate = path.clone().into_os_string();
+			candidate.push(extension);
+			if vfs::is_file(&candidate) {
+				// Non-UTF-8 bytes are replaced only in path reported to caller
+				return Some(candidate.to_string_lossy().into_owned());
+			}
+		}
+	}
+
+	None
+}
@@ -0,0 +1,163 @@
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap},
+	fmt::Write,
+};
+
+/// Module dependency graph
+///
+/// Edges point from module to its direct dependencies. Graph of loaded modules returned by
+/// `holder_graph` has edges from modules to modules they use.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::ModuleIndex::open(liblmod::Selection::Current) {
+///     Ok(index) => {
+///         if let Some(graph) = index.dependency_graph("kvm_intel") {
+///             println!("{}", graph.to_dot());
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to open module index: {e}"),
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DependencyGraph {
+	edges: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl DependencyGraph {
+	/// Adds module to graph
+	pub(crate) fn add_node(&mut self, name: String) {
+		self.edges.entry(name).or_default();
+	}
+
+	/// Adds dependency edge to graph
+	pub(crate) fn add_edge(&mut self, from: String, to: String) {
+		self.add_node(to.clone());
+		self.edges.entry(from).or_default().insert(to);
+	}
+
+	/// Returns names of all modules in graph
+	pub fn nodes(&self) -> impl Iterator<Item = &str> {
+		self.edges.keys().map(|name| name.as_str())
+	}
+
+	/// Returns direct dependencies of module
+	pub fn dependencies(&self, name: &str) -> impl Iterator<Item = &str> {
+		self.edges
+			.get(name)
+			.into_iter()
+			.flatten()
+			.map(|dep| dep.as_str())
+	}
+
+	/// Returns modules directly depending on module
+	pub fn dependents<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+		self.edges
+			.iter()
+			.filter(move |(_, deps)| deps.contains(name))
+			.map(|(from, _)| from.as_str())
+	}
+
+	/// Returns modules no other module depends on (e.g. unused loaded modules)
+	pub fn roots(&self) -> impl Iterator<Item = &str> {
+		let used: BTreeSet<&String> = self.edges.values().flatten().collect();
+		self.edges
+			.keys()
+			.filter(move |name| !used.contains(name))
+			.map(|name| name.as_str())
+	}
+
+	/// Returns modules without dependencies
+	pub fn leaves(&self) -> impl Iterator<Item = &str> {
+		self.edges
+			.iter()
+			.filter(|(_, deps)| deps.is_empty())
+			.map(|(name, _)| name.as_str())
+	}
+
+	/// Returns groups of modules depending on each other, each sorted by name
+	///
+	/// Dependency cycles are rejected by depmod, so they are expected only in graphs of
+	/// loaded modules (e.g. holders left by forced loading).
+	pub fn cycles(&self) -> Vec<Vec<String>> {
+		// Tarjan's algorithm, strongly connected components are cycles
+		struct State<'a> {
+			graph: &'a DependencyGraph,
+			index: HashMap<&'a str, (usize, usize)>,
+			stack: Vec<&'a str>,
+			cycles: Vec<Vec<String>>,
+		}
+
+		fn visit<'a>(state: &mut State<'a>, name: &'a str) {
+			let position = state.index.len();
+			state.index.insert(name, (position, position));
+			state.stack.push(name);
+
+			for dep in state.graph.dependencies(name) {
+				let low = match state.index.get(dep) {
+					None => {
+						visit(state, dep);
+						state.index[dep].1
+					}
+					Some(&(index, _)) if state.stack.contains(&dep) => index,
+					Some(_) => continue,
+				};
+				let entry = state.index.get_mut(name).unwrap();
+				entry.1 = entry.1.min(low);
+			}
+
+			let (index, low) = state.index[name];
+			if index != low {
+				return;
+			}
+
+			let start = state.stack.iter().rposition(|m| *m == name).unwrap();
+			let mut component: Vec<String> =
+				state.stack.drain(start..).map(|m| m.to_string()).collect();
+			if component.len() > 1 || state.graph.dependencies(name).any(|dep| dep == name) {
+				component.sort();
+				state.cycles.push(component);
+			}
+		}
+
+		let mut state = State {
+			graph: self,
+			index: HashMap::new(),
+			stack: Vec::new(),
+			cycles: Vec::new(),
+		};
+		for name in self.nodes() {
+			if !state.index.contains_key(name) {
+				visit(&mut state, name);
+			}
+		}
+
+		state.cycles
+	}
+
+	/// Returns all edges of graph as (module, dependency) pairs
+	pub fn edges(&self) -> impl Iterator<Item = (&str, &str)> {
+		self.edges
+			.iter()
+			.flat_map(|(from, deps)| deps.iter().map(move |to| (from.as_str(), to.as_str())))
+	}
+
+	/// Renders graph in Graphviz DOT format
+	pub fn to_dot(&self) -> String {
+		let mut dot = String::from("digraph modules {\n");
+		for (name, deps) in &self.edges {
+			if deps.is_empty() {
+				let _ = writeln!(dot, "\t\"{}\";", name);
+			}
+			for dep in deps {
+				let _ = writeln!(dot, "\t\"{}\" -> \"{}\";", name, dep);
+			}
+		}
+		dot.push_str("}\n");
+
+		dot
+	}
+}
@@ -0,0 +1,90 @@
+use std::{
+	fs::{File, OpenOptions},
+	io::{self, ErrorKind},
+	os::unix::fs::OpenOptionsExt,
+	path::{Path, PathBuf},
+};
+
+use crate::{index, vfs, ModuleIndex, Selection};
+
+/// Module kept in use by open device node, so it cannot be unloaded until guard is dropped
+///
+/// Device node provided by module (e.g. `/dev/kvm` or `/dev/fuse`) holds reference to the
+/// module while it's open. Unloading it (also by other processes) fails with module being in
+/// use, unless forced. Node has to be provided by module itself, nodes of other modules or
+/// sysfs files don't keep module in use.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::ModuleGuard::acquire("kvm") {
+///     Ok(guard) => {
+///         println!("Module kvm is pinned by {}", guard.device().display());
+///         // kvm can't be unloaded until guard is dropped
+///         drop(guard);
+///     }
+///     Err(e) => eprintln!("Failed to pin module kvm: {e}"),
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ModuleGuard {
+	name: String,
+	device: PathBuf,
+	_file: File,
+}
+
+impl ModuleGuard {
+	/// Keeps module in use through device node listed for it in modules.devname of current kernel
+	///
+	/// Module which is not loaded yet is loaded by kernel when node is opened.
+	pub fn acquire(name: impl AsRef<str>) -> io::Result<ModuleGuard> {
+		let name = index::normalize(name.as_ref());
+		let index = ModuleIndex::shared(Selection::Current)?;
+		match index.device_names(&name).first() {
+			Some(devname) => ModuleGuard::with_device(&name, Path::new("/dev").join(devname)),
+			None => Err(io::Error::new(
+				ErrorKind::NotFound,
+				format!("Module {} has no device node in modules.devname", name),
+			)),
+		}
+	}
+
+	/// Keeps module in use through device node provided by it
+	pub fn with_device(name: impl AsRef<str>, device: impl AsRef<Path>) -> io::Result<ModuleGuard> {
+		let name = index::normalize(name.as_ref());
+		let device = device.as_ref();
+
+		// Opening must not wait for device (e.g. for carrier of serial line)
+		let file = OpenOptions::new()
+			.read(true)
+			.custom_flags(libc::O_NONBLOCK)
+			.open(device)?;
+		if !vfs::is_dir(format!("/sys/module/{}", name)) {
+			return Err(io::Error::new(
+				ErrorKind::NotFound,
+				format!(
+					"Module {} is not loaded after opening {}",
+					name,
+					device.display()
+				),
+			));
+		}
+
+		Ok(ModuleGuard {
+			name,
+			device: device.to_path_buf(),
+			_file: file,
+		})
+	}
+
+	/// Returns name of module kept in use
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	/// Returns path of device node keeping module in use
+	pub fn device(&self) -> &Path {
+		&self.device
+	}
+}
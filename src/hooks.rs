@@ -0,0 +1,152 @@
+use std::{
+	io,
+	sync::{Arc, RwLock},
+};
+
+/// Process-wide hooks run around module operations
+static HOOKS: RwLock<Vec<Arc<dyn ModuleHook>>> = RwLock::new(Vec::new());
+
+/// Module operation reported to hooks
+#[derive(Debug)]
+pub enum HookEvent<'a> {
+	/// Module is going to be inserted into kernel
+	BeforeLoad {
+		/// Name of module (path for compressed modules)
+		module: &'a str,
+
+		/// Parameters of module
+		params: &'a str,
+	},
+
+	/// Module insertion has finished
+	AfterLoad {
+		/// Name of module (path for compressed modules)
+		module: &'a str,
+
+		/// Parameters of module
+		params: &'a str,
+
+		/// Result of insertion
+		result: &'a io::Result<()>,
+	},
+
+	/// Module is going to be removed from kernel
+	BeforeUnload {
+		/// Name of module
+		module: &'a str,
+	},
+
+	/// Module removal has finished
+	AfterUnload {
+		/// Name of module
+		module: &'a str,
+
+		/// Result of removal
+		result: &'a io::Result<()>,
+	},
+}
+
+/// Hook run before and after every module insertion and removal
+///
+/// Hooks are implemented for closures taking `&HookEvent`.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// liblmod::add_hook(|event: &liblmod::HookEvent| {
+///     if let liblmod::HookEvent::AfterLoad { module, result: Ok(()), .. } = event {
+///         println!("Module {module} has been loaded");
+///     }
+/// });
+/// ```
+pub trait ModuleHook: Send + Sync {
+	/// Handles module operation
+	fn handle(&self, event: &HookEvent);
+}
+
+impl<F: Fn(&HookEvent) + Send + Sync> ModuleHook for F {
+	fn handle(&self, event: &HookEvent) {
+		self(event)
+	}
+}
+
+/// Registers process-wide hook, hooks are run in order of registration
+pub fn add_hook(hook: impl ModuleHook + 'static) {
+	HOOKS
+		.write()
+		.unwrap_or_else(|e| e.into_inner())
+		.push(Arc::new(hook));
+}
+
+/// Removes all registered hooks
+pub fn clear_hooks() {
+	HOOKS.write().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Returns registered hooks
+fn hooks() -> Vec<Arc<dyn ModuleHook>> {
+	HOOKS.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Runs hooks with event
+fn run(hooks: &[Arc<dyn ModuleHook>], event: HookEvent) {
+	for hook in hooks {
+		hook.handle(&event);
+	}
+}
+
+/// Runs insertion of module between load hooks, name of module is determined only if needed
+pub(crate) fn around_load(
+	module: impl FnOnce() -> String,
+	params: &str,
+	load: impl FnOnce() -> io::Result<()>,
+) -> io::Result<()> {
+	let hooks = hooks();
+	if hooks.is_empty() {
+		return load();
+	}
+
+	let module = module();
+	run(
+		&hooks,
+		HookEvent::BeforeLoad {
+			module: &module,
+			params,
+		},
+	);
+	let result = load();
+	run(
+		&hooks,
+		HookEvent::AfterLoad {
+			module: &module,
+			params,
+			result: &result,
+		},
+	);
+
+	result
+}
+
+/// Runs removal of module between unload hooks
+pub(crate) fn around_unload(
+	module: &str,
+	unload: impl FnOnce() -> io::Result<()>,
+) -> io::Result<()> {
+	let hooks = hooks();
+	if hooks.is_empty() {
+		return unload();
+	}
+
+	run(&hooks, HookEvent::BeforeUnload { module });
+	let result = unload();
+	run(
+		&hooks,
+		HookEvent::AfterUnload {
+			module,
+			result: &result,
+		},
+	);
+
+	result
+}
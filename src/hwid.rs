@@ -0,0 +1,209 @@
+use std::io;
+
+use crate::{ModuleIndex, Selection};
+
+/// Identification of PCI device
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let device = liblmod::PciDevice::new(0x8086, 0x10d3).class(0x020000);
+/// assert_eq!(device.modalias(), "pci:v00008086d000010D3svFFFFFFFFsdFFFFFFFFbc02sc00i00");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PciDevice {
+	/// Vendor ID
+	pub vendor: u32,
+
+	/// Device ID
+	pub device: u32,
+
+	/// Subsystem vendor ID
+	pub subvendor: u32,
+
+	/// Subsystem device ID
+	pub subdevice: u32,
+
+	/// Class code (base class, subclass and programming interface, e.g. `0x020000`)
+	pub class: u32,
+}
+
+impl PciDevice {
+	/// Creates device identification without subsystem (`PCI_ANY_ID`) and class
+	pub fn new(vendor: u32, device: u32) -> PciDevice {
+		PciDevice {
+			vendor,
+			device,
+			subvendor: 0xffffffff,
+			subdevice: 0xffffffff,
+			class: 0,
+		}
+	}
+
+	/// Sets subsystem vendor and device ID
+	pub fn subsystem(mut self, subvendor: u32, subdevice: u32) -> PciDevice {
+		self.subvendor = subvendor;
+		self.subdevice = subdevice;
+		self
+	}
+
+	/// Sets class code
+	pub fn class(mut self, class: u32) -> PciDevice {
+		self.class = class;
+		self
+	}
+
+	/// Returns modalias of device, as reported by kernel in `/sys/bus/pci/devices/*/modalias`
+	pub fn modalias(&self) -> String {
+		format!(
+			"pci:v{:08X}d{:08X}sv{:08X}sd{:08X}bc{:02X}sc{:02X}i{:02X}",
+			self.vendor,
+			self.device,
+			self.subvendor,
+			self.subdevice,
+			(self.class >> 16) & 0xff,
+			(self.class >> 8) & 0xff,
+			self.class & 0xff
+		)
+	}
+}
+
+/// Identification of USB device interface
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let device = liblmod::UsbDevice::new(0x0bda, 0x8153).interface_class(0xff, 0xff, 0x00);
+/// assert_eq!(device.modalias(), "usb:v0BDAp8153d0000dc00dsc00dp00icFFiscFFip00in00");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UsbDevice {
+	/// Vendor ID
+	pub vendor: u16,
+
+	/// Product ID
+	pub product: u16,
+
+	/// Device release number (bcdDevice)
+	pub release: u16,
+
+	/// Device class
+	pub device_class: u8,
+
+	/// Device subclass
+	pub device_subclass: u8,
+
+	/// Device protocol
+	pub device_protocol: u8,
+
+	/// Interface class
+	pub interface_class: u8,
+
+	/// Interface subclass
+	pub interface_subclass: u8,
+
+	/// Interface protocol
+	pub interface_protocol: u8,
+
+	/// Interface number
+	pub interface_number: u8,
+}
+
+impl UsbDevice {
+	/// Creates device identification with zeroed release, classes and interface number
+	pub fn new(vendor: u16, product: u16) -> UsbDevice {
+		UsbDevice {
+			vendor,
+			product,
+			release: 0,
+			device_class: 0,
+			device_subclass: 0,
+			device_protocol: 0,
+			interface_class: 0,
+			interface_subclass: 0,
+			interface_protocol: 0,
+			interface_number: 0,
+		}
+	}
+
+	/// Sets device release number (bcdDevice)
+	pub fn release(mut self, release: u16) -> UsbDevice {
+		self.release = release;
+		self
+	}
+
+	/// Sets device class, subclass and protocol
+	pub fn device_class(mut self, class: u8, subclass: u8, protocol: u8) -> UsbDevice {
+		self.device_class = class;
+		self.device_subclass = subclass;
+		self.device_protocol = protocol;
+		self
+	}
+
+	/// Sets interface class, subclass and protocol
+	pub fn interface_class(mut self, class: u8, subclass: u8, protocol: u8) -> UsbDevice {
+		self.interface_class = class;
+		self.interface_subclass = subclass;
+		self.interface_protocol = protocol;
+		self
+	}
+
+	/// Sets interface number
+	pub fn interface_number(mut self, number: u8) -> UsbDevice {
+		self.interface_number = number;
+		self
+	}
+
+	/// Returns modalias of device interface, as reported by kernel in `/sys/bus/usb/devices/*/modalias`
+	pub fn modalias(&self) -> String {
+		format!(
+			"usb:v{:04X}p{:04X}d{:04X}dc{:02X}dsc{:02X}dp{:02X}ic{:02X}isc{:02X}ip{:02X}in{:02X}",
+			self.vendor,
+			self.product,
+			self.release,
+			self.device_class,
+			self.device_subclass,
+			self.device_protocol,
+			self.interface_class,
+			self.interface_subclass,
+			self.interface_protocol,
+			self.interface_number
+		)
+	}
+}
+
+/// Returns names of modules supporting PCI device, as listed in modules.alias
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let device = liblmod::PciDevice::new(0x8086, 0x10d3).class(0x020000);
+/// match liblmod::module_for_pci(&device, liblmod::Selection::Current) {
+///     Ok(modules) => println!("Drivers for {}: {:?}", device.modalias(), modules),
+///     Err(e) => eprintln!("Failed to read module index: {e}"),
+/// }
+/// ```
+pub fn module_for_pci(device: &PciDevice, kernel: Selection) -> io::Result<Vec<String>> {
+	Ok(ModuleIndex::shared(kernel)?.resolve_alias(&device.modalias()))
+}
+
+/// Returns names of modules supporting USB device interface, as listed in modules.alias
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let device = liblmod::UsbDevice::new(0x0bda, 0x8153).interface_class(0xff, 0xff, 0x00);
+/// match liblmod::module_for_usb(&device, liblmod::Selection::Current) {
+///     Ok(modules) => println!("Drivers for {}: {:?}", device.modalias(), modules),
+///     Err(e) => eprintln!("Failed to read module index: {e}"),
+/// }
+/// ```
+pub fn module_for_usb(device: &UsbDevice, kernel: Selection) -> io::Result<Vec<String>> {
+	Ok(ModuleIndex::shared(kernel)?.resolve_alias(&device.modalias()))
+}
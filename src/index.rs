@@ -0,0 +1,1757 @@
+use std::{
+	collections::{BTreeMap, HashMap, HashSet},
+	ffi::{CString, OsStr, OsString},
+	fs,
+	io::{self, BufRead, BufReader, ErrorKind, Read},
+	path::{Component, Path, PathBuf},
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc, Mutex, OnceLock, RwLock,
+	},
+	time::SystemTime,
+};
+
+/// Process-wide indices shared by modprobe calls, keyed by kernel release
+static SHARED: OnceLock<RwLock<HashMap<OsString, Arc<ModuleIndex>>>> = OnceLock::new();
+
+/// Whether shared indices are re-read after depmod regenerates them
+static AUTO_REFRESH: AtomicBool = AtomicBool::new(true);
+
+/// Directories containing module directories of kernels, from the most preferred one
+static BASE_DIRS: RwLock<Vec<PathBuf>> = RwLock::new(Vec::new());
+
+/// Whether module files and manifests are looked up in all base directories (`set_module_roots`)
+static LAYERED: AtomicBool = AtomicBool::new(false);
+
+/// Module directories of Android-derived systems, from the most preferred one
+const ANDROID_DIRS: [&str; 3] = [
+	"/odm/lib/modules",
+	"/vendor_dlkm/lib/modules",
+	"/vendor/lib/modules",
+];
+
+/// Base directories used unless configured otherwise (merged-/usr systems may have only the latter)
+const DEFAULT_BASE_DIRS: [&str; 2] = ["/lib/modules", "/usr/lib/modules"];
+
+#[cfg(feature = "alias")]
+use crate::{binindex::BinIndex, AliasMatcher};
+use crate::{
+	compress,
+	config::Depmod,
+	kernel, manifest, resolver,
+	vfs::{self, Vfs},
+	DependencyGraph, KernelVersion, LoadPlan, MemoryFs, ModuleInfo, Selection, StaticNode,
+};
+
+/// Parsed modules manifests (modules.order, modules.dep, modules.weakdep and modules.builtin) of a kernel
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::ModuleIndex::open(liblmod::Selection::Current) {
+///     Ok(index) => println!("kvm is provided by {:?}", index.resolve("kvm")),
+///     Err(e) => eprintln!("Failed to open module index: {e}"),
+/// }
+/// ```
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModuleIndex {
+	kernel: String,
+	basepath: PathBuf,
+	modules: HashMap<String, String>,
+	deps: HashMap<String, Vec<String>>,
+	weakdeps: HashMap<String, Vec<String>>,
+	builtin: HashSet<String>,
+	order: HashMap<String, usize>,
+	#[cfg_attr(feature = "serde", serde(default))]
+	duplicates: HashMap<String, Vec<(usize, String)>>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	symbols: OnceLock<HashMap<String, String>>,
+	#[cfg(feature = "alias")]
+	#[cfg_attr(feature = "serde", serde(skip))]
+	aliases: OnceLock<Option<BinIndex>>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	depends: OnceLock<HashMap<String, Vec<String>>>,
+	#[cfg(feature = "alias")]
+	#[cfg_attr(feature = "serde", serde(skip))]
+	modaliases: OnceLock<Vec<(String, String)>>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	modified: Option<SystemTime>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	manifests: Option<Arc<MemoryFs>>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	paths: HashMap<String, PathBuf>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	plans: Mutex<HashMap<PlanKey, Arc<LoadPlan>>>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	layers: Vec<PathBuf>,
+}
+
+/// Requested module name, parameters and fingerprint of options load plan is cached for
+type PlanKey = (String, String, u64);
+
+impl ModuleIndex {
+	/// Reads modules manifests of selected or current running kernel
+	///
+	/// Malformed or truncated modules.order and modules.dep are rejected with
+	/// `Error::ManifestParse`, pointing at the broken entry. Entries with absolute paths or `..`
+	/// components, which could point outside of module directory, are rejected with
+	/// `Error::OutsideModuleTree`.
+	///
+	/// If module directory of kernel exists, but depmod hasn't been run for it yet (e.g. kernel
+	/// prepared by image build), index is generated from module files found in it. Their
+	/// dependencies, aliases and exported symbols are read from the files when needed.
+	/// Compressed files are read only with `compression` feature. Nothing is written to module
+	/// directory.
+	///
+	/// Trees shipping modules.dep without modules.order are supported as well, modules are then
+	/// resolved from modules.dep and module files in directory, without position in load order.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// liblmod::set_filesystem(
+	///     liblmod::MemoryFs::new()
+	///         .file("/lib/modules/6.1.0/modules.order", "kernel/arch/x86/kvm/kvm.ko\n")
+	///         .file("/lib/modules/6.1.0/modules.dep", "kernel/arch/x86/kvm/kvm.ko:\nkernel/arch/x86/kv"),
+	/// );
+	///
+	/// match liblmod::ModuleIndex::open(liblmod::Selection::Other("6.1.0".to_string())) {
+	///     Err(e) => assert!(matches!(
+	///         liblmod::Error::from_io(&e),
+	///         Some(liblmod::Error::ManifestParse { line: 2, .. })
+	///     )),
+	///     Ok(_) => panic!("truncated modules.dep has been accepted"),
+	/// }
+	///
+	/// liblmod::clear_filesystem();
+	/// ```
+	pub fn open(kernel: Selection) -> io::Result<ModuleIndex> {
+		ModuleIndex::open_release(release(kernel)?)
+	}
+
+	/// Reads modules manifests of kernel release, which doesn't have to be valid UTF-8
+	pub(crate) fn open_release(release: OsString) -> io::Result<ModuleIndex> {
+		let basepath = module_dir(&release);
+		let mut index = ModuleIndex::open_dir(release.clone(), basepath)?;
+		if LAYERED.load(Ordering::Relaxed) {
+			index.add_layers(&release)?;
+		}
+
+		Ok(index)
+	}
+
+	/// Adds modules of kernel release from module directories in all module roots
+	///
+	/// Modules not listed in manifests of index are read from manifests of other roots, earlier
+	/// roots taking precedence. Module files are looked up in every root by `path`.
+	fn add_layers(&mut self, release: &OsStr) -> io::Result<()> {
+		self.layers = base_dirs()
+			.iter()
+			.map(|dir| dir.join(release))
+			.filter(|dir| vfs::is_dir(dir))
+			.collect();
+
+		for layer in self.layers.clone() {
+			if layer == self.basepath || !vfs::exists(layer.join("modules.dep")) {
+				continue;
+			}
+
+			let other = ModuleIndex::open_dir(release.to_os_string(), layer)?;
+			for (name, module) in other.modules {
+				if self.modules.contains_key(&name) {
+					continue;
+				}
+				if let Some(deps) = other.deps.get(&module) {
+					self.deps.insert(module.clone(), deps.clone());
+				}
+				self.modules.insert(name, module);
+			}
+			self.builtin.extend(other.builtin);
+		}
+
+		Ok(())
+	}
+
+	/// Reads modules manifests of kernel release from module directory
+	pub(crate) fn open_dir(release: OsString, basepath: PathBuf) -> io::Result<ModuleIndex> {
+		ModuleIndex::open_paths(release, basepath, HashMap::new())
+	}
+
+	/// Reads modules manifests of kernel release from module directory, except for manifests
+	/// with paths given by file name
+	pub(crate) fn open_paths(
+		release: OsString,
+		basepath: PathBuf,
+		paths: HashMap<String, PathBuf>,
+	) -> io::Result<ModuleIndex> {
+		// Non-UTF-8 bytes are replaced only in release reported to caller
+		let kernelname = release.to_string_lossy().into_owned();
+
+		// Construct modules manifests paths
+		let modified = modification_time(&basepath);
+		let modulespath = manifest_path(&basepath, &paths, "modules.order");
+		let depspath = manifest_path(&basepath, &paths, "modules.dep");
+		let weakdepspath = manifest_path(&basepath, &paths, "modules.weakdep");
+		let builtinpath = manifest_path(&basepath, &paths, "modules.builtin");
+
+		// Read modules.dep and modules.order, without them find module files (depmod not run yet)
+		let (order, deps, found) = match vfs::exists(&depspath) || !vfs::is_dir(&basepath) {
+			true => {
+				let deps: HashMap<String, Vec<String>> =
+					manifest::read_dep(&depspath)?.into_iter().collect();
+
+				// Stripped-down trees may ship modules.dep without modules.order, module files
+				// not listed in modules.dep are found in module directory then
+				match manifest::read_order(&modulespath) {
+					Ok(order) => (order, deps, Vec::new()),
+					Err(e) if e.kind() == ErrorKind::NotFound => {
+						let found = find_tree_modules(&basepath)
+							.unwrap_or_default()
+							.into_iter()
+							.filter(|module| !deps.contains_key(module))
+							.collect();
+						(Vec::new(), deps, found)
+					}
+					Err(e) => return Err(e),
+				}
+			}
+			false => (Vec::new(), HashMap::new(), find_tree_modules(&basepath)?),
+		};
+
+		// Read modules.weakdep (generated only by kmod 33 and newer)
+		let mut weakdeps: HashMap<String, Vec<String>> = HashMap::new();
+		match vfs::open(&weakdepspath) {
+			Ok(fd) => {
+				for line in BufReader::new(fd).lines() {
+					let line = line?;
+					let mut words = line.split_whitespace();
+					if words.next() != Some("weakdep") {
+						continue;
+					}
+
+					if let Some(module) = words.next() {
+						weakdeps
+							.entry(normalize(module))
+							.or_default()
+							.extend(words.map(normalize));
+					}
+				}
+			}
+			Err(e) if e.kind() == ErrorKind::NotFound => (),
+			Err(e) => return Err(e),
+		}
+
+		// Read modules.builtin
+		let mut builtin = HashSet::new();
+		match vfs::open(&builtinpath) {
+			Ok(fd) => {
+				for line in BufReader::new(fd).lines() {
+					let line = line?;
+					if !line.is_empty() {
+						builtin.insert(module_name(&line));
+					}
+				}
+			}
+			Err(e) if e.kind() == ErrorKind::NotFound => (),
+			Err(e) => return Err(e),
+		}
+
+		// Choose preferred path of every module name, as configured in depmod.d
+		let depmod = Depmod::load()?;
+		let ordered: HashSet<&String> = order.iter().collect();
+		let mut others: Vec<&String> = deps
+			.keys()
+			.chain(&found)
+			.filter(|m| !ordered.contains(m))
+			.collect();
+		others.sort();
+		let mut candidates: HashMap<String, Vec<(usize, String)>> = HashMap::new();
+		for module in order.iter().chain(others) {
+			candidates
+				.entry(module_name(module))
+				.or_default()
+				.push((depmod.priority(&kernelname, module), module.clone()));
+		}
+
+		// Of modules with the same priority, the first one in modules.order (then by path) wins
+		let mut modules: HashMap<String, String> = HashMap::new();
+		let mut duplicates: HashMap<String, Vec<(usize, String)>> = HashMap::new();
+		for (name, mut list) in candidates {
+			list.sort_by_key(|(priority, _)| *priority);
+			modules.insert(name.clone(), list[0].1.clone());
+			if list.len() > 1 {
+				duplicates.insert(name, list);
+			}
+		}
+
+		// Remember position of modules in modules.order
+		let order = order
+			.into_iter()
+			.enumerate()
+			.map(|(position, module)| (module, position))
+			.collect();
+
+		Ok(ModuleIndex {
+			kernel: kernelname,
+			basepath,
+			modules,
+			deps,
+			weakdeps,
+			builtin,
+			order,
+			duplicates,
+			symbols: OnceLock::new(),
+			#[cfg(feature = "alias")]
+			aliases: OnceLock::new(),
+			depends: OnceLock::new(),
+			#[cfg(feature = "alias")]
+			modaliases: OnceLock::new(),
+			modified,
+			manifests: None,
+			paths,
+			plans: Mutex::new(HashMap::new()),
+			layers: Vec::new(),
+		})
+	}
+
+	/// Builds index from contents of modules manifests instead of reading module directory
+	///
+	/// Manifests are given by file name (e.g. `modules.dep`, `modules.order`, `modules.alias`,
+	/// `modules.builtin`, `modules.symbols` or `modules.devname`), modules.dep is required. They
+	/// are never read from filesystem, even when index looks them up later, so index can be
+	/// received over IPC or embedded into program. Module files themselves are still read from
+	/// `basepath`. depmod.d configuration is not read, so modules of the same name are preferred
+	/// only by modules.order.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// let index = liblmod::ModuleIndex::from_manifests(
+	///     "6.1.0",
+	///     "/lib/modules/6.1.0",
+	///     [
+	///         ("modules.dep", "kernel/kvm.ko:\nkernel/kvm-intel.ko: kernel/kvm.ko\n"),
+	///         ("modules.order", "kernel/kvm.ko\nkernel/kvm-intel.ko\n"),
+	///         ("modules.alias", "alias cpu:type:x86*feature:*0085* kvm_intel\n"),
+	///     ],
+	/// )
+	/// .unwrap();
+	/// assert_eq!(index.resolve("kvm_intel"), Some("kernel/kvm-intel.ko"));
+	/// # #[cfg(feature = "alias")]
+	/// assert_eq!(index.resolve_alias("cpu:type:x86,ven0000fam0006mod003A:feature:,0085,"), ["kvm_intel"]);
+	///
+	/// // Modules are loaded with the index instead of system one
+	/// let options = liblmod::Options::new().resolver(index);
+	/// let _ = liblmod::modprobe_with("kvm_intel", "", &options);
+	/// ```
+	pub fn from_manifests<'a>(
+		kernel: impl AsRef<str>,
+		basepath: impl AsRef<Path>,
+		manifests: impl IntoIterator<Item = (&'a str, &'a str)>,
+	) -> io::Result<ModuleIndex> {
+		let basepath = basepath.as_ref();
+		let fs = manifests
+			.into_iter()
+			.fold(MemoryFs::new(), |fs, (name, content)| {
+				fs.file(basepath.join(name), content)
+			});
+
+		let fs = Arc::new(fs);
+		let mut index = vfs::with_memory(fs.clone(), || {
+			ModuleIndex::open_dir(kernel.as_ref().into(), basepath.to_path_buf())
+		})?;
+		index.manifests = Some(fs);
+		Ok(index)
+	}
+
+	/// Reads modules manifests from explicit paths instead of module directory
+	///
+	/// Manifests are given by file name (e.g. `modules.dep`, `modules.order`, `modules.alias`,
+	/// `modules.alias.bin`, `modules.builtin`, `modules.symbols` or `modules.devname`) with their
+	/// paths. Manifests not given are read from `basepath`, which module paths listed in
+	/// modules.dep are relative to. This supports staged module sets of A/B systems and
+	/// layouts like `/boot/modules-<release>` without symbolic links.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// liblmod::set_filesystem(
+	///     liblmod::MemoryFs::new()
+	///         .file("/boot/modules-6.1.0/kernel/kvm.ko", "")
+	///         .file("/var/lib/slot-b/modules.dep", "kernel/kvm.ko:\n")
+	///         .file("/var/lib/slot-b/modules.order", "kernel/kvm.ko\n"),
+	/// );
+	///
+	/// let index = liblmod::ModuleIndex::from_manifest_paths(
+	///     "6.1.0",
+	///     "/boot/modules-6.1.0",
+	///     [
+	///         ("modules.dep", "/var/lib/slot-b/modules.dep"),
+	///         ("modules.order", "/var/lib/slot-b/modules.order"),
+	///     ],
+	/// )
+	/// .unwrap();
+	/// let module = index.resolve("kvm").unwrap();
+	/// assert_eq!(index.path(module), std::path::Path::new("/boot/modules-6.1.0/kernel/kvm.ko"));
+	///
+	/// liblmod::clear_filesystem();
+	/// ```
+	pub fn from_manifest_paths<'a, P: AsRef<Path>>(
+		kernel: impl AsRef<str>,
+		basepath: impl AsRef<Path>,
+		paths: impl IntoIterator<Item = (&'a str, P)>,
+	) -> io::Result<ModuleIndex> {
+		let paths = paths
+			.into_iter()
+			.map(|(name, path)| (name.to_string(), path.as_ref().to_path_buf()))
+			.collect();
+		ModuleIndex::open_paths(
+			kernel.as_ref().into(),
+			basepath.as_ref().to_path_buf(),
+			paths,
+		)
+	}
+
+	/// Returns index shared by the whole process
+	///
+	/// Index is read on first use and then reused by subsequent calls, also from other threads.
+	/// Unless disabled with `set_auto_refresh`, index is re-read when modules manifests are
+	/// modified (e.g. by depmod).
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// match liblmod::ModuleIndex::shared(liblmod::Selection::Current) {
+	///     Ok(index) => println!("kvm is provided by {:?}", index.resolve("kvm")),
+	///     Err(e) => eprintln!("Failed to open module index: {e}"),
+	/// }
+	/// ```
+	pub fn shared(kernel: Selection) -> io::Result<Arc<ModuleIndex>> {
+		ModuleIndex::shared_release(release(kernel)?)
+	}
+
+	/// Returns index of kernel release shared by the whole process
+	pub(crate) fn shared_release(release: OsString) -> io::Result<Arc<ModuleIndex>> {
+		if vfs::is_memory() {
+			return Ok(Arc::new(ModuleIndex::open_release(release)?));
+		}
+
+		let cache = SHARED.get_or_init(|| RwLock::new(HashMap::new()));
+
+		// Reuse cached index if it's still up to date
+		if let Some(index) = cache
+			.read()
+			.unwrap_or_else(|e| e.into_inner())
+			.get(&release)
+		{
+			if !AUTO_REFRESH.load(Ordering::Relaxed)
+				|| index.modified == modification_time(&index.basepath)
+			{
+				return Ok(index.clone());
+			}
+		}
+
+		let index = Arc::new(ModuleIndex::open_release(release.clone())?);
+		cache
+			.write()
+			.unwrap_or_else(|e| e.into_inner())
+			.insert(release, index.clone());
+
+		Ok(index)
+	}
+
+	/// Adds directory with out-of-tree modules (e.g. build tree), preferred over modules of kernel
+	///
+	/// Dependencies are read from modules.dep in the directory (paths relative to the directory).
+	/// Without modules.dep, the directory is searched for module files, whose dependencies are
+	/// read from their `depends` field. Dependencies not found in the directory are resolved by
+	/// name among already known modules.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// if let Ok(mut index) = liblmod::ModuleIndex::open(liblmod::Selection::Current) {
+	///     if let Err(e) = index.add_directory("./build") {
+	///         eprintln!("Failed to read build directory: {e}");
+	///     }
+	/// }
+	/// ```
+	pub fn add_directory(&mut self, dir: impl AsRef<Path>) -> io::Result<()> {
+		let dir = vfs::canonicalize(dir)?;
+		let absolute = |module: &str| dir.join(module).to_string_lossy().into_owned();
+
+		// Read modules.dep of directory or find module files, which are left out of dependencies
+		self.depends = OnceLock::new();
+		let deps = match manifest::read_dep(&dir.join("modules.dep")) {
+			Ok(deps) => deps,
+			Err(e) if e.kind() == ErrorKind::NotFound => {
+				for module in find_modules(&dir, &dir)? {
+					self.prefer(module_name(&module), absolute(&module));
+				}
+				return Ok(());
+			}
+			Err(e) => return Err(e),
+		};
+
+		// Modules of directory take precedence
+		for (module, _) in &deps {
+			self.prefer(module_name(module), absolute(module));
+		}
+		for (module, list) in deps {
+			let list = list
+				.iter()
+				.map(|dep| match self.modules.get(&module_name(dep)) {
+					Some(known) => known.clone(),
+					None => absolute(dep),
+				})
+				.collect();
+			self.deps.insert(absolute(&module), list);
+		}
+
+		Ok(())
+	}
+
+	/// Makes module preferred over other modules with the same name, which are kept as its
+	/// duplicates with lower priority
+	fn prefer(&mut self, name: String, module: String) {
+		let previous = match self.duplicates.remove(&name) {
+			Some(list) => list,
+			None => self
+				.modules
+				.get(&name)
+				.map(|known| vec![(0, known.clone())])
+				.unwrap_or_default(),
+		};
+
+		let mut list = vec![(0, module.clone())];
+		list.extend(
+			previous
+				.into_iter()
+				.filter(|(_, known)| *known != module)
+				.map(|(priority, known)| (priority + 1, known)),
+		);
+		if list.len() > 1 {
+			self.duplicates.insert(name.clone(), list);
+		}
+		self.modules.insert(name, module);
+	}
+
+	/// Reads flat module directories instead of module directory of kernel (e.g. Android partitions)
+	///
+	/// Earlier directories take precedence. Dependencies are read from modules.dep of directory
+	/// (paths relative to the directory) and without it from `depends` field of module
+	/// files. Load order is read from `modules.load` files, which list modules in order they are
+	/// loaded by Android init.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// liblmod::set_filesystem(
+	///     liblmod::MemoryFs::new()
+	///         .file("/vendor/lib/modules/modules.dep", "wlan.ko: cfg80211.ko\ncfg80211.ko:\n")
+	///         .file("/vendor/lib/modules/modules.load", "cfg80211.ko\nwlan.ko\n")
+	///         .file("/vendor/lib/modules/cfg80211.ko", "")
+	///         .file("/vendor/lib/modules/wlan.ko", ""),
+	/// );
+	///
+	/// let index = liblmod::ModuleIndex::open_flat(&["/vendor/lib/modules"]).unwrap();
+	/// assert_eq!(index.resolve("wlan"), Some("/vendor/lib/modules/wlan.ko"));
+	/// assert_eq!(index.dependencies("/vendor/lib/modules/wlan.ko"), ["/vendor/lib/modules/cfg80211.ko"]);
+	/// assert_eq!(index.order("/vendor/lib/modules/cfg80211.ko"), Some(0));
+	///
+	/// liblmod::clear_filesystem();
+	/// ```
+	pub fn open_flat<P: AsRef<Path>>(dirs: &[P]) -> io::Result<ModuleIndex> {
+		let mut modules: HashMap<String, String> = HashMap::new();
+		let mut direct: HashMap<String, Vec<String>> = HashMap::new();
+		let mut order: HashMap<String, usize> = HashMap::new();
+		for dir in dirs {
+			let dir = vfs::canonicalize(dir)?;
+
+			// Read modules.dep of directory or find module files with their dependencies
+			let mut entries: Vec<(String, Vec<String>)> = Vec::new();
+			match manifest::read_dep(&dir.join("modules.dep")) {
+				Ok(deps) => {
+					for (module, list) in deps {
+						entries.push((module, list.iter().map(|dep| module_name(dep)).collect()));
+					}
+				}
+				Err(e) if e.kind() == ErrorKind::NotFound => {
+					for module in find_modules(&dir, &dir)? {
+						let depends = match ModuleInfo::from_file(dir.join(&module)) {
+							Ok(info) => info.depends.iter().map(|d| normalize(d)).collect(),
+							Err(_) => Vec::new(),
+						};
+						entries.push((module, depends));
+					}
+				}
+				Err(e) => return Err(e),
+			}
+
+			for (module, depends) in entries {
+				let name = module_name(&module);
+				if !modules.contains_key(&name) {
+					modules.insert(
+						name.clone(),
+						dir.join(module).to_string_lossy().into_owned(),
+					);
+					direct.insert(name, depends);
+				}
+			}
+
+			// Read load order of directory
+			if let Ok(list) = vfs::read_to_string(dir.join("modules.load")) {
+				for line in list
+					.lines()
+					.map(|line| line.trim())
+					.filter(|l| !l.is_empty())
+				{
+					if let Some(path) = modules.get(&module_name(line)) {
+						let position = order.len();
+						order.entry(path.clone()).or_insert(position);
+					}
+				}
+			}
+		}
+
+		let deps = modules
+			.iter()
+			.map(|(name, path)| {
+				let list = all_dependencies(&direct, name)
+					.iter()
+					.filter_map(|dep| modules.get(dep).cloned())
+					.collect();
+				(path.clone(), list)
+			})
+			.collect();
+
+		Ok(ModuleIndex {
+			kernel: kernel::current_release()?.to_string_lossy().into_owned(),
+			basepath: dirs
+				.first()
+				.map(|dir| dir.as_ref().to_path_buf())
+				.unwrap_or_default(),
+			modules,
+			deps,
+			weakdeps: HashMap::new(),
+			builtin: HashSet::new(),
+			order,
+			duplicates: HashMap::new(),
+			symbols: OnceLock::new(),
+			#[cfg(feature = "alias")]
+			aliases: OnceLock::new(),
+			depends: OnceLock::new(),
+			#[cfg(feature = "alias")]
+			modaliases: OnceLock::new(),
+			modified: None,
+			manifests: None,
+			paths: HashMap::new(),
+			plans: Mutex::new(HashMap::new()),
+			layers: Vec::new(),
+		})
+	}
+
+	/// Reads module directories of Android-derived systems, see `open_flat`
+	///
+	/// Directories `/odm/lib/modules`, `/vendor_dlkm/lib/modules` and `/vendor/lib/modules` are
+	/// read in this order, missing ones are skipped.
+	pub fn android() -> io::Result<ModuleIndex> {
+		let dirs: Vec<&str> = ANDROID_DIRS
+			.into_iter()
+			.filter(|dir| vfs::is_dir(dir))
+			.collect();
+		ModuleIndex::open_flat(&dirs)
+	}
+
+	/// Drops all shared indices, they will be read again on next use
+	pub fn invalidate() {
+		if let Some(cache) = SHARED.get() {
+			cache.write().unwrap_or_else(|e| e.into_inner()).clear();
+		}
+	}
+
+	/// Sets directories searched for module directory of kernel (`<dir>/<release>`), in order
+	///
+	/// The first directory containing modules.dep of kernel is used. By default `/lib/modules` is
+	/// searched first and then `/usr/lib/modules` (used alone by some merged-/usr and ostree-based
+	/// systems). Empty list restores default directories. Shared indices are dropped.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// liblmod::ModuleIndex::set_base_dirs(&["/usr/lib/modules", "/lib/modules"]);
+	/// ```
+	pub fn set_base_dirs<P: AsRef<Path>>(dirs: &[P]) {
+		ModuleIndex::set_roots(dirs, false);
+	}
+
+	/// Sets layered module roots, looked up in turn for module files and manifests
+	///
+	/// Like `set_base_dirs`, roots contain module directories of kernels (`<root>/<release>`) and
+	/// manifests are read from the first one containing modules.dep. Module files are then looked
+	/// up in module directories of all roots in order, so dependencies listed in manifests of one
+	/// root can be found in another (e.g. overlay of immutable system or live CD over read-only
+	/// squashfs or erofs tree). Modules missing from the first manifests are resolved from
+	/// manifests of other roots. Empty list restores default directories without layering.
+	/// Shared indices are dropped.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// liblmod::set_filesystem(
+	///     liblmod::MemoryFs::new()
+	///         .file("/run/modules-overlay/6.1.0/modules.dep", "extra/mydrv.ko: kernel/kvm.ko\nkernel/kvm.ko:\n")
+	///         .file("/run/modules-overlay/6.1.0/extra/mydrv.ko", "")
+	///         .file("/usr/lib/modules/6.1.0/modules.dep", "kernel/kvm.ko:\nkernel/tun.ko:\n")
+	///         .file("/usr/lib/modules/6.1.0/kernel/kvm.ko", "")
+	///         .file("/usr/lib/modules/6.1.0/kernel/tun.ko", ""),
+	/// );
+	/// liblmod::ModuleIndex::set_module_roots(&["/run/modules-overlay", "/usr/lib/modules"]);
+	///
+	/// let index = liblmod::ModuleIndex::open(liblmod::Selection::Other("6.1.0".to_string())).unwrap();
+	/// assert_eq!(index.path("kernel/kvm.ko").to_str(), Some("/usr/lib/modules/6.1.0/kernel/kvm.ko"));
+	/// assert_eq!(index.path("extra/mydrv.ko").to_str(), Some("/run/modules-overlay/6.1.0/extra/mydrv.ko"));
+	/// assert_eq!(index.resolve("tun"), Some("kernel/tun.ko"));
+	///
+	/// liblmod::ModuleIndex::set_module_roots::<&str>(&[]);
+	/// liblmod::clear_filesystem();
+	/// ```
+	pub fn set_module_roots<P: AsRef<Path>>(roots: &[P]) {
+		ModuleIndex::set_roots(roots, !roots.is_empty());
+	}
+
+	/// Stores base directories and layering before shared indices are dropped, so indices read
+	/// again see both
+	fn set_roots<P: AsRef<Path>>(dirs: &[P], layered: bool) {
+		*BASE_DIRS.write().unwrap_or_else(|e| e.into_inner()) =
+			dirs.iter().map(|dir| dir.as_ref().to_path_buf()).collect();
+		LAYERED.store(layered, Ordering::Relaxed);
+		ModuleIndex::invalidate();
+	}
+
+	/// Enables or disables re-reading of shared indices modified after they were read
+	pub fn set_auto_refresh(enabled: bool) {
+		AUTO_REFRESH.store(enabled, Ordering::Relaxed);
+	}
+
+	/// Returns release of the kernel this index belongs to
+	///
+	/// Bytes of release which are not valid UTF-8 are replaced with `U+FFFD`.
+	pub fn kernel(&self) -> &str {
+		&self.kernel
+	}
+
+	/// Returns module path (relative to module directory) for specified module name
+	///
+	/// Name has to match module file name exactly, but `-` and `_` are treated as equivalent.
+	/// If there are more modules with the same name, the one preferred by depmod.d configuration
+	/// is returned (by default `updates` before `extra`, in-tree modules and `weak-updates`), of
+	/// equally preferred ones the first in modules.order (see `candidates`).
+	/// Names in `symbol:<name>` form are resolved to module exporting the symbol. Other names
+	/// not matching any module are looked up in modules.alias.
+	pub fn resolve(&self, name: &str) -> Option<&str> {
+		if let Some(symbol) = name.strip_prefix("symbol:") {
+			return self.resolve(self.symbol_owner(symbol)?);
+		}
+
+		if let Some(module) = self.modules.get(&normalize(name)) {
+			return Some(module);
+		}
+
+		self.resolve_alias(name)
+			.iter()
+			.find_map(|module| self.modules.get(module))
+			.map(|module| module.as_str())
+	}
+
+	/// Returns all module paths (relative to module directory) with module name, preferred first
+	///
+	/// Modules are ordered by `override` and `search` directives of depmod.d (by default
+	/// `updates`, `extra`, in-tree modules and `weak-updates`). Modules in the same directory are
+	/// ordered by modules.order, unlisted ones by path.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// liblmod::set_filesystem(
+	///     liblmod::MemoryFs::new()
+	///         .file("/lib/modules/6.1.0/modules.order", "kernel/fs/foo.ko\nextra/a/foo.ko\nextra/b/foo.ko\n")
+	///         .file("/lib/modules/6.1.0/modules.dep", "kernel/fs/foo.ko:\nextra/a/foo.ko:\nextra/b/foo.ko:\n"),
+	/// );
+	///
+	/// let index = liblmod::ModuleIndex::open(liblmod::Selection::Other("6.1.0".to_string())).unwrap();
+	/// assert_eq!(
+	///     index.candidates("foo"),
+	///     ["extra/a/foo.ko", "extra/b/foo.ko", "kernel/fs/foo.ko"]
+	/// );
+	/// assert_eq!(index.ambiguous("foo"), ["extra/a/foo.ko", "extra/b/foo.ko"]);
+	///
+	/// liblmod::clear_filesystem();
+	/// ```
+	pub fn candidates(&self, name: &str) -> Vec<&str> {
+		let name = normalize(name);
+		match self.duplicates.get(&name) {
+			Some(list) => list.iter().map(|(_, module)| module.as_str()).collect(),
+			None => self
+				.modules
+				.get(&name)
+				.map(|m| m.as_str())
+				.into_iter()
+				.collect(),
+		}
+	}
+
+	/// Returns paths of modules with module name which have the same priority as preferred one
+	///
+	/// Preferred module is chosen deterministically even then, but the choice may not be
+	/// intended. Empty list is returned if module is unambiguous.
+	pub fn ambiguous(&self, name: &str) -> Vec<&str> {
+		let list = match self.duplicates.get(&normalize(name)) {
+			Some(list) => list,
+			None => return Vec::new(),
+		};
+		let tied: Vec<&str> = list
+			.iter()
+			.take_while(|(priority, _)| *priority == list[0].0)
+			.map(|(_, module)| module.as_str())
+			.collect();
+		match tied.len() {
+			1 => Vec::new(),
+			_ => tied,
+		}
+	}
+
+	/// Returns names of modules matching alias as listed in modules.alias
+	///
+	/// modules.alias.bin is memory-mapped on first lookup and searched without reading it whole.
+	/// If it's missing, modules.alias is scanned instead. Missing or unreadable index is treated
+	/// as empty. Aliases are resolved only with `alias` feature, no module matches without it.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// if let Ok(index) = liblmod::ModuleIndex::open(liblmod::Selection::Current) {
+	///     println!("fs-ext4 is provided by {:?}", index.resolve_alias("fs-ext4"));
+	/// }
+	/// ```
+	pub fn resolve_alias(&self, alias: &str) -> Vec<String> {
+		let mut names: Vec<String> = Vec::new();
+		for module in self
+			.lookup_alias(&normalize_alias(alias))
+			.unwrap_or_default()
+		{
+			let module = normalize(&module);
+			if !names.contains(&module) {
+				names.push(module);
+			}
+		}
+
+		names
+	}
+
+	/// Looks up normalized alias in modules.alias.bin, or in modules.alias if it's missing
+	#[cfg(feature = "alias")]
+	fn lookup_alias(&self, alias: &str) -> io::Result<Vec<String>> {
+		let bin = self
+			.aliases
+			.get_or_init(|| match self.manifests.is_some() || vfs::is_memory() {
+				true => None,
+				false => BinIndex::open(self.manifest_path("modules.alias.bin")).ok(),
+			});
+		match bin {
+			Some(bin) => bin.lookup_wild(alias),
+			None => self.scan_aliases(alias),
+		}
+	}
+
+	/// Aliases are not resolved without `alias` feature
+	#[cfg(not(feature = "alias"))]
+	fn lookup_alias(&self, _alias: &str) -> io::Result<Vec<String>> {
+		Ok(Vec::new())
+	}
+
+	/// Builds matcher of aliases of all modules, usable without module directory
+	///
+	/// Aliases are read from modules.alias, or from module files if it's missing. See
+	/// `AliasMatcher` for serializing matcher. Requires `alias` feature.
+	#[cfg(feature = "alias")]
+	pub fn alias_matcher(&self) -> io::Result<AliasMatcher> {
+		let aliases = match self.open_manifest("modules.alias") {
+			Ok(fd) => {
+				let mut aliases = Vec::new();
+				for line in BufReader::new(fd).lines() {
+					let line = line?;
+					let mut words = line.split_whitespace();
+					if words.next() != Some("alias") {
+						continue;
+					}
+
+					if let (Some(pattern), Some(module)) = (words.next(), words.next()) {
+						aliases.push((normalize_alias(pattern), normalize(module)));
+					}
+				}
+				aliases
+			}
+			Err(e) if e.kind() == ErrorKind::NotFound => self
+				.modaliases
+				.get_or_init(|| self.read_modaliases())
+				.clone(),
+			Err(e) => return Err(e),
+		};
+
+		Ok(AliasMatcher::new(&self.kernel, aliases))
+	}
+
+	/// Iterates over all modules available in module directory, sorted by path
+	///
+	/// Entries are built while iterating, so module files are not read and only size of each
+	/// file is checked. Aliases are read from modules.alias in one pass when the first entry is
+	/// built; if it's missing (depmod hasn't been run), they are read from each module file
+	/// instead (compressed files only with `compression` feature).
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// liblmod::set_filesystem(
+	///     liblmod::MemoryFs::new()
+	///         .file("/lib/modules/6.1.0/modules.order", "kernel/kvm.ko\nkernel/kvm-intel.ko.xz\n")
+	///         .file("/lib/modules/6.1.0/modules.dep", "kernel/kvm.ko:\nkernel/kvm-intel.ko.xz: kernel/kvm.ko\n")
+	///         .file("/lib/modules/6.1.0/modules.alias", "alias cpu:type:x86,*0085* kvm_intel\n")
+	///         .file("/lib/modules/6.1.0/kernel/kvm-intel.ko.xz", "data"),
+	/// );
+	///
+	/// let index = liblmod::ModuleIndex::open(liblmod::Selection::Other("6.1.0".to_string())).unwrap();
+	/// let modules: Vec<_> = index.iter_modules().collect();
+	/// assert_eq!(modules[0].name, "kvm_intel");
+	/// assert_eq!(modules[0].size, Some(4));
+	/// assert_eq!(modules[0].compression.as_deref(), Some("xz"));
+	/// assert_eq!(modules[0].aliases, ["cpu:type:x86,*0085*"]);
+	/// assert_eq!(modules[0].depends, ["kvm"]);
+	/// assert_eq!(modules[1].name, "kvm");
+	///
+	/// liblmod::clear_filesystem();
+	/// ```
+	pub fn iter_modules(&self) -> impl Iterator<Item = ModuleEntry> + '_ {
+		let mut modules: Vec<(&String, &String)> = self.modules.iter().collect();
+		modules.sort_by_key(|(_, module)| *module);
+
+		let mut aliases = None;
+		modules.into_iter().map(move |(name, module)| {
+			let path = self.path(module);
+			let aliases = match aliases.get_or_insert_with(|| self.read_alias_lists()) {
+				Some(aliases) => aliases.get(name).cloned().unwrap_or_default(),
+				None => module_info(&path)
+					.map(|info| info.aliases)
+					.unwrap_or_default(),
+			};
+
+			ModuleEntry {
+				name: name.clone(),
+				module: module.clone(),
+				size: vfs::size(&path).ok(),
+				compression: crate::loader::file_compression(&path),
+				aliases,
+				depends: self
+					.dependencies(module)
+					.iter()
+					.map(|dep| module_name(dep))
+					.collect(),
+				path,
+			}
+		})
+	}
+
+	/// Reads aliases of every module from modules.alias, `None` if it's missing
+	fn read_alias_lists(&self) -> Option<HashMap<String, Vec<String>>> {
+		let fd = self.open_manifest("modules.alias").ok()?;
+		let mut aliases: HashMap<String, Vec<String>> = HashMap::new();
+		for line in BufReader::new(fd).lines().map_while(Result::ok) {
+			let mut words = line.split_whitespace();
+			if words.next() != Some("alias") {
+				continue;
+			}
+
+			if let (Some(pattern), Some(module)) = (words.next(), words.next()) {
+				aliases
+					.entry(normalize(module))
+					.or_default()
+					.push(pattern.to_string());
+			}
+		}
+
+		Some(aliases)
+	}
+
+	/// Returns load plan cached by `cache_plan`
+	pub(crate) fn cached_plan(
+		&self,
+		name: &str,
+		params: &str,
+		options: u64,
+	) -> Option<Arc<LoadPlan>> {
+		let plans = self.plans.lock().unwrap_or_else(|e| e.into_inner());
+		plans
+			.get(&(name.to_string(), params.to_string(), options))
+			.cloned()
+	}
+
+	/// Remembers load plan, plans are dropped together with index (e.g. when it's re-read)
+	pub(crate) fn cache_plan(&self, name: &str, params: &str, options: u64, plan: Arc<LoadPlan>) {
+		let mut plans = self.plans.lock().unwrap_or_else(|e| e.into_inner());
+		plans.insert((name.to_string(), params.to_string(), options), plan);
+	}
+
+	/// Returns path of manifest, in module directory unless given explicitly
+	fn manifest_path(&self, name: &str) -> PathBuf {
+		manifest_path(&self.basepath, &self.paths, name)
+	}
+
+	/// Opens manifest of module directory, or one index has been built from
+	fn open_manifest(&self, name: &str) -> io::Result<Box<dyn Read>> {
+		let path = self.manifest_path(name);
+		match &self.manifests {
+			Some(manifests) => manifests.open(&path),
+			None => vfs::open(path),
+		}
+	}
+
+	#[cfg(feature = "alias")]
+	fn scan_aliases(&self, alias: &str) -> io::Result<Vec<String>> {
+		let mut modules = Vec::new();
+		let fd = match self.open_manifest("modules.alias") {
+			Ok(fd) => fd,
+			Err(e) if e.kind() == ErrorKind::NotFound => {
+				// Aliases are read from module files until depmod writes modules.alias
+				let aliases = self.modaliases.get_or_init(|| self.read_modaliases());
+				return Ok(aliases
+					.iter()
+					.filter(|(pattern, _)| fnmatch(pattern.as_bytes(), alias.as_bytes()))
+					.map(|(_, module)| module.clone())
+					.collect());
+			}
+			Err(e) => return Err(e),
+		};
+		for line in BufReader::new(fd).lines() {
+			let line = line?;
+			let mut words = line.split_whitespace();
+			if words.next() != Some("alias") {
+				continue;
+			}
+
+			if let (Some(pattern), Some(module)) = (words.next(), words.next()) {
+				if fnmatch(normalize_alias(pattern).as_bytes(), alias.as_bytes()) {
+					modules.push(module.to_string());
+				}
+			}
+		}
+
+		Ok(modules)
+	}
+
+	/// Reads aliases of modules from their files, as patterns with module names
+	#[cfg(feature = "alias")]
+	fn read_modaliases(&self) -> Vec<(String, String)> {
+		let mut modules: Vec<(&String, &String)> = self.modules.iter().collect();
+		modules.sort();
+
+		let mut aliases = Vec::new();
+		for (name, module) in modules {
+			if let Ok(info) = module_info(&self.path(module)) {
+				for alias in info.aliases {
+					aliases.push((normalize_alias(&alias), name.clone()));
+				}
+			}
+		}
+
+		aliases
+	}
+
+	/// Returns dependencies of module as listed in modules.dep
+	///
+	/// Dependencies of modules missing from modules.dep (e.g. built or copied after depmod has
+	/// been run) are read from `depends` field of their files and resolved by name.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// if let Ok(index) = liblmod::ModuleIndex::open(liblmod::Selection::Current) {
+	///     if let Some(module) = index.resolve("kvm_intel") {
+	///         println!("{module} depends on {:?}", index.dependencies(module));
+	///     }
+	/// }
+	/// ```
+	pub fn dependencies(&self, module: &str) -> &[String] {
+		if let Some(deps) = self.deps.get(module) {
+			return deps;
+		}
+
+		match self.depends.get_or_init(|| self.read_depends()).get(module) {
+			Some(deps) => deps,
+			None => &[],
+		}
+	}
+
+	/// Reads dependencies of modules missing from modules.dep from their files
+	fn read_depends(&self) -> HashMap<String, Vec<String>> {
+		let missing: Vec<&String> = self
+			.modules
+			.values()
+			.filter(|module| !self.deps.contains_key(*module))
+			.collect();
+		if missing.is_empty() {
+			return HashMap::new();
+		}
+
+		// Modules listed in modules.dep keep their dependencies, compressed files are readable
+		// only with compression feature
+		let mut direct: HashMap<String, Vec<String>> = HashMap::new();
+		for (name, module) in &self.modules {
+			let list = match self.deps.get(module) {
+				Some(deps) => deps.iter().map(|dep| module_name(dep)).collect(),
+				None => match module_info(&self.path(module)) {
+					Ok(info) => info
+						.depends
+						.iter()
+						.filter(|dep| !dep.is_empty())
+						.map(|dep| normalize(dep))
+						.collect(),
+					Err(_) => Vec::new(),
+				},
+			};
+			direct.insert(name.clone(), list);
+		}
+
+		missing
+			.into_iter()
+			.map(|module| {
+				let list = all_dependencies(&direct, &module_name(module))
+					.iter()
+					.filter_map(|dep| self.modules.get(dep).cloned())
+					.collect();
+				(module.clone(), list)
+			})
+			.collect()
+	}
+
+	/// Returns compression method of module files (e.g. `xz`), `None` if they are uncompressed
+	///
+	/// Distributions compress modules with one method, but out-of-tree modules may differ, so the
+	/// most common method is returned.
+	pub(crate) fn compression(&self) -> Option<String> {
+		let mut counts: BTreeMap<Option<String>, usize> = BTreeMap::new();
+		for module in self.modules.values() {
+			*counts
+				.entry(crate::loader::file_compression(Path::new(module)))
+				.or_default() += 1;
+		}
+
+		counts
+			.into_iter()
+			.max_by_key(|(_, count)| *count)
+			.and_then(|(method, _)| method)
+	}
+
+	/// Returns names of all modules provided by kernel, sorted
+	///
+	/// Names similar to misspelled module name are suggested in `Error::ModuleNotFound`.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// liblmod::set_filesystem(
+	///     liblmod::MemoryFs::new()
+	///         .file("/lib/modules/6.1.0/modules.order", "kernel/nvme-tcp.ko\nkernel/nvme-rdma.ko\n")
+	///         .file("/lib/modules/6.1.0/modules.dep", "kernel/nvme-tcp.ko:\nkernel/nvme-rdma.ko:\n"),
+	/// );
+	///
+	/// let index = liblmod::ModuleIndex::open(liblmod::Selection::Other("6.1.0".to_string())).unwrap();
+	/// assert_eq!(index.names(), ["nvme_rdma", "nvme_tcp"]);
+	///
+	/// let options = liblmod::Options::new().kernel(liblmod::Selection::Other("6.1.0".to_string()));
+	/// let error = liblmod::modprobe_with("nvme_tpc", "", &options).unwrap_err();
+	/// match liblmod::Error::from_io(&error) {
+	///     Some(liblmod::Error::ModuleNotFound { suggestions, .. }) => assert_eq!(suggestions, &["nvme_tcp"]),
+	///     _ => panic!("unexpected error: {error}"),
+	/// }
+	///
+	/// liblmod::clear_filesystem();
+	/// ```
+	pub fn names(&self) -> Vec<String> {
+		let mut names: Vec<String> = self.modules.keys().cloned().collect();
+		names.sort();
+		names
+	}
+
+	/// Returns paths of all module files listed by manifests, including dependencies and
+	/// duplicates, sorted
+	#[cfg(feature = "modinfo")]
+	pub(crate) fn files(&self) -> Vec<&str> {
+		let mut files: HashSet<&str> = self.modules.values().map(String::as_str).collect();
+		for (module, deps) in &self.deps {
+			files.insert(module);
+			files.extend(deps.iter().map(String::as_str));
+		}
+		for list in self.duplicates.values() {
+			files.extend(list.iter().map(|(_, module)| module.as_str()));
+		}
+
+		let mut files: Vec<&str> = files.into_iter().collect();
+		files.sort();
+		files
+	}
+
+	/// Returns position of module in modules.order, `None` for modules not listed there
+	pub fn order(&self, module: &str) -> Option<usize> {
+		self.order.get(module).copied()
+	}
+
+	/// Checks if module is built into kernel, as listed in modules.builtin
+	pub fn is_builtin(&self, name: &str) -> bool {
+		self.builtin.contains(&normalize(name))
+	}
+
+	/// Returns device nodes (relative to `/dev`) created for module before it's loaded, as listed in modules.devname
+	///
+	/// Opening such node loads module on demand and keeps it in use while node is open.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// liblmod::set_filesystem(
+	///     liblmod::MemoryFs::new()
+	///         .file("/lib/modules/6.1.0/modules.order", "kernel/sound/core/snd-timer.ko\n")
+	///         .file("/lib/modules/6.1.0/modules.dep", "kernel/sound/core/snd-timer.ko:\n")
+	///         .file("/lib/modules/6.1.0/modules.devname", "fuse fuse c10:229\nsnd_timer snd/timer c116:33\n"),
+	/// );
+	///
+	/// let index = liblmod::ModuleIndex::open(liblmod::Selection::Other("6.1.0".to_string())).unwrap();
+	/// assert_eq!(index.device_names("snd-timer"), ["snd/timer"]);
+	/// assert!(index.device_names("kvm").is_empty());
+	///
+	/// liblmod::clear_filesystem();
+	/// ```
+	pub fn device_names(&self, name: &str) -> Vec<String> {
+		let name = normalize(name);
+		self.static_nodes()
+			.unwrap_or_default()
+			.into_iter()
+			.filter(|node| node.module == name)
+			.map(|node| node.name)
+			.collect()
+	}
+
+	/// Returns device nodes of all modules listed in modules.devname, see `static_nodes`
+	pub fn static_nodes(&self) -> io::Result<Vec<StaticNode>> {
+		let mut list = String::new();
+		match self.open_manifest("modules.devname") {
+			Ok(mut fd) => fd.read_to_string(&mut list)?,
+			Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+			Err(e) => return Err(e),
+		};
+
+		Ok(crate::devname::parse(&list))
+	}
+
+	/// Returns names of all modules which (directly or indirectly) depend on module
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// if let Ok(index) = liblmod::ModuleIndex::open(liblmod::Selection::Current) {
+	///     println!("Modules depending on usbcore: {:?}", index.dependents("usbcore"));
+	/// }
+	/// ```
+	pub fn dependents(&self, name: &str) -> Vec<String> {
+		let module = match self.resolve(name) {
+			Some(module) => module,
+			None => return Vec::new(),
+		};
+
+		// modules.dep lists all dependencies, including indirect ones
+		let mut dependents: Vec<String> = self
+			.deps
+			.iter()
+			.filter(|(_, deps)| deps.iter().any(|dep| dep == module))
+			.map(|(dependent, _)| module_name(dependent))
+			.collect();
+		dependents.sort();
+
+		dependents
+	}
+
+	/// Returns name of module exporting symbol as listed in modules.symbols
+	///
+	/// modules.symbols is read on first lookup, missing or unreadable file is treated as empty.
+	pub fn symbol_owner(&self, symbol: &str) -> Option<&str> {
+		self.symbols
+			.get_or_init(|| self.read_symbols().unwrap_or_default())
+			.get(symbol)
+			.map(|module| module.as_str())
+	}
+
+	fn read_symbols(&self) -> io::Result<HashMap<String, String>> {
+		let mut symbols = HashMap::new();
+		let fd = match self.open_manifest("modules.symbols") {
+			Ok(fd) => fd,
+			Err(e) if e.kind() == ErrorKind::NotFound => {
+				// Exported symbols are read from module files until depmod writes modules.symbols
+				#[cfg(feature = "modinfo")]
+				for (name, module) in &self.modules {
+					if let Ok(exports) = crate::ModuleSymbols::from_file(self.path(module)) {
+						for symbol in exports.exported {
+							symbols.entry(symbol.name).or_insert_with(|| name.clone());
+						}
+					}
+				}
+				return Ok(symbols);
+			}
+			Err(e) => return Err(e),
+		};
+		for line in BufReader::new(fd).lines() {
+			let line = line?;
+			let mut words = line.split_whitespace();
+			if words.next() != Some("alias") {
+				continue;
+			}
+
+			let symbol = words.next().and_then(|alias| alias.strip_prefix("symbol:"));
+			if let (Some(symbol), Some(module)) = (symbol, words.next()) {
+				symbols.insert(symbol.to_string(), normalize(module));
+			}
+		}
+
+		Ok(symbols)
+	}
+
+	/// Returns names of weak dependencies of module as listed in modules.weakdep
+	///
+	/// Weak dependencies are optional, module works without them.
+	pub fn weak_dependencies(&self, name: &str) -> &[String] {
+		match self.weakdeps.get(&normalize(name)) {
+			Some(weakdeps) => weakdeps,
+			None => &[],
+		}
+	}
+
+	/// Returns dependency graph of module, or `None` if module is not provided by kernel
+	pub fn dependency_graph(&self, name: &str) -> Option<DependencyGraph> {
+		let module = self.resolve(name)?;
+
+		let mut graph = DependencyGraph::default();
+		self.add_to_graph(&mut graph, module);
+		for dep in self.dependencies(module) {
+			self.add_to_graph(&mut graph, dep);
+		}
+
+		Some(graph)
+	}
+
+	/// Returns dependency graph of all modules provided by kernel
+	pub fn full_dependency_graph(&self) -> DependencyGraph {
+		let mut graph = DependencyGraph::default();
+		for module in self.deps.keys() {
+			self.add_to_graph(&mut graph, module);
+		}
+
+		graph
+	}
+
+	/// Adds module with edges to its direct dependencies to graph
+	fn add_to_graph(&self, graph: &mut DependencyGraph, module: &str) {
+		graph.add_node(module_name(module));
+		for dep in resolver::direct_dependencies(self, module) {
+			graph.add_edge(module_name(module), module_name(dep));
+		}
+	}
+
+	/// Returns absolute path of module
+	///
+	/// Entries of modules.dep are relative to module directory, path is always inside of it:
+	/// root and `..` components of other paths are dropped. Only modules of directories added
+	/// with `add_directory` or read with `open_flat` are returned with their absolute paths.
+	/// With layered roots (see `set_module_roots`), module directory of the first root
+	/// containing the file is used.
+	pub fn path(&self, module: &str) -> PathBuf {
+		if Path::new(module).is_absolute() && self.is_added(module) {
+			return PathBuf::from(module);
+		}
+		let module: PathBuf = Path::new(module)
+			.components()
+			.filter(|component| matches!(component, Component::Normal(_)))
+			.collect();
+
+		// Module files of layered roots are looked up in turn
+		if let Some(path) = self
+			.layers
+			.iter()
+			.map(|layer| layer.join(&module))
+			.find(|path| vfs::exists(path))
+		{
+			return path;
+		}
+
+		self.basepath.join(module)
+	}
+
+	/// Checks if absolute path is module of directory given by caller, not listed by manifests
+	fn is_added(&self, module: &str) -> bool {
+		self.deps.contains_key(module)
+			|| self
+				.modules
+				.get(&module_name(module))
+				.is_some_and(|known| known == module)
+	}
+
+	/// Returns absolute path of module with symbolic links resolved (e.g. `/lib` to `/usr/lib`)
+	///
+	/// If the file cannot be resolved, path from `path` is returned.
+	pub fn resolved_path(&self, module: &str) -> PathBuf {
+		let path = self.path(module);
+		vfs::canonicalize(&path).unwrap_or(path)
+	}
+}
+
+/// Returns paths (relative to base) of module files in directory and its subdirectories
+fn find_modules(base: &Path, dir: &Path) -> io::Result<Vec<String>> {
+	let mut modules = Vec::new();
+	for path in vfs::read_dir(dir)? {
+		if vfs::is_dir(&path) {
+			modules.extend(find_modules(base, &path)?);
+			continue;
+		}
+
+		if is_module_file(&path.to_string_lossy()) {
+			if let Ok(relative) = path.strip_prefix(base) {
+				modules.push(relative.to_string_lossy().into_owned());
+			}
+		}
+	}
+
+	Ok(modules)
+}
+
+/// Returns paths (relative to module directory) of module files in module directory of kernel
+///
+/// Like depmod, `build` and `source` links to kernel source tree are skipped.
+fn find_tree_modules(basepath: &Path) -> io::Result<Vec<String>> {
+	let mut modules = Vec::new();
+	for path in vfs::read_dir(basepath)? {
+		if path
+			.file_name()
+			.is_some_and(|name| name == "build" || name == "source")
+		{
+			continue;
+		}
+
+		if vfs::is_dir(&path) {
+			modules.extend(find_modules(basepath, &path)?);
+		} else if is_module_file(&path.to_string_lossy()) {
+			if let Ok(relative) = path.strip_prefix(basepath) {
+				modules.push(relative.to_string_lossy().into_owned());
+			}
+		}
+	}
+
+	Ok(modules)
+}
+
+/// Returns image of module file, decompressed if it's compressed
+pub(crate) fn module_image(path: &Path) -> io::Result<Vec<u8>> {
+	let image = vfs::read(path)?;
+	match crate::loader::file_compression(path) {
+		Some(method) => compress::decompress(&image, &method),
+		None => Ok(image),
+	}
+}
+
+/// Reads information of module file, which may be compressed
+pub(crate) fn module_info(path: &Path) -> io::Result<ModuleInfo> {
+	ModuleInfo::from_image(&module_image(path)?)
+}
+
+/// Checks if path is relative and stays in directory it's relative to (has no `..` components)
+pub(crate) fn is_beneath(path: &str) -> bool {
+	Path::new(path)
+		.components()
+		.all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Checks if path names module file, possibly compressed
+pub(crate) fn is_module_file(path: &str) -> bool {
+	// Uncompressed modules are recognized without looking up decompressors
+	path.ends_with(".ko")
+		|| compress::strip_extension(path).is_some_and(|path| path.ends_with(".ko"))
+}
+
+/// Collects all dependencies of module from its direct ones, the deepest dependency last
+fn all_dependencies(direct: &HashMap<String, Vec<String>>, name: &str) -> Vec<String> {
+	fn visit(
+		direct: &HashMap<String, Vec<String>>,
+		name: &str,
+		seen: &mut HashSet<String>,
+		list: &mut Vec<String>,
+	) {
+		for dep in direct.get(name).into_iter().flatten() {
+			if seen.insert(dep.clone()) {
+				visit(direct, dep, seen, list);
+				list.push(dep.clone());
+			}
+		}
+	}
+
+	// Dependencies are visited before modules using them, so the list is reversed
+	let mut seen = HashSet::from([name.to_string()]);
+	let mut list = Vec::new();
+	visit(direct, name, &mut seen, &mut list);
+	list.reverse();
+	list
+}
+
+/// Size of name field of kernel module structure, including terminating null byte
+///
+/// Kernel defines it as `64 - sizeof(unsigned long)`, so it's 60 bytes on 32-bit architectures.
+pub(crate) const MODULE_NAME_LEN: usize = 64 - std::mem::size_of::<libc::c_ulong>();
+
+/// Replaces dashes with underscores, as kernel does with module names
+pub(crate) fn normalize(name: &str) -> String {
+	name.replace('-', "_")
+}
+
+/// Checks if name fits into kernel module structure and has only characters of module names
+pub(crate) fn is_valid_name(name: &str) -> bool {
+	!name.is_empty()
+		&& name.len() < MODULE_NAME_LEN
+		&& name
+			.bytes()
+			.all(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'-')
+}
+
+/// Matches text against shell wildcard pattern
+pub(crate) fn fnmatch(pattern: &[u8], text: &[u8]) -> bool {
+	let (pattern, text) = match (CString::new(pattern), CString::new(text)) {
+		(Ok(pattern), Ok(text)) => (pattern, text),
+		_ => return false,
+	};
+
+	unsafe { libc::fnmatch(pattern.as_ptr(), text.as_ptr(), 0) == 0 }
+}
+
+/// Replaces dashes with underscores outside of bracket expressions, as depmod does with aliases
+pub(crate) fn normalize_alias(alias: &str) -> String {
+	let mut bracket = false;
+	alias
+		.chars()
+		.map(|c| match c {
+			'[' => {
+				bracket = true;
+				c
+			}
+			']' => {
+				bracket = false;
+				c
+			}
+			'-' if !bracket => '_',
+			c => c,
+		})
+		.collect()
+}
+
+/// Extracts normalized module name from module path (e.g. `kernel/fs/fat/vfat.ko.xz` -> `vfat`)
+pub(crate) fn module_name(path: &str) -> String {
+	let file = match path.rsplit_once('/') {
+		Some((_, file)) => file,
+		None => path,
+	};
+
+	// Strip compression and module extensions, uncompressed modules without looking up
+	// decompressors
+	let name = match file.strip_suffix(".ko") {
+		Some(name) => name,
+		None => {
+			let file = compress::strip_extension(file).unwrap_or(file);
+			file.strip_suffix(".ko").unwrap_or(file)
+		}
+	};
+
+	normalize(name)
+}
+
+/// Returns release of selected kernel
+pub(crate) fn release(kernel: Selection) -> io::Result<OsString> {
+	match kernel {
+		Selection::Other(a) => Ok(a.into()),
+		Selection::Current => kernel::current_release(),
+	}
+}
+
+/// Module available in module directory, returned by `ModuleIndex::iter_modules`
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModuleEntry {
+	/// Name of module
+	pub name: String,
+
+	/// Path of module file as listed in modules.dep (relative to module directory)
+	pub module: String,
+
+	/// Absolute path of module file
+	pub path: PathBuf,
+
+	/// Size of module file in bytes, `None` if file is missing
+	pub size: Option<u64>,
+
+	/// Compression method of module file (`xz`, `zstd` or `gzip`)
+	pub compression: Option<String>,
+
+	/// Alias patterns of module (e.g. `pci:v00008086d*`)
+	pub aliases: Vec<String>,
+
+	/// Names of all (direct and indirect) dependencies of module, the deepest dependency last
+	pub depends: Vec<String>,
+}
+
+/// Module file provided by installed kernel, returned by `kernels_providing`
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KernelModule {
+	/// Release of kernel (e.g. `6.1.0-13-amd64`)
+	pub kernel: String,
+
+	/// Absolute path of module file
+	pub path: PathBuf,
+
+	/// Compression method of module file (`xz`, `zstd` or `gzip`)
+	pub compression: Option<String>,
+
+	/// Whether kernel is the running one
+	pub running: bool,
+}
+
+/// Returns installed kernels providing module as loadable module file, from the oldest kernel
+///
+/// All kernels with module directory in base directories (see `ModuleIndex::set_base_dirs`)
+/// are checked. Kernels with unreadable modules manifests are skipped. Module built into kernel
+/// is not reported.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// liblmod::set_filesystem(
+///     liblmod::MemoryFs::new()
+///         .file("/lib/modules/6.1.0/modules.dep", "kernel/drivers/net/r8169.ko.xz:\n")
+///         .file("/lib/modules/6.1.0/modules.order", "kernel/drivers/net/r8169.ko.xz\n")
+///         .file("/lib/modules/6.6.0/modules.dep", "kernel/fs/ext4.ko:\n")
+///         .file("/lib/modules/6.6.0/modules.order", "kernel/fs/ext4.ko\n"),
+/// );
+///
+/// let kernels = liblmod::kernels_providing("r8169").unwrap();
+/// assert_eq!(kernels.len(), 1);
+/// assert_eq!(kernels[0].kernel, "6.1.0");
+/// assert_eq!(kernels[0].compression.as_deref(), Some("xz"));
+///
+/// liblmod::clear_filesystem();
+/// ```
+pub fn kernels_providing(name: impl AsRef<str>) -> io::Result<Vec<KernelModule>> {
+	let name = normalize(name.as_ref());
+	let running = kernel::current_release().ok();
+
+	let mut releases: Vec<OsString> = Vec::new();
+	for dir in base_dirs() {
+		let entries = match vfs::read_dir(&dir) {
+			Ok(entries) => entries,
+			Err(e) if e.kind() == ErrorKind::NotFound => continue,
+			Err(e) => return Err(e),
+		};
+		for entry in entries {
+			if let Some(release) = entry.file_name().filter(|_| vfs::is_dir(&entry)) {
+				if !releases.iter().any(|known| known == release) {
+					releases.push(release.to_os_string());
+				}
+			}
+		}
+	}
+
+	let mut modules = Vec::new();
+	for release in releases {
+		let index = match ModuleIndex::open_release(release.clone()) {
+			Ok(index) => index,
+			Err(_) => continue,
+		};
+		if let Some(module) = index.resolve(&name) {
+			let path = index.path(module);
+			modules.push(KernelModule {
+				kernel: index.kernel().to_string(),
+				compression: crate::loader::file_compression(&path),
+				path,
+				running: running.as_ref() == Some(&release),
+			});
+		}
+	}
+
+	// Releases which are not versions are sorted by name
+	modules.sort_by(|a, b| {
+		match (
+			a.kernel.parse::<KernelVersion>(),
+			b.kernel.parse::<KernelVersion>(),
+		) {
+			(Ok(x), Ok(y)) => x.cmp(&y).then_with(|| a.kernel.cmp(&b.kernel)),
+			_ => a.kernel.cmp(&b.kernel),
+		}
+	});
+	Ok(modules)
+}
+
+/// Returns directories containing module directories of kernels, from the most preferred one
+pub(crate) fn base_dirs() -> Vec<PathBuf> {
+	let dirs = BASE_DIRS.read().unwrap_or_else(|e| e.into_inner());
+	match dirs.is_empty() {
+		true => DEFAULT_BASE_DIRS.iter().map(PathBuf::from).collect(),
+		false => dirs.clone(),
+	}
+}
+
+/// Returns module directory of kernel release in the first base directory which contains it
+pub(crate) fn module_dir(release: &OsStr) -> PathBuf {
+	let dirs = base_dirs();
+	// Directory without modules.dep is used only if no other has it (depmod not run yet)
+	let candidates = dirs.iter().map(|dir| dir.join(release));
+	candidates
+		.clone()
+		.find(|dir| vfs::exists(dir.join("modules.dep")))
+		.or_else(|| candidates.clone().find(|dir| vfs::is_dir(dir)))
+		.unwrap_or_else(|| dirs[0].join(release))
+}
+
+/// Returns explicitly given path of manifest, or its path in module directory
+fn manifest_path(basepath: &Path, paths: &HashMap<String, PathBuf>, name: &str) -> PathBuf {
+	match paths.get(name) {
+		Some(path) => path.clone(),
+		None => basepath.join(name),
+	}
+}
+
+/// Returns the latest modification time of modules manifests
+fn modification_time(basepath: &Path) -> Option<SystemTime> {
+	["modules.order", "modules.dep"]
+		.iter()
+		.filter_map(|file| fs::metadata(basepath.join(file)).ok())
+		.filter_map(|metadata| metadata.modified().ok())
+		.max()
+}
@@ -0,0 +1,137 @@
+use std::{
+	collections::HashMap,
+	io,
+	sync::{Arc, Condvar, Mutex, OnceLock},
+};
+
+use crate::Error;
+
+/// Modules being loaded by threads of this process, by name
+static LOADING: OnceLock<Mutex<HashMap<String, Arc<Loading>>>> = OnceLock::new();
+
+/// Loading of module by one thread, waited for by others loading the same module
+#[derive(Default)]
+struct Loading {
+	result: Mutex<Option<Result<(), Failure>>>,
+	done: Condvar,
+}
+
+/// Error of loading copied for waiting threads, as `io::Error` can't be cloned
+#[derive(Clone)]
+struct Failure {
+	/// Error number of plain OS error
+	errno: Option<i32>,
+
+	/// Failed syscall with its error number, for errors of kernel explained by library
+	syscall: Option<(&'static str, i32)>,
+	kind: io::ErrorKind,
+	message: String,
+}
+
+impl Failure {
+	fn from_io(error: &io::Error) -> Failure {
+		Failure {
+			errno: error.raw_os_error(),
+			syscall: Error::syscall(error).zip(Error::errno(error)),
+			kind: error.kind(),
+			message: error.to_string(),
+		}
+	}
+
+	fn to_io(&self) -> io::Error {
+		match (self.errno, self.syscall) {
+			(Some(errno), _) => io::Error::from_raw_os_error(errno),
+			(None, Some((syscall, errno))) => {
+				Error::syscall_copy(syscall, errno, self.kind, self.message.clone())
+			}
+			(None, None) => io::Error::new(self.kind, self.message.clone()),
+		}
+	}
+}
+
+/// Removes loading from registry and wakes waiting threads, also if loading thread panics
+struct Registration {
+	name: String,
+	loading: Arc<Loading>,
+}
+
+impl Drop for Registration {
+	fn drop(&mut self) {
+		registry()
+			.lock()
+			.unwrap_or_else(|e| e.into_inner())
+			.remove(&self.name);
+
+		let mut result = self
+			.loading
+			.result
+			.lock()
+			.unwrap_or_else(|e| e.into_inner());
+		result.get_or_insert_with(|| {
+			Err(Failure {
+				errno: None,
+				syscall: None,
+				kind: io::ErrorKind::Interrupted,
+				message: format!("Loading of module {} has been interrupted", self.name),
+			})
+		});
+		self.loading.done.notify_all();
+	}
+}
+
+/// Loads module unless another thread is already loading it
+///
+/// Only one thread of process loads module at once. Others wait for it and share its outcome:
+/// `Ok(None)` if module has been loaded by other thread, or its error (errors of kernel keep
+/// their error numbers and syscalls, other errors only their kind and message). Fake kernel and
+/// in-memory filesystem are per thread, so with `testing` feature every thread loads modules on
+/// its own.
+pub(crate) fn load<T>(name: &str, load: impl FnOnce() -> io::Result<T>) -> io::Result<Option<T>> {
+	if cfg!(feature = "testing") || crate::vfs::is_memory() {
+		return load().map(Some);
+	}
+
+	let (loading, leader) = {
+		let mut registry = registry().lock().unwrap_or_else(|e| e.into_inner());
+		match registry.get(name) {
+			Some(loading) => (loading.clone(), false),
+			None => {
+				let loading = Arc::new(Loading::default());
+				registry.insert(name.to_string(), loading.clone());
+				(loading, true)
+			}
+		}
+	};
+
+	if !leader {
+		let mut result = loading.result.lock().unwrap_or_else(|e| e.into_inner());
+		while result.is_none() {
+			result = loading.done.wait(result).unwrap_or_else(|e| e.into_inner());
+		}
+		return match result.as_ref() {
+			Some(Err(failure)) => Err(failure.to_io()),
+			_ => Ok(None),
+		};
+	}
+
+	let registration = Registration {
+		name: name.to_string(),
+		loading,
+	};
+	let result = load();
+	*registration
+		.loading
+		.result
+		.lock()
+		.unwrap_or_else(|e| e.into_inner()) = Some(match &result {
+		Ok(_) => Ok(()),
+		Err(e) => Err(Failure::from_io(e)),
+	});
+	drop(registration);
+
+	result.map(Some)
+}
+
+fn registry() -> &'static Mutex<HashMap<String, Arc<Loading>>> {
+	LOADING.get_or_init(|| Mutex::new(HashMap::new()))
+}
@@ -1,26 +1,236 @@
-use std::os::raw::*;
-
-#[repr(C)]
-pub struct Utsname {
-	pub sysname: [c_char; 65],
-	pub nodename: [c_char; 65],
-	pub release: [c_char; 65],
-	pub version: [c_char; 65],
-	pub machine: [c_char; 65],
-}
-
-impl Utsname {
-	pub fn new() -> Utsname {
-		Utsname {
-			sysname: [0; 65],
-			nodename: [0; 65],
-			release: [0; 65],
-			version: [0; 65],
-			machine: [0; 65],
+use std::{
+	ffi::OsString,
+	fs, io,
+	os::{raw::c_char, unix::ffi::OsStringExt},
+};
+
+use crate::{vfs, Error, KernelVersion, ModuleIndex, Selection};
+
+/// Capability required for loading and unloading modules
+const CAP_SYS_MODULE: u32 = 16;
+
+/// Identification of running kernel returned by uname
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::kernel_info() {
+///     Ok(info) => println!("Running {:?} {:?} on {:?}", info.sysname, info.release, info.machine),
+///     Err(e) => eprintln!("Failed to get kernel information: {e}"),
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KernelInfo {
+	/// Name of operating system (e.g. `Linux`)
+	pub sysname: OsString,
+
+	/// Network name of machine
+	pub nodename: OsString,
+
+	/// Release of kernel (e.g. `6.8.0-45-generic`)
+	pub release: OsString,
+
+	/// Build version of kernel
+	pub version: OsString,
+
+	/// Hardware name (e.g. `x86_64`)
+	pub machine: OsString,
+}
+
+impl KernelInfo {
+	/// Parses kernel release as version
+	pub fn release_version(&self) -> io::Result<KernelVersion> {
+		self.release.to_string_lossy().parse()
+	}
+}
+
+/// Returns identification of current running kernel
+pub fn kernel_info() -> io::Result<KernelInfo> {
+	let mut utsname: libc::utsname = unsafe { std::mem::zeroed() };
+	if unsafe { libc::uname(&mut utsname) } != 0 {
+		return Err(io::Error::last_os_error());
+	}
+
+	Ok(KernelInfo {
+		sysname: field(&utsname.sysname),
+		nodename: field(&utsname.nodename),
+		release: field(&utsname.release),
+		version: field(&utsname.version),
+		machine: field(&utsname.machine),
+	})
+}
+
+/// Converts NUL-terminated utsname field without losing non-UTF-8 bytes
+fn field(chars: &[c_char]) -> OsString {
+	let bytes: Vec<u8> = chars
+		.iter()
+		.take_while(|c| **c != 0)
+		.map(|c| *c as u8)
+		.collect();
+	OsString::from_vec(bytes)
+}
+
+/// Module loading features of running kernel, returned by `kernel_capabilities`
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KernelCapabilities {
+	/// Kernel loads modules from file descriptors with finit_module (Linux 3.8+)
+	pub finit_module: bool,
+
+	/// Compression method kernel decompresses modules with (e.g. `xz`), `None` if it can't
+	pub decompression: Option<String>,
+
+	/// Kernel checks signatures of modules (`CONFIG_MODULE_SIG`)
+	pub signatures: bool,
+
+	/// Kernel refuses modules without valid signature (`module.sig_enforce`)
+	pub signatures_enforced: bool,
+
+	/// Compression method of module files installed for running kernel, `None` if uncompressed
+	pub compressed_modules: Option<String>,
+}
+
+/// Taint flags of running kernel as bit mask
+const TAINTED: &str = "/proc/sys/kernel/tainted";
+
+/// Letters of taint flags by their bit, as printed by kernel (e.g. in `/sys/module/<name>/taint`)
+const TAINT_FLAGS: [char; 20] = [
+	'P', 'F', 'S', 'R', 'M', 'B', 'U', 'D', 'A', 'W', 'C', 'I', 'O', 'E', 'L', 'K', 'X', 'T', 'N',
+	'J',
+];
+
+/// Reads taint flags of running kernel, `None` if they're not available
+pub(crate) fn taints() -> Option<u64> {
+	vfs::read_to_string(TAINTED).ok()?.trim().parse().ok()
+}
+
+/// Returns letters of taint flags set in mask, unknown bits are skipped
+pub(crate) fn taint_flags(mask: u64) -> String {
+	TAINT_FLAGS
+		.iter()
+		.enumerate()
+		.filter(|(bit, _)| mask & (1 << bit) != 0)
+		.map(|(_, flag)| *flag)
+		.collect()
+}
+
+/// Parameter of kernel enforcing module signatures, present only with module signing support
+const SIG_ENFORCE: &str = "/sys/module/module/parameters/sig_enforce";
+
+/// Checks if running kernel refuses modules without valid signature (`CONFIG_MODULE_SIG_FORCE` or
+/// `module.sig_enforce=1`)
+pub(crate) fn signatures_enforced() -> bool {
+	vfs::read_to_string(SIG_ENFORCE).is_ok_and(|enforce| enforce.trim() == "Y")
+}
+
+/// Reports which module loading features running kernel supports
+///
+/// Features are probed in sysfs and derived from kernel version, missing information is reported
+/// as unsupported. Compressed modules can be loaded only if `decompression` matches
+/// `compressed_modules`.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::kernel_capabilities() {
+///     Ok(caps) if caps.compressed_modules.is_some() && caps.decompression != caps.compressed_modules => {
+///         eprintln!("Modules of running kernel have to be decompressed before loading")
+///     }
+///     Ok(caps) => println!("Signatures enforced: {}", caps.signatures_enforced),
+///     Err(e) => eprintln!("Failed to probe kernel: {e}"),
+/// }
+/// ```
+pub fn kernel_capabilities() -> io::Result<KernelCapabilities> {
+	let version: KernelVersion = release()?.parse()?;
+	let sig_enforce = vfs::read_to_string(SIG_ENFORCE).ok();
+
+	Ok(KernelCapabilities {
+		finit_module: version >= KernelVersion::new(3, 8, 0),
+		decompression: crate::loader::kernel_compression()?,
+		signatures: sig_enforce.is_some(),
+		signatures_enforced: signatures_enforced(),
+		compressed_modules: ModuleIndex::shared(Selection::Current)
+			.ok()
+			.and_then(|index| index.compression())
+			.map(|method| method.to_string()),
+	})
+}
+
+/// File with release of running kernel, used when uname is not available
+const OSRELEASE: &str = "/proc/sys/kernel/osrelease";
+
+/// Returns release of current running kernel, which doesn't have to be valid UTF-8
+///
+/// If uname fails (e.g. it's filtered by seccomp in sandbox), release is read from
+/// `/proc/sys/kernel/osrelease` and the original error is returned only if that fails too.
+pub(crate) fn current_release() -> io::Result<OsString> {
+	let error = match kernel_info() {
+		Ok(info) => return Ok(info.release),
+		Err(e) => e,
+	};
+
+	match fs::read(OSRELEASE) {
+		Ok(mut release) => {
+			while release.last().is_some_and(|c| c.is_ascii_whitespace()) {
+				release.pop();
+			}
+			Ok(OsString::from_vec(release))
 		}
+		Err(_) => Err(error),
 	}
 }
 
-extern "C" {
-	pub fn uname(utsname: *mut Utsname) -> c_int;
+/// Returns release of current running kernel
+pub fn release() -> io::Result<String> {
+	current_release()?.into_string().map_err(|release| {
+		io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("Kernel release {:?} is not valid UTF-8", release),
+		)
+	})
+}
+
+/// Returns hardware name of current running kernel (e.g. x86_64)
+pub fn machine() -> io::Result<String> {
+	Ok(kernel_info()?.machine.to_string_lossy().into_owned())
+}
+
+/// Checks if current process is permitted to load and unload modules
+///
+/// If capabilities cannot be determined, decision is left to kernel.
+pub fn check_permitted() -> io::Result<()> {
+	// Fake kernel of `testing` feature doesn't need any permissions
+	if cfg!(feature = "testing") {
+		return Ok(());
+	}
+
+	// Other systems than Linux don't have kernel modules loadable by this crate
+	if !cfg!(target_os = "linux") {
+		return Err(crate::backend::unsupported());
+	}
+
+	// Check for CAP_SYS_MODULE capability
+	if let Ok(status) = fs::read_to_string("/proc/self/status") {
+		let effective = status
+			.lines()
+			.find_map(|line| line.strip_prefix("CapEff:"))
+			.and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok());
+		if let Some(caps) = effective {
+			if caps & (1 << CAP_SYS_MODULE) == 0 {
+				return Err(Error::MissingCapability.into());
+			}
+		}
+	}
+
+	// Check if module loading hasn't been disabled
+	if let Ok(disabled) = fs::read_to_string("/proc/sys/kernel/modules_disabled") {
+		if disabled.trim() == "1" {
+			return Err(Error::ModulesDisabled.into());
+		}
+	}
+
+	Ok(())
 }
@@ -0,0 +1,224 @@
+use std::{
+	collections::HashMap,
+	io::{self, ErrorKind},
+	path::Path,
+};
+
+use crate::{vfs, ModuleSignature};
+
+/// Keys of kernel with their type and description, readable by process
+const PROC_KEYS: &str = "/proc/keys";
+
+/// Keyrings kernel verifies module signatures with (secondary one links builtin and machine ones)
+const TRUSTED_KEYRINGS: [&str; 2] = [".builtin_trusted_keys", ".secondary_trusted_keys"];
+
+/// Operation of keyctl reading payload of key (list of key serials for keyrings)
+#[cfg(target_os = "linux")]
+const KEYCTL_READ: libc::c_int = 11;
+
+/// Key in trusted keyring of kernel, which module signatures are verified with
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::trusted_keys() {
+///     Ok(keys) => {
+///         for key in keys {
+///             println!("{} ({:?}) in {}", key.name, key.key_id, key.keyring);
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to read trusted keyrings: {e}"),
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrustedKey {
+	/// Serial number of key in kernel
+	pub serial: u32,
+
+	/// Keyring containing key (e.g. `.builtin_trusted_keys` or `.machine`)
+	pub keyring: String,
+
+	/// Common name of certificate subject
+	pub name: String,
+
+	/// Hexadecimal subject key identifier (or serial number) of certificate
+	pub key_id: Option<String>,
+}
+
+impl TrustedKey {
+	/// Checks if module signature has been made with this key
+	///
+	/// Signatures identifying key (by subject key identifier, or by issuer and serial number)
+	/// are matched only by identifier of key. Signatures without key identifier are matched by
+	/// name of signer.
+	pub fn signed(&self, signature: &ModuleSignature) -> bool {
+		match &signature.key_id {
+			Some(signature_id) => self.key_id.as_ref() == Some(signature_id),
+			None => signature.signer.as_ref() == Some(&self.name),
+		}
+	}
+}
+
+/// Lists keys in keyrings kernel verifies module signatures with
+///
+/// Keys are read from `/proc/keys` and keyrings are listed with `keyctl`, so usually root is
+/// required. Keyrings linked into trusted keyrings (e.g. `.machine` with keys enrolled in MOK)
+/// are read as well. If no trusted keyring is visible, `ErrorKind::NotFound` is returned.
+pub fn trusted_keys() -> io::Result<Vec<TrustedKey>> {
+	let listed = read_proc_keys()?;
+
+	let mut pending: Vec<(u32, String)> = listed
+		.iter()
+		.filter(|(_, (kind, description))| {
+			kind == "keyring" && TRUSTED_KEYRINGS.contains(&description.as_str())
+		})
+		.map(|(serial, (_, description))| (*serial, description.clone()))
+		.collect();
+	if pending.is_empty() {
+		return Err(io::Error::new(
+			ErrorKind::NotFound,
+			"Trusted keyrings are not listed in /proc/keys",
+		));
+	}
+	pending.sort();
+
+	let mut keys = Vec::new();
+	let mut seen = Vec::new();
+	while let Some((keyring, keyring_name)) = pending.pop() {
+		if seen.contains(&keyring) {
+			continue;
+		}
+		seen.push(keyring);
+
+		for serial in read_keyring(keyring)? {
+			match listed.get(&serial) {
+				Some((kind, description)) if kind == "keyring" => {
+					pending.push((serial, description.clone()))
+				}
+				Some((kind, description)) if kind.starts_with("asymmetri") => {
+					let (name, key_id) = match description.rsplit_once(": ") {
+						Some((name, key_id)) => (name, Some(key_id.to_string())),
+						None => (description.as_str(), None),
+					};
+					keys.push(TrustedKey {
+						serial,
+						keyring: keyring_name.clone(),
+						name: name.to_string(),
+						key_id,
+					});
+				}
+				_ => (),
+			}
+		}
+	}
+
+	keys.sort_by_key(|key| key.serial);
+	Ok(keys)
+}
+
+/// Returns key of trusted keyring module file is signed with
+///
+/// `Ok(None)` means that module isn't signed or its key isn't trusted by kernel, so it can't be
+/// loaded while signatures are enforced (e.g. with Secure Boot). Compressed files are read only
+/// with `compression` feature.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::trusted_signing_key("./vboxdrv.ko") {
+///     Ok(Some(key)) => println!("vboxdrv.ko is signed with {}", key.name),
+///     Ok(None) => eprintln!("vboxdrv.ko won't load with Secure Boot"),
+///     Err(e) => eprintln!("Failed to check signature of vboxdrv.ko: {e}"),
+/// }
+/// ```
+pub fn trusted_signing_key(path: impl AsRef<Path>) -> io::Result<Option<TrustedKey>> {
+	let image = crate::index::module_image(path.as_ref())?;
+	let signature = match ModuleSignature::from_image(&image) {
+		Some(signature) => signature,
+		None => return Ok(None),
+	};
+
+	Ok(trusted_keys()?
+		.into_iter()
+		.find(|key| key.signed(&signature)))
+}
+
+/// Reads types and descriptions of keys listed in /proc/keys by serial numbers
+fn read_proc_keys() -> io::Result<HashMap<u32, (String, String)>> {
+	let mut keys = HashMap::new();
+	for line in vfs::read_to_string(PROC_KEYS)?.lines() {
+		// serial, flags, usage, timeout, permissions, uid, gid, type and description
+		let mut rest = line;
+		let mut fields = Vec::new();
+		for _ in 0..8 {
+			rest = rest.trim_start();
+			let end = rest.find(' ').unwrap_or(rest.len());
+			fields.push(&rest[..end]);
+			rest = &rest[end..];
+		}
+
+		let serial = match u32::from_str_radix(fields[0], 16) {
+			Ok(serial) => serial,
+			Err(_) => continue,
+		};
+
+		// Keyrings end with number of keys, asymmetric keys with subtype and short key ID
+		let description = rest.trim_start();
+		let description = match fields[7] {
+			"keyring" => description.split(": ").next().unwrap_or_default(),
+			_ => description
+				.rsplit_once(": X509.")
+				.map_or(description, |(description, _)| description),
+		};
+		keys.insert(serial, (fields[7].to_string(), description.to_string()));
+	}
+
+	Ok(keys)
+}
+
+/// Lists serial numbers of keys linked into keyring
+#[cfg(target_os = "linux")]
+fn read_keyring(keyring: u32) -> io::Result<Vec<u32>> {
+	use libc::{c_long, syscall, SYS_keyctl};
+
+	let read = |buffer: &mut [u8]| -> io::Result<usize> {
+		let ret = unsafe {
+			syscall(
+				SYS_keyctl,
+				KEYCTL_READ as c_long,
+				keyring as c_long,
+				buffer.as_mut_ptr(),
+				buffer.len(),
+			)
+		};
+		match ret {
+			-1 => Err(io::Error::last_os_error()),
+			len => Ok(len as usize),
+		}
+	};
+
+	// Keys may be added between calls, so buffer is grown until whole list fits
+	let mut buffer = vec![0; read(&mut [])?];
+	loop {
+		let len = read(&mut buffer)?;
+		if len <= buffer.len() {
+			buffer.truncate(len);
+			break;
+		}
+		buffer.resize(len, 0);
+	}
+
+	Ok(buffer
+		.chunks_exact(4)
+		.map(|serial| u32::from_ne_bytes([serial[0], serial[1], serial[2], serial[3]]))
+		.collect())
+}
+
+/// Keyrings exist only in Linux
+#[cfg(not(target_os = "linux"))]
+fn read_keyring(_keyring: u32) -> io::Result<Vec<u32>> {
+	Err(crate::backend::unsupported())
+}
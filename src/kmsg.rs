@@ -0,0 +1,211 @@
+use std::{
+	fs,
+	io::{self, ErrorKind, Read, Seek, SeekFrom},
+	os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+	time::Duration,
+};
+
+use crate::index;
+
+/// Kernel log device
+const KMSG: &str = "/dev/kmsg";
+
+/// Maximum size of one kernel log record
+const RECORD_SIZE: usize = 8192;
+
+/// Record of kernel log
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Record {
+	/// Sequence number of record
+	pub sequence: u64,
+
+	/// Priority of record (0 is emergency, 7 is debug)
+	pub priority: u8,
+
+	/// Syslog facility of record (0 for kernel messages)
+	pub facility: u8,
+
+	/// Time since boot when record was logged
+	pub timestamp: Duration,
+
+	/// Text of record
+	pub message: String,
+
+	/// Subsystem of device record is about (e.g. `pci`)
+	pub subsystem: Option<String>,
+
+	/// Device record is about (e.g. `+pci:0000:00:1f.3`)
+	pub device: Option<String>,
+}
+
+impl Record {
+	/// Parses record as read from `/dev/kmsg`
+	///
+	/// Record is `priority,sequence,timestamp,flags;message` line followed by continuation lines
+	/// with `KEY=value` properties, each starting with space.
+	pub fn parse(record: &str) -> Option<Record> {
+		let mut lines = record.lines();
+		let (prefix, message) = lines.next()?.split_once(';')?;
+		let mut fields = prefix.split(',');
+		let level: u32 = fields.next()?.parse().ok()?;
+		let sequence = fields.next()?.parse().ok()?;
+		let timestamp = Duration::from_micros(fields.next()?.parse().ok()?);
+
+		let mut subsystem = None;
+		let mut device = None;
+		for line in lines {
+			match line.trim_start().split_once('=') {
+				Some(("SUBSYSTEM", value)) => subsystem = Some(value.to_string()),
+				Some(("DEVICE", value)) => device = Some(value.to_string()),
+				_ => (),
+			}
+		}
+
+		Some(Record {
+			sequence,
+			priority: (level & 7) as u8,
+			facility: (level >> 3) as u8,
+			timestamp,
+			message: unescape(message),
+			subsystem,
+			device,
+		})
+	}
+
+	/// Checks if record mentions module by its name (as a whole word)
+	pub fn mentions(&self, module: &str) -> bool {
+		let module = index::normalize(module);
+		let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+		self.message.match_indices(&module).any(|(i, _)| {
+			let before = self.message[..i].chars().next_back();
+			let after = self.message[i + module.len()..].chars().next();
+			!before.is_some_and(is_word) && !after.is_some_and(is_word)
+		})
+	}
+}
+
+/// Reader of kernel log records (`/dev/kmsg`)
+///
+/// Reading kernel log may require `CAP_SYSLOG` capability, depending on `kernel.dmesg_restrict`
+/// sysctl. Iterator ends when there are no more records, unless reader follows kernel log.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::kmsg::Reader::open() {
+///     Ok(reader) => {
+///         for record in reader.module("kvm").flatten() {
+///             println!("[{:?}] {}", record.timestamp, record.message);
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to read kernel log: {e}"),
+/// }
+/// ```
+pub struct Reader {
+	file: fs::File,
+	module: Option<String>,
+}
+
+impl Reader {
+	/// Opens kernel log, starting with the oldest record still available
+	pub fn open() -> io::Result<Reader> {
+		let file = fs::OpenOptions::new()
+			.read(true)
+			.custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+			.open(KMSG)?;
+
+		Ok(Reader { file, module: None })
+	}
+
+	/// Opens kernel log, skipping records already present
+	pub fn tail() -> io::Result<Reader> {
+		let mut reader = Reader::open()?;
+		reader.file.seek(SeekFrom::End(0))?;
+
+		Ok(reader)
+	}
+
+	/// Returns only records mentioning module
+	pub fn module(mut self, name: impl AsRef<str>) -> Reader {
+		self.module = Some(name.as_ref().to_string());
+		self
+	}
+
+	/// Waits for new records instead of ending iteration when there are no more records
+	pub fn follow(self, follow: bool) -> io::Result<Reader> {
+		let fd = self.file.as_raw_fd();
+		let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+		if flags == -1 {
+			return Err(io::Error::last_os_error());
+		}
+
+		let flags = match follow {
+			true => flags & !libc::O_NONBLOCK,
+			false => flags | libc::O_NONBLOCK,
+		};
+		if unsafe { libc::fcntl(fd, libc::F_SETFL, flags) } == -1 {
+			return Err(io::Error::last_os_error());
+		}
+
+		Ok(self)
+	}
+
+	/// Reads next record, `None` if there are no more records
+	fn read_record(&mut self) -> io::Result<Option<Record>> {
+		let mut buf = vec![0u8; RECORD_SIZE];
+		loop {
+			// Every read returns exactly one record
+			let len = match self.file.read(&mut buf) {
+				Ok(0) => return Ok(None),
+				Ok(o) => o,
+				Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+				Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+				// Records have been overwritten before they were read
+				Err(e) if e.raw_os_error() == Some(libc::EPIPE) => continue,
+				Err(e) => return Err(e),
+			};
+
+			let record = match Record::parse(&String::from_utf8_lossy(&buf[..len])) {
+				Some(o) => o,
+				None => continue,
+			};
+			match &self.module {
+				Some(module) if !record.mentions(module) => continue,
+				_ => return Ok(Some(record)),
+			}
+		}
+	}
+}
+
+impl Iterator for Reader {
+	type Item = io::Result<Record>;
+
+	fn next(&mut self) -> Option<io::Result<Record>> {
+		self.read_record().transpose()
+	}
+}
+
+/// Decodes `\xNN` escapes used by kernel for non-printable characters
+fn unescape(message: &str) -> String {
+	let mut bytes = Vec::with_capacity(message.len());
+	let mut rest = message.as_bytes();
+	while let Some((&byte, tail)) = rest.split_first() {
+		if byte == b'\\' && tail.len() >= 3 && tail[0] == b'x' {
+			if let Some(decoded) = std::str::from_utf8(&tail[1..3])
+				.ok()
+				.and_then(|hex| u8::from_str_radix(hex, 16).ok())
+			{
+				bytes.push(decoded);
+				rest = &tail[3..];
+				continue;
+			}
+		}
+
+		bytes.push(byte);
+		rest = tail;
+	}
+
+	String::from_utf8_lossy(&bytes).into_owned()
+}
@@ -2,58 +2,494 @@
 //!
 //! ### Features:
 //! - Loading modules (modprobe)
-//! - Unloading modules (rmmod)
+//! - Loading multiple modules at once (modprobe -a)
+//! - Loading module files with dependencies from their modinfo (out-of-tree modules)
+//! - Resolving load plans ahead of time and executing them later
+//! - Load plans cached in shared index, so repeated `ensure_loaded` calls only check loaded modules
+//! - Resolving module aliases (modules.alias.bin is memory-mapped) with `alias` feature
+//! - Suggesting similar module names when module isn't found (case, typos, aliases)
+//! - Reporting all modules matching alias, including blacklisted ones with source of blacklist with `alias` feature
+//! - Alias matcher serializable into compact blob, for matching devices without module tree with `alias` feature
+//! - Finding drivers for PCI and USB devices with `uevent` feature
+//! - Autoloading modules for devices (coldplug and uevents) with blacklist and denylist (`uevent`)
+//! - Parallel coldplug with bounded number of threads (`Options::jobs`)
+//! - Binding devices to drivers (bind, unbind, new_id, driver_override) with `uevent` feature
+//! - Loading modules listed in modules-load.d
+//! - Applying manifests of modules with parameters and kernel version conditions, all or none
+//! - Module options from modprobe.d configuration and kernel command line with `config` feature
+//! - User-defined aliases from modprobe.d, including chained aliases with `config` feature
+//! - Install and remove commands from modprobe.d, which can be ignored like `modprobe -i` with `config` feature
+//! - Recognizing modules disabled with `install <name> /bin/false` (or `/bin/true`)
+//! - Detecting loops and deep nesting of install and remove commands across processes
+//! - Options from `MODPROBE_OPTIONS` and `LIBLMOD_OPTIONS` environment variables read by `Options::new`, dry runs
+//! - Showing effective modprobe.d configuration with sources of directives (modprobe -c) with `config` feature
+//! - Options of modules merged across files and kernel command line (`Config::merged_options`)
+//! - Module search order and overrides from depmod.d configuration with `config` feature
+//! - Deterministic choice between module files with the same name, reporting ambiguous ones
+//! - Custom module resolution backends (e.g. embedded module archives)
+//! - Configuration and module indices built from strings, passed to modprobe without filesystem reads
+//! - Manifests of module directory read from explicit paths (A/B systems, staged module sets)
+//! - Loading modules from additional out-of-tree directories
+//! - Module directories in `/lib/modules` or `/usr/lib/modules` (merged-/usr systems)
+//! - Layered module roots (overlays over read-only squashfs or erofs trees) looked up in turn
+//! - Iterating over all available modules with size, compression, aliases and dependencies
+//! - Finding installed kernels providing module (e.g. before removing old kernels)
+//! - Verifying whole module trees (missing files, architecture and version magic of modules) with `modinfo` feature
+//! - Flat module directories of Android-derived systems (`/vendor/lib/modules`)
+//! - Optional inter-process locking of module operations
+//! - Threads loading the same module wait for the first one and share its outcome
+//! - Confining reads of module files to module directory (openat2 RESOLVE_BENEATH)
+//! - Optionally refusing to load module files which are symbolic links
+//! - Loading compressed modules decompressed by kernel
+//! - Streaming decompression of modules with bounded memory (`compression` feature)
+//! - Registering decompressors of other module compression formats (e.g. lz4 or vendor ones)
+//! - Reading module files into one buffer reused for whole dependency chain
+//! - Loading modules streamed from any reader
+//! - Thin wrappers of module syscalls (init_module, finit_module, delete_module)
+//! - Calling module syscalls through libc wrappers, interceptable with `LD_PRELOAD`
+//! - Error numbers and failed syscalls retained behind structured errors of kernel
+//! - Loading modules with timeout for modules hanging in initialization
+//! - Per-module timeouts and retries of loading from policy file, tunable without code changes
+//! - Lower CPU and I/O priority of modprobe work for preloading in background during boot
+//! - Prefetching module files into page cache ahead of critical phases of boot
+//! - Retrying with re-read index when module files are missing during package upgrades
+//! - Attaching kernel log messages to errors of failed loading
+//! - Reading structured kernel log records (kmsg) filtered by module
+//! - Checking SHA-256 digest of modules before loading with `integrity` feature
+//! - Signers of module signatures, unsigned modules refused before loading when signatures are enforced
+//! - Checking if signing key of module is in trusted keyrings of kernel with `keys` feature
+//! - Pluggable policies deciding which modules can be loaded
+//! - Cancelling module loading from another thread
+//! - Hooks run before and after loading and unloading modules
+//! - Timing metrics of resolution, file reads and syscalls per module
+//! - Per-phase load timings in `LoadReport`, traced as spans with `tracing` feature
+//! - Sizes, compression and decompression strategy of every loaded module in `LoadReport`
+//! - Kernel taint flags newly set by loading modules, with likely responsible module
+//! - Dependency graph export (Graphviz DOT)
+//! - Computing modules and firmware needed by modules (e.g. for initramfs)
+//! - Staging module trees with chosen compression (xz and zstd with `compression` feature)
+//! - Reading module information (modinfo) and validating parameters with `modinfo` feature
+//! - Classifying modules and loaded modules as in-tree, out-of-tree or staging (`ModuleOrigin`)
+//! - Listing sections, exported and undefined symbols of module files with `modinfo` feature
+//! - Checking symbol versions (modversions) against kallsyms or Module.symvers, naming mismatches with `modinfo` feature
+//! - Checking presence of firmware required by modules with `modinfo` feature
+//! - Kernel information and version parsing (uname, with /proc fallback in sandboxes)
+//! - Overriding machine of uname per call, for module trees of other architecture than process
+//! - Detecting module loading features of kernel (finit_module, decompression, signatures)
+//! - Unloading modules (rmmod), with holders of busy modules and retrying
+//! - Listing loaded modules (lsmod) with their memory usage and versions
+//! - Current parameters of loaded modules compared with defaults documented by them
+//! - Graph of loaded modules and their users, with roots, leaves and cycles
+//! - Detecting loaded modules which differ from module files (e.g. after upgrade)
+//! - Making sure modules are loaded or unloaded, reporting whether anything changed
+//! - Classifying modules as builtin, loaded, available or missing in one pass
+//! - Validating and normalizing module names with rules of kernel (length, characters, dashes)
+//! - Keeping modules in use through their device nodes, so they cannot be unloaded
+//! - Static device nodes of modules for early boot, like `kmod static-nodes` (with tmpfiles.d lines)
+//! - Unloading unused modules (autoclean)
+//! - Unloading all modules matching pattern, users first
+//! - Unloading module together with all modules using it, leaf-first
+//! - Unloading lists of modules past failures with per-module results, optionally users first
+//! - Replacing loaded module with new image, loading the old one back on failure (`reload`)
+//! - Sessions tracking why modules were loaded, unloading them in reverse order or when dropped
+//! - Watching for loaded and removed modules and waiting until module is live (`watch` feature)
+//! - Reloading modprobe.d configuration, watching it for changed directives with `watch` feature
+//! - Serialization of module data with `serde` feature
+//! - JSON output of module information and loaded modules with `json` feature
+//! - Recording loaded and unloaded modules in system log with `audit` feature
+//! - Asynchronous stream of uevents filtered by kernel with `async` feature
+//! - Fake kernel recording loaded and unloaded modules with `testing` feature
+//! - Validating modules.dep and modules.order, reporting malformed entries with their position
+//! - Dependencies of modules missing from modules.dep read from their modinfo
+//! - In-memory filesystem for testing resolution without root and real kernel tree
+//! - libkmod-like object model (`compat::kmod`) for projects ported from libkmod (`compat` feature)
+//! - Builds on other Unix systems (e.g. macOS), where module operations fail with `Error::Unsupported`
+//! - Minimal default build for initramfs tools, other subsystems enabled by features (`full` for all)
 //!
 //! ### Example code:
-//! ```rust
+//! ```rust,no_run
 //! extern crate liblmod;
 //!
 //! fn main() -> std::io::Result<()> {
 //!     println!("Unloading module kvm");
-//!     liblmod::rmmod("kvm".to_string(), liblmod::Flags::Force)?;
+//!     liblmod::rmmod("kvm", liblmod::Flags::Force)?;
 //!
 //!     println!("Loading module kvm");
-//!     liblmod::modprobe("kvm".to_string(), "".to_string(), liblmod::Selection::Current)
+//!     liblmod::modprobe("kvm", "", liblmod::Selection::Current)
 //! }
 //! ```
 
+#[cfg(feature = "alias")]
+mod alias;
+#[cfg(feature = "audit")]
+mod audit;
+#[cfg(feature = "uevent")]
+mod autoload;
+mod backend;
+mod batch;
+#[cfg(feature = "uevent")]
+pub mod binding;
+#[cfg(feature = "alias")]
+mod binindex;
+mod buffer;
+mod cancel;
+mod closure;
+#[cfg(feature = "compat")]
+pub mod compat;
+mod compress;
+mod config;
+mod confine;
+mod deadline;
+mod devname;
+mod elf;
+mod error;
+#[cfg(feature = "modinfo")]
+mod firmware;
+mod graph;
+mod guard;
+mod hooks;
+#[cfg(feature = "uevent")]
+mod hwid;
+mod index;
+mod inflight;
+#[cfg(feature = "keys")]
+mod keys;
+pub mod kmsg;
+mod loaded;
 pub mod loader;
-mod module_libc;
+mod lock;
+mod manifest;
+mod metrics;
+mod modinfo;
+mod modprobe;
+mod params;
+mod plan;
+mod policy;
+mod prefetch;
+mod priority;
+mod reload;
+mod resolver;
+mod rmmod;
+mod session;
+mod signature;
+mod suggest;
+#[cfg(feature = "modinfo")]
+mod symbols;
+pub mod sys;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "uevent")]
+pub mod uevent;
+#[cfg(feature = "modinfo")]
+mod verify;
+mod version;
+mod vfs;
+#[cfg(feature = "watch")]
+pub mod watch;
+
+#[cfg(feature = "alias")]
+pub use alias::AliasMatcher;
+#[cfg(feature = "uevent")]
+pub use autoload::{
+	autoload, coldplug, resolve_alias, AliasCandidate, AliasResolution, AutoloadReport, Skipped,
+};
+pub use batch::{apply_manifest, ManifestEntry, ManifestReport, ModuleManifest};
+pub use cancel::CancellationToken;
+pub use closure::{closure_for, stage_modules, Closure, Compression};
+pub use compress::{register_decompressor, Decompressor};
+pub use config::{Config, ConfigChanges, Directive};
+pub use deadline::{DeadlineRule, LoadDeadlines};
+pub use devname::{static_nodes, NodeKind, StaticNode};
+pub use error::{CrcMismatch, Error};
+#[cfg(feature = "modinfo")]
+pub use firmware::{find_firmware, module_firmware, Firmware};
+pub use graph::DependencyGraph;
+pub use guard::ModuleGuard;
+pub use hooks::{add_hook, clear_hooks, HookEvent, ModuleHook};
+#[cfg(feature = "uevent")]
+pub use hwid::{module_for_pci, module_for_usb, PciDevice, UsbDevice};
+pub use index::{kernels_providing, KernelModule, ModuleEntry, ModuleIndex};
+pub use kernel::{kernel_capabilities, kernel_info, KernelCapabilities, KernelInfo};
+#[cfg(feature = "keys")]
+pub use keys::{trusted_keys, trusted_signing_key, TrustedKey};
+pub use loaded::{
+	holder_graph, lsmod, module_parameters, LoadedModule, ModuleMemory, ParameterState,
+};
+pub use metrics::{enable_metrics, take_metrics, LoadTimings, Metrics, ModuleMetrics};
+#[cfg(feature = "modinfo")]
+pub use modinfo::modinfo;
+pub use modinfo::{ModuleInfo, ModuleOrigin, Parameter};
+pub use modprobe::{
+	Decompression, ImageReport, LicensePolicy, LoadReport, ModuleReport, Options, Outcome,
+	Provenance, TaintReport,
+};
+pub use params::{ParamValue, Params};
+pub use plan::{LoadPlan, PlanStep};
+pub use policy::{clear_load_policy, set_load_policy, Decision, LoadPolicy};
+pub use prefetch::{prefetch, prefetch_with, PrefetchReport};
+pub use priority::IoPriority;
+pub use reload::ReloadOptions;
+pub use resolver::ModuleResolver;
+pub use rmmod::RemoveOptions;
+pub use session::{ModuleSession, SessionModule};
+pub use signature::ModuleSignature;
+#[cfg(feature = "modinfo")]
+pub use symbols::{
+	check_modversions, module_symbols, ExportedSymbol, ModuleSymbols, Section, SymbolVersion,
+	Symvers,
+};
+#[cfg(feature = "modinfo")]
+pub use verify::{verify_tree, verify_tree_for, TreeReport};
+pub use version::KernelVersion;
+pub use vfs::{clear_filesystem, set_filesystem, MemoryFs};
 
-use std::io::ErrorKind;
 use std::{
-	ffi::CStr,
-	fs,
-	io::{self, BufRead, BufReader, Read},
-	os::raw::*,
+	collections::{BTreeMap, HashSet},
+	io::{self, ErrorKind},
 	path::Path,
+	time::Duration,
 };
 
+use metrics::Phase;
+
 /// Loads module by path
 ///
+/// Compressed modules (`.ko.xz`, `.ko.zst` and `.ko.gz`) are decompressed by kernel if it
+/// supports their compression method (see `loader::kernel_compression`). Otherwise they are
+/// decompressed in chunks into memory file with decompressor of library (see `Decompressor`),
+/// up to `loader::decompression_limit`. Architecture of module is checked against machine of
+/// running kernel, `modprobe_path_with` checks it against `Options::machine` instead.
+///
 /// Example
 /// ```rust
 /// extern crate liblmod;
 ///
-/// if let Err(e) = liblmod::load("./example_module.ko", "example.param=0".to_string()) {
+/// if let Err(e) = liblmod::load("./example_module.ko", "example.param=0") {
+///     eprintln!("Failed to load module: {e}");
+/// }
+/// ```
+pub fn load(path: impl AsRef<Path>, params: impl AsRef<str>) -> io::Result<()> {
+	load_path(path.as_ref(), params.as_ref(), None, true, None, None, 0).map(|_| ())
+}
+
+/// Loads module by path after checking SHA-256 digest of the file
+///
+/// Module is not inserted if digest of the file (compressed one for compressed modules) doesn't
+/// match, `Error::IntegrityMismatch` is returned instead.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let digest = [0u8; 32]; // Digest published with module
+/// if let Err(e) = liblmod::load_verified("./example_module.ko", "example.param=0", &digest) {
 ///     eprintln!("Failed to load module: {e}");
 /// }
 /// ```
-pub fn load(path_str: &str, params: String) -> io::Result<()> {
-	let path = Path::new(path_str);
+#[cfg(feature = "integrity")]
+pub fn load_verified(
+	path: impl AsRef<Path>,
+	params: impl AsRef<str>,
+	digest: &[u8; 32],
+) -> io::Result<()> {
+	load_path(
+		path.as_ref(),
+		params.as_ref(),
+		Some(digest),
+		true,
+		None,
+		None,
+		0,
+	)
+	.map(|_| ())
+}
 
-	// Read data from file
-	let mut file = fs::File::open(path)?;
-	let mut image = Vec::new();
-	file.read_to_end(&mut image)?;
+/// Loads module by path, giving up after timeout
+///
+/// Module is loaded on helper thread, so module hanging in its init function doesn't block
+/// caller. On timeout `Error::Timeout` is returned, but the helper thread keeps waiting and
+/// module may still be loaded later. Like with `load`, architecture of module is checked against
+/// machine of running kernel.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// use std::time::Duration;
+///
+/// if let Err(e) = liblmod::load_timeout("./example_module.ko", "", Duration::from_secs(10)) {
+///     eprintln!("Failed to load module: {e}");
+/// }
+/// ```
+pub fn load_timeout(
+	path: impl AsRef<Path>,
+	params: impl AsRef<str>,
+	timeout: Duration,
+) -> io::Result<()> {
+	let path = path.as_ref().to_path_buf();
+	let params = params.as_ref().to_string();
+	let name = index::module_name(&path.to_string_lossy());
 
-	// Call a loader
-	loader::load(&image, params)
+	// Wait for loading on helper thread
+	deadline::run_timeout(&name, timeout, move || {
+		load_path(&path, &params, None, true, None, None, 0).map(|_| ())
+	})
+}
+
+/// Loads module file, load policy is consulted only if requested
+///
+/// `flags` are additional flags of `finit_module` (e.g. `sys::MODULE_INIT_IGNORE_VERMAGIC`),
+/// they are ignored for in-memory files.
+#[cfg_attr(not(feature = "integrity"), allow(unused_variables))]
+pub(crate) fn load_path(
+	path: &Path,
+	params: &str,
+	digest: Option<&[u8; 32]>,
+	consult_policy: bool,
+	machine: Option<&str>,
+	cancellation: Option<&CancellationToken>,
+	flags: u32,
+) -> io::Result<(LoadTimings, ImageReport)> {
+	// Fail early if process is not permitted to load modules
+	kernel::check_permitted()?;
+
+	// In-memory files have no descriptor to pass to kernel, they are never decompressed
+	let compression = loader::file_compression(path);
+	let method = compression.clone().filter(|_| !vfs::is_memory());
+	let library = match &method {
+		Some(method) => !loader::kernel_decompresses(method)?,
+		None => false,
+	};
+
+	// Read data from file into buffer sized from metadata and reused for following modules of
+	// the same call. Compressed modules are read only if digest of the whole file has to be
+	// checked, or if load policy inspects image decompressed by kernel, others are streamed from
+	// file (by library) or passed to kernel as file.
+	let needed = digest.is_some()
+		|| match &method {
+			None => true,
+			Some(_) => !library && consult_policy && policy::is_set(),
+		};
+	let mut timings = LoadTimings::default();
+	let mut file = None;
+	let mut file_size = 0;
+	let (image, read) = metrics::phase(Phase::Read, path, || {
+		if vfs::is_memory() {
+			return vfs::read(path);
+		}
+
+		let file = file.insert(loader::open(path)?);
+		file_size = file.metadata().map_or(0, |metadata| metadata.len());
+		if !needed {
+			return Ok(Vec::new());
+		}
+
+		let mut image = buffer::take(file_size as usize);
+		loader::read_file(file, &mut image, cancellation)?;
+		Ok(image)
+	});
+	timings.read = read;
+	let image = image?;
+	if file.is_none() {
+		file_size = image.len() as u64;
+	}
+
+	#[cfg(feature = "integrity")]
+	if let Some(digest) = digest {
+		loader::verify(&image, digest)?;
+	}
+
+	// Policy inspects decompressed image
+	if consult_policy && !library {
+		match &method {
+			Some(method) => policy::check_compressed(&image, method, path)?,
+			None => policy::check(&image, path)?,
+		}
+	}
+
+	let mut report = ImageReport {
+		file_size,
+		size: Some(file_size),
+		compression,
+		decompression: Decompression::None,
+	};
+	let decompressed = match (&method, &file) {
+		(Some(method), Some(file)) if library => {
+			let (decompressed, decompress) =
+				metrics::phase(Phase::Decompress, path, || match image.is_empty() {
+					true => loader::decompress_file(file, path, method, machine, cancellation),
+					false => {
+						loader::decompress_file(&image[..], path, method, machine, cancellation)
+					}
+				});
+			timings.decompress = decompress;
+			let mut decompressed = decompressed?;
+			if consult_policy && policy::is_set() {
+				policy::check(&loader::read_back(&mut decompressed)?, path)?;
+			}
+			Some(decompressed)
+		}
+		_ => None,
+	};
+
+	match (&decompressed, &method) {
+		(Some(decompressed), _) => {
+			report.size = decompressed.metadata().ok().map(|metadata| metadata.len());
+			report.decompression = Decompression::Library;
+		}
+		(None, Some(method)) => {
+			report.size = file
+				.as_ref()
+				.and_then(|file| compress::uncompressed_size(file, method));
+			report.decompression = Decompression::Kernel;
+		}
+		(None, None) => (),
+	}
+
+	let (result, syscall) = metrics::phase(Phase::Syscall, path, || {
+		match (decompressed, &file) {
+			(Some(decompressed), _) => loader::finit(&decompressed, path, params, flags),
+
+			// Let kernel decompress compressed modules
+			(None, Some(file)) if method.is_some() => {
+				loader::finit(file, path, params, sys::MODULE_INIT_COMPRESSED_FILE | flags)
+			}
+
+			// Flags can be passed only with file descriptor
+			(None, Some(file)) if flags != 0 => loader::check(&image, path, machine)
+				.and_then(|()| loader::finit(file, path, params, flags)),
+
+			// Call a loader
+			_ => loader::insert(&image, path, params, machine),
+		}
+	});
+	timings.syscall = syscall;
+	metrics::module(path, file_size as usize, &timings);
+	buffer::give(image);
+
+	result.map(|()| (timings, report))
 }
 
 mod kernel;
 
+/// Returns version of current running kernel (`uname -r`)
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::uname() {
+///     Ok(version) => println!("Running kernel {}.{}", version.major, version.minor),
+///     Err(e) => eprintln!("Failed to get kernel version: {e}"),
+/// }
+/// ```
+pub fn uname() -> io::Result<KernelVersion> {
+	kernel::release()?.parse()
+}
+
 /// Enum for modprobe function
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Selection {
 	/// Use current kernel
 	Current,
@@ -69,138 +505,819 @@ pub enum Selection {
 /// extern crate liblmod;
 ///
 /// println!("Loading for current running");
-/// if let Err(e) = liblmod::modprobe("kvm".to_string(), "".to_string(), liblmod::Selection::Current) {
+/// if let Err(e) = liblmod::modprobe("kvm", "", liblmod::Selection::Current) {
 ///     eprintln!("Failed to load module kvm for current running kernel: {e}");
 /// }
 ///
 /// println!("Loading for 5.4-x86_64");
-/// if let Err(e) = liblmod::modprobe("kvm".to_string(), "".to_string(), liblmod::Selection::Other("5.4-x86_64".to_string())) {
+/// if let Err(e) = liblmod::modprobe("kvm", "", liblmod::Selection::Other("5.4-x86_64".to_string())) {
 ///     eprintln!("Failed to load module kvm for kernel 5.4-x86_64");
 /// }
 /// ```
-pub fn modprobe(name: String, params: String, kernel: Selection) -> io::Result<()> {
-	// Get kernel version
-	let kernelname = match kernel {
-		Selection::Other(a) => a,
-		Selection::Current => {
-			let mut uname = kernel::Utsname::new();
-			unsafe {
-				if kernel::uname(&mut uname) != 0 {
-					return Err(io::Error::last_os_error());
-				}
-
-				CStr::from_ptr(uname.release.as_ptr())
-					.to_str()
-					.unwrap()
-					.to_string()
-			}
-		}
-	};
+pub fn modprobe(
+	name: impl AsRef<str>,
+	params: impl AsRef<str>,
+	kernel: Selection,
+) -> io::Result<()> {
+	modprobe_with(name, params, &Options::new().kernel(kernel)).map(|_| ())
+}
 
-	// Construct modules manifests paths
-	let basepath = format!("/lib/modules/{}", &kernelname);
-	let modulespath = format!("{}/modules.order", &basepath);
-	let depspath = format!("{}/modules.dep", &basepath);
-
-	let mut module: String = String::new();
-	let mut path: String = String::new();
-
-	// Get path for specified module from modules.order
-	{
-		let fd = fs::File::open(&modulespath)?;
-		let br = BufReader::new(fd);
-		for line in br.lines() {
-			let unwrapped = match line {
-				Ok(o) => o,
-				Err(e) => return Err(e),
-			};
-			if unwrapped.contains(format!("/{}.ko", &name).as_str()) {
-				module = unwrapped.clone();
-				path = format!("{}/{}", &basepath, unwrapped.clone());
-			}
-		}
+/// Loads module with specified options and reports whether kernel state has changed
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::modprobe_with("kvm", "", &liblmod::Options::new()) {
+///     Ok(liblmod::Outcome::Loaded) => println!("Module kvm has been loaded"),
+///     Ok(liblmod::Outcome::AlreadyLoaded) => println!("Module kvm was already loaded"),
+///     Ok(liblmod::Outcome::Builtin) => println!("Module kvm is built into kernel"),
+///     Ok(liblmod::Outcome::NotAvailable) => println!("Module kvm is not available"),
+///     Ok(liblmod::Outcome::DryRun) => println!("Module kvm would be loaded"),
+///     Ok(liblmod::Outcome::DisabledByConfig) => println!("Module kvm is disabled in modprobe.d"),
+///     Err(e) => eprintln!("Failed to load module kvm: {e}"),
+/// }
+/// ```
+pub fn modprobe_with(
+	name: impl AsRef<str>,
+	params: impl AsRef<str>,
+	options: &Options,
+) -> io::Result<Outcome> {
+	modprobe_report(name, params, options).map(|report| report.outcome)
+}
+
+/// Loads module with specified options and reports what has been done with every processed module
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::modprobe_report("kvm", "", &liblmod::Options::new()) {
+///     Ok(report) => {
+///         for module in report.loaded() {
+///             println!("Loaded {} from {} with params '{}'", module.name, module.path, module.params);
+///             if let Some(image) = &module.image {
+///                 println!("{} bytes on disk, {:?} decompressed", image.file_size, image.size);
+///             }
+///         }
+///         let timings = report.timings;
+///         println!(
+///             "Read {:?}, decompressed {:?}, inserted {:?}",
+///             timings.read, timings.decompress, timings.syscall
+///         );
+///         if let Some(taint) = &report.taint {
+///             eprintln!("Kernel tainted with {} (by module {:?})", taint.flags, taint.module);
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to load module kvm: {e}"),
+/// }
+/// ```
+pub fn modprobe_report(
+	name: impl AsRef<str>,
+	params: impl AsRef<str>,
+	options: &Options,
+) -> io::Result<LoadReport> {
+	modprobe::resilient(options, |index, config| {
+		modprobe::probe(index, config, name.as_ref(), params.as_ref(), options)
+	})
+}
+
+/// Loads module file (e.g. out-of-tree module) with dependencies listed in its `.modinfo`
+///
+/// Dependencies are resolved with module index of current running kernel and loaded like with
+/// `modprobe`, options from modprobe.d apply to dependencies and module file itself.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::modprobe_path("./vboxdrv.ko", "") {
+///     Ok(report) => println!("Module vboxdrv: {:?}", report.outcome),
+///     Err(e) => eprintln!("Failed to load vboxdrv.ko: {e}"),
+/// }
+/// ```
+pub fn modprobe_path(path: impl AsRef<Path>, params: impl AsRef<str>) -> io::Result<LoadReport> {
+	modprobe_path_with(path, params, &Options::new())
+}
+
+/// Loads module file with dependencies listed in its `.modinfo`, using specified options
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let options = liblmod::Options::new().module_params("vboxdrv", "force_async_tsc=1");
+/// if let Err(e) = liblmod::modprobe_path_with("./vboxdrv.ko", "", &options) {
+///     eprintln!("Failed to load vboxdrv.ko: {e}");
+/// }
+/// ```
+pub fn modprobe_path_with(
+	path: impl AsRef<Path>,
+	params: impl AsRef<str>,
+	options: &Options,
+) -> io::Result<LoadReport> {
+	modprobe::resilient(options, |index, config| {
+		let (plan, resolve) = metrics::time(|| {
+			metrics::resolve(|| {
+				plan::build_path(index, config, path.as_ref(), params.as_ref(), options)
+			})
+		});
+
+		let _lock = lock::Lock::acquire_if(options.lock)?;
+		let mut report = plan::execute(&plan?, options)?;
+		report.timings.resolve = resolve;
+		Ok(report)
+	})
+}
 
-		if path.eq("") {
-			return Err(io::Error::new(
-				io::ErrorKind::Other,
-				format!("Module is not provided by {kernelname} kernel"),
-			));
+/// Computes plan for loading module with its dependencies, without loading anything
+///
+/// Module files, licenses and parameters are checked now, while load policy and already loaded
+/// modules are consulted when plan is executed. Plan can be inspected, stored (with `serde`
+/// feature) and executed later with `execute`.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::resolve("kvm_intel", "nested=1", &liblmod::Options::new()) {
+///     Ok(plan) => {
+///         for step in &plan.steps {
+///             println!("Would load {} with params '{}'", step.path.display(), step.params);
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to resolve module kvm_intel: {e}"),
+/// }
+/// ```
+pub fn resolve(
+	name: impl AsRef<str>,
+	params: impl AsRef<str>,
+	options: &Options,
+) -> io::Result<LoadPlan> {
+	modprobe::resilient(options, |index, config| {
+		metrics::resolve(|| plan::build(index, config, name.as_ref(), params.as_ref(), options))
+	})
+}
+
+/// Loads modules of plan computed by `resolve`, skipping ones which are already loaded
+///
+/// Kernel selection, resolver and search directories of options are not used, plan already
+/// contains paths of module files. Plan made for other kernel than running one fails with
+/// `Error::VersionMismatch`.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let options = liblmod::Options::new();
+/// match liblmod::resolve("kvm", "", &options) {
+///     Ok(plan) => match liblmod::execute(&plan, &options) {
+///         Ok(report) => println!("Module kvm: {:?}", report.outcome),
+///         Err(e) => eprintln!("Failed to load module kvm: {e}"),
+///     },
+///     Err(e) => eprintln!("Failed to resolve module kvm: {e}"),
+/// }
+/// ```
+pub fn execute(plan: &LoadPlan, options: &Options) -> io::Result<LoadReport> {
+	// Plan refers to module files of kernel it has been made for
+	let kernel = kernel::release()?;
+	if plan.kernel != kernel {
+		return Err(Error::VersionMismatch {
+			module: plan.name.clone(),
+			vermagic: None,
+			kernel,
 		}
+		.into());
 	}
 
-	// Load dependencies for module
-	if !module.eq("") {
-		let fd = fs::File::open(&depspath)?;
-		let br = BufReader::new(fd);
-		for line in br.lines() {
-			let unwrapped = match line {
-				Ok(o) => o,
-				Err(e) => return Err(e),
-			};
-			if unwrapped.starts_with(&module) {
-				let split: Vec<&str> = unwrapped.split(" ").collect();
-				let length = split.len();
-				if length > 1 {
-					for dep in &split[1..] {
-						let modpath = format!("{}/{}", &basepath, dep);
-
-						match load(modpath.as_str(), String::new()) {
-							Err(e) => {
-								if e.kind() != ErrorKind::AlreadyExists {
-									return Err(e);
-								}
-							}
-							Ok(_) => (),
-						}
-					}
-				}
+	let _lock = lock::Lock::acquire_if(options.lock)?;
+	plan::execute(plan, options)
+}
+
+/// Returns effective modprobe configuration with file each directive comes from, like `modprobe -c`
+///
+/// Directives are listed in order they are read: modprobe.d files in alphabetical order (file in
+/// more important directory overriding file with the same name) followed by module options from
+/// kernel command line.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::show_config() {
+///     Ok(directives) => {
+///         for directive in directives {
+///             println!("{directive}  # {}", directive.source.display());
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to read modprobe configuration: {e}"),
+/// }
+/// ```
+pub fn show_config() -> io::Result<Vec<Directive>> {
+	Ok(Config::load()?.directives().to_vec())
+}
+
+/// Loads multiple modules sharing one parsed index, like `modprobe -a`
+///
+/// Modules are loaded in order of modules.order, so drivers claim devices in the same order as
+/// with modprobe of distribution (which keeps naming of network interfaces and disks stable).
+/// Modules not listed there are loaded last, in order of `names`. Failure of one module doesn't
+/// stop loading of the others.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// match liblmod::modprobe_all(&["kvm", "vhost_net", "tun"], &liblmod::Options::new()) {
+///     Ok(results) => {
+///         for (name, result) in results {
+///             if let Err(e) = result {
+///                 eprintln!("Failed to load module {name}: {e}");
+///             }
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to read module index: {e}"),
+/// }
+/// ```
+pub fn modprobe_all<S: AsRef<str>>(
+	names: &[S],
+	options: &Options,
+) -> io::Result<BTreeMap<String, io::Result<Outcome>>> {
+	let index = options.index()?;
+	let config = options.load_config()?;
+
+	// Unlisted modules get no position, so they are sorted last
+	let mut names: Vec<&str> = names.iter().map(|name| name.as_ref()).collect();
+	names.sort_by_key(|name| {
+		index
+			.resolve(name)
+			.and_then(|module| index.order(module))
+			.unwrap_or(usize::MAX)
+	});
+
+	let mut results = BTreeMap::new();
+	for name in names {
+		let result = match modprobe::probe(&*index, &config, name, "", options) {
+			Err(e) if e.kind() == ErrorKind::NotFound && options.resilient > 0 => {
+				modprobe::resilient(options, |index, config| {
+					modprobe::probe(index, config, name, "", options)
+				})
 			}
-		}
+			result => result,
+		};
+		results.insert(name.to_string(), result.map(|report| report.outcome));
 	}
 
-	// Load final module
-	load(path.as_str(), params)
+	Ok(results)
+}
+
+/// Loads modules listed in modules-load.d directories, like systemd-modules-load
+///
+/// Lists are read from `/etc/modules-load.d`, `/run/modules-load.d`, `/usr/local/lib/modules-load.d`,
+/// `/usr/lib/modules-load.d` and `/lib/modules-load.d`. File in more important directory overrides
+/// file with the same name in less important one.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// match liblmod::modules_load(&liblmod::Options::new()) {
+///     Ok(results) => {
+///         for (name, result) in results {
+///             if let Err(e) = result {
+///                 eprintln!("Failed to load module {name}: {e}");
+///             }
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to read modules-load.d: {e}"),
+/// }
+/// ```
+pub fn modules_load(options: &Options) -> io::Result<BTreeMap<String, io::Result<Outcome>>> {
+	modprobe_all(&config::modules_load_list()?, options)
 }
 
 /// Flags for rmmod
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Flags {
 	/// Module unloading without any flags
 	None,
 
-	/// Force module unloading
+	/// Force module unloading, with [`sys::O_NONBLOCK`] and [`sys::O_TRUNC`] flags
 	Force,
 
-	/// Module unloading with O_NONBLOCK flag
+	/// Module unloading with [`sys::O_NONBLOCK`] flag
 	Casual,
+
+	/// Blocking module unloading (without [`sys::O_NONBLOCK`] flag), waits until module is unused
+	///
+	/// Call may never return if module is never released. Kernels since 3.13 don't wait anymore
+	/// and fail like with `Casual`.
+	Blocking,
+
+	/// Blocking module unloading with caller-side timeout
+	///
+	/// Unloading is done on helper thread. On timeout `Error::Timeout` is returned, but the
+	/// helper thread keeps waiting and module may still be unloaded later.
+	BlockingTimeout(std::time::Duration),
+
+	/// Forced module unloading without [`sys::O_NONBLOCK`] flag ([`sys::O_TRUNC`] alone), used by
+	/// kexec and emergency tooling
+	///
+	/// **Dangerous:** module is removed even if it's in use, like with `Force`, which taints
+	/// kernel and can crash it when users of module touch it later. Kernel has to be built with
+	/// `CONFIG_MODULE_FORCE_UNLOAD`. Kernels before 3.13 additionally wait until module is
+	/// released, so call may never return. Newer kernels don't wait and behave like with `Force`.
+	ForceWait,
 }
 
 /// Removes kernel module from current running kernel
 ///
+/// Livepatch modules are not removed, use `rmmod_with` to allow it. Unless removal is forced or
+/// blocking, modules referenced or held by other modules (as listed in `/proc/modules` and sysfs)
+/// are not removed and `Error::ModuleBusy` with their holders is returned.
+///
 /// Example:
 /// ```rust
 /// extern crate liblmod;
 ///
-/// if let Err(e) = liblmod::rmmod("kvm".to_string(), liblmod::Flags::None) {
-///     eprintln!("Failed to unload kernel module kvm: {e}");
+/// match liblmod::rmmod("kvm", liblmod::Flags::None) {
+///     Err(e) => match liblmod::Error::from_io(&e) {
+///         Some(liblmod::Error::ModuleBusy { holders, .. }) => {
+///             eprintln!("Module kvm is used by {}", holders.join(", "))
+///         }
+///         _ => eprintln!("Failed to unload kernel module kvm: {e}"),
+///     },
+///     Ok(()) => println!("Module kvm has been unloaded"),
+/// }
+/// ```
+pub fn rmmod(name: impl AsRef<str>, flags: Flags) -> io::Result<()> {
+	rmmod::remove(name.as_ref(), flags, &RemoveOptions::new())
+}
+
+/// Removes kernel module from current running kernel with specified options
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// let options = liblmod::RemoveOptions::new().allow_livepatch(true);
+/// if let Err(e) = liblmod::rmmod_with("livepatch_sample", liblmod::Flags::Casual, &options) {
+///     eprintln!("Failed to unload kernel module livepatch_sample: {e}");
 /// }
 /// ```
-pub fn rmmod(name: String, flags: Flags) -> io::Result<()> {
-	let mut flags_raw: c_uint = 0;
+pub fn rmmod_with(name: impl AsRef<str>, flags: Flags, options: &RemoveOptions) -> io::Result<()> {
+	rmmod::remove(name.as_ref(), flags, options)
+}
+
+/// Unloads all modules which are not used by anything
+///
+/// Modules are unloaded until no unused module is left, so dependencies of unloaded modules are
+/// unloaded too. If `allowlist` is not empty, only modules listed there are unloaded. Modules
+/// listed in `denylist` are never unloaded.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// match liblmod::autoclean::<&str, &str>(&[], &["kvm"]) {
+///     Ok(results) => {
+///         for (name, result) in results {
+///             match result {
+///                 Ok(()) => println!("Unloaded module {name}"),
+///                 Err(e) => eprintln!("Failed to unload module {name}: {e}"),
+///             }
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to list loaded modules: {e}"),
+/// }
+/// ```
+pub fn autoclean<A: AsRef<str>, D: AsRef<str>>(
+	allowlist: &[A],
+	denylist: &[D],
+) -> io::Result<BTreeMap<String, io::Result<()>>> {
+	let allowlist: Vec<String> = allowlist
+		.iter()
+		.map(|n| index::normalize(n.as_ref()))
+		.collect();
+	let denylist: Vec<String> = denylist
+		.iter()
+		.map(|n| index::normalize(n.as_ref()))
+		.collect();
+
+	let mut results = BTreeMap::new();
+	loop {
+		let unused: Vec<LoadedModule> = lsmod()?
+			.into_iter()
+			.filter(|module| module.is_unused())
+			.filter(|module| allowlist.is_empty() || allowlist.contains(&module.name))
+			.filter(|module| !denylist.contains(&module.name))
+			.filter(|module| !results.contains_key(&module.name))
+			.collect();
+		if unused.is_empty() {
+			break;
+		}
 
-	// Construct flags for module unloading (Linux 6.0 API: https://github.com/torvalds/linux/blob/v6.0/include/uapi/asm-generic/fcntl.h)
-	match flags {
-		Flags::None => (),
-		Flags::Force => {
-			flags_raw =
-				u32::from_str_radix("4000", 8).unwrap() | u32::from_str_radix("1000", 8).unwrap()
+		for module in unused {
+			let result = rmmod(&module.name, Flags::Casual);
+			results.insert(module.name, result);
 		}
-		Flags::Casual => flags_raw = u32::from_str_radix("4000", 8).unwrap(),
 	}
 
-	// Call kernel to unload module
-	if module_libc::delete_module(name, flags_raw) == -1 {
-		return Err(io::Error::last_os_error());
+	Ok(results)
+}
+
+/// Unloads all loaded modules with names matching glob pattern (e.g. `nf_*` or `snd_*`)
+///
+/// Modules are unloaded after all matching modules using them, so whole subsystem can be torn
+/// down at once. Dashes in pattern are treated as underscores.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// match liblmod::rmmod_matching("snd_*", liblmod::Flags::None) {
+///     Ok(results) => {
+///         for (name, result) in results {
+///             if let Err(e) = result {
+///                 eprintln!("Failed to unload module {name}: {e}");
+///             }
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to list loaded modules: {e}"),
+/// }
+/// ```
+pub fn rmmod_matching(
+	pattern: impl AsRef<str>,
+	flags: Flags,
+) -> io::Result<BTreeMap<String, io::Result<()>>> {
+	let pattern = index::normalize_alias(pattern.as_ref());
+	let remaining = lsmod()?
+		.into_iter()
+		.filter(|module| index::fnmatch(pattern.as_bytes(), module.name.as_bytes()))
+		.map(|module| (module.name, module.used_by))
+		.collect();
+
+	Ok(remove_users_first(remaining, flags, &RemoveOptions::new()))
+}
+
+/// Unloads loaded module together with all modules using it, directly or through other modules
+///
+/// Modules are unloaded leaf-first: every module after all modules using it, the requested one
+/// last. Users are read from `/proc/modules` and holders in sysfs. Modules the requested one
+/// depends on are not unloaded.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// // Unloads iwlmvm, iwlwifi, ... before mac80211
+/// match liblmod::rmmod_tree("mac80211", liblmod::Flags::None) {
+///     Ok(results) => {
+///         for (name, result) in results {
+///             if let Err(e) = result {
+///                 eprintln!("Failed to unload module {name}: {e}");
+///             }
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to list loaded modules: {e}"),
+/// }
+/// ```
+pub fn rmmod_tree(
+	name: impl AsRef<str>,
+	flags: Flags,
+) -> io::Result<BTreeMap<String, io::Result<()>>> {
+	let name = index::normalize(name.as_ref());
+	let graph = holder_graph()?;
+
+	// Collect modules using module, directly or transitively
+	let mut tree = vec![name];
+	let mut i = 0;
+	while i < tree.len() {
+		let users: Vec<String> = graph
+			.dependents(&tree[i])
+			.filter(|user| !tree.iter().any(|known| known == user))
+			.map(|user| user.to_string())
+			.collect();
+		tree.extend(users);
+		i += 1;
 	}
 
-	Ok(())
+	let remaining = tree
+		.into_iter()
+		.map(|module| {
+			let users = graph
+				.dependents(&module)
+				.map(|user| user.to_string())
+				.collect();
+			(module, users)
+		})
+		.collect();
+
+	Ok(remove_users_first(remaining, flags, &RemoveOptions::new()))
+}
+
+/// Unloads all given modules, continuing past failures, and reports result for every module
+///
+/// Modules are unloaded in given order, see `rmmod_all_with` for unloading users first.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// for (name, result) in liblmod::rmmod_all(&["wireguard", "tun"], liblmod::Flags::Casual) {
+///     if let Err(e) = result {
+///         eprintln!("Failed to unload module {name}: {e}");
+///     }
+/// }
+/// ```
+pub fn rmmod_all<S: AsRef<str>>(names: &[S], flags: Flags) -> BTreeMap<String, io::Result<()>> {
+	rmmod_all_with(names, flags, &RemoveOptions::new())
+}
+
+/// Unloads all given modules with specified options, continuing past failures
+///
+/// With `RemoveOptions::users_first`, every module is unloaded after given modules using it
+/// (users are read from `/proc/modules` and holders in sysfs). If they can't be read, modules
+/// are unloaded in given order.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// let options = liblmod::RemoveOptions::new().users_first(true);
+/// let results = liblmod::rmmod_all_with(&["kvm", "kvm_intel"], liblmod::Flags::Casual, &options);
+/// for (name, result) in results {
+///     if let Err(e) = result {
+///         eprintln!("Failed to unload module {name}: {e}");
+///     }
+/// }
+/// ```
+pub fn rmmod_all_with<S: AsRef<str>>(
+	names: &[S],
+	flags: Flags,
+	options: &RemoveOptions,
+) -> BTreeMap<String, io::Result<()>> {
+	let mut names: Vec<String> = names
+		.iter()
+		.map(|name| index::normalize(name.as_ref()))
+		.collect();
+	let mut seen = HashSet::new();
+	names.retain(|name| seen.insert(name.clone()));
+
+	let graph = match options.users_first {
+		true => holder_graph().ok(),
+		false => None,
+	};
+	let remaining = names
+		.into_iter()
+		.map(|name| {
+			let users = match &graph {
+				Some(graph) => graph
+					.dependents(&name)
+					.map(|user| user.to_string())
+					.collect(),
+				None => Vec::new(),
+			};
+			(name, users)
+		})
+		.collect();
+
+	remove_users_first(remaining, flags, options)
+}
+
+/// Unloads modules with their users, each module after other listed modules using it
+fn remove_users_first(
+	mut remaining: Vec<(String, Vec<String>)>,
+	flags: Flags,
+	options: &RemoveOptions,
+) -> BTreeMap<String, io::Result<()>> {
+	let mut results = BTreeMap::new();
+	while !remaining.is_empty() {
+		// Unload modules not used by other remaining modules first
+		let names: Vec<String> = remaining.iter().map(|(name, _)| name.clone()).collect();
+		let (mut ready, blocked): (Vec<_>, Vec<_>) = remaining
+			.into_iter()
+			.partition(|(_, users)| !users.iter().any(|user| names.contains(user)));
+
+		// Circular usage cannot be resolved, remaining modules are unloaded in any order
+		remaining = match ready.is_empty() {
+			true => {
+				ready = blocked;
+				Vec::new()
+			}
+			false => blocked,
+		};
+
+		for (name, _) in ready {
+			let result = rmmod::remove(&name, flags, options);
+			results.insert(name, result);
+		}
+	}
+
+	results
+}
+
+/// Change of kernel state made by `ensure_loaded` and `ensure_unloaded`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Change {
+	/// Module has been loaded or unloaded
+	Changed,
+
+	/// Module was already loaded or built into kernel
+	AlreadyPresent,
+
+	/// Module was not loaded
+	AlreadyAbsent,
+}
+
+/// Replaces loaded module with new image, for iterating on drivers under development
+///
+/// `target` is either name of module or path of module file (containing `/` or ending with
+/// module extension). Loaded module is unloaded, waiting while it's in use up to timeout from
+/// `ReloadOptions::timeout`, then module is loaded like with `modprobe_report` (by name) or
+/// `modprobe_path_with` (by path). If module isn't loaded, it's just loaded.
+///
+/// If loading fails, module file of the old module (installed one, if its srcversion matches
+/// loaded module) is loaded back with options from modprobe.d and `Error::ReloadFailed` is
+/// returned, telling whether it has been restored.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::reload("./mydriver.ko", "debug=1", &liblmod::ReloadOptions::new()) {
+///     Ok(report) => println!("Module mydriver: {:?}", report.outcome),
+///     Err(e) => match liblmod::Error::from_io(&e) {
+///         Some(liblmod::Error::ReloadFailed { restored: true, .. }) => {
+///             eprintln!("New mydriver.ko failed, old module is back: {e}")
+///         }
+///         _ => eprintln!("Failed to reload mydriver.ko: {e}"),
+///     },
+/// }
+/// ```
+pub fn reload(
+	target: impl AsRef<str>,
+	params: impl AsRef<str>,
+	options: &ReloadOptions,
+) -> io::Result<LoadReport> {
+	reload::reload(target.as_ref(), params.as_ref(), options)
+}
+
+/// Makes sure module is loaded for current running kernel, loading it if needed
+///
+/// Parameters are used only if module is loaded by this call, parameters of already loaded module
+/// are not changed. Module disabled in modprobe.d with install command doing nothing (e.g.
+/// `install foo /bin/false`) fails with `Error::DisabledByConfig`.
+///
+/// Load plan of module is cached in shared module index, so repeated calls (e.g. from
+/// supervision loops) only check if module is loaded. Plans are resolved again when module
+/// index is re-read.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// match liblmod::ensure_loaded("tun", "") {
+///     Ok(liblmod::Change::Changed) => println!("Module tun has been loaded"),
+///     Ok(_) => println!("Module tun was already present"),
+///     Err(e) => eprintln!("Failed to load module tun: {e}"),
+/// }
+/// ```
+pub fn ensure_loaded(name: impl AsRef<str>, params: impl AsRef<str>) -> io::Result<Change> {
+	let options = Options::new();
+	if modprobe::is_present(name.as_ref(), params.as_ref(), &options)? {
+		return Ok(Change::AlreadyPresent);
+	}
+
+	match modprobe_with(name.as_ref(), params, &options)? {
+		Outcome::Loaded | Outcome::DryRun => Ok(Change::Changed),
+		Outcome::DisabledByConfig => Err(Error::DisabledByConfig(name.as_ref().to_string()).into()),
+		_ => Ok(Change::AlreadyPresent),
+	}
+}
+
+/// Makes sure module is not loaded into current running kernel, unloading it if needed
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// match liblmod::ensure_unloaded("pcspkr") {
+///     Ok(liblmod::Change::Changed) => println!("Module pcspkr has been unloaded"),
+///     Ok(_) => println!("Module pcspkr was not loaded"),
+///     Err(e) => eprintln!("Failed to unload module pcspkr: {e}"),
+/// }
+/// ```
+pub fn ensure_unloaded(name: impl AsRef<str>) -> io::Result<Change> {
+	let name = index::normalize(name.as_ref());
+	if !modprobe::is_loaded(&name) {
+		return Ok(Change::AlreadyAbsent);
+	}
+
+	// Module may have been unloaded meanwhile by someone else
+	match rmmod(&name, Flags::None) {
+		Ok(()) => Ok(Change::Changed),
+		Err(e) if Error::errno(&e) == Some(libc::ENOENT) => Ok(Change::AlreadyAbsent),
+		Err(e) => Err(e),
+	}
+}
+
+/// Availability of module in current running kernel, returned by `classify`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ModuleStatus {
+	/// Module is built into kernel (listed in modules.builtin)
+	Builtin,
+
+	/// Module is loaded (listed in `/proc/modules`)
+	Loaded,
+
+	/// Module can be loaded from module directory of kernel
+	Available,
+
+	/// Module is neither built into kernel nor available
+	Missing,
+}
+
+/// Tells for every name whether module is built into current running kernel, loaded, available or
+/// missing, without loading anything
+///
+/// Module index and `/proc/modules` are read once for all names. Statuses are returned in order
+/// of names.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::classify(&["ext4", "vfat", "foo"]) {
+///     Ok(statuses) => {
+///         for (name, status) in ["ext4", "vfat", "foo"].iter().zip(statuses) {
+///             println!("{name}: {status:?}");
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to classify modules: {e}"),
+/// }
+/// ```
+pub fn classify<S: AsRef<str>>(names: &[S]) -> io::Result<Vec<ModuleStatus>> {
+	let index = ModuleIndex::shared(Selection::Current)?;
+	let loaded: HashSet<String> = match lsmod() {
+		Ok(modules) => modules.into_iter().map(|module| module.name).collect(),
+		Err(e) if e.kind() == ErrorKind::NotFound => HashSet::new(),
+		Err(e) => return Err(e),
+	};
+
+	Ok(names
+		.iter()
+		.map(|name| {
+			let name = index::normalize(name.as_ref());
+			if index.is_builtin(&name) {
+				ModuleStatus::Builtin
+			} else if loaded.contains(&name)
+				|| (cfg!(feature = "testing") && modprobe::is_loaded(&name))
+			{
+				ModuleStatus::Loaded
+			} else if index.resolve(&name).is_some() {
+				ModuleStatus::Available
+			} else {
+				ModuleStatus::Missing
+			}
+		})
+		.collect())
+}
+
+/// Normalizes module name the way kernel does, replacing dashes with underscores
+///
+/// Kernel treats `snd-hda-intel` and `snd_hda_intel` as the same module and lists loaded modules
+/// with underscores only.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// assert_eq!(liblmod::normalize_name("snd-hda-intel"), "snd_hda_intel");
+/// ```
+pub fn normalize_name(name: impl AsRef<str>) -> String {
+	index::normalize(name.as_ref())
+}
+
+/// Checks if name can be name of kernel module
+///
+/// Valid names are not empty, fit into name field of kernel module structure, which is
+/// `64 - sizeof(unsigned long)` bytes including terminating null byte (at most 55 bytes long on
+/// 64-bit architectures, 59 on 32-bit ones), and contain only ASCII letters, digits, underscores
+/// and dashes. Names are checked this way before unloading modules, failing with
+/// `Error::InvalidModuleName`. Aliases (e.g. `pci:v00008086d*`) are not valid module names.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// assert!(liblmod::is_valid_module_name("snd-hda-intel"));
+/// assert!(!liblmod::is_valid_module_name("../../etc/passwd"));
+/// assert!(!liblmod::is_valid_module_name(&"a".repeat(60)));
+/// ```
+pub fn is_valid_module_name(name: impl AsRef<str>) -> bool {
+	index::is_valid_name(name.as_ref())
 }
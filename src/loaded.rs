@@ -0,0 +1,427 @@
+use std::{collections::BTreeMap, io, path::Path};
+
+use crate::{
+	index, modinfo, vfs, DependencyGraph, ModuleIndex, ModuleInfo, ModuleOrigin, Selection,
+};
+
+/// Module loaded into running kernel, as listed in `/proc/modules`
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoadedModule {
+	/// Name of module
+	pub name: String,
+
+	/// Memory used by module in bytes
+	pub size: u64,
+
+	/// Number of references to module, `None` if module cannot be unloaded
+	pub refcount: Option<u32>,
+
+	/// Names of modules using this module
+	pub used_by: Vec<String>,
+
+	/// State of module (Live, Loading or Unloading)
+	pub state: String,
+
+	/// Taint flags of module (e.g. `OE`), empty if module doesn't taint kernel
+	pub taints: String,
+
+	/// Whether module is a livepatch
+	pub livepatch: bool,
+
+	/// Whether module comes from kernel tree, staging drivers or outside of it, from taint flags
+	pub origin: ModuleOrigin,
+
+	/// Memory used by module, as reported by sysfs
+	pub memory: ModuleMemory,
+
+	/// Version of module (`/sys/module/<name>/version`), if module declares it
+	pub version: Option<String>,
+
+	/// Checksum of module source (`/sys/module/<name>/srcversion`), if module has it
+	pub srcversion: Option<String>,
+}
+
+/// Memory used by loaded module, read from `/sys/module/<name>`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModuleMemory {
+	/// Size of core (permanent) part of module in bytes (`coresize`)
+	pub core_size: Option<u64>,
+
+	/// Size of init part of module in bytes, freed after initialization (`initsize`)
+	pub init_size: Option<u64>,
+
+	/// Sizes of memory regions in bytes by region name (`mem/`, only on newer kernels)
+	pub regions: BTreeMap<String, u64>,
+}
+
+impl ModuleMemory {
+	/// Reads memory usage of loaded module from sysfs, missing values are left empty
+	fn read(name: &str) -> ModuleMemory {
+		let base = format!("/sys/module/{}", name);
+		let size = |path: &str| vfs::read_to_string(path).ok()?.trim().parse().ok();
+
+		let mut regions = BTreeMap::new();
+		for path in vfs::read_dir(format!("{}/mem", base)).unwrap_or_default() {
+			if let (Some(region), Some(value)) = (path.file_name(), size(&path.to_string_lossy())) {
+				regions.insert(region.to_string_lossy().into_owned(), value);
+			}
+		}
+
+		ModuleMemory {
+			core_size: size(&format!("{}/coresize", base)),
+			init_size: size(&format!("{}/initsize", base)),
+			regions,
+		}
+	}
+}
+
+impl LoadedModule {
+	/// Checks if module is unused and can be safely unloaded
+	pub fn is_unused(&self) -> bool {
+		self.refcount == Some(0) && self.used_by.is_empty() && self.state == "Live"
+	}
+
+	/// Checks if module file differs from loaded module (e.g. after upgrade), comparing srcversion
+	///
+	/// Module without srcversion (either loaded one or the file) is never reported as stale.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// let index = liblmod::ModuleIndex::open(liblmod::Selection::Current);
+	/// if let (Ok(index), Ok(modules)) = (index, liblmod::lsmod()) {
+	///     for module in modules {
+	///         if let Some(path) = index.resolve(&module.name) {
+	///             if let Ok(true) = module.is_stale(index.path(path)) {
+	///                 println!("Module {} has been upgraded, reload it", module.name);
+	///             }
+	///         }
+	///     }
+	/// }
+	/// ```
+	pub fn is_stale(&self, path: impl AsRef<Path>) -> io::Result<bool> {
+		let loaded = match &self.srcversion {
+			Some(o) => o,
+			None => return Ok(false),
+		};
+
+		Ok(match ModuleInfo::from_file(path)?.srcversion {
+			Some(file) => &file != loaded,
+			None => false,
+		})
+	}
+
+	/// Serializes loaded module to JSON
+	///
+	/// Object has the same fields as `LoadedModule`, `refcount` is `null` if module cannot be
+	/// unloaded. Fields are only added in new versions, never renamed or removed.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// if let Ok(modules) = liblmod::lsmod() {
+	///     for module in modules {
+	///         println!("{}", module.to_json());
+	///     }
+	/// }
+	/// ```
+	#[cfg(feature = "json")]
+	pub fn to_json(&self) -> String {
+		serde_json::to_string(self).unwrap()
+	}
+
+	fn parse(line: &str) -> Option<LoadedModule> {
+		let mut fields = line.split_whitespace();
+		let name = fields.next()?.to_string();
+		let size = fields.next()?.parse().ok()?;
+		let refcount = fields.next()?.parse().ok();
+		let used_by = fields
+			.next()?
+			.split(',')
+			.filter(|user| !user.is_empty() && *user != "-")
+			.map(|user| user.to_string())
+			.collect();
+		let state = fields.next()?.to_string();
+
+		// Skip address, taint flags are at the end in parentheses
+		let taints = fields
+			.find_map(|field| field.strip_prefix('(')?.strip_suffix(')'))
+			.unwrap_or("")
+			.to_string();
+
+		// Livepatch modules taint kernel with K flag
+		let livepatch = taints.contains('K') || crate::rmmod::is_livepatch(&name);
+		let origin = ModuleOrigin::from_taints(&taints);
+		let memory = ModuleMemory::read(&name);
+		let attribute = |attribute: &str| {
+			vfs::read_to_string(format!("/sys/module/{}/{}", name, attribute))
+				.ok()
+				.map(|value| value.trim().to_string())
+		};
+		let version = attribute("version");
+		let srcversion = attribute("srcversion");
+
+		Some(LoadedModule {
+			name,
+			size,
+			refcount,
+			used_by,
+			state,
+			taints,
+			livepatch,
+			origin,
+			memory,
+			version,
+			srcversion,
+		})
+	}
+}
+
+/// Lists modules loaded into running kernel
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::lsmod() {
+///     Ok(modules) => {
+///         for module in modules {
+///             println!("{} {} {:?}", module.name, module.size, module.used_by);
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to list loaded modules: {e}"),
+/// }
+/// ```
+pub fn lsmod() -> io::Result<Vec<LoadedModule>> {
+	Ok(vfs::read_to_string("/proc/modules")?
+		.lines()
+		.filter_map(LoadedModule::parse)
+		.collect())
+}
+
+/// Parameter of loaded module with its current value and default documented by module
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParameterState {
+	/// Name of parameter
+	pub name: String,
+
+	/// Current value (`/sys/module/<name>/parameters/<parameter>`), `None` if it isn't readable
+	pub value: Option<String>,
+
+	/// Default value found in description of parameter (e.g. `(default: 1)`), `None` if not documented
+	pub default: Option<String>,
+
+	/// Description of parameter
+	pub description: Option<String>,
+
+	/// Type of parameter (e.g. `int`, `bool` or `charp`)
+	pub kind: Option<String>,
+}
+
+impl ParameterState {
+	/// Checks if current value differs from documented default
+	///
+	/// Booleans (`Y`, `1`, `on`...) and integers in any base are compared by their value.
+	/// Parameters without readable value or documented default are never reported as different.
+	pub fn differs(&self) -> bool {
+		match (&self.value, &self.default) {
+			(Some(value), Some(default)) => !same_value(value, default),
+			_ => false,
+		}
+	}
+}
+
+/// Lists parameters of loaded module with their current values and documented defaults
+///
+/// Parameters are declared in modinfo of module file of current running kernel. Parameters
+/// exposed in sysfs by module missing from module directory (e.g. loaded from other path) are
+/// listed without description and default. Only parameters declared readable by module are
+/// present in sysfs, values of others are `None`. Returns `ErrorKind::NotFound` if module isn't
+/// loaded.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::module_parameters("kvm") {
+///     Ok(parameters) => {
+///         for parameter in parameters.iter().filter(|parameter| parameter.differs()) {
+///             println!(
+///                 "{} = {:?} (default {:?})",
+///                 parameter.name, parameter.value, parameter.default
+///             );
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to read parameters of kvm: {e}"),
+/// }
+/// ```
+pub fn module_parameters(name: impl AsRef<str>) -> io::Result<Vec<ParameterState>> {
+	let name = index::normalize(name.as_ref());
+	let base = format!("/sys/module/{}", name);
+	if !vfs::exists(&base) {
+		return Err(io::Error::new(
+			io::ErrorKind::NotFound,
+			format!("Module {} is not loaded", name),
+		));
+	}
+
+	// Module file may be missing or differ from loaded module, sysfs is used then
+	let info = ModuleIndex::shared(Selection::Current)
+		.ok()
+		.and_then(|index| {
+			let path = index.path(index.resolve(&name)?);
+			index::module_info(&path).ok()
+		})
+		.unwrap_or_default();
+
+	let value = |parameter: &str| {
+		vfs::read_to_string(format!("{}/parameters/{}", base, parameter))
+			.ok()
+			.map(|value| value.trim_end_matches('\n').to_string())
+	};
+	let mut parameters: Vec<ParameterState> = info
+		.parameters
+		.iter()
+		.map(|parameter| ParameterState {
+			name: parameter.name.clone(),
+			value: value(&parameter.name),
+			default: parameter
+				.description
+				.as_deref()
+				.and_then(documented_default),
+			description: parameter.description.clone(),
+			kind: parameter.kind.clone(),
+		})
+		.collect();
+
+	for path in vfs::read_dir(format!("{}/parameters", base)).unwrap_or_default() {
+		let parameter = match path.file_name() {
+			Some(parameter) => parameter.to_string_lossy().into_owned(),
+			None => continue,
+		};
+		if info.parameter(&parameter).is_none() {
+			parameters.push(ParameterState {
+				value: value(&parameter),
+				name: parameter,
+				default: None,
+				description: None,
+				kind: None,
+			});
+		}
+	}
+
+	Ok(parameters)
+}
+
+/// Finds default value in description of parameter, like `(default: 1)`, `[default=on]` or
+/// `Default is 0.`
+fn documented_default(description: &str) -> Option<String> {
+	let lower = description.to_ascii_lowercase();
+	let start = lower.rfind("default")? + "default".len();
+	let mut rest = &description[start..];
+	let mut separated = false;
+	for prefix in ["s to ", "value ", "is ", ":", "="] {
+		rest = rest.trim_start();
+		if let Some(stripped) = rest.strip_prefix(prefix) {
+			(rest, separated) = (stripped, true);
+		}
+	}
+	let rest = rest.trim_start();
+
+	// Without separator only numbers are taken (e.g. `(default 0)`), not following words
+	if !separated && !rest.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+		return None;
+	}
+
+	let value = match rest.strip_prefix(['"', '\'']) {
+		Some(quoted) => quoted.split(['"', '\'']).next()?,
+		None => rest
+			.split(|c: char| c.is_whitespace() || matches!(c, ')' | ']' | ',' | ';'))
+			.next()?
+			.trim_end_matches('.'),
+	};
+	match value.is_empty() {
+		true => None,
+		false => Some(value.to_string()),
+	}
+}
+
+/// Compares values of parameter, booleans and integers by their meaning
+fn same_value(value: &str, default: &str) -> bool {
+	let boolean = |value: &str| match value.to_ascii_lowercase().as_str() {
+		"y" | "1" | "on" | "true" | "yes" | "enabled" => Some(true),
+		"n" | "0" | "off" | "false" | "no" | "disabled" => Some(false),
+		_ => None,
+	};
+	let integer = |value: &str| match value.strip_prefix('-') {
+		Some(value) => modinfo::parse_integer(value).map(|value| -(value as i128)),
+		None => modinfo::parse_integer(value).map(|value| value as i128),
+	};
+
+	let (value, default) = (value.trim(), default.trim());
+	value == default
+		|| matches!((integer(value), integer(default)), (Some(a), Some(b)) if a == b)
+		|| matches!((boolean(value), boolean(default)), (Some(a), Some(b)) if a == b)
+}
+
+/// Returns memory used by loaded modules in bytes, as listed in `/proc/modules`
+pub(crate) fn modules_size() -> u64 {
+	vfs::read_to_string("/proc/modules")
+		.unwrap_or_default()
+		.lines()
+		.filter_map(|line| line.split_whitespace().nth(1)?.parse::<u64>().ok())
+		.sum()
+}
+
+/// Builds graph of modules loaded into running kernel, edges point from modules to modules they use
+///
+/// Users are read from `/proc/modules` together with holders from sysfs, which also lists
+/// users not reported by `/proc/modules` (e.g. on older kernels).
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::holder_graph() {
+///     Ok(graph) => {
+///         println!("Unused modules: {:?}", graph.roots().collect::<Vec<_>>());
+///         println!("Modules using no other: {:?}", graph.leaves().collect::<Vec<_>>());
+///         for cycle in graph.cycles() {
+///             println!("Modules using each other: {:?}", cycle);
+///         }
+///         println!("{}", graph.to_dot());
+///     }
+///     Err(e) => eprintln!("Failed to list loaded modules: {e}"),
+/// }
+/// ```
+pub fn holder_graph() -> io::Result<DependencyGraph> {
+	let mut graph = DependencyGraph::default();
+	for module in lsmod()? {
+		let holders = holders(&module.name);
+		for user in module.used_by.iter().chain(&holders) {
+			// Pseudo-users like [permanent] are not modules
+			if !user.starts_with('[') {
+				graph.add_edge(user.clone(), module.name.clone());
+			}
+		}
+		graph.add_node(module.name);
+	}
+
+	Ok(graph)
+}
+
+/// Returns names of modules holding module, as listed in sysfs
+pub(crate) fn holders(name: &str) -> Vec<String> {
+	let mut holders: Vec<String> = vfs::read_dir(format!("/sys/module/{}/holders", name))
+		.unwrap_or_default()
+		.iter()
+		.filter_map(|holder| Some(holder.file_name()?.to_string_lossy().into_owned()))
+		.collect();
+	holders.sort();
+	holders
+}
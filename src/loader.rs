@@ -1,28 +1,659 @@
-use std::io;
+use std::{
+	ffi::CStr,
+	fs,
+	io::{self, ErrorKind, Read, Seek, SeekFrom},
+	os::{fd::AsRawFd, raw::c_uint},
+	path::Path,
+	sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+#[cfg(target_os = "linux")]
+use std::{ffi::CString, os::fd::FromRawFd};
+
+#[cfg(feature = "integrity")]
+use sha2::{Digest, Sha256};
+
+use crate::{CancellationToken, Error};
+
+/// File with compression method of modules supported by kernel (CONFIG_MODULE_DECOMPRESS)
+const SYSFS_COMPRESSION: &str = "/sys/module/compression";
+
+/// Memory statistics of kernel, attached to allocation failures of modules
+const MEMINFO: &str = "/proc/meminfo";
+
+/// Size of end of decompressed image read back to look for module signature
+const SIGNATURE_TAIL: u64 = 64 * 1024;
+
+/// Size of chunks module files are read in when loading can be cancelled
+const READ_CHUNK: usize = 1024 * 1024;
+
+/// Whether kernel log messages are attached to errors of failed loading
+static CAPTURE_KERNEL_LOG: AtomicBool = AtomicBool::new(false);
+
+/// Whether module files which are symbolic links are loaded
+static FOLLOW_SYMLINKS: AtomicBool = AtomicBool::new(true);
+
+/// Maximum size of modules decompressed by library, `u64::MAX` if unlimited
+static DECOMPRESSION_LIMIT: AtomicU64 = AtomicU64::new(u64::MAX);
 
 /// Load kernel module by byte array.
 ///
+/// Image is checked to be a relocatable ELF object matching architecture of running kernel
+/// before it's passed to kernel.
+///
 /// Example:
-/// ```rust
+/// ```rust,no_run
 /// extern crate liblmod;
 ///
 /// use std::io::Read;
+/// # fn main() -> std::io::Result<()> {
 /// let mut file = std::fs::File::open(std::path::Path::new("./module.ko"))?;
 /// let mut image = Vec::new();
 /// file.read_to_end(&mut image)?;
 ///
-/// if let Err(e) = liblmod::loader::load(&image, "module.param=0".to_string()) {
+/// if let Err(e) = liblmod::loader::load(&image, "module.param=0") {
 ///     eprintln!("Failed to insert module by image: {e}");
 /// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn load(image: &[u8], params: impl AsRef<str>) -> io::Result<()> {
+	crate::policy::check(image, Path::new(""))?;
+	insert(image, Path::new(""), params.as_ref(), None)
+}
+
+/// Inserts module image read from source (empty if unknown) without consulting load policy
+///
+/// Architecture of image is checked against machine, or the one of running kernel if not given.
+pub(crate) fn insert(
+	image: &[u8],
+	source: &Path,
+	params: &str,
+	machine: Option<&str>,
+) -> io::Result<()> {
+	check(image, source, machine)?;
+	let cparams = crate::backend::cstring(params)?;
+
+	// Call kernel to load module
+	let result = crate::hooks::around_load(
+		|| image_name(image, source),
+		params,
+		|| {
+			capture(
+				|| image_name(image, source),
+				|| init_module(image, source, &cparams),
+			)
+		},
+	)
+	.map_err(|e| {
+		Error::syscall_failed("init_module", e, |e| {
+			classify(e, &image_name(image, source), image)
+		})
+	});
+
+	#[cfg(feature = "audit")]
+	crate::audit::load(&image_name(image, source), params, &result);
+
+	result
+}
+
+/// Checks if image read from source can be loaded by running kernel (fake kernel accepts anything)
+pub(crate) fn check(image: &[u8], source: &Path, machine: Option<&str>) -> io::Result<()> {
+	if cfg!(feature = "testing") {
+		return Ok(());
+	}
+
+	match machine {
+		Some(machine) => crate::elf::check(image, machine)?,
+		None => crate::elf::check(image, &crate::kernel::machine()?)?,
+	}
+
+	// Unsigned modules would be rejected with error not telling why
+	if crate::kernel::signatures_enforced() && crate::ModuleSignature::from_image(image).is_none() {
+		return Err(Error::SignatureRequired {
+			module: image_name(image, source),
+			signer: None,
+		}
+		.into());
+	}
+
+	Ok(())
+}
+
+/// Calls kernel to insert module image
+#[cfg(not(feature = "testing"))]
+fn init_module(image: &[u8], _source: &Path, params: &CStr) -> io::Result<()> {
+	crate::sys::init_module(image, params)
+}
+
+/// Records insertion of module image in fake kernel
+#[cfg(feature = "testing")]
+fn init_module(image: &[u8], source: &Path, params: &CStr) -> io::Result<()> {
+	crate::testing::load(&image_name(image, source), &params.to_string_lossy())
+}
+
+/// Enables or disables attaching kernel log messages to errors of failed loading
+///
+/// When enabled, kernel log (`/dev/kmsg`) is read around every loading. If kernel rejects module
+/// (`EINVAL`, `ENOEXEC` or `ENOENT`, e.g. because of symbol version mismatch), messages mentioning
+/// module are returned in `Error::KernelLog`. Reading kernel log may require `CAP_SYSLOG`, without
+/// it errors are returned unchanged.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// liblmod::loader::capture_kernel_log(true);
+/// if let Err(e) = liblmod::load("./example_module.ko", "") {
+///     if let Some(liblmod::Error::KernelLog { messages, .. }) = liblmod::Error::from_io(&e) {
+///         eprintln!("Kernel says: {}", messages.join("\n"));
+///     }
+/// }
+/// ```
+pub fn capture_kernel_log(enabled: bool) {
+	CAPTURE_KERNEL_LOG.store(enabled, Ordering::Relaxed);
+}
+
+/// Selects whether module files which are symbolic links are loaded (enabled by default)
+///
+/// When disabled, module files are opened with `O_NOFOLLOW`, so loading module through symbolic
+/// link (e.g. planted by attacker) fails with `Error::SymlinkRejected`. Links in directories
+/// leading to module file are still followed. Weak-updates directories of some distributions
+/// consist of symbolic links, their modules cannot be loaded then.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// liblmod::loader::follow_symlinks(false);
+/// if let Err(e) = liblmod::load("./example_module.ko", "") {
+///     if let Some(liblmod::Error::SymlinkRejected(path)) = liblmod::Error::from_io(&e) {
+///         eprintln!("Refusing to load module through symbolic link {path}");
+///     }
+/// }
+/// ```
+pub fn follow_symlinks(follow: bool) {
+	FOLLOW_SYMLINKS.store(follow, Ordering::Relaxed);
+}
+
+/// Sets maximum size of compressed modules decompressed by library (unlimited by default)
+///
+/// Modules compressed with method running kernel can't decompress itself are decompressed in
+/// fixed-size chunks into anonymous memory file (memfd) passed to kernel, so decompressed image
+/// never has to be held in process memory. Limit caps size of memory file, loading module
+/// exceeding it fails with `Error::DecompressionLimit`. `None` removes the limit. Requires
+/// compression feature.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// // Device has 128 MiB of RAM, refuse to decompress huge modules
+/// liblmod::loader::decompression_limit(Some(32 * 1024 * 1024));
+/// if let Err(e) = liblmod::load("./example_module.ko.xz", "") {
+///     if let Some(liblmod::Error::DecompressionLimit { limit, .. }) = liblmod::Error::from_io(&e) {
+///         eprintln!("Module is larger than {limit} bytes");
+///     }
+/// }
+/// ```
+pub fn decompression_limit(limit: Option<u64>) {
+	DECOMPRESSION_LIMIT.store(limit.unwrap_or(u64::MAX), Ordering::Relaxed);
+}
+
+/// Opens module file for loading according to symbolic link policy
+pub(crate) fn open(path: &Path) -> io::Result<fs::File> {
+	crate::confine::open(path, FOLLOW_SYMLINKS.load(Ordering::Relaxed))
+}
+
+/// Runs loading of module, attaching new kernel log messages about module to its failure
+fn capture(
+	module: impl FnOnce() -> String,
+	load: impl FnOnce() -> io::Result<()>,
+) -> io::Result<()> {
+	if !CAPTURE_KERNEL_LOG.load(Ordering::Relaxed) {
+		return load();
+	}
+
+	let tail = crate::kmsg::Reader::tail();
+	let result = load();
+	let (e, tail) = match (result, tail) {
+		(Err(e), Ok(tail))
+			if matches!(
+				e.raw_os_error(),
+				Some(libc::EINVAL | libc::ENOEXEC | libc::ENOENT)
+			) =>
+		{
+			(e, tail)
+		}
+		(result, _) => return result,
+	};
+
+	let module = crate::index::module_name(&module());
+	let messages: Vec<String> = tail
+		.module(&module)
+		.map_while(Result::ok)
+		.map(|record| record.message)
+		.collect();
+	if messages.is_empty() {
+		return Err(e);
+	}
+
+	Err(Error::KernelLog {
+		module,
+		messages,
+		source: e,
+	}
+	.into())
+}
+
+/// Replaces errno of failed insertion with module management error explaining it
+///
+/// `ENOEXEC` and `EINVAL` are told apart by version magic of image (empty if unknown). `EINVAL`
+/// is also returned for invalid parameters, so it's kept unless version magic differs.
+fn classify(error: io::Error, module: &str, image: &[u8]) -> io::Error {
+	let errno = match error.raw_os_error() {
+		Some(errno) => errno,
+		None => return error,
+	};
+	let module = crate::index::module_name(module);
+
+	match errno {
+		libc::EEXIST => Error::AlreadyLoaded(module).into(),
+		libc::ENOMEM => {
+			// Values of /proc/meminfo are in KiB (e.g. `VmallocTotal:   34359738367 kB`)
+			let meminfo = crate::vfs::read_to_string(MEMINFO).unwrap_or_default();
+			let meminfo = |field: &str| {
+				let line = meminfo.lines().find_map(|line| line.strip_prefix(field))?;
+				let kib: u64 = line
+					.trim_start_matches(':')
+					.split_whitespace()
+					.next()?
+					.parse()
+					.ok()?;
+				Some(kib * 1024)
+			};
+			Error::OutOfMemory {
+				module,
+				image_size: image.len() as u64,
+				modules_size: crate::loaded::modules_size(),
+				vmalloc_total: meminfo("VmallocTotal"),
+				vmalloc_used: meminfo("VmallocUsed"),
+				available: meminfo("MemAvailable"),
+				source: error,
+			}
+			.into()
+		}
+		#[cfg(target_os = "linux")]
+		libc::ENOKEY => Error::SignatureRequired {
+			module,
+			signer: crate::ModuleSignature::from_image(image).and_then(|sig| sig.signer),
+		}
+		.into(),
+		#[cfg(target_os = "linux")]
+		libc::EKEYREJECTED => Error::SignatureRejected(module).into(),
+		libc::ENOEXEC | libc::EINVAL => {
+			let kernel = crate::kernel::current_release()
+				.map(|release| release.to_string_lossy().into_owned())
+				.unwrap_or_default();
+			let vermagic = crate::ModuleInfo::from_image(image)
+				.ok()
+				.and_then(|info| info.vermagic);
+
+			// Version magic starts with release of kernel module is built for
+			match &vermagic {
+				Some(magic) if magic.split_whitespace().next() != Some(kernel.as_str()) => {
+					Error::VersionMismatch {
+						module,
+						vermagic,
+						kernel,
+					}
+					.into()
+				}
+				_ if errno == libc::EINVAL => error,
+				Some(_) => {
+					// Kernel of the same release rejects symbols built with different versions
+					#[cfg(feature = "modinfo")]
+					let symbols = crate::symbols::running_mismatches(image);
+					#[cfg(not(feature = "modinfo"))]
+					let symbols: Vec<crate::CrcMismatch> = Vec::new();
+					if !symbols.is_empty() {
+						return Error::SymbolVersionMismatch { module, symbols }.into();
+					}
+					Error::VersionMismatch {
+						module,
+						vermagic,
+						kernel,
+					}
+					.into()
+				}
+				None => Error::InvalidModuleFormat(format!(
+					"Module {} has been rejected by kernel",
+					module
+				))
+				.into(),
+			}
+		}
+		_ => error,
+	}
+}
+
+/// Returns compression method kernel can decompress modules with (e.g. `xz`, `zstd` or `gzip`)
+///
+/// `None` is returned if kernel can't decompress modules itself.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::loader::kernel_compression() {
+///     Ok(Some(method)) => println!("Kernel decompresses {method} modules"),
+///     Ok(None) => println!("Kernel doesn't decompress modules"),
+///     Err(e) => eprintln!("Failed to check module compression: {e}"),
+/// }
+/// ```
+pub fn kernel_compression() -> io::Result<Option<String>> {
+	match fs::read_to_string(SYSFS_COMPRESSION) {
+		Ok(method) => Ok(Some(method.trim().to_string())),
+		Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(e),
+	}
+}
+
+/// Load compressed kernel module by file, letting kernel decompress it
+///
+/// Compressed file is passed directly to kernel, so it has to be compressed with method
+/// returned by `kernel_compression`.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let file = std::fs::File::open("./module.ko.xz")?;
+/// if let Err(e) = liblmod::loader::load_compressed(&file, "param=0") {
+///     eprintln!("Failed to insert compressed module: {e}");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn load_compressed(file: &fs::File, params: impl AsRef<str>) -> io::Result<()> {
+	// Compressed image cannot be inspected, policy gets only its path
+	crate::policy::check(&[], Path::new(&file_name(file)))?;
+
+	// Call kernel to decompress and load module
+	finit(
+		file,
+		Path::new(""),
+		params.as_ref(),
+		crate::sys::MODULE_INIT_COMPRESSED_FILE,
+	)
+}
+
+/// Load kernel module by image streamed from reader (e.g. pipe or network stream)
+///
+/// Image is copied into anonymous memory file (memfd) which is passed to kernel, so no temporary
+/// file is needed. Unlike `load`, image is checked only by kernel.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let stream = std::net::TcpStream::connect("192.168.1.1:8000")?;
+/// if let Err(e) = liblmod::loader::load_from_reader(stream, "param=0") {
+///     eprintln!("Failed to insert module from network: {e}");
+/// }
+/// # Ok(())
+/// # }
 /// ```
-pub fn load(image: &[u8], params: String) -> io::Result<()> {
-	// Count size of image
-	let size = image.len() as std::os::raw::c_uint;
+pub fn load_from_reader(mut reader: impl Read, params: impl AsRef<str>) -> io::Result<()> {
+	crate::kernel::check_permitted()?;
+
+	// Create anonymous memory file
+	let mut file = memfd()?;
+
+	// Copy image into memory file
+	io::copy(&mut reader, &mut file)?;
+
+	// Image is read back only if policy has to inspect it
+	if crate::policy::is_set() {
+		crate::policy::check(&read_back(&mut file)?, Path::new(""))?;
+	}
 
 	// Call kernel to load module
-	if crate::module_libc::init_module(image, size, params) != 0 {
+	finit(&file, Path::new(""), params.as_ref(), 0)
+}
+
+/// Creates anonymous memory file
+#[cfg(target_os = "linux")]
+fn memfd() -> io::Result<fs::File> {
+	let name = CString::new("liblmod").unwrap();
+	let fd = unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC) };
+	if fd == -1 {
 		return Err(io::Error::last_os_error());
 	}
 
+	Ok(unsafe { fs::File::from_raw_fd(fd) })
+}
+
+/// Anonymous memory files are specific to Linux
+#[cfg(not(target_os = "linux"))]
+fn memfd() -> io::Result<fs::File> {
+	Err(crate::backend::unsupported())
+}
+
+/// Calls kernel to load module from file descriptor, naming it by its source (if known)
+pub(crate) fn finit(file: &fs::File, source: &Path, params: &str, flags: c_uint) -> io::Result<()> {
+	let name = || match source.as_os_str().is_empty() {
+		true => file_name(file),
+		false => source.display().to_string(),
+	};
+	let cparams = crate::backend::cstring(params)?;
+	let result = crate::hooks::around_load(name, params, || {
+		capture(name, || finit_module(file, source, &cparams, flags))
+	})
+	.map_err(|e| Error::syscall_failed("finit_module", e, |e| classify(e, &name(), &[])));
+
+	#[cfg(feature = "audit")]
+	crate::audit::load(&name(), params, &result);
+
+	result
+}
+
+/// Calls kernel to insert module from file descriptor
+#[cfg(not(feature = "testing"))]
+fn finit_module(file: &fs::File, _source: &Path, params: &CStr, flags: c_uint) -> io::Result<()> {
+	crate::sys::finit_module(file, params, flags)
+}
+
+/// Records insertion of module file in fake kernel
+#[cfg(feature = "testing")]
+fn finit_module(file: &fs::File, source: &Path, params: &CStr, _flags: c_uint) -> io::Result<()> {
+	let name = match source.as_os_str().is_empty() {
+		true => file_name(file),
+		false => crate::index::module_name(&source.to_string_lossy()),
+	};
+	crate::testing::load(&name, &params.to_string_lossy())
+}
+
+/// Returns name of module from its image, or from its source if image has no name
+fn image_name(image: &[u8], source: &Path) -> String {
+	match crate::ModuleInfo::from_image(image)
+		.ok()
+		.and_then(|info| info.name)
+	{
+		Some(name) => name,
+		None if !source.as_os_str().is_empty() => {
+			crate::index::module_name(&source.to_string_lossy())
+		}
+		None => "(image)".to_string(),
+	}
+}
+
+/// Returns path of module file (name cannot be read from compressed file)
+fn file_name(file: &fs::File) -> String {
+	fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd()))
+		.map(|path| path.to_string_lossy().into_owned())
+		.unwrap_or_else(|_| "(file)".to_string())
+}
+
+/// Returns compression method used by module file, based on its extension
+pub(crate) fn file_compression(path: &Path) -> Option<String> {
+	crate::compress::method(path.extension()?.to_str()?)
+}
+
+/// Checks if kernel decompresses modules compressed with method itself
+pub(crate) fn kernel_decompresses(method: &str) -> io::Result<bool> {
+	Ok(kernel_compression()?.as_deref() == Some(method))
+}
+
+/// Decompresses module file read from input into memory file, checking it like `insert`
+///
+/// Architecture of image is checked against machine (or the one of running kernel) with its
+/// first chunk, before the rest is decompressed. Cancellation is checked between chunks.
+pub(crate) fn decompress_file(
+	input: impl Read,
+	path: &Path,
+	method: &str,
+	machine: Option<&str>,
+	cancellation: Option<&CancellationToken>,
+) -> io::Result<fs::File> {
+	if !crate::compress::is_supported(method) {
+		return Err(Error::InvalidModuleFormat(format!(
+			"{} compressed modules are not supported by running kernel",
+			method
+		))
+		.into());
+	}
+
+	// Fake kernel accepts anything
+	let checked = !cfg!(feature = "testing");
+	let machine = match (checked, machine) {
+		(false, _) => None,
+		(true, Some(machine)) => Some(machine.to_string()),
+		(true, None) => Some(crate::kernel::machine()?),
+	};
+
+	// Decompressed image goes straight into memory file, only one chunk is held at a time
+	let limit = DECOMPRESSION_LIMIT.load(Ordering::Relaxed);
+	let mut image = memfd()?;
+	let size =
+		crate::compress::decompress_into(input, method, &mut image, limit, |offset, chunk| {
+			if let Some(token) = cancellation {
+				token.check()?;
+			}
+			match (&machine, offset) {
+				(Some(machine), 0) => crate::elf::check_header(chunk, machine),
+				_ => Ok(()),
+			}
+		})?;
+	if size.is_none() {
+		return Err(Error::DecompressionLimit {
+			module: path.display().to_string(),
+			limit,
+		}
+		.into());
+	}
+
+	// Unsigned modules would be rejected with error not telling why, signature ends image
+	if checked
+		&& crate::kernel::signatures_enforced()
+		&& crate::ModuleSignature::from_image(&tail(&mut image)?).is_none()
+	{
+		return Err(Error::SignatureRequired {
+			module: crate::index::module_name(&path.to_string_lossy()),
+			signer: None,
+		}
+		.into());
+	}
+
+	Ok(image)
+}
+
+/// Reads end of memory file long enough to hold module signature
+fn tail(file: &mut fs::File) -> io::Result<Vec<u8>> {
+	let size = file.metadata()?.len();
+	file.seek(SeekFrom::Start(size.saturating_sub(SIGNATURE_TAIL)))?;
+	let mut tail = Vec::new();
+	file.read_to_end(&mut tail)?;
+	Ok(tail)
+}
+
+/// Reads rest of file into image, checking cancellation token between chunks
+pub(crate) fn read_file(
+	file: &mut fs::File,
+	image: &mut Vec<u8>,
+	cancellation: Option<&CancellationToken>,
+) -> io::Result<()> {
+	let Some(token) = cancellation else {
+		file.read_to_end(image)?;
+		return Ok(());
+	};
+
+	loop {
+		token.check()?;
+		let start = image.len();
+		image.resize(start + READ_CHUNK, 0);
+		let read = file.read(&mut image[start..]);
+		image.truncate(start + *read.as_ref().unwrap_or(&0));
+		match read {
+			Ok(0) => return Ok(()),
+			Ok(_) => (),
+			Err(e) if e.kind() == ErrorKind::Interrupted => (),
+			Err(e) => return Err(e),
+		}
+	}
+}
+
+/// Reads back whole image from memory file
+pub(crate) fn read_back(file: &mut fs::File) -> io::Result<Vec<u8>> {
+	let mut image = Vec::new();
+	file.seek(SeekFrom::Start(0))?;
+	file.read_to_end(&mut image)?;
+	Ok(image)
+}
+
+/// Load kernel module by byte array after checking its SHA-256 digest
+///
+/// Image is not passed to kernel if its digest doesn't match, `Error::IntegrityMismatch` is
+/// returned instead.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let image = std::fs::read("./module.ko")?;
+/// let digest = [0u8; 32]; // Digest published with module
+///
+/// if let Err(e) = liblmod::loader::load_verified(&image, "param=0", &digest) {
+///     eprintln!("Failed to insert module by image: {e}");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "integrity")]
+pub fn load_verified(image: &[u8], params: impl AsRef<str>, digest: &[u8; 32]) -> io::Result<()> {
+	verify(image, digest)?;
+	load(image, params)
+}
+
+/// Checks if SHA-256 digest of data matches expected one
+#[cfg(feature = "integrity")]
+pub(crate) fn verify(data: &[u8], expected: &[u8; 32]) -> io::Result<()> {
+	let found: [u8; 32] = Sha256::digest(data).into();
+	if &found != expected {
+		return Err(Error::IntegrityMismatch {
+			expected: hex(expected),
+			found: hex(&found),
+		}
+		.into());
+	}
+
 	Ok(())
 }
+
+#[cfg(feature = "integrity")]
+fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
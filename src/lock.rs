@@ -0,0 +1,48 @@
+use std::{
+	fs, io,
+	os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+};
+
+/// Lock file shared by all processes using this crate
+const LOCK_PATH: &str = "/run/liblmod.lock";
+
+/// Exclusive inter-process lock serializing module operations, released when dropped
+pub(crate) struct Lock {
+	_file: fs::File,
+}
+
+impl Lock {
+	/// Waits until no other process (or thread) holds the lock and takes it
+	pub(crate) fn acquire() -> io::Result<Lock> {
+		let file = fs::OpenOptions::new()
+			.read(true)
+			.write(true)
+			.create(true)
+			.truncate(false)
+			.mode(0o600)
+			.custom_flags(libc::O_CLOEXEC)
+			.open(LOCK_PATH)?;
+
+		// Lock is bound to open file, so it's released when file is closed
+		loop {
+			if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+				break;
+			}
+
+			let e = io::Error::last_os_error();
+			if e.kind() != io::ErrorKind::Interrupted {
+				return Err(e);
+			}
+		}
+
+		Ok(Lock { _file: file })
+	}
+
+	/// Takes the lock only if requested
+	pub(crate) fn acquire_if(lock: bool) -> io::Result<Option<Lock>> {
+		match lock {
+			true => Lock::acquire().map(Some),
+			false => Ok(None),
+		}
+	}
+}
@@ -0,0 +1,149 @@
+use std::{
+	collections::HashMap,
+	io::{self, BufRead, BufReader},
+	path::Path,
+};
+
+use crate::{index, vfs, Error};
+
+/// Reads modules.dep, listing every module followed by a colon and its dependencies
+///
+/// Blank lines and comments (`#`) are skipped. Lines which don't match `module: dep dep`
+/// grammar (e.g. truncated by interrupted depmod) are reported with their position.
+pub(crate) fn read_dep(path: &Path) -> io::Result<Vec<(String, Vec<String>)>> {
+	let mut entries = Vec::new();
+	let mut seen: HashMap<String, usize> = HashMap::new();
+	for (number, line) in lines(path)? {
+		let line = line?;
+		let (module, list) = match line.split_once(':') {
+			Some(entry) => entry,
+			None => {
+				return Err(error(
+					path,
+					number,
+					line.len() + 1,
+					"expected ':' after module path",
+				))
+			}
+		};
+
+		let target = module.trim();
+		let column = module.len() - module.trim_start().len() + 1;
+		if target.is_empty() {
+			return Err(error(path, number, column, "missing module path"));
+		}
+		if target.contains(char::is_whitespace) {
+			return Err(error(
+				path,
+				number,
+				column,
+				"module path contains whitespace",
+			));
+		}
+		check_module(path, number, column, target)?;
+		if let Some(first) = seen.insert(target.to_string(), number) {
+			return Err(error(
+				path,
+				number,
+				column,
+				&format!("duplicate entry of {} (first at line {})", target, first),
+			));
+		}
+
+		let mut deps = Vec::new();
+		for (offset, dep) in words(list) {
+			let column = module.len() + 1 + offset + 1;
+			if dep.contains(':') {
+				return Err(error(path, number, column, "unexpected ':' in dependency"));
+			}
+			check_module(path, number, column, dep)?;
+			deps.push(dep.to_string());
+		}
+
+		entries.push((target.to_string(), deps));
+	}
+
+	Ok(entries)
+}
+
+/// Reads modules.order, listing one module path per line
+pub(crate) fn read_order(path: &Path) -> io::Result<Vec<String>> {
+	let mut order = Vec::new();
+	for (number, line) in lines(path)? {
+		let line = line?;
+		let mut words = words(&line);
+		let (offset, module) = match words.next() {
+			Some(word) => word,
+			None => continue,
+		};
+		if let Some((offset, _)) = words.next() {
+			return Err(error(
+				path,
+				number,
+				offset + 1,
+				"expected one module per line",
+			));
+		}
+
+		check_module(path, number, offset + 1, module)?;
+		order.push(module.to_string());
+	}
+
+	Ok(order)
+}
+
+/// Returns numbered lines of manifest, except blank lines and comments
+fn lines(path: &Path) -> io::Result<impl Iterator<Item = (usize, io::Result<String>)>> {
+	Ok(BufReader::new(vfs::open(path)?)
+		.lines()
+		.enumerate()
+		.map(|(i, line)| (i + 1, line))
+		.filter(|(_, line)| match line {
+			Ok(line) => {
+				let line = line.trim();
+				!line.is_empty() && !line.starts_with('#')
+			}
+			Err(_) => true,
+		}))
+}
+
+/// Splits text into words with their byte offsets
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+	text.split(char::is_whitespace)
+		.scan(0, |offset, word| {
+			let start = *offset;
+			*offset += word.len() + 1;
+			Some((start, word))
+		})
+		.filter(|(_, word)| !word.is_empty())
+}
+
+/// Checks if word names module file inside module directory
+///
+/// Absolute paths and `..` components could point at any file of the system, they are
+/// rejected with `Error::OutsideModuleTree`.
+fn check_module(path: &Path, line: usize, column: usize, word: &str) -> io::Result<()> {
+	if !index::is_module_file(word) {
+		return Err(error(
+			path,
+			line,
+			column,
+			&format!("{} is not a module file", word),
+		));
+	}
+	if !index::is_beneath(word) {
+		return Err(Error::OutsideModuleTree(word.to_string()).into());
+	}
+
+	Ok(())
+}
+
+fn error(path: &Path, line: usize, column: usize, reason: &str) -> io::Error {
+	Error::ManifestParse {
+		file: path.display().to_string(),
+		line,
+		column,
+		reason: reason.to_string(),
+	}
+	.into()
+}
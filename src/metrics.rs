@@ -0,0 +1,204 @@
+use std::{
+	cell::RefCell,
+	path::Path,
+	sync::atomic::{AtomicBool, Ordering},
+	time::{Duration, Instant},
+};
+
+/// Whether time of module operations is measured
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+	/// Metrics recorded on current thread since they were taken last time
+	static RECORDED: RefCell<Metrics> = RefCell::new(Metrics::default());
+}
+
+/// Time spent in module operations of current thread, returned by `take_metrics`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Metrics {
+	/// Time spent reading module index and configuration and resolving load plans
+	pub resolve: Duration,
+
+	/// Loaded module files, in order of loading
+	pub modules: Vec<ModuleMetrics>,
+}
+
+/// Time spent loading single module file
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModuleMetrics {
+	/// Path of module file
+	pub path: String,
+
+	/// Number of bytes read from module file
+	pub bytes_read: u64,
+
+	/// Time spent reading module file
+	pub read: Duration,
+
+	/// Compression method of module file
+	pub compression: Option<String>,
+
+	/// Time spent decompressing module by library, zero if kernel decompresses it within syscall
+	pub decompress: Duration,
+
+	/// Time spent in syscall inserting module, including decompression and module initialization
+	pub syscall: Duration,
+}
+
+/// Time spent in phases of loading modules, returned in `LoadReport`
+///
+/// Unlike `Metrics`, timings are always measured.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoadTimings {
+	/// Time spent resolving module and its dependencies into load plan
+	pub resolve: Duration,
+
+	/// Time spent reading module files
+	pub read: Duration,
+
+	/// Time spent decompressing module files by library (not by kernel)
+	pub decompress: Duration,
+
+	/// Time spent in syscalls inserting modules, including linking and initialization by kernel
+	pub syscall: Duration,
+}
+
+impl LoadTimings {
+	/// Returns total time of all phases
+	pub fn total(&self) -> Duration {
+		self.resolve + self.read + self.decompress + self.syscall
+	}
+}
+
+impl std::ops::AddAssign for LoadTimings {
+	fn add_assign(&mut self, other: LoadTimings) {
+		self.resolve += other.resolve;
+		self.read += other.read;
+		self.decompress += other.decompress;
+		self.syscall += other.syscall;
+	}
+}
+
+/// Phase of loading module file, traced as span with `tracing` feature
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Phase {
+	Read,
+	Decompress,
+	Syscall,
+}
+
+impl Metrics {
+	/// Returns total time spent in syscalls of all modules
+	pub fn syscall(&self) -> Duration {
+		self.modules.iter().map(|module| module.syscall).sum()
+	}
+
+	/// Returns total number of bytes read from module files
+	pub fn bytes_read(&self) -> u64 {
+		self.modules.iter().map(|module| module.bytes_read).sum()
+	}
+}
+
+/// Enables or disables measuring time of module operations (disabled by default)
+///
+/// Metrics are recorded per thread, so they are not mixed between concurrent callers. Modules
+/// loaded with timeout (`load_timeout`) are recorded on calling thread, unless loading times out.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// liblmod::enable_metrics(true);
+/// let _ = liblmod::modprobe("kvm", "", liblmod::Selection::Current);
+///
+/// let metrics = liblmod::take_metrics();
+/// println!("Resolution took {:?}", metrics.resolve);
+/// for module in &metrics.modules {
+///     println!(
+///         "{}: {} bytes read in {:?}, inserted in {:?}",
+///         module.path, module.bytes_read, module.read, module.syscall
+///     );
+/// }
+/// ```
+pub fn enable_metrics(enabled: bool) {
+	ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns metrics recorded on current thread since last call, clearing them
+pub fn take_metrics() -> Metrics {
+	RECORDED.with(|recorded| recorded.take())
+}
+
+/// Adds metrics recorded on another thread to ones of current thread
+pub(crate) fn merge(metrics: Metrics) {
+	RECORDED.with(|recorded| {
+		let mut recorded = recorded.borrow_mut();
+		recorded.resolve += metrics.resolve;
+		recorded.modules.extend(metrics.modules);
+	});
+}
+
+/// Checks if metrics are recorded
+pub(crate) fn is_enabled() -> bool {
+	ENABLED.load(Ordering::Relaxed)
+}
+
+/// Runs part of resolution, adding its duration to recorded metrics
+pub(crate) fn resolve<T>(f: impl FnOnce() -> T) -> T {
+	#[cfg(feature = "tracing")]
+	let _span = tracing::info_span!("resolve").entered();
+
+	if !is_enabled() {
+		return f();
+	}
+
+	let start = Instant::now();
+	let result = f();
+	let elapsed = start.elapsed();
+	RECORDED.with(|recorded| recorded.borrow_mut().resolve += elapsed);
+	result
+}
+
+/// Measures time spent in function
+pub(crate) fn time<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+	let start = Instant::now();
+	let result = f();
+	(result, start.elapsed())
+}
+
+/// Measures time spent in phase of loading module file, within tracing span of the phase
+pub(crate) fn phase<T>(phase: Phase, path: &Path, f: impl FnOnce() -> T) -> (T, Duration) {
+	#[cfg(feature = "tracing")]
+	let _span = {
+		let path = path.display();
+		match phase {
+			Phase::Read => tracing::info_span!("read", %path).entered(),
+			Phase::Decompress => tracing::info_span!("decompress", %path).entered(),
+			Phase::Syscall => tracing::info_span!("syscall", %path).entered(),
+		}
+	};
+	#[cfg(not(feature = "tracing"))]
+	let _ = (phase, path);
+
+	time(f)
+}
+
+/// Records loading of module file
+pub(crate) fn module(path: &Path, bytes_read: usize, timings: &LoadTimings) {
+	if !is_enabled() {
+		return;
+	}
+
+	let module = ModuleMetrics {
+		path: path.to_string_lossy().into_owned(),
+		bytes_read: bytes_read as u64,
+		read: timings.read,
+		compression: crate::loader::file_compression(path),
+		decompress: timings.decompress,
+		syscall: timings.syscall,
+	};
+	RECORDED.with(|recorded| recorded.borrow_mut().modules.push(module));
+}
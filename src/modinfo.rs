@@ -0,0 +1,384 @@
+use std::{ffi::OsStr, io, path::Path};
+
+use crate::{config, elf::Elf, index, Error, ModuleSignature};
+#[cfg(feature = "modinfo")]
+use crate::{suggest, ModuleIndex, Selection};
+
+/// Information embedded in `.modinfo` section of module image
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// # #[cfg(feature = "modinfo")]
+/// # {
+/// match liblmod::modinfo("kvm", liblmod::Selection::Current) {
+///     Ok(info) => println!("kvm is licensed under {:?}", info.license),
+///     Err(e) => eprintln!("Failed to read information about module kvm: {e}"),
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModuleInfo {
+	/// Name of module
+	pub name: Option<String>,
+
+	/// License of module
+	pub license: Option<String>,
+
+	/// Description of module
+	pub description: Option<String>,
+
+	/// Authors of module
+	pub authors: Vec<String>,
+
+	/// Version of module
+	pub version: Option<String>,
+
+	/// Checksum of module source
+	pub srcversion: Option<String>,
+
+	/// Version magic of kernel which module is built for
+	pub vermagic: Option<String>,
+
+	/// Names of modules this module depends on
+	pub depends: Vec<String>,
+
+	/// Aliases of module
+	pub aliases: Vec<String>,
+
+	/// Firmware files used by module
+	pub firmware: Vec<String>,
+
+	/// Declared module parameters
+	pub parameters: Vec<Parameter>,
+
+	/// Module is built as part of kernel tree
+	pub intree: bool,
+
+	/// Whether module comes from kernel tree, staging drivers or outside of kernel tree
+	pub origin: ModuleOrigin,
+
+	/// Signature appended to module image, `None` if module isn't signed
+	pub signature: Option<ModuleSignature>,
+
+	/// All `key=value` entries in order of appearance
+	pub fields: Vec<(String, String)>,
+}
+
+/// Origin of module, for inventory of drivers not coming from upstream kernel
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// if let Ok(modules) = liblmod::lsmod() {
+///     for module in modules {
+///         if module.origin != liblmod::ModuleOrigin::InTree {
+///             println!("{} is not an upstream driver ({:?})", module.name, module.origin);
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ModuleOrigin {
+	/// Module is built as part of kernel tree
+	InTree,
+
+	/// Module is built outside of kernel tree (e.g. DKMS or vendor driver), taints kernel with `O`
+	#[default]
+	OutOfTree,
+
+	/// Module is a staging driver of kernel tree (not of upstream quality), taints kernel with `C`
+	Staging,
+}
+
+impl ModuleOrigin {
+	/// Guesses origin of module from its path, `None` if path doesn't tell anything
+	///
+	/// Modules in `drivers/staging` are staging drivers, modules in `extra`, `updates` and
+	/// `weak-updates` directories are installed by packages built outside of kernel tree.
+	pub(crate) fn from_path(path: &Path) -> Option<ModuleOrigin> {
+		let components: Vec<_> = path.iter().collect();
+		if components
+			.windows(2)
+			.any(|pair| pair[0] == "drivers" && pair[1] == "staging")
+		{
+			return Some(ModuleOrigin::Staging);
+		}
+		if components.iter().any(|component| {
+			["extra", "updates", "weak-updates"]
+				.map(OsStr::new)
+				.contains(component)
+		}) {
+			return Some(ModuleOrigin::OutOfTree);
+		}
+
+		None
+	}
+
+	/// Reads origin of loaded module from its taint flags
+	pub(crate) fn from_taints(taints: &str) -> ModuleOrigin {
+		if taints.contains('C') {
+			ModuleOrigin::Staging
+		} else if taints.contains('O') {
+			ModuleOrigin::OutOfTree
+		} else {
+			ModuleOrigin::InTree
+		}
+	}
+}
+
+/// Parameter declared by module
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Parameter {
+	/// Name of parameter
+	pub name: String,
+
+	/// Description of parameter
+	pub description: Option<String>,
+
+	/// Type of parameter (e.g. `int`, `bool`, `charp` or `array of int`)
+	pub kind: Option<String>,
+}
+
+impl ModuleInfo {
+	/// Reads module information from uncompressed module image
+	pub fn from_image(image: &[u8]) -> io::Result<ModuleInfo> {
+		let elf = Elf::parse(image)?;
+		let section = elf.section(".modinfo")?.ok_or_else(|| {
+			io::Error::from(Error::InvalidModuleFormat(
+				"image doesn't have .modinfo section".to_string(),
+			))
+		})?;
+
+		let mut info = ModuleInfo::default();
+		for entry in section.split(|b| *b == 0) {
+			let entry = String::from_utf8_lossy(entry);
+			if let Some((key, value)) = entry.split_once('=') {
+				info.add(key, value);
+			}
+		}
+		info.signature = ModuleSignature::from_image(image);
+		if info.intree {
+			info.origin = ModuleOrigin::InTree;
+		}
+		if info
+			.fields
+			.iter()
+			.any(|(key, value)| key == "staging" && value == "Y")
+		{
+			info.origin = ModuleOrigin::Staging;
+		}
+
+		Ok(info)
+	}
+
+	/// Reads module information from module file, decompressing it if it's compressed
+	///
+	/// Origin of in-tree modules is also guessed from path, so in-tree modules installed into
+	/// `updates` or `extra` directories (e.g. rebuilt by vendor) are reported as out-of-tree.
+	pub fn from_file(path: impl AsRef<Path>) -> io::Result<ModuleInfo> {
+		let mut info = ModuleInfo::from_image(&index::module_image(path.as_ref())?)?;
+		if info.origin == ModuleOrigin::InTree {
+			if let Some(origin) = ModuleOrigin::from_path(path.as_ref()) {
+				info.origin = origin;
+			}
+		}
+
+		Ok(info)
+	}
+
+	/// Serializes module information to JSON
+	///
+	/// Object has the same fields as `ModuleInfo`, `parameters` are objects with `name`,
+	/// `description` and `kind` fields and `fields` are `[key, value]` pairs. Missing values are
+	/// `null`. Fields are only added in new versions, never renamed or removed.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// if let Ok(info) = liblmod::modinfo("kvm", liblmod::Selection::Current) {
+	///     println!("{}", info.to_json());
+	/// }
+	/// ```
+	#[cfg(feature = "json")]
+	pub fn to_json(&self) -> String {
+		serde_json::to_string(self).unwrap()
+	}
+
+	/// Checks if license of module is compatible with GPL
+	///
+	/// Kernel is tainted when module with incompatible (or without) license is loaded.
+	pub fn is_gpl_compatible(&self) -> bool {
+		matches!(
+			self.license.as_deref(),
+			Some(
+				"GPL"
+					| "GPL v2" | "GPL and additional rights"
+					| "Dual BSD/GPL"
+					| "Dual MIT/GPL"
+					| "Dual MPL/GPL"
+			)
+		)
+	}
+
+	/// Returns declared parameter with specified name
+	pub fn parameter(&self, name: &str) -> Option<&Parameter> {
+		let name = index::normalize(name);
+		self.parameters
+			.iter()
+			.find(|p| index::normalize(&p.name) == name)
+	}
+
+	/// Checks if every parameter is declared by module and its value matches declared type
+	///
+	/// Parameters with unknown type are accepted with any value.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// # #[cfg(feature = "modinfo")]
+	/// # {
+	/// if let Ok(info) = liblmod::modinfo("kvm", liblmod::Selection::Current) {
+	///     if let Err(e) = info.validate_params("ignore_msrs=1") {
+	///         eprintln!("Invalid parameters for module kvm: {e}");
+	///     }
+	/// }
+	/// # }
+	/// ```
+	pub fn validate_params(&self, params: impl AsRef<str>) -> io::Result<()> {
+		let module = self.name.clone().unwrap_or_default();
+		for word in config::cmdline_words(params.as_ref()) {
+			let (key, value) = word.split_once('=').unwrap_or((word, ""));
+			let parameter = match self.parameter(key) {
+				Some(o) => o,
+				None => {
+					return Err(Error::UnknownParameter {
+						module,
+						parameter: key.to_string(),
+					}
+					.into())
+				}
+			};
+
+			let kind = match &parameter.kind {
+				Some(o) => o,
+				None => continue,
+			};
+			let unquoted = value
+				.strip_prefix('"')
+				.and_then(|v| v.strip_suffix('"'))
+				.unwrap_or(value);
+			if !value_matches(kind, unquoted) {
+				return Err(Error::InvalidParameterValue {
+					module,
+					parameter: key.to_string(),
+					value: value.to_string(),
+					expected: kind.clone(),
+				}
+				.into());
+			}
+		}
+
+		Ok(())
+	}
+
+	fn add(&mut self, key: &str, value: &str) {
+		self.fields.push((key.to_string(), value.to_string()));
+		let value = value.to_string();
+		match key {
+			"name" => self.name = Some(value),
+			"license" => self.license = Some(value),
+			"description" => self.description = Some(value),
+			"author" => self.authors.push(value),
+			"version" => self.version = Some(value),
+			"srcversion" => self.srcversion = Some(value),
+			"vermagic" => self.vermagic = Some(value),
+			"alias" => self.aliases.push(value),
+			"firmware" => self.firmware.push(value),
+			"intree" => self.intree = value == "Y",
+			"depends" => {
+				self.depends = value
+					.split(',')
+					.filter(|d| !d.is_empty())
+					.map(|d| d.to_string())
+					.collect()
+			}
+			// Parameters are declared as `parm=name:description` and `parmtype=name:type`
+			"parm" | "parmtype" => {
+				let (name, rest) = value.split_once(':').unwrap_or((&value, ""));
+				let parameter = match self.parameters.iter_mut().find(|p| p.name == name) {
+					Some(o) => o,
+					None => {
+						self.parameters.push(Parameter {
+							name: name.to_string(),
+							description: None,
+							kind: None,
+						});
+						self.parameters.last_mut().unwrap()
+					}
+				};
+				if key == "parm" {
+					parameter.description = Some(rest.to_string());
+				} else {
+					parameter.kind = Some(rest.to_string());
+				}
+			}
+			_ => (),
+		}
+	}
+}
+
+/// Reads information about module for selected kernel
+///
+/// Requires `modinfo` feature.
+#[cfg(feature = "modinfo")]
+pub fn modinfo(name: impl AsRef<str>, kernel: Selection) -> io::Result<ModuleInfo> {
+	let index = ModuleIndex::shared(kernel)?;
+	let module = index
+		.resolve(name.as_ref())
+		.ok_or_else(|| suggest::not_found(&*index, name.as_ref()))?;
+
+	ModuleInfo::from_file(index.path(module))
+}
+
+/// Checks if value can be parsed by kernel as parameter of specified type
+fn value_matches(kind: &str, value: &str) -> bool {
+	if let Some(element) = kind.strip_prefix("array of ") {
+		return value.split(',').all(|v| value_matches(element, v));
+	}
+
+	match kind {
+		"byte" | "ushort" | "uint" | "ulong" | "ullong" | "hexint" => {
+			parse_integer(value).is_some()
+		}
+		"short" | "int" | "long" | "llong" => {
+			parse_integer(value.strip_prefix('-').unwrap_or(value)).is_some()
+		}
+		"bool" | "invbool" => {
+			matches!(value, "" | "1" | "0" | "y" | "Y" | "n" | "N" | "on" | "off")
+		}
+		_ => true,
+	}
+}
+
+/// Parses unsigned integer like kernel's `kstrtoull` with automatic base
+pub(crate) fn parse_integer(value: &str) -> Option<u64> {
+	let value = value.strip_prefix('+').unwrap_or(value);
+	if let Some(hex) = value
+		.strip_prefix("0x")
+		.or_else(|| value.strip_prefix("0X"))
+	{
+		u64::from_str_radix(hex, 16).ok()
+	} else if value.len() > 1 && value.starts_with('0') {
+		u64::from_str_radix(&value[1..], 8).ok()
+	} else {
+		value.parse().ok()
+	}
+}
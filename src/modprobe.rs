@@ -0,0 +1,1044 @@
+use std::{
+	collections::HashMap,
+	ffi::OsString,
+	hash::{DefaultHasher, Hash, Hasher},
+	io::{self, ErrorKind},
+	path::{Path, PathBuf},
+	process::{Command, Stdio},
+	sync::Arc,
+	thread,
+	time::Duration,
+};
+
+use crate::{
+	cancel::CancellationToken, config, index, lock::Lock, metrics, plan, priority, Config, Error,
+	IoPriority, LoadDeadlines, LoadTimings, ModuleIndex, ModuleInfo, ModuleResolver, Selection,
+};
+
+/// System modprobe used in fallback mode
+const MODPROBE: &str = "/sbin/modprobe";
+
+/// Interval between attempts to load module whose files are missing in resilient mode
+const RESILIENT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Environment variables with default options, later ones take precedence
+const ENVIRONMENT: [&str; 2] = ["MODPROBE_OPTIONS", "LIBLMOD_OPTIONS"];
+
+/// Options for loading modules
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let options = liblmod::Options::new().kernel(liblmod::Selection::Other("5.4-x86_64".to_string()));
+/// ```
+#[derive(Clone)]
+pub struct Options {
+	pub(crate) kernel: Selection,
+	pub(crate) release: Option<OsString>,
+	pub(crate) machine: Option<String>,
+	pub(crate) first_time: bool,
+	pub(crate) quiet: bool,
+	pub(crate) fallback: bool,
+	pub(crate) validate_params: bool,
+	pub(crate) search_dirs: Vec<PathBuf>,
+	pub(crate) license_policy: LicensePolicy,
+	pub(crate) resolver: Option<Arc<dyn ModuleResolver>>,
+	pub(crate) config: Option<Arc<Config>>,
+	pub(crate) lock: bool,
+	pub(crate) cancellation: Option<CancellationToken>,
+	pub(crate) rollback: bool,
+	pub(crate) ignore_install: bool,
+	pub(crate) module_params: HashMap<String, String>,
+	pub(crate) resilient: u32,
+	#[cfg(feature = "uevent")]
+	pub(crate) denylist: Option<Arc<Denylist>>,
+	pub(crate) dry_run: bool,
+	pub(crate) dirname: Option<PathBuf>,
+	pub(crate) manifest_paths: HashMap<String, PathBuf>,
+	pub(crate) reject_ambiguous: bool,
+	pub(crate) jobs: usize,
+	pub(crate) nice: Option<i32>,
+	pub(crate) io_priority: Option<IoPriority>,
+	pub(crate) deadlines: Option<Arc<LoadDeadlines>>,
+}
+
+/// Function deciding whether module must not be autoloaded, see `Options::denylist`
+#[cfg(feature = "uevent")]
+type Denylist = dyn Fn(&str) -> bool + Send + Sync;
+
+impl Options {
+	/// Creates options for current running kernel, with defaults from environment
+	///
+	/// Options of modprobe in `MODPROBE_OPTIONS` and then `LIBLMOD_OPTIONS` variables are
+	/// applied: `-q`/`--quiet`, `-n`/`--dry-run`, `-i`/`--ignore-install`, `--first-time`,
+	/// `-d`/`--dirname` and `-S`/`--set-version`. Other words are ignored, so tuning doesn't
+	/// need changes in application (e.g. in containers). Options set later by application take
+	/// precedence. Policy files are not read, see `LoadDeadlines::system`.
+	///
+	/// In secure execution (setuid or setgid program, or file capabilities), `-d` and `-S` are
+	/// ignored, so environment of unprivileged user can't redirect loading to other module tree.
+	pub fn new() -> Options {
+		let mut options = Options::without_environment();
+		for variable in ENVIRONMENT {
+			if let Some(value) = std::env::var_os(variable) {
+				options = options.apply_words(&value.to_string_lossy());
+			}
+		}
+
+		options
+	}
+
+	/// Creates options for current running kernel, without reading environment or policy files
+	pub fn without_environment() -> Options {
+		Options {
+			kernel: Selection::Current,
+			release: None,
+			machine: None,
+			first_time: false,
+			quiet: false,
+			fallback: false,
+			validate_params: false,
+			search_dirs: Vec::new(),
+			license_policy: LicensePolicy::Allow,
+			resolver: None,
+			config: None,
+			lock: false,
+			cancellation: None,
+			rollback: false,
+			ignore_install: false,
+			module_params: HashMap::new(),
+			resilient: 0,
+			#[cfg(feature = "uevent")]
+			denylist: None,
+			dry_run: false,
+			dirname: None,
+			manifest_paths: HashMap::new(),
+			reject_ambiguous: false,
+			jobs: 1,
+			nice: None,
+			io_priority: None,
+			deadlines: None,
+		}
+	}
+
+	/// Selects kernel which modules are loaded for
+	///
+	/// Module options and blacklist from kernel command line apply only to running kernel
+	/// (`Selection::Current`).
+	pub fn kernel(mut self, kernel: Selection) -> Options {
+		self.kernel = kernel;
+		self
+	}
+
+	/// Uses release for current running kernel instead of asking kernel for it
+	///
+	/// Useful in restricted containers, where neither uname nor `/proc/sys/kernel/osrelease` is
+	/// available, but modules can still be loaded (e.g. via a broker). Unlike
+	/// `Selection::Other`, release doesn't have to be valid UTF-8. Ignored if other kernel is
+	/// selected.
+	pub fn release(mut self, release: impl Into<OsString>) -> Options {
+		self.release = Some(release.into());
+		self
+	}
+
+	/// Uses machine (e.g. `aarch64`) instead of the one of uname when checking architecture of modules
+	///
+	/// Useful when uname of process doesn't name architecture modules are built for, e.g. 32-bit
+	/// userland with 32-bit personality (`armv8l`) on arm64 kernel. Module index is still selected
+	/// with `kernel` and `release`, independently of machine. Machine applies to every module
+	/// loaded with options, including contexts of `compat::kmod`, while `load` and `load_timeout`
+	/// always use machine of uname.
+	pub fn machine(mut self, machine: impl Into<String>) -> Options {
+		self.machine = Some(machine.into());
+		self
+	}
+
+	/// Fails with `AlreadyExists` error if module is already loaded, like `modprobe --first-time`
+	pub fn first_time(mut self, first_time: bool) -> Options {
+		self.first_time = first_time;
+		self
+	}
+
+	/// Reports missing module as `Outcome::NotAvailable` instead of an error, like `modprobe -q`
+	pub fn quiet(mut self, quiet: bool) -> Options {
+		self.quiet = quiet;
+		self
+	}
+
+	/// Retries with system modprobe (`/sbin/modprobe`) if module cannot be loaded directly
+	///
+	/// This handles setups not supported by this crate (e.g. softdep rules). Fallback isn't used
+	/// for permission errors. If system modprobe fails too, the original error is returned.
+	pub fn fallback(mut self, fallback: bool) -> Options {
+		self.fallback = fallback;
+		self
+	}
+
+	/// Checks parameters passed by caller against parameters declared by module before loading
+	///
+	/// Undeclared parameter fails with `Error::UnknownParameter` and value not matching declared
+	/// type fails with `Error::InvalidParameterValue`. Modules which can't be decompressed are not
+	/// checked.
+	pub fn validate_params(mut self, validate_params: bool) -> Options {
+		self.validate_params = validate_params;
+		self
+	}
+
+	/// Adds directory with out-of-tree modules consulted before module directory of kernel
+	///
+	/// See `ModuleIndex::add_directory`. Can be called multiple times, later directories take
+	/// precedence.
+	pub fn search_dir(mut self, dir: impl AsRef<Path>) -> Options {
+		self.search_dirs.push(dir.as_ref().to_path_buf());
+		self
+	}
+
+	/// Selects what happens with modules whose license is not compatible with GPL
+	///
+	/// Licenses of module and its dependencies are checked before anything is loaded, compressed
+	/// modules are decompressed for that. With `LicensePolicy::Reject`, modules whose license
+	/// can't be read (e.g. compressed with method library can't decompress) are refused too.
+	pub fn license_policy(mut self, license_policy: LicensePolicy) -> Options {
+		self.license_policy = license_policy;
+		self
+	}
+
+	/// Resolves modules with custom resolver instead of module index of selected kernel
+	///
+	/// Selected kernel and search directories are ignored, modules are resolved only by resolver.
+	pub fn resolver(mut self, resolver: impl ModuleResolver + 'static) -> Options {
+		self.resolver = Some(Arc::new(resolver));
+		self
+	}
+
+	/// Uses configuration instead of reading modprobe.d and kernel command line
+	///
+	/// Together with `resolver` (e.g. `ModuleIndex::from_manifests`), modules are resolved
+	/// without reading any configuration or manifests from filesystem.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// # #[cfg(feature = "config")]
+	/// # {
+	/// let config = liblmod::Config::new().file("/etc/modprobe.d/kvm.conf", "options kvm_intel nested=1\n");
+	/// let options = liblmod::Options::new().config(config);
+	/// if let Err(e) = liblmod::modprobe_with("kvm_intel", "", &options) {
+	///     eprintln!("Failed to load kvm_intel: {e}");
+	/// }
+	/// # }
+	/// ```
+	pub fn config(mut self, config: Config) -> Options {
+		self.config = Some(Arc::new(config));
+		self
+	}
+
+	/// Serializes loading with other processes using this crate, like kmod does
+	///
+	/// Whole loading sequence (module with its dependencies) is done while holding exclusive lock
+	/// on `/run/liblmod.lock`, so concurrent processes don't race on shared dependencies.
+	pub fn lock(mut self, lock: bool) -> Options {
+		self.lock = lock;
+		self
+	}
+
+	/// Allows cancelling loading with token, see `CancellationToken`
+	pub fn cancellation(mut self, token: CancellationToken) -> Options {
+		self.cancellation = Some(token);
+		self
+	}
+
+	/// Unloads dependencies inserted by this call if loading fails, restoring previous kernel state
+	///
+	/// Only modules which have been loaded by this call are unloaded, in reverse order of loading.
+	/// Rollback is best effort: modules which cannot be unloaded (e.g. because they have been
+	/// taken into use meanwhile) are left loaded and the original error is returned.
+	pub fn rollback(mut self, rollback: bool) -> Options {
+		self.rollback = rollback;
+		self
+	}
+
+	/// Loads module directly even if modprobe.d has `install` command for it, like `modprobe -i`
+	///
+	/// Without this option, `install` command of requested module is run with `/bin/sh` instead of
+	/// loading the module. Commands of dependencies are not run.
+	pub fn ignore_install(mut self, ignore_install: bool) -> Options {
+		self.ignore_install = ignore_install;
+		self
+	}
+
+	/// Passes parameters to module when it's loaded, including dependencies of requested module
+	///
+	/// Parameters are added after ones from modprobe.d and, for requested module, after ones
+	/// passed to the call. Calling it again for the same module adds more parameters. They are
+	/// not passed to system modprobe in fallback mode.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// // Load kvm_intel, passing nested=1 to kvm dependency
+	/// let options = liblmod::Options::new().module_params("kvm", "nested=1");
+	/// if let Err(e) = liblmod::modprobe_with("kvm_intel", "", &options) {
+	///     eprintln!("Failed to load module kvm_intel: {e}");
+	/// }
+	/// ```
+	pub fn module_params(mut self, name: impl AsRef<str>, params: impl AsRef<str>) -> Options {
+		let current = self
+			.module_params
+			.entry(index::normalize(name.as_ref()))
+			.or_default();
+		*current = join_params(current, params.as_ref());
+		self
+	}
+
+	/// Re-reads index and configuration and retries up to `retries` times if files are missing
+	///
+	/// During kernel package upgrade, modules.dep can reference module files which are not
+	/// unpacked yet, or manifests can be momentarily missing. In resilient mode, loading and
+	/// resolution failing with `NotFound` are retried after 500 ms with freshly read index,
+	/// so modules which are really missing are reported only after all retries.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// let options = liblmod::Options::new().resilient(5);
+	/// if let Err(e) = liblmod::modprobe_with("kvm", "", &options) {
+	///     eprintln!("Failed to load module kvm: {e}");
+	/// }
+	/// ```
+	pub fn resilient(mut self, retries: u32) -> Options {
+		self.resilient = retries;
+		self
+	}
+
+	/// Refuses to autoload modules for which function returns `true`, in addition to blacklist
+	///
+	/// Consulted by `coldplug`, `autoload` and `uevent::UeventListener::autoload` for modules
+	/// matched by modaliases of devices, refused modules are reported as `Skipped::Denied`.
+	/// Modules requested by name are not affected.
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// // Never autoload nouveau, even if it's not blacklisted
+	/// let options = liblmod::Options::new().denylist(|name| name == "nouveau");
+	/// if let Err(e) = liblmod::coldplug(&options) {
+	///     eprintln!("Failed to load modules for devices: {e}");
+	/// }
+	/// ```
+	#[cfg(feature = "uevent")]
+	pub fn denylist(mut self, denylist: impl Fn(&str) -> bool + Send + Sync + 'static) -> Options {
+		self.denylist = Some(Arc::new(denylist));
+		self
+	}
+
+	/// Resolves modules and runs checks without loading anything, like `modprobe -n`
+	///
+	/// Modules which would be inserted are reported with `Outcome::DryRun` and install commands
+	/// from modprobe.d are not run.
+	pub fn dry_run(mut self, dry_run: bool) -> Options {
+		self.dry_run = dry_run;
+		self
+	}
+
+	/// Reads modules from `<dir>/lib/modules/<release>` instead of system directories, like
+	/// `modprobe -d`
+	pub fn dirname(mut self, dir: impl AsRef<Path>) -> Options {
+		self.dirname = Some(dir.as_ref().to_path_buf());
+		self
+	}
+
+	/// Reads manifest of module directory (e.g. `modules.dep`, `modules.alias` or
+	/// `modules.order`) from explicit path
+	///
+	/// Module files are still found in module directory (see `dirname`), other manifests are
+	/// read from it as well. See `ModuleIndex::from_manifest_paths`.
+	pub fn manifest_path(mut self, name: impl AsRef<str>, path: impl AsRef<Path>) -> Options {
+		self.manifest_paths
+			.insert(name.as_ref().to_string(), path.as_ref().to_path_buf());
+		self
+	}
+
+	/// Fails with `Error::AmbiguousModule` if module or its dependency has several equally
+	/// preferred files (e.g. two copies in `extra`), instead of using the first one
+	///
+	/// See `ModuleIndex::ambiguous` for how modules are preferred.
+	pub fn reject_ambiguous(mut self, reject: bool) -> Options {
+		self.reject_ambiguous = reject;
+		self
+	}
+
+	/// Sets number of threads loading modules for devices in `coldplug` and `autoload` (1 by
+	/// default, 0 uses number of CPUs)
+	///
+	/// Module is loaded only after modules it depends on which match other devices. Metrics are
+	/// recorded per thread, so modules loaded by worker threads are not recorded. Fake kernel of
+	/// `testing` feature is per thread as well, modules are loaded serially with it.
+	pub fn jobs(mut self, jobs: usize) -> Options {
+		self.jobs = match jobs {
+			0 => thread::available_parallelism().map_or(1, |jobs| jobs.get()),
+			jobs => jobs,
+		};
+		self
+	}
+
+	/// Sets niceness of threads resolving, decompressing and loading modules (-20 to 19)
+	///
+	/// Thread gets its previous niceness back when done, unless raising priority back is not
+	/// permitted (without CAP_SYS_NICE). Priorities are changed only in Linux, failures to
+	/// change them are ignored.
+	pub fn nice(mut self, nice: i32) -> Options {
+		self.nice = Some(nice.clamp(-20, 19));
+		self
+	}
+
+	/// Sets timeouts and retries of loading modules (e.g. `LoadDeadlines::system`)
+	///
+	/// Only loading by kernel is timed, timed out module may still be loaded later. See
+	/// `LoadDeadlines`.
+	pub fn deadlines(mut self, deadlines: LoadDeadlines) -> Options {
+		self.deadlines = Some(Arc::new(deadlines));
+		self
+	}
+
+	/// Sets I/O priority of threads reading and resolving modules, like `ionice`
+	///
+	/// Useful for preloading modules in background without competing with boot on slow storage
+	/// (e.g. eMMC). Previous priority is restored like with `Options::nice`.
+	pub fn io_priority(mut self, priority: IoPriority) -> Options {
+		self.io_priority = Some(priority);
+		self
+	}
+
+	/// Applies options of modprobe written in environment variable
+	fn apply_words(mut self, value: &str) -> Options {
+		let mut words = value.split_whitespace();
+		while let Some(word) = words.next() {
+			// Options with value accept it in the same or in the next word
+			let (option, value) = match word.split_once('=') {
+				Some((option, value)) if option.starts_with("--") => (option, Some(value)),
+				_ if word.len() > 2 && (word.starts_with("-d") || word.starts_with("-S")) => {
+					(&word[..2], Some(&word[2..]))
+				}
+				_ => (word, None),
+			};
+			match option {
+				"-q" | "--quiet" => self.quiet = true,
+				"-n" | "--dry-run" | "--show" => self.dry_run = true,
+				"-i" | "--ignore-install" => self.ignore_install = true,
+				"--first-time" => self.first_time = true,
+				"-d" | "--dirname" => {
+					if let Some(dir) = value.or_else(|| words.next()) {
+						if !is_secure_execution() {
+							self.dirname = Some(PathBuf::from(dir));
+						}
+					}
+				}
+				"-S" | "--set-version" => {
+					if let Some(release) = value.or_else(|| words.next()) {
+						if !is_secure_execution() {
+							self.kernel = Selection::Other(release.to_string());
+						}
+					}
+				}
+				_ => (),
+			}
+		}
+
+		self
+	}
+
+	/// Checks if module is refused by denylist
+	#[cfg(feature = "uevent")]
+	pub(crate) fn is_denied(&self, name: &str) -> bool {
+		self.denylist
+			.as_ref()
+			.is_some_and(|denylist| denylist(name))
+	}
+
+	/// Returns parameters of module passed to the call followed by ones set with `module_params`
+	pub(crate) fn params(&self, name: &str, params: &str) -> String {
+		match self.module_params.get(name) {
+			Some(extra) => join_params(params, extra),
+			None => params.to_string(),
+		}
+	}
+
+	/// Fails with `Error::Cancelled` if loading has been cancelled
+	pub(crate) fn check_cancelled(&self) -> io::Result<()> {
+		match &self.cancellation {
+			Some(token) => token.check(),
+			None => Ok(()),
+		}
+	}
+
+	/// Returns configuration set with `config` or reads system one, without options from kernel
+	/// command line if other kernel is selected
+	pub(crate) fn load_config(&self) -> io::Result<Arc<Config>> {
+		match &self.config {
+			Some(config) => Ok(config.clone()),
+			None => metrics::resolve(|| Config::load_for(&self.kernel)).map(Arc::new),
+		}
+	}
+
+	/// Returns fingerprint of options affecting load plans, `None` if plans can't be cached
+	pub(crate) fn fingerprint(&self) -> Option<u64> {
+		// Plans are cached in shared index, other options also change what is loaded
+		if self.resolver.is_some()
+			|| self.config.is_some()
+			|| !self.search_dirs.is_empty()
+			|| self.dirname.is_some()
+			|| !self.manifest_paths.is_empty()
+			|| self.first_time
+			|| self.fallback
+		{
+			return None;
+		}
+
+		let mut hasher = DefaultHasher::new();
+		(self.quiet, self.validate_params, self.reject_ambiguous).hash(&mut hasher);
+		(self.license_policy as u8).hash(&mut hasher);
+		let mut params: Vec<_> = self.module_params.iter().collect();
+		params.sort();
+		params.hash(&mut hasher);
+		Some(hasher.finish())
+	}
+
+	/// Returns custom resolver or module index for selected kernel extended with search directories
+	pub(crate) fn index(&self) -> io::Result<Arc<dyn ModuleResolver>> {
+		if let Some(resolver) = &self.resolver {
+			return Ok(resolver.clone());
+		}
+
+		metrics::resolve(|| self.open_index())
+	}
+
+	/// Reads index of selected kernel
+	fn open_index(&self) -> io::Result<Arc<dyn ModuleResolver>> {
+		let release = match (&self.kernel, &self.release) {
+			(Selection::Current, Some(release)) => release.clone(),
+			(kernel, _) => index::release(kernel.clone())?,
+		};
+		if self.search_dirs.is_empty() && self.dirname.is_none() && self.manifest_paths.is_empty() {
+			return Ok(ModuleIndex::shared_release(release)?);
+		}
+
+		let basepath = match &self.dirname {
+			Some(dir) => dir.join("lib/modules").join(&release),
+			None => index::module_dir(&release),
+		};
+		let mut index = ModuleIndex::open_paths(release, basepath, self.manifest_paths.clone())?;
+		for dir in &self.search_dirs {
+			index.add_directory(dir)?;
+		}
+
+		Ok(Arc::new(index))
+	}
+}
+
+impl Default for Options {
+	fn default() -> Options {
+		Options::new()
+	}
+}
+
+/// Checks if process runs with privileges not given by its user (`AT_SECURE`)
+#[cfg(target_os = "linux")]
+fn is_secure_execution() -> bool {
+	unsafe { libc::getauxval(libc::AT_SECURE) != 0 }
+}
+
+/// Checks if process runs with effective user or group other than real one
+#[cfg(not(target_os = "linux"))]
+fn is_secure_execution() -> bool {
+	unsafe { libc::getuid() != libc::geteuid() || libc::getgid() != libc::getegid() }
+}
+
+/// Handling of modules with license not compatible with GPL, which taint kernel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LicensePolicy {
+	/// Load modules regardless of their license
+	Allow,
+
+	/// Load modules, but add warning to `LoadReport::warnings`
+	Warn,
+
+	/// Refuse to load modules, fails with `Error::ProprietaryModule`
+	Reject,
+}
+
+/// Result of successful module loading
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Outcome {
+	/// Module has been inserted into kernel
+	Loaded,
+
+	/// Module was already loaded, kernel state has not been changed
+	AlreadyLoaded,
+
+	/// Module is built into kernel, kernel state has not been changed
+	Builtin,
+
+	/// Module is not provided by kernel (returned only in quiet mode)
+	NotAvailable,
+
+	/// Module would have been inserted into kernel, but dry run is enabled
+	DryRun,
+
+	/// Module is disabled with install command doing nothing (e.g. `install foo /bin/false`), the
+	/// command has not been run
+	DisabledByConfig,
+}
+
+/// Report of module loading
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoadReport {
+	/// Outcome for requested module
+	pub outcome: Outcome,
+
+	/// Processed modules in order of loading, requested module is the last one
+	pub modules: Vec<ModuleReport>,
+
+	/// Warnings about loaded modules (e.g. tainting kernel)
+	pub warnings: Vec<String>,
+
+	/// Time spent in phases of loading, summed over processed modules
+	pub timings: LoadTimings,
+
+	/// Taint flags newly set in kernel while modules have been loaded, `None` if there are none
+	pub taint: Option<TaintReport>,
+}
+
+impl LoadReport {
+	/// Returns modules which have been inserted into kernel
+	pub fn loaded(&self) -> impl Iterator<Item = &ModuleReport> {
+		self.modules
+			.iter()
+			.filter(|module| module.outcome == Outcome::Loaded)
+	}
+
+	/// Returns modules which were skipped, because they were already present
+	pub fn skipped(&self) -> impl Iterator<Item = &ModuleReport> {
+		self.modules
+			.iter()
+			.filter(|module| module.outcome != Outcome::Loaded)
+	}
+}
+
+/// Report of single module processed by modprobe
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModuleReport {
+	/// Name of module
+	pub name: String,
+
+	/// Absolute path of module file with symbolic links resolved
+	pub path: String,
+
+	/// Parameters module has been loaded with
+	pub params: String,
+
+	/// What has been done with module
+	pub outcome: Outcome,
+
+	/// Why module has been processed
+	pub provenance: Provenance,
+
+	/// Sizes of module file and its decompression, for modules loaded by this call
+	pub image: Option<ImageReport>,
+}
+
+/// Sizes of loaded module file and how it has been decompressed
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ImageReport {
+	/// Size of module file in bytes
+	pub file_size: u64,
+
+	/// Size of decompressed image in bytes, for modules decompressed by kernel read from header or
+	/// trailer of compressed file (`None` if it's not recorded there)
+	pub size: Option<u64>,
+
+	/// Compression method of module file (`xz`, `zstd` or `gzip`)
+	pub compression: Option<String>,
+
+	/// Where module image has been decompressed
+	pub decompression: Decompression,
+}
+
+/// Taint flags set in kernel by loading modules, e.g. by out-of-tree or unsigned module
+///
+/// Flags of kernel are compared before and after loading, so flags set meanwhile by other
+/// processes are reported as well.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TaintReport {
+	/// Newly set bits of `/proc/sys/kernel/tainted`
+	pub mask: u64,
+
+	/// Letters of newly set flags (e.g. `OE` for out-of-tree module without valid signature)
+	pub flags: String,
+
+	/// Loaded module likely responsible for taint, whose own taint flags include new ones
+	///
+	/// If no loaded module has such flags, but only one module has been loaded, it's reported.
+	pub module: Option<String>,
+}
+
+/// Way module image has been decompressed before linking by kernel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Decompression {
+	/// Image has been passed to kernel as read from file
+	None,
+
+	/// Library has decompressed image into memory, as kernel doesn't support its compression
+	Library,
+
+	/// Kernel has decompressed module file itself
+	Kernel,
+}
+
+/// Reason of loading module
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Provenance {
+	/// Module has been requested by caller
+	Requested,
+
+	/// Module is dependency of requested module (listed in modules.dep)
+	Dependency,
+
+	/// Module is weak dependency of requested module (listed in modules.weakdep)
+	WeakDependency,
+
+	/// Module has been loaded by install command from modprobe.d
+	InstallCommand,
+}
+
+/// Loads module with its dependencies using already parsed index and configuration
+pub(crate) fn probe(
+	index: &dyn ModuleResolver,
+	config: &Config,
+	name: &str,
+	params: &str,
+	options: &Options,
+) -> io::Result<LoadReport> {
+	let _lock = Lock::acquire_if(options.lock)?;
+	if !options.fallback {
+		return probe_native(index, config, name, params, options);
+	}
+
+	match probe_native(index, config, name, params, options) {
+		Err(e)
+			if e.kind() != ErrorKind::PermissionDenied
+				&& e.kind() != ErrorKind::AlreadyExists
+				&& e.kind() != ErrorKind::Interrupted =>
+		{
+			fallback(name, params, options).map_err(|_| e)
+		}
+		result => result,
+	}
+}
+
+/// Runs operation with index and configuration, re-reading them and retrying in resilient mode
+pub(crate) fn resilient<T>(
+	options: &Options,
+	mut operation: impl FnMut(&dyn ModuleResolver, &Config) -> io::Result<T>,
+) -> io::Result<T> {
+	priority::scope(options, || {
+		let mut retries = 0;
+		loop {
+			let result = options.index().and_then(|index| {
+				let config = options.load_config()?;
+				operation(&*index, &config)
+			});
+
+			match result {
+				// Module files or manifests may be replaced by package upgrade meanwhile
+				Err(e) if e.kind() == ErrorKind::NotFound && retries < options.resilient => {
+					retries += 1;
+					thread::sleep(RESILIENT_INTERVAL);
+					options.check_cancelled()?;
+					ModuleIndex::invalidate();
+				}
+				result => return result,
+			}
+		}
+	})
+}
+
+/// Checks if module is already present, using load plan cached in shared index
+///
+/// Repeated checks of the same module only look up plan and check if module is loaded. Options
+/// which can't be cached and failures of resolution leave decision to modprobe.
+pub(crate) fn is_present(name: &str, params: &str, options: &Options) -> io::Result<bool> {
+	let fingerprint = match options.fingerprint() {
+		Some(fingerprint) => fingerprint,
+		None => return Ok(false),
+	};
+	let release = match (&options.kernel, &options.release) {
+		(Selection::Current, Some(release)) => release.clone(),
+		(kernel, _) => index::release(kernel.clone())?,
+	};
+
+	let index = metrics::resolve(|| ModuleIndex::shared_release(release))?;
+	let plan = match index.cached_plan(name, params, fingerprint) {
+		Some(plan) => plan,
+		None => {
+			let config = options.load_config()?;
+			let plan =
+				match metrics::resolve(|| plan::build(&*index, &config, name, params, options)) {
+					Ok(plan) => Arc::new(plan),
+					Err(_) => return Ok(false),
+				};
+			index.cache_plan(name, params, fingerprint, plan.clone());
+			plan
+		}
+	};
+
+	Ok(match (plan.skip, plan.steps.last()) {
+		(Some(Outcome::Builtin), _) => true,
+		(None, Some(step)) => is_loaded(&step.name),
+		_ => false,
+	})
+}
+
+/// Loads module using system modprobe
+fn fallback(name: &str, params: &str, options: &Options) -> io::Result<LoadReport> {
+	// Name would be taken for option of modprobe
+	if name.starts_with('-') {
+		return Err(Error::InvalidModuleName(name.to_string()).into());
+	}
+
+	let mut command = Command::new(MODPROBE);
+	if options.quiet {
+		command.arg("-q");
+	}
+	if options.first_time {
+		command.arg("--first-time");
+	}
+	if options.ignore_install {
+		command.arg("--ignore-install");
+	}
+	if options.dry_run {
+		command.arg("--dry-run");
+	}
+	if let Some(dir) = &options.dirname {
+		command.arg("--dirname").arg(dir);
+	}
+	if let Selection::Other(kernel) = &options.kernel {
+		command.arg("-S").arg(kernel);
+	}
+	command
+		.arg("--")
+		.arg(name)
+		.args(config::cmdline_words(params));
+
+	let status = command
+		.stdin(Stdio::null())
+		.stdout(Stdio::null())
+		.stderr(Stdio::null())
+		.status()?;
+	if !status.success() {
+		return Err(io::Error::other(format!("{} failed: {}", MODPROBE, status)));
+	}
+
+	// System modprobe doesn't report what it has done
+	Ok(LoadReport {
+		outcome: match options.dry_run {
+			true => Outcome::DryRun,
+			false => Outcome::Loaded,
+		},
+		modules: Vec::new(),
+		warnings: Vec::new(),
+		timings: LoadTimings::default(),
+		taint: None,
+	})
+}
+
+/// Loads module with its dependencies directly using syscalls
+fn probe_native(
+	index: &dyn ModuleResolver,
+	config: &Config,
+	name: &str,
+	params: &str,
+	options: &Options,
+) -> io::Result<LoadReport> {
+	options.check_cancelled()?;
+
+	// Module which is already loaded is reported without checking it
+	let target = config.resolve_alias(name)?;
+	let module = index.resolve(&target);
+	let modname = match module {
+		Some(module) => index::module_name(module),
+		None => index::normalize(&target),
+	};
+	if let Some(module) = module.filter(|_| is_loaded(&modname)) {
+		return Ok(LoadReport {
+			outcome: already_loaded(&modname, options)?,
+			modules: vec![ModuleReport {
+				name: modname,
+				path: index.resolved_path(module).to_string_lossy().into_owned(),
+				params: String::new(),
+				outcome: Outcome::AlreadyLoaded,
+				provenance: Provenance::Requested,
+				image: None,
+			}],
+			warnings: Vec::new(),
+			timings: LoadTimings::default(),
+			taint: None,
+		});
+	}
+
+	// Run install command instead of loading module, it doesn't report what it has done
+	if let Some(command) = config.install_command(&modname) {
+		if !options.ignore_install {
+			let outcome = match options.dry_run {
+				_ if config.is_disabled(&modname) => Outcome::DisabledByConfig,
+				true => Outcome::DryRun,
+				false => {
+					config::run_command(
+						"install",
+						command,
+						&modname,
+						&config.params(&modname, params),
+					)?;
+					Outcome::Loaded
+				}
+			};
+			return Ok(LoadReport {
+				outcome,
+				modules: Vec::new(),
+				warnings: Vec::new(),
+				timings: LoadTimings::default(),
+				taint: None,
+			});
+		}
+	}
+
+	let (plan, resolve) =
+		metrics::time(|| metrics::resolve(|| plan::build(index, config, name, params, options)));
+	let mut report = plan::execute(&plan?, options)?;
+	report.timings.resolve = resolve;
+	Ok(report)
+}
+
+/// Joins parameter strings, skipping empty ones
+fn join_params(first: &str, second: &str) -> String {
+	match (first.is_empty(), second.is_empty()) {
+		(_, true) => first.to_string(),
+		(true, false) => second.to_string(),
+		(false, false) => format!("{} {}", first, second),
+	}
+}
+
+/// Checks licenses of module and its dependencies according to license policy
+pub(crate) fn check_license(
+	index: &dyn ModuleResolver,
+	module: &str,
+	options: &Options,
+	warnings: &mut Vec<String>,
+) -> io::Result<()> {
+	if options.license_policy == LicensePolicy::Allow {
+		return Ok(());
+	}
+
+	for path in index
+		.dependencies(module)
+		.iter()
+		.chain([&module.to_string()])
+	{
+		options.check_cancelled()?;
+		let name = index::module_name(path);
+		match ModuleInfo::from_file(index.path(path)) {
+			Ok(info) => check_info_license(&name, info, options, warnings)?,
+
+			// License of module which can't be read can't be allowed
+			Err(e) if options.license_policy == LicensePolicy::Reject => return Err(e),
+			Err(e) => warnings.push(format!("License of module {} can't be read: {}", name, e)),
+		}
+	}
+
+	Ok(())
+}
+
+/// Checks license of module read from its file according to license policy
+pub(crate) fn check_info_license(
+	name: &str,
+	info: ModuleInfo,
+	options: &Options,
+	warnings: &mut Vec<String>,
+) -> io::Result<()> {
+	if info.is_gpl_compatible() {
+		return Ok(());
+	}
+
+	match options.license_policy {
+		LicensePolicy::Allow => Ok(()),
+		LicensePolicy::Reject => Err(Error::ProprietaryModule {
+			module: name.to_string(),
+			license: info.license,
+		}
+		.into()),
+		_ => {
+			warnings.push(format!(
+				"Module {} has license {} not compatible with GPL, loading it taints kernel",
+				name,
+				info.license.as_deref().unwrap_or("(none)")
+			));
+			Ok(())
+		}
+	}
+}
+
+/// Checks caller parameters against parameters declared in module file
+pub(crate) fn validate_params(path: &Path, name: &str, params: &str) -> io::Result<()> {
+	// Modules which can't be decompressed or parsed are left to kernel
+	let mut info = match ModuleInfo::from_file(path) {
+		Ok(o) => o,
+		Err(e) if matches!(e.kind(), ErrorKind::InvalidData | ErrorKind::Unsupported) => {
+			return Ok(())
+		}
+		Err(e) => return Err(e),
+	};
+	info.name.get_or_insert_with(|| name.to_string());
+
+	info.validate_params(params)
+}
+
+/// Checks if module is present in current running kernel
+pub(crate) fn is_loaded(name: &str) -> bool {
+	#[cfg(feature = "testing")]
+	return crate::testing::is_loaded(name);
+
+	#[cfg(not(feature = "testing"))]
+	crate::vfs::exists(format!("/sys/module/{}/initstate", name))
+}
+
+pub(crate) fn builtin(name: &str, options: &Options) -> io::Result<Outcome> {
+	if options.first_time {
+		return Err(io::Error::new(
+			ErrorKind::AlreadyExists,
+			format!("Module {} is built into kernel", name),
+		));
+	}
+
+	Ok(Outcome::Builtin)
+}
+
+pub(crate) fn already_loaded(name: &str, options: &Options) -> io::Result<Outcome> {
+	if options.first_time {
+		return Err(Error::AlreadyLoaded(name.to_string()).into());
+	}
+
+	Ok(Outcome::AlreadyLoaded)
+}
@@ -0,0 +1,134 @@
+use std::fmt;
+
+/// Builder of module parameters string
+///
+/// Values containing whitespace are quoted. Kernel doesn't support escape sequences, so
+/// double quotes inside values are passed as they are.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let params = liblmod::Params::new()
+///     .set("nested", true)
+///     .set("path", "/lib/firmware custom")
+///     .set("ids", [1, 2, 3]);
+/// assert_eq!(params.as_ref(), "nested=1 path=\"/lib/firmware custom\" ids=1,2,3");
+///
+/// if let Err(e) = liblmod::modprobe("kvm", params, liblmod::Selection::Current) {
+///     eprintln!("Failed to load module kvm: {e}");
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Params {
+	params: String,
+}
+
+impl Params {
+	/// Creates empty parameters
+	pub fn new() -> Params {
+		Params::default()
+	}
+
+	/// Sets parameter, if parameter is set again the last value is used by kernel
+	pub fn set(mut self, key: impl AsRef<str>, value: impl ParamValue) -> Params {
+		if !self.params.is_empty() {
+			self.params.push(' ');
+		}
+
+		let value = value.to_param();
+		self.params.push_str(key.as_ref());
+		self.params.push('=');
+		if value.is_empty() || value.contains(char::is_whitespace) {
+			self.params.push('"');
+			self.params.push_str(&value);
+			self.params.push('"');
+		} else {
+			self.params.push_str(&value);
+		}
+
+		self
+	}
+}
+
+impl AsRef<str> for Params {
+	fn as_ref(&self) -> &str {
+		&self.params
+	}
+}
+
+impl fmt::Display for Params {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.params)
+	}
+}
+
+/// Value of module parameter
+pub trait ParamValue {
+	/// Formats value as expected by kernel
+	fn to_param(&self) -> String;
+}
+
+impl ParamValue for bool {
+	fn to_param(&self) -> String {
+		match self {
+			true => "1".to_string(),
+			false => "0".to_string(),
+		}
+	}
+}
+
+impl ParamValue for &str {
+	fn to_param(&self) -> String {
+		self.to_string()
+	}
+}
+
+impl ParamValue for String {
+	fn to_param(&self) -> String {
+		self.clone()
+	}
+}
+
+impl ParamValue for char {
+	fn to_param(&self) -> String {
+		self.to_string()
+	}
+}
+
+macro_rules! impl_param_value_int {
+	($($t:ty),*) => {
+		$(
+			impl ParamValue for $t {
+				fn to_param(&self) -> String {
+					self.to_string()
+				}
+			}
+		)*
+	};
+}
+
+impl_param_value_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+// Arrays are passed as comma separated values
+impl<T: ParamValue> ParamValue for &[T] {
+	fn to_param(&self) -> String {
+		self.iter()
+			.map(|value| value.to_param())
+			.collect::<Vec<String>>()
+			.join(",")
+	}
+}
+
+impl<T: ParamValue> ParamValue for Vec<T> {
+	fn to_param(&self) -> String {
+		self.as_slice().to_param()
+	}
+}
+
+impl<T: ParamValue, const N: usize> ParamValue for [T; N] {
+	fn to_param(&self) -> String {
+		self.as_slice().to_param()
+	}
+}
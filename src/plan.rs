@@ -0,0 +1,479 @@
+use std::{
+	collections::HashSet,
+	io::{self, ErrorKind},
+	path::{self, Path, PathBuf},
+};
+
+use crate::{
+	index,
+	modprobe::{self, LoadReport, ModuleReport, Options, Outcome, Provenance, TaintReport},
+	resolver, rmmod, suggest, vfs, Config, Error, Flags, LoadTimings, ModuleInfo, ModuleResolver,
+	RemoveOptions,
+};
+
+/// Modules which have to be loaded for requested module, computed by `resolve`
+///
+/// Plan describes only module files and parameters, it doesn't depend on modules currently loaded.
+/// Modules already loaded when plan is executed are skipped. With `serde` feature plan can be
+/// stored (e.g. cached by boot-time tools for identical kernel) and executed later with `execute`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoadPlan {
+	/// Release of kernel plan has been made for
+	pub kernel: String,
+
+	/// Requested module name
+	pub name: String,
+
+	/// Outcome reported without loading anything (module is built into kernel or not available)
+	pub skip: Option<Outcome>,
+
+	/// Modules in order of loading, requested module is the last one
+	pub steps: Vec<PlanStep>,
+
+	/// Warnings about planned modules (e.g. tainting kernel)
+	pub warnings: Vec<String>,
+}
+
+/// Single module of load plan
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlanStep {
+	/// Name of module
+	pub name: String,
+
+	/// Absolute path of module file
+	pub path: PathBuf,
+
+	/// Absolute path of module file with symbolic links resolved
+	pub resolved_path: PathBuf,
+
+	/// Parameters module will be loaded with (including modprobe.d and kernel command line options)
+	pub params: String,
+
+	/// Weak dependency this step is loaded for, failures of such steps are ignored
+	pub weak: Option<String>,
+
+	/// Requested module followed by modules depending on this one, empty unless it is dependency
+	pub chain: Vec<String>,
+}
+
+impl LoadPlan {
+	/// Returns why module of step is loaded, requested module is the last step
+	pub(crate) fn provenance(&self, step: &PlanStep) -> Provenance {
+		match &step.weak {
+			Some(_) => Provenance::WeakDependency,
+			None if self.steps.last().map(|last| &last.name) == Some(&step.name) => {
+				Provenance::Requested
+			}
+			None => Provenance::Dependency,
+		}
+	}
+}
+
+/// Computes plan for loading module with its dependencies
+pub(crate) fn build(
+	index: &dyn ModuleResolver,
+	config: &Config,
+	name: &str,
+	params: &str,
+	options: &Options,
+) -> io::Result<LoadPlan> {
+	let mut plan = LoadPlan {
+		kernel: index.kernel().to_string(),
+		name: name.to_string(),
+		skip: None,
+		steps: Vec::new(),
+		warnings: Vec::new(),
+	};
+
+	// Aliases from modprobe.d take precedence over module names and modules.alias
+	let name = &config.resolve_alias(name)?;
+
+	// Get path for specified module from modules.order
+	let module = match index.resolve(name) {
+		Some(module) => module,
+		None if index.is_builtin(name)
+			|| index
+				.resolve_alias(name)
+				.iter()
+				.any(|m| index.is_builtin(m)) =>
+		{
+			plan.skip = Some(Outcome::Builtin);
+			return Ok(plan);
+		}
+		None if options.quiet => {
+			plan.skip = Some(Outcome::NotAvailable);
+			return Ok(plan);
+		}
+		None => return Err(suggest::not_found(index, name).into()),
+	};
+
+	// Check parameters passed by caller, including ones of dependencies
+	let modname = index::module_name(module);
+	let params = &options.params(&modname, params);
+	if options.validate_params {
+		if !params.is_empty() {
+			modprobe::validate_params(&index.path(module), &modname, params)?;
+		}
+		for dep in index.dependencies(module) {
+			let depname = index::module_name(dep);
+			let params = options.params(&depname, "");
+			if !params.is_empty() {
+				modprobe::validate_params(&index.path(dep), &depname, &params)?;
+			}
+		}
+	}
+
+	// Module files are chosen deterministically, but equally preferred ones may be refused
+	if options.reject_ambiguous {
+		for module in index
+			.dependencies(module)
+			.iter()
+			.map(|m| m.as_str())
+			.chain([module])
+		{
+			let paths = index.ambiguous(&index::module_name(module));
+			if !paths.is_empty() {
+				return Err(Error::AmbiguousModule {
+					module: index::module_name(module),
+					paths,
+				}
+				.into());
+			}
+		}
+	}
+
+	// Check licenses before anything is loaded
+	modprobe::check_license(index, module, options, &mut plan.warnings)?;
+
+	// Weak dependencies are optional, so ones with rejected license are left out
+	for weakdep in index.weak_dependencies(&modname) {
+		if let Some(weakmodule) = index.resolve(weakdep) {
+			if modprobe::check_license(index, weakmodule, options, &mut plan.warnings).is_ok() {
+				add_steps(
+					&mut plan,
+					index,
+					config,
+					options,
+					weakmodule,
+					"",
+					Some(weakdep),
+				);
+			}
+		}
+	}
+
+	add_steps(&mut plan, index, config, options, module, params, None);
+
+	Ok(plan)
+}
+
+/// Computes plan for loading module file, dependencies from its `.modinfo` are resolved with index
+pub(crate) fn build_path(
+	index: &dyn ModuleResolver,
+	config: &Config,
+	path: &Path,
+	params: &str,
+	options: &Options,
+) -> io::Result<LoadPlan> {
+	let info = ModuleInfo::from_file(path)?;
+	let name = match &info.name {
+		Some(name) => index::normalize(name),
+		None => index::module_name(&path.to_string_lossy()),
+	};
+	let mut plan = LoadPlan {
+		kernel: index.kernel().to_string(),
+		name: name.clone(),
+		skip: None,
+		steps: Vec::new(),
+		warnings: Vec::new(),
+	};
+
+	let params = options.params(&name, params);
+	if options.validate_params && !params.is_empty() {
+		info.validate_params(&params)?;
+	}
+
+	// Dependencies have to be provided by kernel, module file itself can be anywhere
+	let mut deps = Vec::new();
+	for depname in info.depends.iter().filter(|dep| !dep.is_empty()) {
+		match index.resolve(depname) {
+			Some(dep) => deps.push(dep),
+			None if index.is_builtin(depname) => continue,
+			None => {
+				return Err(io::Error::new(
+					ErrorKind::NotFound,
+					format!(
+						"Dependency {} of {} is not provided by {} kernel",
+						depname,
+						path.display(),
+						index.kernel()
+					),
+				))
+			}
+		}
+	}
+
+	for dep in &deps {
+		if options.validate_params {
+			let depname = index::module_name(dep);
+			let params = options.params(&depname, "");
+			if !params.is_empty() {
+				modprobe::validate_params(&index.path(dep), &depname, &params)?;
+			}
+		}
+		modprobe::check_license(index, dep, options, &mut plan.warnings)?;
+	}
+	modprobe::check_info_license(&name, info, options, &mut plan.warnings)?;
+
+	for dep in deps {
+		let start = plan.steps.len();
+		add_steps(&mut plan, index, config, options, dep, "", None);
+		for step in &mut plan.steps[start..] {
+			if step.chain.is_empty() {
+				step.chain.push(step.name.clone());
+			}
+			step.chain.insert(0, name.clone());
+		}
+	}
+
+	// Dependencies shared by several dependencies are loaded once, before first module needing them
+	let mut seen = HashSet::new();
+	plan.steps.retain(|step| seen.insert(step.name.clone()));
+
+	plan.steps.push(PlanStep {
+		params: config.params(&name, &params),
+		path: path::absolute(path)?,
+		resolved_path: vfs::canonicalize(path)?,
+		name,
+		weak: None,
+		chain: Vec::new(),
+	});
+
+	Ok(plan)
+}
+
+/// Adds steps loading resolved module with its dependencies
+fn add_steps(
+	plan: &mut LoadPlan,
+	index: &dyn ModuleResolver,
+	config: &Config,
+	options: &Options,
+	module: &str,
+	params: &str,
+	weak: Option<&str>,
+) {
+	// modules.dep lists the deepest dependency last
+	for dep in index.dependencies(module).iter().rev() {
+		let depname = index::module_name(dep);
+		plan.steps.push(PlanStep {
+			params: config.params(&depname, &options.params(&depname, "")),
+			name: depname,
+			path: index.path(dep),
+			resolved_path: index.resolved_path(dep),
+			weak: weak.map(|w| w.to_string()),
+			chain: match weak {
+				Some(_) => Vec::new(),
+				None => resolver::dependency_chain(index, module, dep),
+			},
+		});
+	}
+
+	let modname = index::module_name(module);
+	let params = match weak {
+		Some(_) => options.params(&modname, params),
+		None => params.to_string(),
+	};
+	plan.steps.push(PlanStep {
+		params: config.params(&modname, &params),
+		name: modname,
+		path: index.path(module),
+		resolved_path: index.resolved_path(module),
+		weak: weak.map(|w| w.to_string()),
+		chain: Vec::new(),
+	});
+}
+
+/// Loads modules of plan, skipping ones which are already loaded
+pub(crate) fn execute(plan: &LoadPlan, options: &Options) -> io::Result<LoadReport> {
+	let mut report = LoadReport {
+		outcome: Outcome::Loaded,
+		modules: Vec::new(),
+		warnings: plan.warnings.clone(),
+		timings: LoadTimings::default(),
+		taint: None,
+	};
+	options.check_cancelled()?;
+
+	let module = match (plan.skip, plan.steps.last()) {
+		(Some(Outcome::Builtin), _) => {
+			report.outcome = modprobe::builtin(&plan.name, options)?;
+			return Ok(report);
+		}
+		(Some(outcome), _) => {
+			report.outcome = outcome;
+			return Ok(report);
+		}
+		(None, Some(module)) => module,
+		(None, None) => {
+			report.outcome = Outcome::NotAvailable;
+			return Ok(report);
+		}
+	};
+
+	// Check if module is already loaded
+	if modprobe::is_loaded(&module.name) {
+		report.outcome = modprobe::already_loaded(&module.name, options)?;
+		report.modules.push(ModuleReport {
+			name: module.name.clone(),
+			path: module.resolved_path.to_string_lossy().into_owned(),
+			params: String::new(),
+			outcome: Outcome::AlreadyLoaded,
+			provenance: Provenance::Requested,
+			image: None,
+		});
+		return Ok(report);
+	}
+
+	// Consult load policy before anything is loaded
+	if crate::policy::is_set() {
+		for step in plan.steps.iter().filter(|step| step.weak.is_none()) {
+			options.check_cancelled()?;
+			crate::policy::check_file(&step.path)?;
+		}
+	}
+
+	let taints = crate::kernel::taints();
+	if let Err(e) = crate::buffer::scope(|| load_steps(plan, options, &mut report)) {
+		if options.rollback {
+			rollback(&report);
+		}
+		return Err(e);
+	}
+	if let Some(before) = taints.filter(|_| report.loaded().next().is_some()) {
+		report.taint = taint(&report, before);
+	}
+
+	if report.outcome == Outcome::AlreadyLoaded {
+		report.outcome = modprobe::already_loaded(&module.name, options)?;
+	}
+
+	Ok(report)
+}
+
+/// Reports taint flags set since before, with loaded module likely responsible for them
+fn taint(report: &LoadReport, before: u64) -> Option<TaintReport> {
+	let mask = crate::kernel::taints()? & !before;
+	if mask == 0 {
+		return None;
+	}
+
+	// Taints of modules are in sysfs, the latest loaded module is checked first
+	let flags = crate::kernel::taint_flags(mask);
+	let loaded: Vec<&str> = report.loaded().map(|module| module.name.as_str()).collect();
+	let module = loaded
+		.iter()
+		.rev()
+		.find(|name| {
+			vfs::read_to_string(format!("/sys/module/{}/taint", name))
+				.is_ok_and(|taints| taints.trim().contains(|flag| flags.contains(flag)))
+		})
+		.or_else(|| match &loaded[..] {
+			[only] => Some(only),
+			_ => None,
+		})
+		.map(|name| name.to_string());
+
+	Some(TaintReport {
+		mask,
+		flags,
+		module,
+	})
+}
+
+/// Loads steps of plan, recording processed modules in report
+fn load_steps(plan: &LoadPlan, options: &Options, report: &mut LoadReport) -> io::Result<()> {
+	// Weak dependencies which are loaded or failed to load are skipped
+	let mut skipped: HashSet<&str> = HashSet::new();
+	for step in &plan.steps {
+		if let Some(weak) = &step.weak {
+			if skipped.contains(weak.as_str()) {
+				continue;
+			}
+			if modprobe::is_loaded(weak) {
+				skipped.insert(weak);
+				continue;
+			}
+		}
+
+		if modprobe::is_loaded(&step.name) {
+			report.modules.push(ModuleReport {
+				name: step.name.clone(),
+				path: step.resolved_path.to_string_lossy().into_owned(),
+				params: String::new(),
+				outcome: Outcome::AlreadyLoaded,
+				provenance: plan.provenance(step),
+				image: None,
+			});
+			continue;
+		}
+
+		options.check_cancelled()?;
+		let mut image = None;
+		let result = match options.dry_run {
+			true => Ok(Outcome::DryRun),
+			false => crate::inflight::load(&step.name, || {
+				crate::deadline::load(&step.name, &step.path, &step.params, options)
+			})
+			.map(|loaded| match loaded {
+				Some((timings, loaded)) => {
+					report.timings += timings;
+					image = Some(loaded);
+					Outcome::Loaded
+				}
+				None => Outcome::AlreadyLoaded,
+			}),
+		};
+		let outcome = match result {
+			Ok(outcome) => outcome,
+			Err(e) if e.kind() == ErrorKind::AlreadyExists => Outcome::AlreadyLoaded,
+			Err(e) => match &step.weak {
+				Some(weak) => {
+					skipped.insert(weak);
+					continue;
+				}
+				None if !step.chain.is_empty() => {
+					return Err(Error::DependencyFailed {
+						chain: step.chain.clone(),
+						source: e,
+					}
+					.into())
+				}
+				None => return Err(e),
+			},
+		};
+		report.modules.push(ModuleReport {
+			name: step.name.clone(),
+			path: step.resolved_path.to_string_lossy().into_owned(),
+			params: step.params.clone(),
+			outcome,
+			provenance: plan.provenance(step),
+			image,
+		});
+		report.outcome = outcome;
+	}
+
+	Ok(())
+}
+
+/// Unloads modules loaded by this call, the last loaded first
+pub(crate) fn rollback(report: &LoadReport) {
+	for module in report.loaded().collect::<Vec<_>>().into_iter().rev() {
+		let _ = rmmod::remove(
+			&module.name,
+			Flags::Casual,
+			&RemoveOptions::new().ignore_remove(true),
+		);
+	}
+}
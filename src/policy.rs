@@ -0,0 +1,122 @@
+use std::{
+	io,
+	path::Path,
+	sync::{Arc, RwLock},
+};
+
+use crate::{compress, index, vfs, Error, ModuleInfo};
+
+/// Process-wide policy consulted before loading modules
+static POLICY: RwLock<Option<Arc<dyn LoadPolicy>>> = RwLock::new(None);
+
+/// Decision of load policy
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Decision {
+	/// Module can be loaded
+	Allow,
+
+	/// Module must not be loaded, with reason
+	Deny(String),
+}
+
+/// Policy deciding whether module can be loaded (e.g. allowlist or signature requirements)
+///
+/// Policy is consulted by `load`, `modprobe` and functions of `loader` module before anything
+/// is inserted into kernel. Compressed modules are decompressed for it, module information is
+/// empty (apart from name) if it cannot be read, e.g. for compression methods library can't
+/// decompress. Source is empty for images not loaded from file.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// use liblmod::{Decision, LoadPolicy, ModuleInfo};
+/// use std::path::Path;
+///
+/// struct Allowlist(Vec<String>);
+///
+/// impl LoadPolicy for Allowlist {
+///     fn allow(&self, info: &ModuleInfo, _source: &Path) -> Decision {
+///         match &info.name {
+///             Some(name) if self.0.contains(name) => Decision::Allow,
+///             _ => Decision::Deny("module is not allowlisted".to_string()),
+///         }
+///     }
+/// }
+///
+/// liblmod::set_load_policy(Allowlist(vec!["kvm".to_string(), "kvm_intel".to_string()]));
+/// ```
+pub trait LoadPolicy: Send + Sync {
+	/// Decides whether module can be loaded
+	fn allow(&self, info: &ModuleInfo, source: &Path) -> Decision;
+}
+
+/// Sets process-wide load policy, replacing the previous one
+pub fn set_load_policy(policy: impl LoadPolicy + 'static) {
+	*POLICY.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(policy));
+}
+
+/// Removes process-wide load policy, all modules are allowed again
+pub fn clear_load_policy() {
+	*POLICY.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Returns current load policy
+fn policy() -> Option<Arc<dyn LoadPolicy>> {
+	POLICY.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Checks if load policy is set
+pub(crate) fn is_set() -> bool {
+	policy().is_some()
+}
+
+/// Consults load policy about module image loaded from source
+pub(crate) fn check(image: &[u8], source: &Path) -> io::Result<()> {
+	let policy = match policy() {
+		Some(o) => o,
+		None => return Ok(()),
+	};
+
+	let mut info = ModuleInfo::from_image(image).unwrap_or_default();
+	if info.name.is_none() && !source.as_os_str().is_empty() {
+		info.name = Some(index::module_name(&source.to_string_lossy()));
+	}
+
+	match policy.allow(&info, source) {
+		Decision::Allow => Ok(()),
+		Decision::Deny(reason) => Err(Error::PolicyDenied {
+			module: info.name.unwrap_or_default(),
+			reason,
+		}
+		.into()),
+	}
+}
+
+/// Consults load policy about module file, decompressed if it's compressed
+pub(crate) fn check_file(path: &Path) -> io::Result<()> {
+	if !is_set() {
+		return Ok(());
+	}
+
+	let data = vfs::read(path)?;
+	match crate::loader::file_compression(path) {
+		Some(method) => check_compressed(&data, &method, path),
+		None => check(&data, path),
+	}
+}
+
+/// Consults load policy about compressed module image, which is inspected only if library can
+/// decompress it
+pub(crate) fn check_compressed(data: &[u8], method: &str, source: &Path) -> io::Result<()> {
+	if !is_set() {
+		return Ok(());
+	}
+
+	let image = match compress::is_supported(method) {
+		true => compress::decompress(data, method)?,
+		false => Vec::new(),
+	};
+	check(&image, source)
+}
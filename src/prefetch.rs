@@ -0,0 +1,116 @@
+use std::{
+	collections::BTreeMap,
+	io,
+	path::{Path, PathBuf},
+};
+
+use crate::{loader, modprobe, plan, vfs, Options};
+
+/// Module files read ahead by `prefetch`
+#[derive(Debug, Default)]
+pub struct PrefetchReport {
+	/// Module files read ahead, dependencies first
+	pub files: Vec<PathBuf>,
+
+	/// Total size of read ahead files in bytes
+	pub size: u64,
+
+	/// Requested modules which couldn't be resolved or read, by module name
+	pub failed: BTreeMap<String, io::Error>,
+}
+
+/// Reads files of modules and their dependencies into page cache, without loading them
+///
+/// Later `modprobe` of these modules (e.g. in critical phase of boot) reads them from memory
+/// instead of slow storage. Modules are resolved like with `modprobe_with` (aliases, softdeps
+/// and options from modprobe.d), and module index stays cached in process. Modules which are
+/// already loaded or built into kernel are skipped, and modules which can't be prefetched don't
+/// stop prefetching of others. In Linux, kernel is asked to read files in background, so this
+/// function returns before they're read.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::prefetch(&["nvme", "xfs"]) {
+///     Ok(report) => {
+///         println!("Prefetched {} files ({} bytes)", report.files.len(), report.size);
+///         for (name, e) in report.failed {
+///             eprintln!("Failed to prefetch module {name}: {e}");
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to prefetch modules: {e}"),
+/// }
+/// ```
+pub fn prefetch<S: AsRef<str>>(names: &[S]) -> io::Result<PrefetchReport> {
+	prefetch_with(names, &Options::new())
+}
+
+/// Reads files of modules and their dependencies into page cache with specified options
+///
+/// See `prefetch`.
+pub fn prefetch_with<S: AsRef<str>>(names: &[S], options: &Options) -> io::Result<PrefetchReport> {
+	modprobe::resilient(options, |index, config| {
+		let mut report = PrefetchReport::default();
+		for name in names {
+			options.check_cancelled()?;
+			let name = name.as_ref();
+			let plan = match plan::build(index, config, name, "", options) {
+				Ok(plan) => plan,
+				Err(e) => {
+					report.failed.insert(name.to_string(), e);
+					continue;
+				}
+			};
+
+			for step in plan.steps {
+				if report.files.contains(&step.path) || modprobe::is_loaded(&step.name) {
+					continue;
+				}
+				match read_ahead(&step.path) {
+					Ok(size) => {
+						report.size += size;
+						report.files.push(step.path);
+					}
+					Err(e) => {
+						report.failed.insert(name.to_string(), e);
+						break;
+					}
+				}
+			}
+		}
+
+		Ok(report)
+	})
+}
+
+/// Starts reading file into page cache, returning its size
+fn read_ahead(path: &Path) -> io::Result<u64> {
+	// In-memory files are already in memory
+	if vfs::is_memory() {
+		return vfs::size(path);
+	}
+
+	let file = loader::open(path)?;
+	let size = file.metadata()?.len();
+	advise(&file)?;
+	Ok(size)
+}
+
+/// Asks kernel to read whole file in background
+#[cfg(target_os = "linux")]
+fn advise(file: &std::fs::File) -> io::Result<()> {
+	use std::os::fd::AsRawFd;
+
+	// posix_fadvise returns error number instead of setting errno
+	match unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_WILLNEED) } {
+		0 => Ok(()),
+		errno => Err(io::Error::from_raw_os_error(errno)),
+	}
+}
+
+/// Other systems than Linux read file right away
+#[cfg(not(target_os = "linux"))]
+fn advise(mut file: &std::fs::File) -> io::Result<()> {
+	io::copy(&mut file, &mut io::sink()).map(|_| ())
+}
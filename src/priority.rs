@@ -0,0 +1,126 @@
+use crate::Options;
+
+/// I/O scheduling class of work done by modprobe, like `ionice -c`
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// // Preloading modules in background, without slowing down boot on slow storage
+/// let options = liblmod::Options::new()
+///     .nice(10)
+///     .io_priority(liblmod::IoPriority::Idle);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoPriority {
+	/// Served before other classes, with level from 0 (highest) to 7 (requires CAP_SYS_ADMIN)
+	RealTime(u8),
+
+	/// Default class, with level from 0 (highest) to 7
+	BestEffort(u8),
+
+	/// Served only when no other process uses disk
+	Idle,
+}
+
+impl IoPriority {
+	/// Returns value of `ioprio_set`, with class in upper bits and level in lower ones
+	#[cfg(target_os = "linux")]
+	fn value(self) -> libc::c_long {
+		let (class, level) = match self {
+			IoPriority::RealTime(level) => (1, level.min(7)),
+			IoPriority::BestEffort(level) => (2, level.min(7)),
+			IoPriority::Idle => (3, 0),
+		};
+		(class << 13) | level as libc::c_long
+	}
+}
+
+/// Runs work with niceness and I/O priority of options, restoring previous ones of thread after
+///
+/// Priorities are best effort, work runs with previous ones if they can't be changed.
+#[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+pub(crate) fn scope<R>(options: &Options, f: impl FnOnce() -> R) -> R {
+	// Priorities are changed only in Linux
+	#[cfg(target_os = "linux")]
+	let _guard = Guard::set(options.nice, options.io_priority);
+	f()
+}
+
+/// Previous priorities of thread, restored when dropped
+#[cfg(target_os = "linux")]
+struct Guard {
+	nice: Option<i32>,
+	io_priority: Option<libc::c_long>,
+}
+
+#[cfg(target_os = "linux")]
+impl Guard {
+	fn set(nice: Option<i32>, io_priority: Option<IoPriority>) -> Guard {
+		// Priorities of thread are changed, Linux doesn't share them with other threads
+		let nice = nice.and_then(|nice| {
+			let previous = sys::nice()?;
+			(previous != nice && sys::set_nice(nice)).then_some(previous)
+		});
+		let io_priority = io_priority.and_then(|io_priority| {
+			let previous = sys::io_priority()?;
+			(previous != io_priority.value() && sys::set_io_priority(io_priority.value()))
+				.then_some(previous)
+		});
+
+		Guard { nice, io_priority }
+	}
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Guard {
+	fn drop(&mut self) {
+		// Raising priority back may need CAP_SYS_NICE, thread keeps lower one without it
+		if let Some(nice) = self.nice {
+			sys::set_nice(nice);
+		}
+		if let Some(io_priority) = self.io_priority {
+			sys::set_io_priority(io_priority);
+		}
+	}
+}
+
+/// Syscalls of priorities of calling thread (`who` 0)
+#[cfg(target_os = "linux")]
+mod sys {
+	use libc::{c_long, syscall, SYS_getpriority, SYS_ioprio_get, SYS_ioprio_set, SYS_setpriority};
+
+	/// Target of `ioprio_get` and `ioprio_set` being thread
+	const IOPRIO_WHO_PROCESS: c_long = 1;
+
+	pub fn nice() -> Option<i32> {
+		// Raw syscall returns 20 - nice, so it's never -1 unlike getpriority() of libc
+		match unsafe { syscall(SYS_getpriority, libc::PRIO_PROCESS as c_long, 0 as c_long) } {
+			-1 => None,
+			ret => Some(20 - ret as i32),
+		}
+	}
+
+	pub fn set_nice(nice: i32) -> bool {
+		let ret = unsafe {
+			syscall(
+				SYS_setpriority,
+				libc::PRIO_PROCESS as c_long,
+				0 as c_long,
+				nice as c_long,
+			)
+		};
+		ret == 0
+	}
+
+	pub fn io_priority() -> Option<c_long> {
+		match unsafe { syscall(SYS_ioprio_get, IOPRIO_WHO_PROCESS, 0 as c_long) } {
+			-1 => None,
+			ret => Some(ret),
+		}
+	}
+
+	pub fn set_io_priority(value: c_long) -> bool {
+		unsafe { syscall(SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0 as c_long, value) == 0 }
+	}
+}
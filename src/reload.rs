@@ -0,0 +1,158 @@
+use std::{
+	io,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use crate::{index, loader, modprobe, policy, rmmod, vfs, Error, Flags, LoadReport, Options};
+
+/// How long unloading of module in use is retried by default
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Options for replacing loaded module with new image, see `reload`
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// use std::time::Duration;
+///
+/// let options = liblmod::ReloadOptions::with_options(liblmod::Options::new().quiet(true))
+///     .timeout(Duration::from_secs(30))
+///     .rollback(false);
+/// ```
+pub struct ReloadOptions {
+	pub(crate) options: Options,
+	pub(crate) timeout: Duration,
+	pub(crate) rollback: bool,
+}
+
+impl ReloadOptions {
+	/// Creates default options
+	pub fn new() -> ReloadOptions {
+		ReloadOptions::with_options(Options::new())
+	}
+
+	/// Creates options loading new module with modprobe options
+	pub fn with_options(options: Options) -> ReloadOptions {
+		ReloadOptions {
+			options,
+			timeout: DEFAULT_TIMEOUT,
+			rollback: true,
+		}
+	}
+
+	/// Sets how long unloading is retried while module is in use (5 seconds by default)
+	///
+	/// If module is still in use, `Error::ModuleBusy` is returned and the old module stays loaded.
+	pub fn timeout(mut self, timeout: Duration) -> ReloadOptions {
+		self.timeout = timeout;
+		self
+	}
+
+	/// Loads the old module again if new one fails to load (enabled by default)
+	pub fn rollback(mut self, rollback: bool) -> ReloadOptions {
+		self.rollback = rollback;
+		self
+	}
+}
+
+impl Default for ReloadOptions {
+	fn default() -> ReloadOptions {
+		ReloadOptions::new()
+	}
+}
+
+/// Image of module file loaded module has been loaded from, kept for rollback
+struct Previous {
+	path: PathBuf,
+	image: Vec<u8>,
+}
+
+/// Replaces loaded module with new image, loading the old one back on failure
+pub(crate) fn reload(
+	target: &str,
+	params: &str,
+	options: &ReloadOptions,
+) -> io::Result<LoadReport> {
+	let path = match target.contains('/') || index::is_module_file(target) {
+		true => Some(Path::new(target)),
+		false => None,
+	};
+	let name = match path {
+		Some(_) => index::module_name(target),
+		None => index::normalize(target),
+	};
+	if !index::is_valid_name(&name) {
+		return Err(Error::InvalidModuleName(name).into());
+	}
+
+	// Old image is read before unloading, file may be replaced by the new one
+	let previous = match options.rollback && modprobe::is_loaded(&name) {
+		true => previous(&name, &options.options),
+		false => None,
+	};
+
+	if modprobe::is_loaded(&name) {
+		let remove = rmmod::RemoveOptions::new()
+			.retry(options.timeout)
+			.ignore_remove(options.options.ignore_install)
+			.lock(options.options.lock);
+		rmmod::remove(&name, Flags::Casual, &remove)?;
+	}
+
+	let result = match path {
+		Some(path) => crate::modprobe_path_with(path, params, &options.options),
+		None => crate::modprobe_report(&name, params, &options.options),
+	};
+	let error = match result {
+		Ok(report) => return Ok(report),
+		Err(e) => e,
+	};
+
+	let restored = match previous {
+		Some(previous) => restore(&name, &previous, &options.options).is_ok(),
+		None => false,
+	};
+	Err(Error::ReloadFailed {
+		module: name,
+		restored,
+		source: error,
+	}
+	.into())
+}
+
+/// Reads installed file of loaded module, `None` if it has been changed since loading
+///
+/// Files are compared by srcversion, modules without srcversion are assumed unchanged.
+fn previous(name: &str, options: &Options) -> Option<Previous> {
+	let index = options.index().ok()?;
+	let path = index.path(index.resolve(name)?);
+	let image = index::module_image(&path).ok()?;
+
+	let loaded = vfs::read_to_string(format!("/sys/module/{}/srcversion", name)).ok();
+	let file = crate::ModuleInfo::from_image(&image)
+		.ok()
+		.and_then(|info| info.srcversion);
+	if let (Some(loaded), Some(file)) = (loaded, file) {
+		if loaded.trim() != file {
+			return None;
+		}
+	}
+
+	Some(Previous { path, image })
+}
+
+/// Loads old module image again with options from modprobe.d
+fn restore(name: &str, previous: &Previous, options: &Options) -> io::Result<()> {
+	let params = options
+		.load_config()?
+		.params(name, &options.params(name, ""));
+	policy::check(&previous.image, &previous.path)?;
+	loader::insert(
+		&previous.image,
+		&previous.path,
+		&params,
+		options.machine.as_deref(),
+	)
+}
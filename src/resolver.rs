@@ -0,0 +1,185 @@
+use std::path::PathBuf;
+
+use crate::{index, ModuleIndex};
+
+/// Source of modules used by modprobe to find module files and their dependencies
+///
+/// `ModuleIndex` is the default resolver, reading modules manifests of kernel. Other resolvers
+/// (e.g. embedded module archives or test fixtures) can be set with `Options::resolver`.
+/// Modules are identified by strings returned from `resolve` and `dependencies`, file of module
+/// is read from `path`.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// use liblmod::ModuleResolver;
+/// use std::{collections::HashMap, path::PathBuf};
+///
+/// struct Fixture(HashMap<String, Vec<String>>);
+///
+/// impl ModuleResolver for Fixture {
+///     fn kernel(&self) -> &str {
+///         "fixture"
+///     }
+///
+///     fn resolve(&self, name: &str) -> Option<&str> {
+///         self.0.get_key_value(name).map(|(module, _)| module.as_str())
+///     }
+///
+///     fn dependencies(&self, module: &str) -> &[String] {
+///         self.0.get(module).map(|deps| deps.as_slice()).unwrap_or(&[])
+///     }
+///
+///     fn path(&self, module: &str) -> PathBuf {
+///         PathBuf::from(format!("./fixtures/{module}.ko"))
+///     }
+/// }
+///
+/// let fixture = Fixture(HashMap::from([
+///     ("kvm".to_string(), Vec::new()),
+///     ("kvm_intel".to_string(), vec!["kvm".to_string()]),
+/// ]));
+/// let options = liblmod::Options::new().resolver(fixture);
+/// ```
+pub trait ModuleResolver: Send + Sync {
+	/// Returns release of the kernel modules are resolved for
+	fn kernel(&self) -> &str;
+
+	/// Returns module for module name, or `None` if module is not provided
+	fn resolve(&self, name: &str) -> Option<&str>;
+
+	/// Returns all (direct and indirect) dependencies of module, the deepest dependency last
+	fn dependencies(&self, module: &str) -> &[String];
+
+	/// Returns absolute path of module file
+	fn path(&self, module: &str) -> PathBuf;
+
+	/// Returns path of module file reported to caller, by default the same as `path`
+	fn resolved_path(&self, module: &str) -> PathBuf {
+		self.path(module)
+	}
+
+	/// Returns names of weak dependencies of module, by default there are none
+	fn weak_dependencies(&self, _name: &str) -> &[String] {
+		&[]
+	}
+
+	/// Returns position of module in load order (e.g. modules.order), by default modules are unordered
+	fn order(&self, _module: &str) -> Option<usize> {
+		None
+	}
+
+	/// Checks if module is built into kernel, by default no module is
+	fn is_builtin(&self, _name: &str) -> bool {
+		false
+	}
+
+	/// Returns names of modules matching alias, by default there are none
+	fn resolve_alias(&self, _alias: &str) -> Vec<String> {
+		Vec::new()
+	}
+
+	/// Returns modules with module name which are equally preferred, by default names are unique
+	fn ambiguous(&self, _name: &str) -> Vec<String> {
+		Vec::new()
+	}
+
+	/// Returns names of all provided modules, suggested for misspelled names (by default none)
+	fn names(&self) -> Vec<String> {
+		Vec::new()
+	}
+}
+
+impl ModuleResolver for ModuleIndex {
+	fn kernel(&self) -> &str {
+		ModuleIndex::kernel(self)
+	}
+
+	fn resolve(&self, name: &str) -> Option<&str> {
+		ModuleIndex::resolve(self, name)
+	}
+
+	fn dependencies(&self, module: &str) -> &[String] {
+		ModuleIndex::dependencies(self, module)
+	}
+
+	fn path(&self, module: &str) -> PathBuf {
+		ModuleIndex::path(self, module)
+	}
+
+	fn resolved_path(&self, module: &str) -> PathBuf {
+		ModuleIndex::resolved_path(self, module)
+	}
+
+	fn weak_dependencies(&self, name: &str) -> &[String] {
+		ModuleIndex::weak_dependencies(self, name)
+	}
+
+	fn order(&self, module: &str) -> Option<usize> {
+		ModuleIndex::order(self, module)
+	}
+
+	fn is_builtin(&self, name: &str) -> bool {
+		ModuleIndex::is_builtin(self, name)
+	}
+
+	fn resolve_alias(&self, alias: &str) -> Vec<String> {
+		ModuleIndex::resolve_alias(self, alias)
+	}
+
+	fn ambiguous(&self, name: &str) -> Vec<String> {
+		ModuleIndex::ambiguous(self, name)
+			.into_iter()
+			.map(|module| module.to_string())
+			.collect()
+	}
+
+	fn names(&self) -> Vec<String> {
+		ModuleIndex::names(self)
+	}
+}
+
+/// Returns dependencies of module which are not needed by its other dependencies
+pub(crate) fn direct_dependencies<'a>(
+	resolver: &'a dyn ModuleResolver,
+	module: &str,
+) -> Vec<&'a String> {
+	// All dependencies are listed, dependency is direct if no other dependency needs it
+	let deps = resolver.dependencies(module);
+	deps.iter()
+		.filter(|dep| {
+			!deps
+				.iter()
+				.any(|other| other != *dep && resolver.dependencies(other).contains(dep))
+		})
+		.collect()
+}
+
+/// Returns names of modules on dependency path from module to its dependency
+pub(crate) fn dependency_chain(
+	resolver: &dyn ModuleResolver,
+	module: &str,
+	dep: &str,
+) -> Vec<String> {
+	let mut chain = vec![index::module_name(module)];
+	let mut current = module;
+	while current != dep {
+		// Follow direct dependency which leads to the wanted one
+		let next = direct_dependencies(resolver, current)
+			.into_iter()
+			.find(|d| *d == dep || resolver.dependencies(d).iter().any(|d| d == dep));
+		match next {
+			Some(next) => {
+				chain.push(index::module_name(next));
+				current = next;
+			}
+			None => {
+				chain.push(index::module_name(dep));
+				break;
+			}
+		}
+	}
+
+	chain
+}
@@ -0,0 +1,235 @@
+use std::{
+	ffi::CStr,
+	io,
+	os::raw::*,
+	path::Path,
+	thread,
+	time::{Duration, Instant},
+};
+
+use crate::{config, index, kernel, loaded::holders, lock::Lock, sys, vfs, Config, Error, Flags};
+
+/// Interval between attempts to unload busy module
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Options for unloading modules
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let options = liblmod::RemoveOptions::new().allow_livepatch(true);
+/// ```
+pub struct RemoveOptions {
+	pub(crate) allow_livepatch: bool,
+	pub(crate) lock: bool,
+	pub(crate) retry: Duration,
+	pub(crate) ignore_remove: bool,
+	pub(crate) users_first: bool,
+}
+
+impl RemoveOptions {
+	/// Creates default options
+	pub fn new() -> RemoveOptions {
+		RemoveOptions {
+			allow_livepatch: false,
+			lock: false,
+			retry: Duration::ZERO,
+			ignore_remove: false,
+			users_first: false,
+		}
+	}
+
+	/// Allows unloading livepatch modules
+	///
+	/// Unloading an active livepatch can destabilize the system, so it's refused by default.
+	pub fn allow_livepatch(mut self, allow: bool) -> RemoveOptions {
+		self.allow_livepatch = allow;
+		self
+	}
+
+	/// Retries unloading of module which is in use until duration elapses
+	///
+	/// If module is still in use, `Error::ModuleBusy` is returned. Not used with
+	/// `Flags::BlockingTimeout`.
+	pub fn retry(mut self, duration: Duration) -> RemoveOptions {
+		self.retry = duration;
+		self
+	}
+
+	/// Unloads module directly even if modprobe.d has `remove` command for it, like `modprobe -i`
+	///
+	/// Without this option, `remove` command of module is run with `/bin/sh` instead of unloading
+	/// the module.
+	pub fn ignore_remove(mut self, ignore_remove: bool) -> RemoveOptions {
+		self.ignore_remove = ignore_remove;
+		self
+	}
+
+	/// Unloads modules given to `rmmod_all_with` after other given modules using them, instead of
+	/// in given order
+	pub fn users_first(mut self, users_first: bool) -> RemoveOptions {
+		self.users_first = users_first;
+		self
+	}
+
+	/// Serializes unloading with other processes using this crate (see `Options::lock`)
+	pub fn lock(mut self, lock: bool) -> RemoveOptions {
+		self.lock = lock;
+		self
+	}
+}
+
+impl Default for RemoveOptions {
+	fn default() -> RemoveOptions {
+		RemoveOptions::new()
+	}
+}
+
+/// Checks if loaded module is a livepatch
+pub(crate) fn is_livepatch(name: &str) -> bool {
+	Path::new(&format!("/sys/kernel/livepatch/{}", name)).exists()
+		|| Path::new(&format!("/sys/module/{}/livepatch", name)).exists()
+}
+
+/// Unloads module from current running kernel
+pub(crate) fn remove(name: &str, flags: Flags, options: &RemoveOptions) -> io::Result<()> {
+	// Fail early if process is not permitted to unload modules
+	kernel::check_permitted()?;
+	let _lock = Lock::acquire_if(options.lock)?;
+
+	// Refuse names kernel couldn't have given to module
+	if !index::is_valid_name(name) {
+		return Err(Error::InvalidModuleName(name.to_string()).into());
+	}
+
+	// Refuse to unload livepatches unless explicitly allowed
+	let name = index::normalize(name);
+	if !options.allow_livepatch && is_livepatch(&name) {
+		return Err(Error::Livepatch(name).into());
+	}
+
+	// Run remove command instead of unloading module
+	if !options.ignore_remove {
+		if let Some(command) = Config::load()?.remove_command(&name) {
+			return config::run_command("remove", command, &name, "");
+		}
+	}
+
+	let flags_raw: c_uint = match flags {
+		Flags::None | Flags::Blocking | Flags::BlockingTimeout(_) => 0,
+		Flags::Force => sys::O_NONBLOCK | sys::O_TRUNC,
+		Flags::ForceWait => sys::O_TRUNC,
+		Flags::Casual => sys::O_NONBLOCK,
+	};
+
+	// Refuse to unload module in use, unless removal is forced or waits until module is unused
+	let check = || match flags {
+		Flags::None | Flags::Casual => check_unused(&name),
+		_ => Ok(()),
+	};
+
+	// Wait for blocking call on helper thread
+	if let Flags::BlockingTimeout(timeout) = flags {
+		let thread_name = name.clone();
+		return crate::deadline::run_timeout(&name, timeout, move || {
+			delete(thread_name, flags_raw)
+		})
+		.map_err(|e| diagnose(&name, e));
+	}
+
+	// Retry while module is in use
+	let deadline = Instant::now() + options.retry;
+	loop {
+		match check().and_then(|_| delete(name.clone(), flags_raw)) {
+			Err(e) if is_busy(&e) && Instant::now() < deadline => thread::sleep(
+				RETRY_INTERVAL.min(deadline.saturating_duration_since(Instant::now())),
+			),
+			result => {
+				return result
+					.map_err(|e| Error::syscall_failed("delete_module", e, |e| diagnose(&name, e)))
+			}
+		}
+	}
+}
+
+/// Explains why module is busy with its refcount and holders from sysfs
+fn diagnose(name: &str, error: io::Error) -> io::Error {
+	if !matches!(error.raw_os_error(), Some(libc::EBUSY | libc::EWOULDBLOCK)) {
+		return error;
+	}
+
+	let refcount = vfs::read_to_string(format!("/sys/module/{}/refcnt", name))
+		.ok()
+		.and_then(|refcount| refcount.trim().parse().ok());
+
+	Error::ModuleBusy {
+		module: name.to_string(),
+		refcount,
+		holders: holders(name),
+	}
+	.into()
+}
+
+/// Checks that module isn't referenced nor held by other modules, as listed in `/proc/modules`
+///
+/// If loaded modules cannot be listed, decision is left to kernel.
+fn check_unused(name: &str) -> io::Result<()> {
+	let module = match crate::lsmod() {
+		Ok(modules) => modules.into_iter().find(|module| module.name == name),
+		Err(_) => None,
+	};
+	let module = match module {
+		Some(module) => module,
+		None => return Ok(()),
+	};
+
+	// Holders are listed both in /proc/modules and sysfs
+	let mut holders = holders(name);
+	for user in module.used_by {
+		if !holders.contains(&user) {
+			holders.push(user);
+		}
+	}
+	holders.sort();
+
+	if module.refcount.unwrap_or(0) == 0 && holders.is_empty() {
+		return Ok(());
+	}
+
+	Err(Error::ModuleBusy {
+		module: name.to_string(),
+		refcount: module.refcount,
+		holders,
+	}
+	.into())
+}
+
+/// Checks if module couldn't be unloaded because it's in use
+fn is_busy(error: &io::Error) -> bool {
+	error.raw_os_error() == Some(libc::EBUSY)
+		|| matches!(Error::from_io(error), Some(Error::ModuleBusy { .. }))
+}
+
+/// Calls kernel to unload module
+fn delete(name: String, flags: c_uint) -> io::Result<()> {
+	let cname = crate::backend::cstring(&name)?;
+	let result = crate::hooks::around_unload(&name, || delete_module(&cname, flags));
+
+	#[cfg(feature = "audit")]
+	crate::audit::unload(&name, &result);
+
+	result
+}
+
+/// Calls kernel to remove module
+#[cfg(not(feature = "testing"))]
+fn delete_module(name: &CStr, flags: c_uint) -> io::Result<()> {
+	sys::delete_module(name, flags)
+}
+
+/// Records removal of module in fake kernel
+#[cfg(feature = "testing")]
+fn delete_module(name: &CStr, _flags: c_uint) -> io::Result<()> {
+	crate::testing::unload(&name.to_string_lossy())
+}
@@ -0,0 +1,155 @@
+use std::{collections::BTreeMap, io};
+
+use crate::{
+	index, modprobe, rmmod, Flags, LoadReport, Options, Outcome, Provenance, RemoveOptions,
+};
+
+/// Modules loaded through session, with reasons of loading them
+///
+/// ModuleSession remembers every module it has inserted into kernel, whether it has been requested,
+/// loaded as dependency or weak dependency, or by install command from modprobe.d. Modules which
+/// were already loaded are not recorded, so `rollback` removes only what session has loaded
+/// (e.g. in test harnesses and short-lived tools cleaning up after themselves). With
+/// `unload_on_drop`, modules are unloaded when session is dropped, also during unwinding of
+/// panicking test.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let mut session = liblmod::ModuleSession::new();
+/// if let Err(e) = session.modprobe("kvm_intel", "") {
+///     eprintln!("Failed to load module kvm_intel: {e}");
+/// }
+/// for module in session.modules() {
+///     println!("{} loaded as {:?}", module.name, module.provenance);
+/// }
+///
+/// for (name, result) in session.rollback() {
+///     if let Err(e) = result {
+///         eprintln!("Failed to unload module {name}: {e}");
+///     }
+/// }
+///
+/// // Modules are unloaded when session goes out of scope
+/// let mut session = liblmod::ModuleSession::new().unload_on_drop(true);
+/// let _ = session.modprobe("tun", "");
+/// ```
+#[derive(Default)]
+pub struct ModuleSession {
+	options: Options,
+	modules: Vec<SessionModule>,
+	unload_on_drop: bool,
+}
+
+/// Module loaded through session
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionModule {
+	/// Name of module (as requested for modules loaded by install commands)
+	pub name: String,
+
+	/// Parameters module has been loaded with
+	pub params: String,
+
+	/// Why module has been loaded
+	pub provenance: Provenance,
+}
+
+impl ModuleSession {
+	/// Creates session loading modules with default options
+	pub fn new() -> ModuleSession {
+		ModuleSession::with_options(Options::new())
+	}
+
+	/// Creates session loading modules with options
+	pub fn with_options(options: Options) -> ModuleSession {
+		ModuleSession {
+			options,
+			modules: Vec::new(),
+			unload_on_drop: false,
+		}
+	}
+
+	/// Unloads modules loaded through session when it's dropped, errors are ignored then
+	pub fn unload_on_drop(mut self, enabled: bool) -> ModuleSession {
+		self.unload_on_drop = enabled;
+		self
+	}
+
+	/// Loads module with its dependencies like `modprobe_report`, recording inserted modules
+	pub fn modprobe(
+		&mut self,
+		name: impl AsRef<str>,
+		params: impl AsRef<str>,
+	) -> io::Result<LoadReport> {
+		let report = crate::modprobe_report(name.as_ref(), params.as_ref(), &self.options)?;
+
+		// Install commands don't report what they have loaded
+		if report.outcome == Outcome::Loaded && report.modules.is_empty() {
+			self.modules.push(SessionModule {
+				name: index::normalize(name.as_ref()),
+				params: params.as_ref().to_string(),
+				provenance: Provenance::InstallCommand,
+			});
+		}
+		for module in report.loaded() {
+			self.modules.push(SessionModule {
+				name: module.name.clone(),
+				params: module.params.clone(),
+				provenance: module.provenance,
+			});
+		}
+
+		Ok(report)
+	}
+
+	/// Returns modules loaded through session, in order of loading
+	pub fn modules(&self) -> &[SessionModule] {
+		&self.modules
+	}
+
+	/// Returns why module has been loaded through session, `None` if session hasn't loaded it
+	pub fn provenance(&self, name: impl AsRef<str>) -> Option<Provenance> {
+		let name = index::normalize(name.as_ref());
+		self.modules
+			.iter()
+			.find(|module| module.name == name)
+			.map(|module| module.provenance)
+	}
+
+	/// Unloads modules loaded through session, the last loaded first
+	///
+	/// Modules already unloaded by someone else are skipped. Modules which fail to unload (e.g.
+	/// because they are in use) are kept in session and reported with their errors, others are
+	/// forgotten. Remove commands from modprobe.d are run for modules loaded by install commands.
+	pub fn rollback(&mut self) -> BTreeMap<String, io::Result<()>> {
+		let mut results = BTreeMap::new();
+		let mut kept = Vec::new();
+		while let Some(module) = self.modules.pop() {
+			if !modprobe::is_loaded(&module.name) {
+				continue;
+			}
+
+			let options =
+				RemoveOptions::new().ignore_remove(module.provenance != Provenance::InstallCommand);
+			let result = rmmod::remove(&module.name, Flags::Casual, &options);
+			if result.is_err() {
+				kept.push(module.clone());
+			}
+			results.insert(module.name, result);
+		}
+
+		kept.reverse();
+		self.modules = kept;
+		results
+	}
+}
+
+impl Drop for ModuleSession {
+	fn drop(&mut self) {
+		if self.unload_on_drop {
+			let _ = self.rollback();
+		}
+	}
+}
@@ -0,0 +1,167 @@
+/// Marker ending module images with appended signature
+const MAGIC: &[u8] = b"~Module signature appended~\n";
+
+/// Size of `struct module_signature` preceding marker
+const INFO_LEN: usize = 12;
+
+/// Signature type with signer name and key ID stored before signature (kernels before 4.3)
+const PKEY_ID_X509: u8 = 1;
+
+/// Signature type with signer inside PKCS#7 message
+const PKEY_ID_PKCS7: u8 = 2;
+
+/// DER tags of constructed sequence and set
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+
+/// DER tag of signer identified by subject key identifier (`[0] IMPLICIT`)
+const SUBJECT_KEY_IDENTIFIER: u8 = 0x80;
+
+/// DER encoding of commonName attribute type (2.5.4.3)
+const COMMON_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x04, 0x03];
+
+/// Signature appended to module image by `sign-file`
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// # let der = |tag: u8, parts: &[&[u8]]| [&[tag, parts.concat().len() as u8][..], &parts.concat()].concat();
+/// # let cn = der(0x30, &[&[0x06, 0x03, 0x55, 0x04, 0x03], &der(0x0c, &[b"Distro"])]);
+/// # let issuer = der(0x30, &[&der(0x31, &[&cn])]);
+/// # let signer = der(0x30, &[&[0x02, 0x01, 0x01], &der(0x30, &[&issuer, &[0x02, 0x02, 0x12, 0x34]])]);
+/// # let signed = der(0x30, &[&[0x02, 0x01, 0x01, 0x31, 0x00, 0x30, 0x00], &der(0x31, &[&signer])]);
+/// # let message = der(0x30, &[&[0x06, 0x00], &der(0xa0, &[&signed])]);
+/// // Module data, PKCS#7 message, signature information and marker
+/// let mut image = b"\x7fELF".to_vec();
+/// image.extend(&message);
+/// image.extend([0, 0, 2, 0, 0, 0, 0, 0]);
+/// image.extend((message.len() as u32).to_be_bytes());
+/// image.extend(b"~Module signature appended~\n");
+///
+/// let signature = liblmod::ModuleSignature::from_image(&image).unwrap();
+/// assert_eq!(signature.signer.as_deref(), Some("Distro"));
+/// assert_eq!(signature.key_id.as_deref(), Some("1234"));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModuleSignature {
+	/// Common name of issuer of certificate module is signed with, `None` if it can't be read
+	pub signer: Option<String>,
+
+	/// Hexadecimal serial number of certificate or its subject key identifier, depending on how
+	/// signature identifies the key, `None` if it can't be read
+	pub key_id: Option<String>,
+
+	/// Size of signature in bytes
+	pub length: usize,
+}
+
+impl ModuleSignature {
+	/// Reads signature appended to uncompressed module image, `None` if image isn't signed
+	pub fn from_image(image: &[u8]) -> Option<ModuleSignature> {
+		let rest = image.strip_suffix(MAGIC)?;
+		let (rest, info) = rest.split_at(rest.len().checked_sub(INFO_LEN)?);
+
+		// algorithm, hash, id_type, signer_len, key_id_len, padding and big endian sig_len
+		let (id_type, signer_len, key_id_len) = (info[2], info[3] as usize, info[4] as usize);
+		let length = u32::from_be_bytes([info[8], info[9], info[10], info[11]]) as usize;
+		let (rest, signature) = rest.split_at(rest.len().checked_sub(length)?);
+
+		let (signer, key_id) = match id_type {
+			PKEY_ID_PKCS7 => signer_identifier(signature).unwrap_or_default(),
+			PKEY_ID_X509 => {
+				let start = rest.len().checked_sub(key_id_len + signer_len)?;
+				let signer = rest.get(start..start + signer_len)?;
+				let key_id = rest.get(start + signer_len..)?;
+				(
+					Some(String::from_utf8_lossy(signer).into_owned()),
+					Some(key_id),
+				)
+			}
+			_ => (None, None),
+		};
+
+		Some(ModuleSignature {
+			signer,
+			key_id: key_id.map(hex),
+			length,
+		})
+	}
+}
+
+/// Reads issuer name and serial number, or subject key identifier, of the first signer of
+/// PKCS#7 message
+fn signer_identifier(message: &[u8]) -> Option<(Option<String>, Option<&[u8]>)> {
+	// ContentInfo with content type followed by explicitly tagged SignedData
+	let (_, content_info, _) = tlv(message)?;
+	let (_, _, rest) = tlv(content_info)?;
+	let (_, content, _) = tlv(rest)?;
+	let (_, mut signed_data, _) = tlv(content)?;
+
+	// Set of signers follows digest algorithms, content and optional certificates and CRLs
+	let mut signer_infos = None;
+	while !signed_data.is_empty() {
+		let (tag, value, rest) = tlv(signed_data)?;
+		if tag == SET {
+			signer_infos = Some(value);
+		}
+		signed_data = rest;
+	}
+
+	// Signer is identified by version followed by issuer and serial number or key identifier
+	let (_, signer_info, _) = tlv(signer_infos?)?;
+	let (_, _, rest) = tlv(signer_info)?;
+	match tlv(rest)? {
+		(SEQUENCE, identifier, _) => {
+			let (_, issuer, rest) = tlv(identifier)?;
+			let (_, serial, _) = tlv(rest)?;
+			Some((common_name(issuer), Some(serial)))
+		}
+		(SUBJECT_KEY_IDENTIFIER, key_id, _) => Some((None, Some(key_id))),
+		_ => None,
+	}
+}
+
+/// Splits DER encoded value into tag, contents and following data
+fn tlv(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+	let (&tag, rest) = data.split_first()?;
+	let (&len, rest) = rest.split_first()?;
+	let (len, rest) = match len {
+		len if len < 0x80 => (len as usize, rest),
+
+		// Long form, number of length bytes is in lower bits
+		len => {
+			let (bytes, rest) = rest.split_at_checked((len & 0x7f) as usize)?;
+			if bytes.len() > 4 {
+				return None;
+			}
+			let len = bytes.iter().fold(0, |len, &b| len << 8 | b as usize);
+			(len, rest)
+		}
+	};
+
+	let (value, rest) = rest.split_at_checked(len)?;
+	Some((tag, value, rest))
+}
+
+/// Finds first common name in DER encoded name
+fn common_name(name: &[u8]) -> Option<String> {
+	let start = name
+		.windows(COMMON_NAME.len())
+		.position(|window| window == COMMON_NAME)?
+		+ COMMON_NAME.len();
+
+	// UTF8String, PrintableString, TeletexString or IA5String
+	let (tag, value, _) = tlv(&name[start..])?;
+	if !matches!(tag, 0x0c | 0x13 | 0x14 | 0x16) {
+		return None;
+	}
+
+	Some(String::from_utf8_lossy(value).into_owned())
+}
+
+/// Formats bytes as lowercase hexadecimal string
+fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
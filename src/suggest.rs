@@ -0,0 +1,62 @@
+use crate::{index, Error, ModuleResolver};
+
+/// Maximum number of suggestions attached to `Error::ModuleNotFound`
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Builds error of module not provided by kernel, with names of similar modules
+pub(crate) fn not_found(resolver: &dyn ModuleResolver, name: &str) -> Error {
+	Error::ModuleNotFound {
+		module: name.to_string(),
+		kernel: resolver.kernel().to_string(),
+		suggestions: suggestions(resolver, name),
+	}
+}
+
+/// Returns names of modules similar to name, the closest first
+///
+/// Names differing only in case, modules matching name as alias regardless of case, names within
+/// small edit distance and names starting with given name are suggested.
+pub(crate) fn suggestions(resolver: &dyn ModuleResolver, name: &str) -> Vec<String> {
+	let wanted = index::normalize(name).to_lowercase();
+	let mut scored: Vec<(usize, String)> = resolver
+		.resolve_alias(&wanted)
+		.into_iter()
+		.map(|module| (0, module))
+		.collect();
+
+	// Short names are similar to too many modules with larger distance
+	let limit = (wanted.chars().count() / 3).max(1);
+	for module in resolver.names() {
+		let distance = distance(&wanted, &module.to_lowercase());
+		let prefix = wanted.len() >= 3 && module.starts_with(&wanted);
+		if distance <= limit || prefix {
+			scored.push((distance, module));
+		}
+	}
+
+	scored.sort();
+	let mut suggestions: Vec<String> = Vec::new();
+	for (_, module) in scored {
+		if !suggestions.contains(&module) && suggestions.len() < MAX_SUGGESTIONS {
+			suggestions.push(module);
+		}
+	}
+
+	suggestions
+}
+
+/// Computes Levenshtein distance of strings
+fn distance(a: &str, b: &str) -> usize {
+	let b: Vec<char> = b.chars().collect();
+	let mut previous: Vec<usize> = (0..=b.len()).collect();
+	for (i, ca) in a.chars().enumerate() {
+		let mut current = vec![i + 1];
+		for (j, cb) in b.iter().enumerate() {
+			let substitution = previous[j] + usize::from(ca != *cb);
+			current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+		}
+		previous = current;
+	}
+
+	previous[b.len()]
+}
@@ -0,0 +1,324 @@
+use std::{collections::HashMap, io, path::Path};
+
+use crate::{elf::Elf, index, suggest, vfs, CrcMismatch, Error, ModuleIndex, Selection};
+
+/// Prefix of symbols describing exported symbols (`EXPORT_SYMBOL`)
+const KSYMTAB: &str = "__ksymtab_";
+
+/// Prefix of symbols whose value is CRC of exported symbol in kallsyms
+const KALLSYMS_CRC: &str = "__crc_";
+
+/// Symbols of running kernel with their addresses
+const KALLSYMS: &str = "/proc/kallsyms";
+
+/// Module.symvers of kernel build tree, relative to module directory
+const BUILD_SYMVERS: &str = "build/Module.symvers";
+
+/// Section of module image
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Section {
+	/// Name of section (e.g. `.text` or `.modinfo`)
+	pub name: String,
+
+	/// Type of section (`SHT_*` constant of ELF, e.g. 1 for `SHT_PROGBITS`)
+	pub kind: u32,
+
+	/// Size of section in bytes
+	pub size: u64,
+}
+
+/// Symbol exported by module for other modules
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExportedSymbol {
+	/// Name of symbol
+	pub name: String,
+
+	/// Symbol is exported only to GPL-compatible modules (`EXPORT_SYMBOL_GPL`)
+	pub gpl_only: bool,
+}
+
+/// Sections and symbols of module image, read without loading it
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::module_symbols("kvm", liblmod::Selection::Current) {
+///     Ok(symbols) => {
+///         for symbol in symbols.exported {
+///             println!("kvm exports {}", symbol.name);
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to read symbols of module kvm: {e}"),
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModuleSymbols {
+	/// Sections in order of section headers
+	pub sections: Vec<Section>,
+
+	/// Symbols exported by module (`__ksymtab`), sorted by name
+	pub exported: Vec<ExportedSymbol>,
+
+	/// Symbols used by module and provided by kernel or other modules, sorted by name
+	pub undefined: Vec<String>,
+
+	/// Versions of symbols used by module (`__versions`), empty without `CONFIG_MODVERSIONS`
+	pub versions: Vec<SymbolVersion>,
+}
+
+/// Version (CRC of prototype) of symbol, compared by kernel with `CONFIG_MODVERSIONS`
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SymbolVersion {
+	/// Name of symbol
+	pub name: String,
+
+	/// CRC of symbol
+	pub crc: u32,
+}
+
+/// Versions of symbols exported by kernel and its modules
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let check = liblmod::Symvers::for_kernel(liblmod::Selection::Current)
+///     .and_then(|symvers| Ok((symvers, liblmod::ModuleSymbols::from_file("./example_module.ko")?)));
+/// match check {
+///     Ok((symvers, symbols)) => {
+///         for mismatch in symvers.mismatches(&symbols) {
+///             println!("{} differs: {:#010x} != {:#010x}", mismatch.symbol, mismatch.module, mismatch.kernel);
+///         }
+///     }
+///     Err(e) => eprintln!("Failed to compare symbol versions: {e}"),
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Symvers {
+	crcs: HashMap<String, u32>,
+}
+
+impl Symvers {
+	/// Reads Module.symvers written by kernel build (`0x<crc> <symbol> <module> <export> ...`)
+	pub fn from_file(path: impl AsRef<Path>) -> io::Result<Symvers> {
+		let mut symvers = Symvers::default();
+		for line in vfs::read_to_string(path)?.lines() {
+			let mut fields = line.split_whitespace();
+			if let (Some(crc), Some(symbol)) = (fields.next(), fields.next()) {
+				if let Some(crc) = parse_crc(crc) {
+					symvers.crcs.insert(symbol.to_string(), crc);
+				}
+			}
+		}
+
+		Ok(symvers)
+	}
+
+	/// Reads versions of symbols exported by running kernel from `__crc_` symbols of kallsyms
+	///
+	/// Only older kernels list CRCs in kallsyms, and addresses are hidden without `CAP_SYSLOG`,
+	/// so result is often empty.
+	pub fn from_kallsyms() -> io::Result<Symvers> {
+		let mut symvers = Symvers::default();
+		for line in vfs::read_to_string(KALLSYMS)?.lines() {
+			let mut fields = line.split_whitespace();
+			if let (Some(value), Some(symbol)) = (fields.next(), fields.nth(1)) {
+				let crc = u64::from_str_radix(value, 16)
+					.ok()
+					.map(|value| value as u32);
+				if let (Some(symbol), Some(crc)) = (symbol.strip_prefix(KALLSYMS_CRC), crc) {
+					// Hidden addresses are zeros
+					if crc != 0 {
+						symvers.crcs.insert(symbol.to_string(), crc);
+					}
+				}
+			}
+		}
+
+		Ok(symvers)
+	}
+
+	/// Reads versions of symbols of selected kernel
+	///
+	/// For running kernel, kallsyms is tried first. Otherwise Module.symvers is read from build
+	/// tree linked from module directory (`build/Module.symvers`).
+	pub fn for_kernel(kernel: Selection) -> io::Result<Symvers> {
+		if kernel == Selection::Current {
+			if let Ok(symvers) = Symvers::from_kallsyms() {
+				if !symvers.is_empty() {
+					return Ok(symvers);
+				}
+			}
+		}
+
+		Symvers::from_file(ModuleIndex::shared(kernel)?.path(BUILD_SYMVERS))
+	}
+
+	/// Returns version of symbol
+	pub fn crc(&self, symbol: &str) -> Option<u32> {
+		self.crcs.get(symbol).copied()
+	}
+
+	/// Returns whether no versions are known
+	pub fn is_empty(&self) -> bool {
+		self.crcs.is_empty()
+	}
+
+	/// Returns symbols used by module whose versions differ from kernel, sorted by name
+	///
+	/// Symbols unknown to kernel are skipped, kernel fails on them with missing symbol.
+	pub fn mismatches(&self, symbols: &ModuleSymbols) -> Vec<CrcMismatch> {
+		let mut mismatches: Vec<CrcMismatch> = symbols
+			.versions
+			.iter()
+			.filter_map(|version| {
+				let kernel = self.crc(&version.name)?;
+				(kernel != version.crc).then(|| CrcMismatch {
+					symbol: version.name.clone(),
+					module: version.crc,
+					kernel,
+				})
+			})
+			.collect();
+		mismatches.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+		mismatches
+	}
+}
+
+impl ModuleSymbols {
+	/// Reads sections and symbols from uncompressed module image
+	pub fn from_image(image: &[u8]) -> io::Result<ModuleSymbols> {
+		let elf = Elf::parse(image)?;
+		let mut symbols = ModuleSymbols {
+			sections: elf
+				.sections()?
+				.into_iter()
+				.map(|(name, kind, size)| Section {
+					name: name.to_string(),
+					kind,
+					size,
+				})
+				.collect(),
+			versions: elf
+				.modversions()?
+				.into_iter()
+				.map(|(name, crc)| SymbolVersion {
+					name: name.to_string(),
+					crc,
+				})
+				.collect(),
+			..Default::default()
+		};
+
+		for (name, section) in elf.symbols()? {
+			if name.is_empty() {
+				continue;
+			}
+			if section == 0 {
+				symbols.undefined.push(name.to_string());
+				continue;
+			}
+
+			// Entries of exported symbols are placed in __ksymtab, __ksymtab_gpl or (since 6.5)
+			// ___ksymtab+<name> and ___ksymtab_gpl+<name> sections
+			let section = elf.section_name_at(section)?;
+			if let Some(exported) = name.strip_prefix(KSYMTAB) {
+				if section.trim_start_matches('_').starts_with("ksymtab") {
+					symbols.exported.push(ExportedSymbol {
+						name: exported.to_string(),
+						gpl_only: section.contains("_gpl"),
+					});
+				}
+			}
+		}
+
+		symbols.exported.sort_by(|a, b| a.name.cmp(&b.name));
+		symbols.exported.dedup();
+		symbols.undefined.sort();
+		symbols.undefined.dedup();
+		Ok(symbols)
+	}
+
+	/// Reads sections and symbols from module file, decompressing it if it's compressed
+	pub fn from_file(path: impl AsRef<Path>) -> io::Result<ModuleSymbols> {
+		ModuleSymbols::from_image(&index::module_image(path.as_ref())?)
+	}
+
+	/// Returns section with specified name
+	pub fn section(&self, name: &str) -> Option<&Section> {
+		self.sections.iter().find(|section| section.name == name)
+	}
+}
+
+/// Reads sections and symbols of module for selected kernel
+pub fn module_symbols(name: impl AsRef<str>, kernel: Selection) -> io::Result<ModuleSymbols> {
+	let index = ModuleIndex::shared(kernel)?;
+	let module = index
+		.resolve(name.as_ref())
+		.ok_or_else(|| suggest::not_found(&*index, name.as_ref()))?;
+
+	ModuleSymbols::from_file(index.path(module))
+}
+
+/// Checks versions of symbols used by module file against selected kernel before loading
+///
+/// `Error::SymbolVersionMismatch` listing every differing symbol is returned, which kernel would
+/// reject with bare `ENOEXEC`. Modules without versions pass.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// match liblmod::check_modversions("./example_module.ko", liblmod::Selection::Current) {
+///     Err(e) => match liblmod::Error::from_io(&e) {
+///         Some(liblmod::Error::SymbolVersionMismatch { symbols, .. }) => {
+///             for mismatch in symbols {
+///                 eprintln!("Symbol {} has different version", mismatch.symbol);
+///             }
+///         }
+///         _ => eprintln!("Failed to check symbol versions: {e}"),
+///     },
+///     Ok(()) => println!("Module matches symbol versions of kernel"),
+/// }
+/// ```
+pub fn check_modversions(path: impl AsRef<Path>, kernel: Selection) -> io::Result<()> {
+	let path = path.as_ref();
+	let symbols = ModuleSymbols::from_file(path)?;
+	if symbols.versions.is_empty() {
+		return Ok(());
+	}
+
+	let mismatches = Symvers::for_kernel(kernel)?.mismatches(&symbols);
+	if mismatches.is_empty() {
+		return Ok(());
+	}
+
+	Err(Error::SymbolVersionMismatch {
+		module: index::module_name(&path.to_string_lossy()),
+		symbols: mismatches,
+	}
+	.into())
+}
+
+/// Returns symbols of module image whose versions differ from running kernel, if known
+pub(crate) fn running_mismatches(image: &[u8]) -> Vec<CrcMismatch> {
+	let symbols = match ModuleSymbols::from_image(image) {
+		Ok(symbols) if !symbols.versions.is_empty() => symbols,
+		_ => return Vec::new(),
+	};
+
+	Symvers::for_kernel(Selection::Current)
+		.map(|symvers| symvers.mismatches(&symbols))
+		.unwrap_or_default()
+}
+
+/// Parses CRC written in hexadecimal with `0x` prefix
+fn parse_crc(crc: &str) -> Option<u32> {
+	let digits = crc.strip_prefix("0x")?;
+	u64::from_str_radix(digits, 16).ok().map(|crc| crc as u32)
+}
@@ -0,0 +1,93 @@
+//! Thin wrappers of module syscalls without resolution of modules and their dependencies
+//!
+//! Functions pass their arguments to kernel unchanged and return errors of kernel as
+//! `io::Error`. They always call running kernel, even with `testing` feature, and don't check
+//! capabilities, architecture of module image, load policy or hooks.
+//!
+//! Kernel is called with `syscall()` by default. `set_backend(Backend::Libc)` switches
+//! `init_module` and `delete_module` to wrappers exported by libc, which can be intercepted by
+//! `LD_PRELOAD` libraries (e.g. in fakeroot environments or with sanitizers used in CI). Backend
+//! is used by whole library, not only by functions of this module.
+//!
+//! Example:
+//! ```rust,no_run
+//! extern crate liblmod;
+//!
+//! use std::{ffi::CString, fs::File};
+//! use liblmod::sys;
+//!
+//! fn main() -> std::io::Result<()> {
+//!     let file = File::open("./example_module.ko")?;
+//!     sys::finit_module(&file, &CString::new("debug=1").unwrap(), 0)?;
+//!     sys::delete_module(&CString::new("example_module").unwrap(), sys::O_NONBLOCK)
+//! }
+//! ```
+
+use std::{ffi::CStr, io, os::fd::AsFd};
+
+use crate::backend;
+
+/// Flag of `finit_module` ignoring symbol version hashes
+pub const MODULE_INIT_IGNORE_MODVERSIONS: u32 = 1;
+
+/// Flag of `finit_module` ignoring kernel version magic
+pub const MODULE_INIT_IGNORE_VERMAGIC: u32 = 2;
+
+/// Flag of `finit_module` asking kernel to decompress module itself (Linux 6.4+)
+pub const MODULE_INIT_COMPRESSED_FILE: u32 = backend::MODULE_INIT_COMPRESSED_FILE;
+
+/// Flag of `delete_module` failing with `EWOULDBLOCK` instead of waiting while module is in use
+pub const O_NONBLOCK: u32 = libc::O_NONBLOCK as u32;
+
+/// Flag of `delete_module` forcing removal of module (requires `CONFIG_MODULE_FORCE_UNLOAD`)
+pub const O_TRUNC: u32 = libc::O_TRUNC as u32;
+
+/// Way of calling module syscalls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+	/// Calling kernel with `syscall()` and syscall numbers (default)
+	Syscall,
+
+	/// Calling `init_module` and `delete_module` functions of libc, `finit_module` (which has no
+	/// libc wrapper) is still called with `syscall()`
+	Libc,
+}
+
+/// Selects way of calling module syscalls for whole process
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// use liblmod::sys::{self, Backend};
+///
+/// sys::set_backend(Backend::Libc);
+/// assert_eq!(sys::backend(), Backend::Libc);
+/// sys::set_backend(Backend::Syscall);
+/// ```
+pub fn set_backend(backend: Backend) {
+	backend::use_libc_wrappers(backend == Backend::Libc);
+}
+
+/// Returns selected way of calling module syscalls
+pub fn backend() -> Backend {
+	match backend::libc_wrappers() {
+		true => Backend::Libc,
+		false => Backend::Syscall,
+	}
+}
+
+/// Inserts module image into kernel
+pub fn init_module(image: &[u8], params: &CStr) -> io::Result<()> {
+	backend::init_module(image, params)
+}
+
+/// Inserts module from file descriptor into kernel, `flags` are `MODULE_INIT_*` bits
+pub fn finit_module(fd: impl AsFd, params: &CStr, flags: u32) -> io::Result<()> {
+	backend::finit_module(fd.as_fd(), params, flags)
+}
+
+/// Removes module from kernel, `flags` are `O_NONBLOCK` and `O_TRUNC` bits
+pub fn delete_module(name: &CStr, flags: u32) -> io::Result<()> {
+	backend::delete_module(name, flags)
+}
@@ -0,0 +1,172 @@
+//! Fake kernel recording module loading and unloading (`testing` feature)
+//!
+//! With `testing` feature, modules are never inserted into or removed from running kernel.
+//! Calls are recorded instead, so code managing modules can be tested without `CAP_SYS_MODULE`
+//! (e.g. in CI containers). Fake kernel keeps track of modules loaded through it: loading loaded
+//! module fails with `EEXIST`, unloading unknown module with `ENOENT` and modprobe treats only
+//! modules loaded through fake kernel as loaded. Capabilities of process and architecture of
+//! module images are not checked, so module files can contain any data.
+//!
+//! State is kept per thread, so tests running in parallel don't see calls of each other.
+//! Combined with `MemoryFs`, modprobe can be tested without any real module files.
+//!
+//! Example:
+//! ```rust
+//! extern crate liblmod;
+//!
+//! use liblmod::testing::{self, Call};
+//!
+//! liblmod::set_filesystem(
+//!     liblmod::MemoryFs::new()
+//!         .file("/lib/modules/6.1.0/modules.order", "kernel/kvm.ko\nkernel/kvm-intel.ko\n")
+//!         .file("/lib/modules/6.1.0/modules.dep", "kernel/kvm.ko:\nkernel/kvm-intel.ko: kernel/kvm.ko\n")
+//!         .file("/lib/modules/6.1.0/kernel/kvm.ko", "")
+//!         .file("/lib/modules/6.1.0/kernel/kvm-intel.ko", ""),
+//! );
+//!
+//! let options = liblmod::Options::new().kernel(liblmod::Selection::Other("6.1.0".to_string()));
+//! liblmod::modprobe_with("kvm_intel", "nested=1", &options).unwrap();
+//! assert_eq!(
+//!     testing::calls(),
+//!     vec![
+//!         Call::Load { module: "kvm".to_string(), params: "".to_string() },
+//!         Call::Load { module: "kvm_intel".to_string(), params: "nested=1".to_string() },
+//!     ]
+//! );
+//!
+//! testing::reset();
+//! liblmod::clear_filesystem();
+//! ```
+
+use std::{
+	cell::RefCell,
+	collections::{BTreeSet, HashMap},
+	io,
+};
+
+use crate::index;
+
+thread_local! {
+	static KERNEL: RefCell<FakeKernel> = RefCell::new(FakeKernel::default());
+}
+
+/// State of fake kernel
+#[derive(Clone, Default)]
+pub(crate) struct FakeKernel {
+	calls: Vec<Call>,
+	loaded: BTreeSet<String>,
+	failures: HashMap<String, i32>,
+}
+
+/// Call made to fake kernel
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Call {
+	/// Module has been inserted
+	Load {
+		/// Name of module
+		module: String,
+
+		/// Parameters of module
+		params: String,
+	},
+
+	/// Module has been removed
+	Unload {
+		/// Name of module
+		module: String,
+	},
+}
+
+/// Returns calls made to fake kernel on current thread, in order
+pub fn calls() -> Vec<Call> {
+	KERNEL.with(|kernel| kernel.borrow().calls.clone())
+}
+
+/// Returns names of modules currently loaded into fake kernel
+pub fn loaded() -> Vec<String> {
+	KERNEL.with(|kernel| kernel.borrow().loaded.iter().cloned().collect())
+}
+
+/// Marks module as already loaded, without recording a call
+pub fn preload(module: impl AsRef<str>) {
+	KERNEL.with(|kernel| {
+		kernel
+			.borrow_mut()
+			.loaded
+			.insert(index::normalize(module.as_ref()))
+	});
+}
+
+/// Makes loading and unloading of module fail with error number (e.g. `libc::ENOEXEC`)
+///
+/// Failed calls are recorded too.
+pub fn fail(module: impl AsRef<str>, errno: i32) {
+	KERNEL.with(|kernel| {
+		kernel
+			.borrow_mut()
+			.failures
+			.insert(index::normalize(module.as_ref()), errno)
+	});
+}
+
+/// Forgets recorded calls, loaded modules and failures
+pub fn reset() {
+	KERNEL.with(|kernel| *kernel.borrow_mut() = FakeKernel::default());
+}
+
+/// Returns copy of fake kernel of current thread, to be shared with helper threads
+pub(crate) fn state() -> FakeKernel {
+	KERNEL.with(|kernel| kernel.borrow().clone())
+}
+
+/// Replaces fake kernel of current thread with one returned by `state` on another thread
+pub(crate) fn set_state(state: FakeKernel) {
+	KERNEL.with(|kernel| *kernel.borrow_mut() = state);
+}
+
+/// Records insertion of module
+pub(crate) fn load(module: &str, params: &str) -> io::Result<()> {
+	let module = index::module_name(module);
+	KERNEL.with(|kernel| {
+		let mut kernel = kernel.borrow_mut();
+		kernel.calls.push(Call::Load {
+			module: module.clone(),
+			params: params.to_string(),
+		});
+
+		if let Some(errno) = kernel.failures.get(&module) {
+			return Err(io::Error::from_raw_os_error(*errno));
+		}
+		if !kernel.loaded.insert(module) {
+			return Err(io::Error::from_raw_os_error(libc::EEXIST));
+		}
+
+		Ok(())
+	})
+}
+
+/// Records removal of module
+pub(crate) fn unload(module: &str) -> io::Result<()> {
+	let module = index::normalize(module);
+	KERNEL.with(|kernel| {
+		let mut kernel = kernel.borrow_mut();
+		kernel.calls.push(Call::Unload {
+			module: module.clone(),
+		});
+
+		if let Some(errno) = kernel.failures.get(&module) {
+			return Err(io::Error::from_raw_os_error(*errno));
+		}
+		if !kernel.loaded.remove(&module) {
+			return Err(io::Error::from_raw_os_error(libc::ENOENT));
+		}
+
+		Ok(())
+	})
+}
+
+/// Checks if module is loaded into fake kernel
+pub(crate) fn is_loaded(module: &str) -> bool {
+	KERNEL.with(|kernel| kernel.borrow().loaded.contains(&index::normalize(module)))
+}
@@ -0,0 +1,642 @@
+//! Kernel uevents received from netlink, for autoloading modules of hotplugged devices
+
+use std::{
+	collections::BTreeMap,
+	io::{self, ErrorKind},
+	os::fd::OwnedFd,
+};
+
+#[cfg(any(target_os = "linux", feature = "async"))]
+use std::os::fd::AsRawFd;
+#[cfg(target_os = "linux")]
+use std::os::fd::FromRawFd;
+
+#[cfg(feature = "async")]
+use std::{
+	os::unix::net::UnixStream,
+	pin::Pin,
+	sync::{Arc, Condvar, Mutex},
+	task::{Context, Poll, Waker},
+	thread,
+};
+
+use crate::{autoload, index, AutoloadReport, Options};
+
+/// Multicast group of uevents sent by kernel (group 2 is used by udev)
+#[cfg(target_os = "linux")]
+const KERNEL_GROUP: u32 = 1;
+
+/// Number of leading bytes of messages searched for end of `action@devpath` header by BPF
+/// program, subsystem of events with longer header is checked after receiving them
+#[cfg(target_os = "linux")]
+const HEADER_SCAN: u32 = 512;
+
+/// Size of buffer for single uevent message
+const MESSAGE_SIZE: usize = 8192;
+
+/// Event of device reported by kernel
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Uevent {
+	/// Action (e.g. `add`, `remove`, `change`, `bind`)
+	pub action: String,
+
+	/// Path of device in sysfs, relative to `/sys`
+	pub devpath: String,
+
+	/// Subsystem of device (e.g. `pci`, `usb`)
+	pub subsystem: Option<String>,
+
+	/// Modalias of device, matched against module aliases
+	pub modalias: Option<String>,
+
+	/// All `KEY=value` entries of event
+	pub env: BTreeMap<String, String>,
+}
+
+impl Uevent {
+	/// Parses uevent message (`action@devpath` followed by `KEY=value` entries separated by NUL)
+	///
+	/// Example:
+	/// ```rust
+	/// extern crate liblmod;
+	///
+	/// let message = b"add@/devices/pci0000:00/0000:00:02.0\0ACTION=add\0DEVPATH=/devices/pci0000:00/0000:00:02.0\0SUBSYSTEM=pci\0MODALIAS=pci:v00008086d00003E92sv00001028sd0000085Cbc03sc00i00\0SEQNUM=2183\0";
+	/// let event = liblmod::uevent::Uevent::parse(message).unwrap();
+	/// assert_eq!(event.action, "add");
+	/// assert_eq!(event.subsystem.as_deref(), Some("pci"));
+	/// assert!(event.modalias.unwrap().starts_with("pci:v00008086"));
+	/// ```
+	pub fn parse(message: &[u8]) -> Option<Uevent> {
+		let mut entries = message
+			.split(|b| *b == 0)
+			.map(|entry| String::from_utf8_lossy(entry).into_owned());
+		let header = entries.next()?;
+		let (action, devpath) = header.split_once('@')?;
+
+		let env: BTreeMap<String, String> = entries
+			.filter_map(|entry| {
+				let (key, value) = entry.split_once('=')?;
+				Some((key.to_string(), value.to_string()))
+			})
+			.collect();
+
+		Some(Uevent {
+			action: env
+				.get("ACTION")
+				.cloned()
+				.unwrap_or_else(|| action.to_string()),
+			devpath: env
+				.get("DEVPATH")
+				.cloned()
+				.unwrap_or_else(|| devpath.to_string()),
+			subsystem: env.get("SUBSYSTEM").cloned(),
+			modalias: env.get("MODALIAS").cloned(),
+			env,
+		})
+	}
+}
+
+/// Selection of uevents reported by `UeventListener` and `UeventStream`
+///
+/// Events are accepted if they match all configured criteria, criteria without any value
+/// accept every event. Actions and subsystems are checked by kernel with BPF program attached to
+/// socket, so listener isn't woken up by other events. Modaliases are matched after receiving
+/// event, as their offsets in messages of kernel vary.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// use liblmod::uevent::{Uevent, UeventFilter};
+///
+/// let filter = UeventFilter::new().action("add").subsystem("pci").modalias("pci:v00008086*");
+/// let event = Uevent::parse(b"add@/devices/pci0000:00/0000:00:02.0\0ACTION=add\0SUBSYSTEM=pci\0MODALIAS=pci:v00008086d00003E92\0").unwrap();
+/// assert!(filter.matches(&event));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UeventFilter {
+	actions: Vec<String>,
+	subsystems: Vec<String>,
+	modaliases: Vec<String>,
+}
+
+impl UeventFilter {
+	/// Creates filter accepting all events
+	pub fn new() -> UeventFilter {
+		UeventFilter::default()
+	}
+
+	/// Accepts events with action (e.g. `add`), can be called multiple times
+	pub fn action(mut self, action: impl Into<String>) -> UeventFilter {
+		self.actions.push(action.into());
+		self
+	}
+
+	/// Accepts events of devices in subsystem (e.g. `usb`), can be called multiple times
+	pub fn subsystem(mut self, subsystem: impl Into<String>) -> UeventFilter {
+		self.subsystems.push(subsystem.into());
+		self
+	}
+
+	/// Accepts events of devices with modalias matching glob pattern (e.g. `usb:*`)
+	///
+	/// Events without modalias are rejected once any pattern is set. Can be called multiple times.
+	pub fn modalias(mut self, pattern: impl Into<String>) -> UeventFilter {
+		self.modaliases.push(pattern.into());
+		self
+	}
+
+	/// Checks if event is accepted by filter
+	pub fn matches(&self, event: &Uevent) -> bool {
+		let subsystem = event.subsystem.as_deref().unwrap_or("");
+		let modalias = event.modalias.as_deref();
+		(self.actions.is_empty() || self.actions.contains(&event.action))
+			&& (self.subsystems.is_empty() || self.subsystems.iter().any(|s| s == subsystem))
+			&& (self.modaliases.is_empty()
+				|| modalias.is_some_and(|modalias| {
+					self.modaliases
+						.iter()
+						.any(|pattern| index::fnmatch(pattern.as_bytes(), modalias.as_bytes()))
+				}))
+	}
+
+	/// Builds BPF program accepting messages starting with `action@` of one of actions, with
+	/// `SUBSYSTEM=` entry of one of subsystems
+	///
+	/// Kernel starts messages with `action@devpath`, `ACTION=action`, `DEVPATH=devpath` and
+	/// `SUBSYSTEM=subsystem`, so subsystem entry is at offset `2 * header + 17`, where `header`
+	/// is length of the first string. Program has no loops, so end of header is searched for in
+	/// unrolled sequence of comparisons.
+	#[cfg(target_os = "linux")]
+	fn program(&self) -> Vec<libc::sock_filter> {
+		let stmt = |code: u32, k: u32| libc::sock_filter {
+			code: code as u16,
+			jt: 0,
+			jf: 0,
+			k,
+		};
+		let accept = stmt(libc::BPF_RET | libc::BPF_K, u32::MAX);
+		let reject = stmt(libc::BPF_RET | libc::BPF_K, 0);
+
+		// Each action is followed by jump to subsystem checks, messages of no action are rejected
+		let mut program = Vec::new();
+		if !self.actions.is_empty() {
+			let blocks: Vec<_> = self
+				.actions
+				.iter()
+				.map(|action| compare(format!("{}@", action).as_bytes(), libc::BPF_ABS, 1))
+				.collect();
+			let mut rest: usize = blocks.iter().map(|block| block.len() + 1).sum();
+			for block in blocks {
+				rest -= block.len() + 1;
+				program.extend(block);
+				program.push(stmt(libc::BPF_JMP | libc::BPF_JA, rest as u32 + 1));
+			}
+			program.push(reject);
+		}
+
+		if self.subsystems.is_empty() {
+			program.push(accept);
+			return program;
+		}
+
+		// Offset of subsystem entry is loaded into X register once end of header is found
+		for offset in 1..HEADER_SCAN {
+			let rest = (HEADER_SCAN - offset - 1) * 4;
+			program.push(stmt(libc::BPF_LD | libc::BPF_B | libc::BPF_ABS, offset));
+			program.push(libc::sock_filter {
+				code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+				jt: 0,
+				jf: 2,
+				k: 0,
+			});
+			program.push(stmt(libc::BPF_LDX | libc::BPF_IMM, 2 * offset + 17));
+			program.push(stmt(libc::BPF_JMP | libc::BPF_JA, rest + 1));
+		}
+		program.push(accept);
+
+		// Loads past end of message would reject it, so length is checked first
+		for subsystem in &self.subsystems {
+			let entry = format!("SUBSYSTEM={}\0", subsystem).into_bytes();
+			let block = compare(&entry, libc::BPF_IND, 1);
+			program.push(stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_LEN, 0));
+			program.push(stmt(libc::BPF_ALU | libc::BPF_SUB | libc::BPF_X, 0));
+			program.push(libc::sock_filter {
+				code: (libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K) as u16,
+				jt: 0,
+				jf: (block.len() + 1) as u8,
+				k: entry.len() as u32,
+			});
+			program.extend(block);
+			program.push(accept);
+		}
+		program.push(reject);
+
+		// Too long program is refused by kernel, subsystems are then checked only after receiving
+		if program.len() > libc::BPF_MAXINSNS as usize {
+			return UeventFilter {
+				subsystems: Vec::new(),
+				..self.clone()
+			}
+			.program();
+		}
+
+		program
+	}
+}
+
+/// Builds BPF instructions comparing bytes at offset (absolute, or relative to X register with
+/// `BPF_IND`), mismatch jumps past comparison and `skip` following instructions
+///
+/// Bytes are compared in words, halfwords and bytes loaded in network byte order.
+#[cfg(target_os = "linux")]
+fn compare(bytes: &[u8], mode: u32, skip: usize) -> Vec<libc::sock_filter> {
+	let mut chunks = Vec::new();
+	let mut offset = 0;
+	while offset < bytes.len() {
+		let (size, len) = match bytes.len() - offset {
+			4.. => (libc::BPF_W, 4),
+			2 | 3 => (libc::BPF_H, 2),
+			_ => (libc::BPF_B, 1),
+		};
+		let value = bytes[offset..offset + len]
+			.iter()
+			.fold(0u32, |value, byte| value << 8 | *byte as u32);
+		chunks.push((size, offset as u32, value));
+		offset += len;
+	}
+
+	let mut program = Vec::new();
+	let count = chunks.len();
+	for (i, (size, offset, value)) in chunks.into_iter().enumerate() {
+		program.push(libc::sock_filter {
+			code: (libc::BPF_LD | size | mode) as u16,
+			jt: 0,
+			jf: 0,
+			k: offset,
+		});
+		program.push(libc::sock_filter {
+			code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+			jt: 0,
+			jf: (2 * (count - i - 1) + skip) as u8,
+			k: value,
+		});
+	}
+
+	program
+}
+
+/// Listens for uevents sent by kernel on netlink socket
+///
+/// Only messages sent by kernel are reported, messages from other processes are dropped.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let options = liblmod::Options::new().denylist(|name| name == "nouveau");
+/// let mut listener = liblmod::uevent::UeventListener::new()?;
+/// loop {
+///     let (event, report) = listener.autoload(&options)?;
+///     for (name, reason) in &report.skipped {
+///         println!("Not loading {name} for {}: {reason:?}", event.devpath);
+///     }
+///     for (name, e) in report.failed() {
+///         eprintln!("Failed to load {name} for {}: {e}", event.devpath);
+///     }
+/// }
+/// # }
+/// ```
+pub struct UeventListener {
+	socket: OwnedFd,
+	filter: UeventFilter,
+}
+
+impl UeventListener {
+	/// Subscribes to uevents of kernel
+	pub fn new() -> io::Result<UeventListener> {
+		UeventListener::with_filter(UeventFilter::new())
+	}
+
+	/// Subscribes to uevents of kernel accepted by filter
+	pub fn with_filter(filter: UeventFilter) -> io::Result<UeventListener> {
+		Ok(UeventListener {
+			socket: socket(&filter)?,
+			filter,
+		})
+	}
+
+	/// Waits for next uevent
+	pub fn next_event(&mut self) -> io::Result<Uevent> {
+		let mut buf = vec![0u8; MESSAGE_SIZE];
+		loop {
+			let len = match receive(&self.socket, &mut buf, 0) {
+				Ok(Some(len)) => len,
+				Ok(None) => continue,
+				Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+				Err(e) => return Err(e),
+			};
+
+			match Uevent::parse(&buf[..len]) {
+				Some(event) if self.filter.matches(&event) => return Ok(event),
+				_ => continue,
+			}
+		}
+	}
+
+	/// Converts listener to asynchronous stream of uevents
+	#[cfg(feature = "async")]
+	pub fn into_stream(self) -> io::Result<UeventStream> {
+		UeventStream::from_listener(self)
+	}
+
+	/// Waits for device with modalias to be added and loads modules matching it
+	///
+	/// Modules blacklisted in modprobe.d or refused by `Options::denylist` are reported as
+	/// skipped, like with `coldplug`. Configuration is read again for every device, so changes
+	/// of blacklist apply without restarting listener.
+	pub fn autoload(&mut self, options: &Options) -> io::Result<(Uevent, AutoloadReport)> {
+		loop {
+			let event = self.next_event()?;
+			let modalias = match (&event.modalias, event.action.as_str()) {
+				(Some(modalias), "add") => modalias.clone(),
+				_ => continue,
+			};
+
+			let index = options.index()?;
+			let config = options.load_config()?;
+			let report = autoload::load_matching(&*index, &config, [modalias].iter(), options)?;
+			return Ok((event, report));
+		}
+	}
+}
+
+impl Iterator for UeventListener {
+	type Item = io::Result<Uevent>;
+
+	fn next(&mut self) -> Option<io::Result<Uevent>> {
+		Some(self.next_event())
+	}
+}
+
+/// Asynchronous stream of uevents, usable with any executor (`async` feature)
+///
+/// Stream is woken up by helper thread waiting for messages on socket, so it doesn't need
+/// reactor of particular runtime. Thread exits when stream is dropped.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// use liblmod::uevent::{UeventFilter, UeventStream};
+///
+/// async fn watch_usb() -> std::io::Result<()> {
+///     let filter = UeventFilter::new().action("add").subsystem("usb").modalias("usb:*");
+///     let mut stream = UeventStream::new(filter)?;
+///     loop {
+///         let event = stream.next_event().await?;
+///         println!("USB device {} added", event.devpath);
+///     }
+/// }
+/// ```
+#[cfg(feature = "async")]
+pub struct UeventStream {
+	listener: UeventListener,
+	waiter: Arc<Waiter>,
+	buf: Vec<u8>,
+	_shutdown: UnixStream,
+}
+
+/// State shared between stream and its helper thread
+#[cfg(feature = "async")]
+#[derive(Default)]
+struct Waiter {
+	state: Mutex<WaitState>,
+	changed: Condvar,
+}
+
+#[cfg(feature = "async")]
+#[derive(Default)]
+struct WaitState {
+	/// Waker of task waiting for message, helper thread waits until it's set
+	waker: Option<Waker>,
+
+	/// Whether stream has been dropped
+	closed: bool,
+}
+
+#[cfg(feature = "async")]
+impl UeventStream {
+	/// Subscribes to uevents of kernel accepted by filter
+	pub fn new(filter: UeventFilter) -> io::Result<UeventStream> {
+		UeventStream::from_listener(UeventListener::with_filter(filter)?)
+	}
+
+	fn from_listener(listener: UeventListener) -> io::Result<UeventStream> {
+		let socket = listener.socket.try_clone()?;
+		let (shutdown, closed) = UnixStream::pair()?;
+		let waiter = Arc::new(Waiter::default());
+		let shared = waiter.clone();
+		thread::Builder::new()
+			.name("liblmod-uevent".to_string())
+			.spawn(move || wait_messages(socket, closed, shared))?;
+
+		Ok(UeventStream {
+			listener,
+			waiter,
+			buf: vec![0u8; MESSAGE_SIZE],
+			_shutdown: shutdown,
+		})
+	}
+
+	/// Waits for next uevent
+	pub async fn next_event(&mut self) -> io::Result<Uevent> {
+		let mut stream = Pin::new(self);
+		std::future::poll_fn(|cx| futures_core::Stream::poll_next(stream.as_mut(), cx))
+			.await
+			.unwrap_or_else(|| Err(ErrorKind::UnexpectedEof.into()))
+	}
+}
+
+#[cfg(feature = "async")]
+impl futures_core::Stream for UeventStream {
+	type Item = io::Result<Uevent>;
+
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<io::Result<Uevent>>> {
+		let stream = self.get_mut();
+		loop {
+			let len = match receive(&stream.listener.socket, &mut stream.buf, libc::MSG_DONTWAIT) {
+				Ok(Some(len)) => len,
+				Ok(None) => continue,
+				Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+				Err(e) if e.kind() == ErrorKind::WouldBlock => {
+					// Message arriving meanwhile is noticed by helper thread, which wakes task
+					stream.waiter.lock().waker = Some(cx.waker().clone());
+					stream.waiter.changed.notify_one();
+					return Poll::Pending;
+				}
+				Err(e) => return Poll::Ready(Some(Err(e))),
+			};
+
+			match Uevent::parse(&stream.buf[..len]) {
+				Some(event) if stream.listener.filter.matches(&event) => {
+					return Poll::Ready(Some(Ok(event)))
+				}
+				_ => continue,
+			}
+		}
+	}
+}
+
+#[cfg(feature = "async")]
+impl Drop for UeventStream {
+	fn drop(&mut self) {
+		self.waiter.lock().closed = true;
+		self.waiter.changed.notify_one();
+	}
+}
+
+#[cfg(feature = "async")]
+impl Waiter {
+	fn lock(&self) -> std::sync::MutexGuard<'_, WaitState> {
+		self.state.lock().unwrap_or_else(|e| e.into_inner())
+	}
+}
+
+/// Wakes task of stream whenever message is available, until stream is dropped
+#[cfg(feature = "async")]
+fn wait_messages(socket: OwnedFd, closed: UnixStream, waiter: Arc<Waiter>) {
+	loop {
+		// Wait until stream has run out of messages
+		let mut state = waiter.lock();
+		while state.waker.is_none() && !state.closed {
+			state = waiter
+				.changed
+				.wait(state)
+				.unwrap_or_else(|e| e.into_inner());
+		}
+		if state.closed {
+			return;
+		}
+		drop(state);
+
+		// The other end of socket pair is closed when stream is dropped
+		let mut fds = [
+			libc::pollfd {
+				fd: socket.as_raw_fd(),
+				events: libc::POLLIN,
+				revents: 0,
+			},
+			libc::pollfd {
+				fd: closed.as_raw_fd(),
+				events: libc::POLLIN,
+				revents: 0,
+			},
+		];
+		if unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) } == -1
+			&& io::Error::last_os_error().kind() == ErrorKind::Interrupted
+		{
+			continue;
+		}
+		if fds[1].revents != 0 {
+			return;
+		}
+
+		// Stream receives message (or error of socket) when woken up
+		let waker = waiter.lock().waker.take();
+		if let Some(waker) = waker {
+			waker.wake();
+		}
+	}
+}
+
+/// Opens netlink socket subscribed to uevents of kernel, with filter of actions attached
+#[cfg(target_os = "linux")]
+fn socket(filter: &UeventFilter) -> io::Result<OwnedFd> {
+	let socket = unsafe {
+		let fd = libc::socket(
+			libc::AF_NETLINK,
+			libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+			libc::NETLINK_KOBJECT_UEVENT,
+		);
+		if fd == -1 {
+			return Err(io::Error::last_os_error());
+		}
+		OwnedFd::from_raw_fd(fd)
+	};
+
+	let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+	addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+	addr.nl_groups = KERNEL_GROUP;
+	let result = unsafe {
+		libc::bind(
+			socket.as_raw_fd(),
+			&addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+			std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+		)
+	};
+	if result == -1 {
+		return Err(io::Error::last_os_error());
+	}
+
+	if !filter.actions.is_empty() || !filter.subsystems.is_empty() {
+		let mut program = filter.program();
+		let fprog = libc::sock_fprog {
+			len: program.len() as libc::c_ushort,
+			filter: program.as_mut_ptr(),
+		};
+		let result = unsafe {
+			libc::setsockopt(
+				socket.as_raw_fd(),
+				libc::SOL_SOCKET,
+				libc::SO_ATTACH_FILTER,
+				&fprog as *const libc::sock_fprog as *const libc::c_void,
+				std::mem::size_of::<libc::sock_fprog>() as libc::socklen_t,
+			)
+		};
+		if result == -1 {
+			return Err(io::Error::last_os_error());
+		}
+	}
+
+	Ok(socket)
+}
+
+/// Uevents are sent only by Linux
+#[cfg(not(target_os = "linux"))]
+fn socket(_filter: &UeventFilter) -> io::Result<OwnedFd> {
+	Err(crate::backend::unsupported())
+}
+
+/// Receives message, `None` if it hasn't been sent by kernel
+#[cfg(target_os = "linux")]
+fn receive(socket: &OwnedFd, buf: &mut [u8], flags: libc::c_int) -> io::Result<Option<usize>> {
+	let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+	let mut addrlen = std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+	let len = unsafe {
+		libc::recvfrom(
+			socket.as_raw_fd(),
+			buf.as_mut_ptr() as *mut libc::c_void,
+			buf.len(),
+			flags,
+			&mut addr as *mut libc::sockaddr_nl as *mut libc::sockaddr,
+			&mut addrlen,
+		)
+	};
+	if len == -1 {
+		return Err(io::Error::last_os_error());
+	}
+
+	// Any process can send to multicast group, kernel messages have port 0
+	match addr.nl_pid {
+		0 => Ok(Some(len as usize)),
+		_ => Ok(None),
+	}
+}
+
+#[cfg(not(target_os = "linux"))]
+fn receive(_socket: &OwnedFd, _buf: &mut [u8], _flags: libc::c_int) -> io::Result<Option<usize>> {
+	Err(crate::backend::unsupported())
+}
@@ -0,0 +1,133 @@
+use std::{collections::BTreeMap, io};
+
+use crate::{elf, index, Error, ModuleIndex, ModuleInfo, Selection};
+
+/// Results of verifying module tree, returned by `verify_tree`
+#[derive(Debug, Default)]
+pub struct TreeReport {
+	/// Release of kernel tree has been verified for
+	pub kernel: String,
+
+	/// Number of checked module files, including those with problems
+	pub checked: usize,
+
+	/// Version magic shared by most modules, which other modules are compared with
+	pub vermagic: Option<String>,
+
+	/// Problems of module files, by path relative to module directory
+	pub problems: BTreeMap<String, io::Error>,
+}
+
+impl TreeReport {
+	/// Returns whether no problems have been found
+	pub fn is_valid(&self) -> bool {
+		self.problems.is_empty()
+	}
+}
+
+/// Checks every module file of kernel tree, without loading any of them
+///
+/// Every module listed in modules.dep, its dependencies and duplicates of the same name are
+/// read (decompressed if needed) and checked to be relocatable ELF objects for machine of
+/// running kernel, with version magic of tree's kernel release. Version magic is also compared
+/// across modules, as modules built with different configuration (e.g. preemption model) are
+/// rejected by kernel. Problems of one module don't stop checking of others.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// liblmod::set_filesystem(
+///     liblmod::MemoryFs::new()
+///         .file("/lib/modules/6.1.0/modules.dep", "kernel/foo.ko: kernel/bar.ko\n")
+///         .file("/lib/modules/6.1.0/kernel/foo.ko", "garbage"),
+/// );
+///
+/// let report = liblmod::verify_tree(liblmod::Selection::Other("6.1.0".to_string())).unwrap();
+/// assert_eq!(report.checked, 2);
+/// assert!(!report.is_valid());
+/// assert_eq!(report.problems["kernel/bar.ko"].kind(), std::io::ErrorKind::NotFound);
+/// assert!(matches!(
+///     liblmod::Error::from_io(&report.problems["kernel/foo.ko"]),
+///     Some(liblmod::Error::InvalidModuleFormat(_))
+/// ));
+///
+/// liblmod::clear_filesystem();
+/// ```
+pub fn verify_tree(kernel: Selection) -> io::Result<TreeReport> {
+	verify_tree_for(kernel, &crate::kernel::machine()?)
+}
+
+/// Checks every module file of kernel tree built for specified machine (e.g. `aarch64`)
+///
+/// Trees of other architectures are verified on build hosts this way, see `verify_tree`.
+pub fn verify_tree_for(kernel: Selection, machine: &str) -> io::Result<TreeReport> {
+	let index = ModuleIndex::open(kernel)?;
+	let mut report = TreeReport {
+		kernel: index.kernel().to_string(),
+		..TreeReport::default()
+	};
+
+	let mut vermagics = BTreeMap::new();
+	for module in index.files() {
+		report.checked += 1;
+		match check(&index, module, machine) {
+			Ok(vermagic) => {
+				vermagics.insert(module, vermagic);
+			}
+			Err(e) => {
+				report.problems.insert(module.to_string(), e);
+			}
+		}
+	}
+
+	// The most common version magic is taken as the one of tree
+	let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+	for vermagic in vermagics.values() {
+		*counts.entry(vermagic).or_default() += 1;
+	}
+	let common = counts
+		.into_iter()
+		.max_by_key(|(_, count)| *count)
+		.map(|(vermagic, _)| vermagic.to_string());
+	if let Some(common) = &common {
+		for (module, vermagic) in &vermagics {
+			if vermagic != common {
+				let reason = format!(
+					"Module {} has version magic {}, other modules have {}",
+					module, vermagic, common
+				);
+				report.problems.insert(module.to_string(), invalid(reason));
+			}
+		}
+	}
+
+	report.vermagic = common;
+	Ok(report)
+}
+
+/// Checks module file, returning its version magic
+fn check(index: &ModuleIndex, module: &str, machine: &str) -> io::Result<String> {
+	let image = index::module_image(&index.path(module))?;
+	elf::check(&image, machine)?;
+
+	let vermagic = ModuleInfo::from_image(&image)?
+		.vermagic
+		.ok_or_else(|| invalid(format!("Module {} has no version magic", module)))?;
+
+	// Version magic starts with kernel release
+	if vermagic.split_whitespace().next() != Some(index.kernel()) {
+		return Err(invalid(format!(
+			"Module {} is built for {}, but tree is for kernel {}",
+			module,
+			vermagic,
+			index.kernel()
+		)));
+	}
+
+	Ok(vermagic)
+}
+
+fn invalid(reason: String) -> io::Error {
+	Error::InvalidModuleFormat(reason).into()
+}
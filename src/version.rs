@@ -0,0 +1,133 @@
+use std::{cmp::Ordering, fmt, io, str::FromStr};
+
+use crate::Error;
+
+/// Kernel version parsed from release string (e.g. `6.8.0-45-generic`)
+///
+/// Versions are compared by their numeric components first, then by the rest of release string,
+/// whose numbers are compared by value (`-10-generic` is newer than `-9-generic`).
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let older: liblmod::KernelVersion = "6.8.0-9-generic".parse().unwrap();
+/// assert!(older < "6.8.0-10-generic".parse().unwrap());
+///
+/// match liblmod::uname() {
+///     Ok(version) if version >= liblmod::KernelVersion::new(5, 4, 0) => println!("Kernel {version} is new enough"),
+///     Ok(version) => println!("Kernel {version} is too old"),
+///     Err(e) => eprintln!("Failed to get kernel version: {e}"),
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KernelVersion {
+	/// Major version
+	pub major: u32,
+
+	/// Minor version
+	pub minor: u32,
+
+	/// Patch level (0 if missing in release string)
+	pub patch: u32,
+
+	/// Rest of release string (e.g. `-45-generic`)
+	pub extra: String,
+}
+
+impl KernelVersion {
+	/// Creates kernel version without extra part
+	pub fn new(major: u32, minor: u32, patch: u32) -> KernelVersion {
+		KernelVersion {
+			major,
+			minor,
+			patch,
+			extra: String::new(),
+		}
+	}
+}
+
+impl Ord for KernelVersion {
+	fn cmp(&self, other: &KernelVersion) -> Ordering {
+		(self.major, self.minor, self.patch)
+			.cmp(&(other.major, other.minor, other.patch))
+			.then_with(|| components(&self.extra).cmp(components(&other.extra)))
+			.then_with(|| self.extra.cmp(&other.extra))
+	}
+}
+
+impl PartialOrd for KernelVersion {
+	fn partial_cmp(&self, other: &KernelVersion) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+/// Part of release string compared as number or as text
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Component<'a> {
+	Text(&'a str),
+	Number(u64),
+}
+
+/// Splits release string into runs of digits and other characters
+fn components(extra: &str) -> impl Iterator<Item = Component<'_>> {
+	let mut rest = extra;
+	std::iter::from_fn(move || {
+		let digits = rest.starts_with(|c: char| c.is_ascii_digit());
+		let end = rest
+			.find(|c: char| c.is_ascii_digit() != digits)
+			.unwrap_or(rest.len());
+		let (run, tail) = rest.split_at(end);
+		rest = tail;
+		match (run.is_empty(), digits) {
+			(true, _) => None,
+			(false, true) => Some(run.parse().map_or(Component::Text(run), Component::Number)),
+			(false, false) => Some(Component::Text(run)),
+		}
+	})
+}
+
+impl FromStr for KernelVersion {
+	type Err = io::Error;
+
+	fn from_str(release: &str) -> io::Result<KernelVersion> {
+		let invalid = || io::Error::from(Error::InvalidKernelVersion(release.to_string()));
+
+		// Split numeric part (e.g. `6.8.0`) from the rest
+		let end = release
+			.find(|c: char| !c.is_ascii_digit() && c != '.')
+			.unwrap_or(release.len());
+		let (mut numbers, mut extra) = release.split_at(end);
+
+		// Components after patch level (e.g. `2.6.32.1`) are kept in the rest
+		if let Some((i, _)) = numbers.match_indices('.').nth(2) {
+			(numbers, extra) = release.split_at(i);
+		}
+
+		let mut components = numbers.split('.').map(|n| n.parse::<u32>());
+		let major = components.next().and_then(|n| n.ok()).ok_or_else(invalid)?;
+		let minor = components.next().and_then(|n| n.ok()).ok_or_else(invalid)?;
+		let patch = match components.next() {
+			Some(n) => n.map_err(|_| invalid())?,
+			None => 0,
+		};
+
+		Ok(KernelVersion {
+			major,
+			minor,
+			patch,
+			extra: extra.to_string(),
+		})
+	}
+}
+
+impl fmt::Display for KernelVersion {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"{}.{}.{}{}",
+			self.major, self.minor, self.patch, self.extra
+		)
+	}
+}
@@ -0,0 +1,272 @@
+use std::{
+	cell::RefCell,
+	collections::BTreeMap,
+	fs,
+	io::{self, ErrorKind, Read},
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+
+thread_local! {
+	/// In-memory filesystem replacing real one on current thread
+	static MEMORY: RefCell<Option<Arc<MemoryFs>>> = const { RefCell::new(None) };
+}
+
+/// Filesystem read by resolution logic (module manifests, configuration, `/proc` and `/sys`)
+pub(crate) trait Vfs {
+	/// Opens file for reading
+	fn open(&self, path: &Path) -> io::Result<Box<dyn Read>>;
+
+	/// Returns paths of entries in directory
+	fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+	/// Reads whole file as string
+	fn read_to_string(&self, path: &Path) -> io::Result<String> {
+		let mut content = String::new();
+		self.open(path)?.read_to_string(&mut content)?;
+		Ok(content)
+	}
+
+	/// Checks if file or directory exists
+	fn exists(&self, path: &Path) -> bool;
+
+	/// Checks if path is directory
+	fn is_dir(&self, path: &Path) -> bool;
+
+	/// Returns size of file in bytes
+	fn size(&self, path: &Path) -> io::Result<u64>;
+
+	/// Returns absolute path with symbolic links resolved
+	fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+	/// Checks if path is symbolic link, in-memory filesystem has none
+	#[cfg(feature = "uevent")]
+	fn is_symlink(&self, _path: &Path) -> bool {
+		false
+	}
+}
+
+/// Filesystem of the system
+struct RealFs;
+
+impl Vfs for RealFs {
+	fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+		Ok(Box::new(crate::confine::open(path, true)?))
+	}
+
+	fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+		fs::read_dir(path)?
+			.map(|entry| entry.map(|entry| entry.path()))
+			.collect()
+	}
+
+	fn exists(&self, path: &Path) -> bool {
+		path.exists()
+	}
+
+	fn is_dir(&self, path: &Path) -> bool {
+		path.is_dir()
+	}
+
+	fn size(&self, path: &Path) -> io::Result<u64> {
+		Ok(fs::metadata(path)?.len())
+	}
+
+	fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+		fs::canonicalize(path)
+	}
+
+	#[cfg(feature = "uevent")]
+	fn is_symlink(&self, path: &Path) -> bool {
+		path.is_symlink()
+	}
+}
+
+/// In-memory filesystem for testing code using this crate without root and real kernel tree
+///
+/// Once set with `set_filesystem`, module manifests, modprobe.d, depmod.d and modules-load.d
+/// configuration, kernel command line, `/proc/modules`, `/sys/module`, modaliases of devices in
+/// `/sys/devices`, firmware and module files
+/// read by resolution logic come from this filesystem instead of the real one. Directories exist
+/// implicitly when they contain a file. Binary indices (e.g. modules.alias.bin) are not used,
+/// their text versions are read instead.
+///
+/// Example:
+/// ```rust
+/// extern crate liblmod;
+///
+/// let fs = liblmod::MemoryFs::new()
+///     .file("/lib/modules/6.1.0/modules.order", "kernel/arch/x86/kvm/kvm.ko\n")
+///     .file("/lib/modules/6.1.0/modules.dep", "kernel/arch/x86/kvm/kvm.ko:\n");
+/// liblmod::set_filesystem(fs);
+///
+/// let index = liblmod::ModuleIndex::open(liblmod::Selection::Other("6.1.0".to_string())).unwrap();
+/// assert_eq!(index.resolve("kvm"), Some("kernel/arch/x86/kvm/kvm.ko"));
+///
+/// liblmod::clear_filesystem();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct MemoryFs {
+	files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryFs {
+	/// Creates empty filesystem
+	pub fn new() -> MemoryFs {
+		MemoryFs::default()
+	}
+
+	/// Adds file with content, replacing existing one
+	pub fn file(mut self, path: impl AsRef<Path>, content: impl AsRef<[u8]>) -> MemoryFs {
+		self.files
+			.insert(path.as_ref().to_path_buf(), content.as_ref().to_vec());
+		self
+	}
+}
+
+impl Vfs for MemoryFs {
+	fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+		match self.files.get(path) {
+			Some(content) => Ok(Box::new(io::Cursor::new(content.clone()))),
+			None => Err(not_found(path)),
+		}
+	}
+
+	fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+		let mut entries: Vec<PathBuf> = Vec::new();
+		for file in self.files.keys() {
+			let child = match file.strip_prefix(path).ok().and_then(|f| f.iter().next()) {
+				Some(o) => path.join(o),
+				None => continue,
+			};
+			if !entries.contains(&child) {
+				entries.push(child);
+			}
+		}
+
+		if entries.is_empty() {
+			return Err(not_found(path));
+		}
+
+		Ok(entries)
+	}
+
+	fn exists(&self, path: &Path) -> bool {
+		self.files.contains_key(path) || self.is_dir(path)
+	}
+
+	fn is_dir(&self, path: &Path) -> bool {
+		self.files
+			.keys()
+			.any(|file| file != path && file.starts_with(path))
+	}
+
+	fn size(&self, path: &Path) -> io::Result<u64> {
+		match self.files.get(path) {
+			Some(content) => Ok(content.len() as u64),
+			None => Err(not_found(path)),
+		}
+	}
+
+	fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+		match self.exists(path) {
+			true => Ok(path.to_path_buf()),
+			false => Err(not_found(path)),
+		}
+	}
+}
+
+fn not_found(path: &Path) -> io::Error {
+	io::Error::new(
+		ErrorKind::NotFound,
+		format!("{} not found in memory filesystem", path.display()),
+	)
+}
+
+/// Replaces real filesystem with in-memory one on current thread
+///
+/// Other threads keep reading the real filesystem, so tests running in parallel don't affect
+/// each other. Shared module indices are not used while in-memory filesystem is set.
+pub fn set_filesystem(fs: MemoryFs) {
+	MEMORY.with(|memory| *memory.borrow_mut() = Some(Arc::new(fs)));
+}
+
+/// Restores real filesystem on current thread
+pub fn clear_filesystem() {
+	MEMORY.with(|memory| *memory.borrow_mut() = None);
+}
+
+/// Checks if in-memory filesystem is set on current thread
+pub(crate) fn is_memory() -> bool {
+	MEMORY.with(|memory| memory.borrow().is_some())
+}
+
+/// Returns in-memory filesystem of current thread, to be shared with worker threads
+pub(crate) fn current() -> Option<Arc<MemoryFs>> {
+	MEMORY.with(|memory| memory.borrow().clone())
+}
+
+/// Sets filesystem of current thread to one returned by `current` on another thread
+pub(crate) fn inherit(fs: Option<Arc<MemoryFs>>) {
+	MEMORY.with(|memory| *memory.borrow_mut() = fs);
+}
+
+/// Runs function with in-memory filesystem set on current thread, restoring previous one
+pub(crate) fn with_memory<R>(fs: Arc<MemoryFs>, f: impl FnOnce() -> R) -> R {
+	let previous = MEMORY.with(|memory| memory.borrow_mut().replace(fs));
+	let result = f();
+	inherit(previous);
+	result
+}
+
+/// Runs function with filesystem of current thread
+fn with<R>(f: impl FnOnce(&dyn Vfs) -> R) -> R {
+	match MEMORY.with(|memory| memory.borrow().clone()) {
+		Some(memory) => f(&*memory),
+		None => f(&RealFs),
+	}
+}
+
+pub(crate) fn open(path: impl AsRef<Path>) -> io::Result<Box<dyn Read>> {
+	with(|vfs| vfs.open(path.as_ref()))
+}
+
+pub(crate) fn read_dir(path: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+	with(|vfs| vfs.read_dir(path.as_ref()))
+}
+
+pub(crate) fn read_to_string(path: impl AsRef<Path>) -> io::Result<String> {
+	with(|vfs| vfs.read_to_string(path.as_ref()))
+}
+
+pub(crate) fn read(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+	let mut content = Vec::new();
+	open(path)?.read_to_end(&mut content)?;
+	Ok(content)
+}
+
+pub(crate) fn exists(path: impl AsRef<Path>) -> bool {
+	with(|vfs| vfs.exists(path.as_ref()))
+}
+
+pub(crate) fn is_dir(path: impl AsRef<Path>) -> bool {
+	with(|vfs| vfs.is_dir(path.as_ref()))
+}
+
+pub(crate) fn size(path: impl AsRef<Path>) -> io::Result<u64> {
+	with(|vfs| vfs.size(path.as_ref()))
+}
+
+#[cfg(any(feature = "compat", feature = "modinfo"))]
+pub(crate) fn is_file(path: impl AsRef<Path>) -> bool {
+	exists(&path) && !is_dir(&path)
+}
+
+pub(crate) fn canonicalize(path: impl AsRef<Path>) -> io::Result<PathBuf> {
+	with(|vfs| vfs.canonicalize(path.as_ref()))
+}
+
+#[cfg(feature = "uevent")]
+pub(crate) fn is_symlink(path: impl AsRef<Path>) -> bool {
+	with(|vfs| vfs.is_symlink(path.as_ref()))
+}
@@ -0,0 +1,315 @@
+use std::{
+	collections::{BTreeSet, VecDeque},
+	fs,
+	io::{self, ErrorKind},
+	os::fd::{AsRawFd, OwnedFd},
+	thread,
+	time::{Duration, Instant},
+};
+
+#[cfg(target_os = "linux")]
+use std::{ffi::CString, os::fd::FromRawFd};
+
+use crate::{index, vfs, Config, ConfigChanges, Error};
+
+/// Directory with loaded modules
+const SYSFS_MODULES: &str = "/sys/module";
+
+/// Interval of polling initstate of module
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Module event reported by `Watcher`
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ModuleEvent {
+	/// Module has been loaded
+	Loaded(String),
+
+	/// Module has been removed
+	Removed(String),
+}
+
+/// Watches `/sys/module` for loaded and removed modules
+///
+/// Watcher is woken up by inotify. Because sysfs doesn't report every change through inotify,
+/// module directory is also rescanned periodically (every second by default).
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// # fn main() -> std::io::Result<()> {
+/// for event in liblmod::watch::Watcher::new()? {
+///     match event? {
+///         liblmod::watch::ModuleEvent::Loaded(name) => println!("Loaded {name}"),
+///         liblmod::watch::ModuleEvent::Removed(name) => println!("Removed {name}"),
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Watcher {
+	inotify: OwnedFd,
+	interval: Duration,
+	known: BTreeSet<String>,
+	pending: VecDeque<ModuleEvent>,
+}
+
+impl Watcher {
+	/// Starts watching modules, currently loaded modules are not reported
+	pub fn new() -> io::Result<Watcher> {
+		Ok(Watcher {
+			inotify: inotify()?,
+			interval: Duration::from_secs(1),
+			known: scan()?,
+			pending: VecDeque::new(),
+		})
+	}
+
+	/// Sets interval of module directory rescanning
+	pub fn interval(mut self, interval: Duration) -> Watcher {
+		self.interval = interval;
+		self
+	}
+}
+
+/// Waits for inotify event or rescan interval, draining pending events
+fn wait(inotify: &OwnedFd, interval: Duration) -> io::Result<()> {
+	let mut pollfd = libc::pollfd {
+		fd: inotify.as_raw_fd(),
+		events: libc::POLLIN,
+		revents: 0,
+	};
+	let timeout = interval.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+	if unsafe { libc::poll(&mut pollfd, 1, timeout) } == -1 {
+		let e = io::Error::last_os_error();
+		if e.kind() != ErrorKind::Interrupted {
+			return Err(e);
+		}
+	}
+
+	// Drain inotify events, changes are detected by rescanning
+	let mut buf = [0u8; 4096];
+	while unsafe {
+		libc::read(
+			inotify.as_raw_fd(),
+			buf.as_mut_ptr() as *mut libc::c_void,
+			buf.len(),
+		)
+	} > 0
+	{}
+
+	Ok(())
+}
+
+/// Sets up inotify watching module directory
+#[cfg(target_os = "linux")]
+fn inotify() -> io::Result<OwnedFd> {
+	let inotify = inotify_init()?;
+	let mask = libc::IN_CREATE | libc::IN_DELETE | libc::IN_ATTRIB;
+	add_watch(&inotify, SYSFS_MODULES, mask)?;
+	Ok(inotify)
+}
+
+/// Creates non-blocking inotify instance
+#[cfg(target_os = "linux")]
+fn inotify_init() -> io::Result<OwnedFd> {
+	let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+	if fd == -1 {
+		return Err(io::Error::last_os_error());
+	}
+
+	Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Watches path for events in mask
+#[cfg(target_os = "linux")]
+fn add_watch(inotify: &OwnedFd, path: &str, mask: u32) -> io::Result<()> {
+	let path = CString::new(path).unwrap();
+	if unsafe { libc::inotify_add_watch(inotify.as_raw_fd(), path.as_ptr(), mask) } == -1 {
+		return Err(io::Error::last_os_error());
+	}
+
+	Ok(())
+}
+
+/// Module directory can be watched only on Linux
+#[cfg(not(target_os = "linux"))]
+fn inotify() -> io::Result<OwnedFd> {
+	Err(crate::backend::unsupported())
+}
+
+impl Iterator for Watcher {
+	type Item = io::Result<ModuleEvent>;
+
+	fn next(&mut self) -> Option<io::Result<ModuleEvent>> {
+		loop {
+			if let Some(event) = self.pending.pop_front() {
+				return Some(Ok(event));
+			}
+
+			if let Err(e) = wait(&self.inotify, self.interval) {
+				return Some(Err(e));
+			}
+
+			let current = match scan() {
+				Ok(o) => o,
+				Err(e) => return Some(Err(e)),
+			};
+			for name in current.difference(&self.known) {
+				self.pending.push_back(ModuleEvent::Loaded(name.clone()));
+			}
+			for name in self.known.difference(&current) {
+				self.pending.push_back(ModuleEvent::Removed(name.clone()));
+			}
+			self.known = current;
+		}
+	}
+}
+
+/// Watches modprobe.d directories, reloading configuration when it changes
+///
+/// Watcher is woken up by inotify on existing configuration directories. Configuration is also
+/// re-read periodically (every 10 seconds by default), so directories created later are noticed
+/// too. Only changes of directives are reported, touching files without changing them is not.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// # fn main() -> std::io::Result<()> {
+/// liblmod::watch::ConfigWatcher::new()?.run(|config, changes| {
+///     for directive in &changes.added {
+///         println!("New directive {directive} from {}", directive.source.display());
+///     }
+///     for directive in &changes.removed {
+///         println!("Removed directive {directive}");
+///     }
+///     println!("pcspkr blacklisted: {}", config.is_blacklisted("pcspkr"));
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ConfigWatcher {
+	inotify: OwnedFd,
+	interval: Duration,
+	config: Config,
+}
+
+impl ConfigWatcher {
+	/// Reads configuration and starts watching it
+	pub fn new() -> io::Result<ConfigWatcher> {
+		Ok(ConfigWatcher {
+			inotify: config_inotify()?,
+			interval: Duration::from_secs(10),
+			config: Config::load()?,
+		})
+	}
+
+	/// Sets interval of re-reading configuration without inotify event
+	pub fn interval(mut self, interval: Duration) -> ConfigWatcher {
+		self.interval = interval;
+		self
+	}
+
+	/// Returns current configuration
+	pub fn config(&self) -> &Config {
+		&self.config
+	}
+
+	/// Waits until configuration changes and returns changed directives
+	pub fn next_change(&mut self) -> io::Result<ConfigChanges> {
+		loop {
+			wait(&self.inotify, self.interval)?;
+			let changes = self.config.reload()?;
+			if !changes.is_empty() {
+				return Ok(changes);
+			}
+		}
+	}
+
+	/// Calls callback with new configuration and changed directives on every change
+	///
+	/// Returns only on failure of reading configuration or waiting for changes.
+	pub fn run(mut self, mut callback: impl FnMut(&Config, &ConfigChanges)) -> io::Result<()> {
+		loop {
+			let changes = self.next_change()?;
+			callback(&self.config, &changes);
+		}
+	}
+}
+
+/// Sets up inotify watching existing modprobe.d directories
+#[cfg(target_os = "linux")]
+fn config_inotify() -> io::Result<OwnedFd> {
+	let inotify = inotify_init()?;
+	let mask = libc::IN_CREATE
+		| libc::IN_DELETE
+		| libc::IN_CLOSE_WRITE
+		| libc::IN_MOVED_FROM
+		| libc::IN_MOVED_TO
+		| libc::IN_DELETE_SELF;
+	for dir in crate::config::CONFIG_DIRS {
+		match add_watch(&inotify, dir, mask) {
+			Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+			_ => (),
+		}
+	}
+
+	Ok(inotify)
+}
+
+/// Configuration directories can be watched only on Linux
+#[cfg(not(target_os = "linux"))]
+fn config_inotify() -> io::Result<OwnedFd> {
+	Err(crate::backend::unsupported())
+}
+
+/// Lists loaded modules (built-in modules don't have initstate)
+fn scan() -> io::Result<BTreeSet<String>> {
+	let mut modules = BTreeSet::new();
+	for entry in fs::read_dir(SYSFS_MODULES)? {
+		let entry = entry?;
+		if entry.path().join("initstate").exists() {
+			modules.insert(entry.file_name().to_string_lossy().into_owned());
+		}
+	}
+
+	Ok(modules)
+}
+
+/// Waits until module is live, polling `/sys/module/<name>/initstate`
+///
+/// Module which is not loaded yet or is still coming (being initialized) is waited for. If it
+/// isn't live before timeout, `Error::Timeout` is returned. Built-in modules don't have
+/// initstate, so they are never reported as live.
+///
+/// Example:
+/// ```rust,no_run
+/// extern crate liblmod;
+///
+/// use std::time::Duration;
+///
+/// if let Err(e) = liblmod::watch::wait_live("kvm", Duration::from_secs(5)) {
+///     eprintln!("Module kvm is not ready: {e}");
+/// }
+/// ```
+pub fn wait_live(name: impl AsRef<str>, timeout: Duration) -> io::Result<()> {
+	let name = index::normalize(name.as_ref());
+	let path = format!("{}/{}/initstate", SYSFS_MODULES, name);
+	let deadline = Instant::now() + timeout;
+	loop {
+		match vfs::read_to_string(&path) {
+			Ok(state) if state.trim() == "live" => return Ok(()),
+			Ok(_) => (),
+			Err(e) if e.kind() == ErrorKind::NotFound => (),
+			Err(e) => return Err(e),
+		}
+
+		let now = Instant::now();
+		if now >= deadline {
+			return Err(Error::Timeout(name).into());
+		}
+		thread::sleep(POLL_INTERVAL.min(deadline - now));
+	}
+}